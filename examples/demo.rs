@@ -1,7 +1,7 @@
 use {
     cosmwasm_std::{from_binary, testing::MockStorage, Storage},
     serde::ser::Serialize,
-    tree::{verify_membership, verify_non_membership, Op, Tree},
+    tree::{prove_queries, verify_membership, verify_non_membership, Op, ProverRequest, Tree},
 };
 
 const TREE: Tree<String, String> = Tree::new_default();
@@ -81,25 +81,30 @@ fn print_orphans(store: &dyn Storage) {
     print_json_pretty(&res)
 }
 
-fn print_values_and_verify(store: &dyn Storage, keys: &[&str]) {
+fn print_values_and_verify(store: &MockStorage, keys: &[&str]) {
     let root = TREE.root(store, None).unwrap();
 
-    let mut responses = vec![];
-    for key in keys {
-        let key = key.to_string();
-        let res = TREE.get(store, &key, true, None).unwrap();
+    let requests = keys
+        .iter()
+        .map(|key| ProverRequest::Get {
+            key: key.to_string(),
+            prove: true,
+        })
+        .collect();
+    let responses = prove_queries(&TREE, store, requests, None).unwrap();
+
+    for res in &responses {
+        let key = &res.key;
         let proof = from_binary(res.proof.as_ref().unwrap()).unwrap();
 
         // verify the proof
         if let Some(value) = &res.value {
-            verify_membership(&root.root_hash, &key, value, &proof).unwrap();
+            verify_membership(&root.root_hash, key, value, &proof).unwrap();
             println!("verified the existence of ({key}, {value})");
         } else {
-            verify_non_membership(&root.root_hash, &key, &proof).unwrap();
+            verify_non_membership(&root.root_hash, key, &proof).unwrap();
             println!("verified the non-existence of {key}");
         }
-
-        responses.push(res);
     }
 
     print_json_pretty(&responses)
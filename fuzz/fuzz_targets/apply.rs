@@ -0,0 +1,66 @@
+//! Apply a sequence of batches to the tree, and check after each one that the
+//! tree contains exactly the same records as a `BTreeMap` that the same ops
+//! have been applied to.
+//!
+//! Keys are short, so that batches often touch keys that are prefixes of each
+//! other, or that share prefixes of odd numbers of nibbles.
+
+#![no_main]
+
+use {
+    arbitrary::Arbitrary,
+    cosmwasm_std::{testing::MockStorage, Order},
+    libfuzzer_sys::fuzz_target,
+    std::collections::BTreeMap,
+    tree::{Batch, Op, Tree},
+};
+
+const TREE: Tree<Vec<u8>, Vec<u8>> = Tree::new_default();
+
+const MAX_KEY_LEN: usize = 4;
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    batches: Vec<Vec<(Vec<u8>, Option<u8>)>>,
+}
+
+fuzz_target!(|input: Input| {
+    let mut store = MockStorage::new();
+    let mut model = BTreeMap::new();
+
+    for ops in input.batches {
+        // keys must not be empty
+        let batch: Batch<_, _> = ops
+            .into_iter()
+            .filter(|(key, _)| !key.is_empty())
+            .map(|(mut key, value)| {
+                key.truncate(MAX_KEY_LEN);
+                (key, value.map_or(Op::Delete, |value| Op::Insert(vec![value])))
+            })
+            .collect();
+        if batch.is_empty() {
+            continue;
+        }
+
+        for (key, op) in &batch {
+            match op {
+                Op::Insert(value) => model.insert(key.clone(), value.clone()),
+                Op::Delete => model.remove(key),
+            };
+        }
+        TREE.apply(&mut store, batch).unwrap();
+
+        // an empty tree has no root node, so it can't be iterated
+        if model.is_empty() {
+            assert!(TREE.root(&store, None).is_err());
+            continue;
+        }
+
+        let items = TREE
+            .iterate(&store, Order::Ascending, None, None, None)
+            .unwrap()
+            .collect::<Result<BTreeMap<_, _>, _>>()
+            .unwrap();
+        assert_eq!(items, model);
+    }
+});
@@ -0,0 +1,28 @@
+//! Check that nibble paths survive serde roundtrips, both starting from a path
+//! and starting from a string.
+
+#![no_main]
+
+use {
+    libfuzzer_sys::fuzz_target,
+    tree::{Nibble, NibblePath},
+};
+
+fuzz_target!(|input: (Vec<u8>, String)| {
+    let (nibbles, hex_str) = input;
+
+    // paths of odd lengths are padded with a zero nibble, which must not be
+    // mistaken for a nibble of the path
+    let nibble_path =
+        nibbles.into_iter().map(|byte| Nibble::new(byte & 0xf)).collect::<NibblePath>();
+    let json = serde_json::to_string(&nibble_path).unwrap();
+    assert_eq!(serde_json::from_str::<NibblePath>(&json).unwrap(), nibble_path);
+
+    // any string that deserializes does so into a path whose serialization is
+    // the same string, apart from the case of hex digits
+    let json = serde_json::to_string(&hex_str).unwrap();
+    if let Ok(nibble_path) = serde_json::from_str::<NibblePath>(&json) {
+        assert_eq!(nibble_path.to_hex(), hex_str.to_lowercase());
+        assert_eq!(nibble_path.num_nibbles, hex_str.len());
+    }
+});
@@ -0,0 +1,27 @@
+//! Check that node keys survive roundtrips through their encoding as storage
+//! keys, and that decoding arbitrary bytes never panics.
+
+#![no_main]
+
+use {
+    cw_storage_plus::{KeyDeserialize, PrimaryKey},
+    libfuzzer_sys::fuzz_target,
+    tree::{Nibble, NibblePath, NodeKey},
+};
+
+fn encode(node_key: &NodeKey) -> Vec<u8> {
+    node_key.key().iter().flat_map(|key| key.as_ref().to_vec()).collect()
+}
+
+fuzz_target!(|input: (u64, Vec<u8>, Vec<u8>)| {
+    let (version, nibbles, raw) = input;
+
+    let nibble_path =
+        nibbles.into_iter().map(|byte| Nibble::new(byte & 0xf)).collect::<NibblePath>();
+    let node_key = NodeKey::new(version, nibble_path);
+    assert_eq!(<&NodeKey>::from_vec(encode(&node_key)).unwrap(), node_key);
+
+    if let Ok(node_key) = <&NodeKey>::from_vec(raw.clone()) {
+        assert_eq!(encode(&node_key), raw);
+    }
+});
@@ -0,0 +1,60 @@
+//! Build a tree, then prove the membership or non-membership of arbitrary keys,
+//! and check that the proofs verify against the root hash.
+
+#![no_main]
+
+use {
+    arbitrary::Arbitrary,
+    cosmwasm_std::{from_binary, testing::MockStorage},
+    libfuzzer_sys::fuzz_target,
+    tree::{
+        compute_root_from_membership, compute_root_from_non_membership, verify_membership,
+        verify_non_membership, Batch, Op, Proof, Tree,
+    },
+};
+
+const TREE: Tree<Vec<u8>, Vec<u8>> = Tree::new_default();
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    records: Vec<(Vec<u8>, Vec<u8>)>,
+    queries: Vec<Vec<u8>>,
+}
+
+fuzz_target!(|input: Input| {
+    // keys must not be empty
+    let batch: Batch<_, _> = input
+        .records
+        .into_iter()
+        .filter(|(key, _)| !key.is_empty())
+        .map(|(key, value)| (key, Op::Insert(value)))
+        .collect();
+    if batch.is_empty() {
+        return;
+    }
+
+    let mut store = MockStorage::new();
+    TREE.apply(&mut store, batch.clone()).unwrap();
+    let root_hash = TREE.root(&store, None).unwrap().root_hash;
+
+    let keys = batch.keys().chain(input.queries.iter().filter(|key| !key.is_empty()));
+    for key in keys {
+        let res = TREE.get(&store, key, true, None).unwrap();
+        let proof: Proof<_, _> = from_binary(&res.proof.unwrap()).unwrap();
+
+        match batch.get(key) {
+            Some(Op::Insert(value)) => {
+                assert_eq!(res.value.as_ref(), Some(value));
+                verify_membership(&root_hash, key, value, &proof).unwrap();
+                assert_eq!(compute_root_from_membership(key, value, &proof).unwrap(), root_hash);
+                // the proof doesn't prove the key's absence
+                assert!(verify_non_membership(&root_hash, key, &proof).is_err());
+            },
+            _ => {
+                assert_eq!(res.value, None);
+                verify_non_membership(&root_hash, key, &proof).unwrap();
+                assert_eq!(compute_root_from_non_membership(key, &proof).unwrap(), root_hash);
+            },
+        }
+    }
+});
@@ -0,0 +1,371 @@
+use {
+    crate::{
+        apply_subtree, stats::StatsDelta, tree::next_version, Batch, Blake3, CollapsePolicy, Hash,
+        HashableValue, JsonCodec, NibblePath, Node, NodeCodec, NodeKey, NodeSource, Op, OpResponse,
+        Tree, TreeError, TreeHasher,
+    },
+    cosmwasm_std::Storage,
+    serde::{de::DeserializeOwned, ser::Serialize},
+    std::collections::HashMap,
+};
+
+/// Applies batches to a `Tree`, under the condition that all keys in each
+/// batch are greater than all keys already in the tree, e.g. auto-incrementing
+/// IDs.
+///
+/// Under this condition, the only existing nodes `apply` ever needs to read are
+/// those on the path from the root to the greatest key, which we call the
+/// right frontier. `Appender` keeps the right frontier in memory between
+/// batches, so that applying a batch doesn't read any node from storage.
+///
+/// If the tree is updated by other means (e.g. `Tree::apply`) in the meantime,
+/// the frontier is reloaded from storage on the next `apply`.
+pub struct Appender<'a, K, V, C = JsonCodec, H = Blake3> {
+    tree: &'a Tree<'a, K, V, C, H>,
+    // the version of the tree that the frontier was loaded at
+    version: Option<u64>,
+    // nodes on the path from the root to the greatest key, ordered by depth
+    frontier: Vec<(NodeKey, Node<K, V>)>,
+}
+
+impl<'a, K, V, C, H> Appender<'a, K, V, C, H>
+where
+    K: Serialize + DeserializeOwned + Clone + PartialEq + AsRef<[u8]>,
+    V: Serialize + DeserializeOwned + Clone + PartialEq + HashableValue,
+    C: NodeCodec<K, V>,
+    H: TreeHasher,
+{
+    pub fn new(tree: &'a Tree<'a, K, V, C, H>) -> Self {
+        Self {
+            tree,
+            version: None,
+            frontier: vec![],
+        }
+    }
+
+    /// The greatest key currently in the tree, or `None` if the tree is empty.
+    pub fn greatest_key(&mut self, store: &dyn Storage) -> Result<Option<K>> {
+        self.sync(store)?;
+        Ok(self.greatest_record_key().cloned())
+    }
+
+    /// Apply a batch of ops to the tree. Same as `Tree::apply`, except that an
+    /// error is returned if any key in the batch is not greater than the
+    /// greatest key currently in the tree.
+    pub fn apply(&mut self, store: &mut dyn Storage, batch: Batch<K, V>) -> Result<()> {
+        let old_version = self.sync(store)?;
+
+        // the prepared batch is sorted so the first key is the smallest
+        let batch = self.tree.normalize_batch(batch)?;
+        let smallest = batch.first().map(|(_, key, _)| key);
+        if let (Some(smallest), Some(greatest)) = (smallest, self.greatest_record_key()) {
+            if smallest.as_ref() <= greatest.as_ref() {
+                return Err(TreeError::NotAppendOnly);
+            }
+        }
+
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        let mut key_filter = self.tree.key_filter_to_update(store, old_version)?;
+        if let Some(key_filter) = &mut key_filter {
+            key_filter.insert_batch(&batch);
+        }
+
+        self.tree.clear_expirations(store, &batch)?;
+
+        // deleting keys greater than the greatest existing key is a no-op, so
+        // the new greatest key is the greatest inserted one, if any
+        let new_greatest = batch
+            .iter()
+            .rev()
+            .find(|(_, _, op)| matches!(op, Op::Insert(_)))
+            .map(|(nibble_path, ..)| nibble_path.clone());
+
+        let new_version = next_version(old_version)?;
+        let mut nodes = FrontierNodes {
+            tree: self.tree,
+            store: &mut *store,
+            frontier: &self.frontier,
+            new_greatest,
+            written: HashMap::new(),
+            nodes_written: 0,
+            stats: self.tree.stats_delta(),
+        };
+        let response =
+            apply_subtree(&mut nodes, new_version, &NodeKey::root(old_version), None, &batch)?;
+        let nodes_written = nodes.nodes_written;
+        let stats = nodes.stats;
+        let written = nodes.written;
+
+        #[cfg(feature = "journal")]
+        if !matches!(response, OpResponse::Unchanged) {
+            self.tree.journal_batch(store, new_version, &batch)?;
+        }
+
+        let version = self.tree.commit_root(
+            store,
+            old_version,
+            new_version,
+            &response,
+            key_filter.as_ref(),
+            nodes_written,
+            stats,
+        )?;
+
+        match response {
+            OpResponse::Updated(root_node) => {
+                self.frontier =
+                    descend_frontier(self.tree, store, new_version, root_node, written)?;
+                self.version = Some(new_version);
+            },
+            OpResponse::Deleted => {
+                self.frontier.clear();
+                self.version = Some(new_version);
+            },
+            // the frontier is unchanged, except that the tree may have a root
+            // alias under the new version (see `Tree::with_root_aliases`)
+            OpResponse::Unchanged => {
+                if let Some((root_node_key, _)) = self.frontier.first_mut() {
+                    *root_node_key = NodeKey::root(version);
+                }
+                self.version = Some(version);
+            },
+        }
+
+        Ok(())
+    }
+
+    // make sure the frontier is up to date with the tree's latest version,
+    // returning that version
+    fn sync(&mut self, store: &dyn Storage) -> Result<u64> {
+        let version = self.tree.version.may_load(store)?.unwrap_or(0);
+        if self.version != Some(version) {
+            self.frontier = load_frontier(self.tree, store, version)?;
+            self.version = Some(version);
+        }
+        Ok(version)
+    }
+
+    // the greatest key is in the deepest node of the frontier, which is always
+    // a leaf
+    fn greatest_record_key(&self) -> Option<&K> {
+        self.frontier.last().and_then(|(_, node)| node.data.as_ref()).map(|record| &record.key)
+    }
+}
+
+fn load_frontier<K, V, C, H>(
+    tree: &Tree<K, V, C, H>,
+    store: &dyn Storage,
+    version: u64,
+) -> Result<Vec<(NodeKey, Node<K, V>)>>
+where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+    C: NodeCodec<K, V>,
+    H: TreeHasher,
+{
+    if version == 0 {
+        return Ok(vec![]);
+    }
+
+    // the tree may have been emptied at this version, in which case there's
+    // no root node
+    let Some(root_node) = tree.nodes.may_load(store, &NodeKey::root(version))? else {
+        return Ok(vec![]);
+    };
+
+    descend_frontier(tree, store, version, root_node, HashMap::new())
+}
+
+// follow the greatest child from the root all the way down to a leaf. nodes
+// are taken from `cached` if available; otherwise loaded from storage.
+fn descend_frontier<K, V, C, H>(
+    tree: &Tree<K, V, C, H>,
+    store: &dyn Storage,
+    version: u64,
+    root_node: Node<K, V>,
+    mut cached: HashMap<NodeKey, Node<K, V>>,
+) -> Result<Vec<(NodeKey, Node<K, V>)>>
+where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+    C: NodeCodec<K, V>,
+    H: TreeHasher,
+{
+    let mut frontier = vec![];
+    let mut node_key = NodeKey::root(version);
+    let mut node = root_node;
+
+    // children are sorted by index, so the last one is the greatest
+    while let Some(child) = node.children.as_ref().last() {
+        let child_node_key = node_key.child(child.version, child.index);
+        let child_node = match cached.remove(&child_node_key) {
+            Some(child_node) => child_node,
+            None => tree.nodes.may_load(store, &child_node_key)?.ok_or_else(|| {
+                TreeError::NonRootNodeNotFound {
+                    node_key: child_node_key.clone(),
+                }
+            })?,
+        };
+
+        frontier.push((node_key, node));
+        node_key = child_node_key;
+        node = child_node;
+    }
+
+    frontier.push((node_key, node));
+
+    Ok(frontier)
+}
+
+// reads are served from the frontier; writes go to storage. nodes written on
+// the path to the new greatest key are also kept in memory, so that the new
+// frontier can be assembled without reading them back from storage.
+struct FrontierNodes<'a, 'b, K, V, C, H> {
+    tree: &'b Tree<'a, K, V, C, H>,
+    store: &'b mut dyn Storage,
+    frontier: &'b [(NodeKey, Node<K, V>)],
+    new_greatest: Option<NibblePath>,
+    written: HashMap<NodeKey, Node<K, V>>,
+    nodes_written: u64,
+    // `None` if stats are not tracked
+    stats: Option<StatsDelta>,
+}
+
+impl<'a, 'b, K, V, C, H> NodeSource<K, V> for FrontierNodes<'a, 'b, K, V, C, H>
+where
+    K: Serialize + DeserializeOwned + Clone,
+    V: Serialize + DeserializeOwned + Clone + HashableValue,
+    C: NodeCodec<K, V>,
+    H: TreeHasher,
+{
+    fn load(&self, node_key: &NodeKey) -> Result<Option<Node<K, V>>> {
+        if let Some((_, node)) = self.frontier.iter().find(|(key, _)| key == node_key) {
+            return Ok(Some(node.clone()));
+        }
+
+        // all nodes the algorithm reads should be in the frontier, but fall
+        // back to storage just in case
+        self.tree.nodes.may_load(self.store, node_key).map_err(Into::into)
+    }
+
+    fn save(&mut self, node_key: NodeKey, node: Node<K, V>) -> Result<()> {
+        self.tree.nodes.save(self.store, &node_key, &node)?;
+        self.nodes_written += 1;
+        if let Some(stats) = &mut self.stats {
+            stats.add_node(&node);
+        }
+
+        if let Some(new_greatest) = &self.new_greatest {
+            if is_prefix(&node_key.nibble_path, new_greatest) {
+                self.written.insert(node_key, node);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn mark_orphaned(&mut self, orphaned_since_version: u64, node_key: &NodeKey) -> Result<()> {
+        // orphaned nodes are on the frontier, so counting them takes no read
+        if self.stats.is_some() {
+            if let (Some(node), Some(stats)) = (self.load(node_key)?, &mut self.stats) {
+                stats.remove_node(&node);
+            }
+        }
+
+        self.tree
+            .mark_node_as_orphaned(self.store, orphaned_since_version, node_key)
+            .map_err(Into::into)
+    }
+
+    fn on_record_change(&mut self, _key: &K, old: Option<&V>, new: Option<&V>) {
+        if let Some(stats) = &mut self.stats {
+            stats.on_record_change(old, new);
+        }
+    }
+
+    fn aggregation(&self) -> Option<fn(&K, &V) -> u128> {
+        self.tree.aggregation
+    }
+
+    fn collapse_policy(&self) -> CollapsePolicy {
+        self.tree.collapse_policy
+    }
+
+    fn hash(&self, node: &Node<K, V>) -> Hash
+    where
+        K: AsRef<[u8]>,
+        V: HashableValue,
+    {
+        node.hash_using::<H>()
+    }
+}
+
+fn is_prefix(prefix: &NibblePath, nibble_path: &NibblePath) -> bool {
+    prefix.num_nibbles <= nibble_path.num_nibbles
+        && (0..prefix.num_nibbles).all(|i| prefix.get_nibble(i) == nibble_path.get_nibble(i))
+}
+
+type Result<T> = std::result::Result<T, TreeError>;
+
+// ----------------------------------- tests -----------------------------------
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::{Appender, Batch, Op, Tree, TreeError},
+        cosmwasm_std::{testing::MockStorage, Order, Storage},
+    };
+
+    const TREE: Tree<Vec<u8>, Vec<u8>> = Tree::new_default();
+
+    fn batch(ids: impl Iterator<Item = u32>) -> Batch<Vec<u8>, Vec<u8>> {
+        ids.map(|id| (id.to_be_bytes().to_vec(), Op::Insert(id.to_le_bytes().to_vec()))).collect()
+    }
+
+    fn dump(store: &MockStorage) -> Vec<(Vec<u8>, Vec<u8>)> {
+        store.range(None, None, Order::Ascending).collect()
+    }
+
+    #[test]
+    fn appending_matches_applying() {
+        let mut expected = MockStorage::new();
+        let mut actual = MockStorage::new();
+        let mut appender = Appender::new(&TREE);
+
+        let mut next_id = 0;
+        for i in 0..20 {
+            let batch = batch(next_id..next_id + 40 + i % 3 * 7);
+            next_id += 40 + i % 3 * 7;
+            TREE.apply(&mut expected, batch.clone()).unwrap();
+            appender.apply(&mut actual, batch).unwrap();
+        }
+
+        // nodes, orphans and version must all be identical
+        assert_eq!(dump(&actual), dump(&expected));
+        assert_eq!(
+            appender.greatest_key(&actual).unwrap(),
+            Some((next_id - 1).to_be_bytes().to_vec()),
+        );
+    }
+
+    #[test]
+    fn rejecting_non_append() {
+        let mut store = MockStorage::new();
+        let mut appender = Appender::new(&TREE);
+        appender.apply(&mut store, batch(10..20)).unwrap();
+
+        assert_eq!(appender.apply(&mut store, batch(19..30)), Err(TreeError::NotAppendOnly));
+        assert_eq!(appender.apply(&mut store, batch(5..6)), Err(TreeError::NotAppendOnly));
+
+        // the tree is updated behind the appender's back. the frontier should
+        // be reloaded
+        TREE.apply(&mut store, batch(20..25)).unwrap();
+        assert_eq!(appender.apply(&mut store, batch(22..30)), Err(TreeError::NotAppendOnly));
+        appender.apply(&mut store, batch(25..30)).unwrap();
+        assert_eq!(TREE.root(&store, None).unwrap().version, 3);
+        assert_eq!(appender.greatest_key(&store).unwrap(), Some(29u32.to_be_bytes().to_vec()));
+    }
+}
@@ -0,0 +1,530 @@
+use {
+    crate::{
+        Child, Hash, HashableValue, Nibble, NibblePath, NibbleRange, NibbleRangeIterator, Node,
+        NodeKey, Op, OpResponse, Record, TreeError, MAX_NIBBLES,
+    },
+    std::{borrow::Cow, collections::HashMap},
+};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+/// Where the apply algorithm reads nodes from, and writes updated nodes to.
+///
+/// `Tree` implements this on top of contract storage. Implement it on your own
+/// type to drive the algorithm over a different node source, e.g. for building
+/// a tree statelessly in memory.
+pub trait NodeSource<K, V> {
+    /// Load the node under the given key, or `None` if it doesn't exist.
+    fn load(&self, node_key: &NodeKey) -> Result<Option<Node<K, V>>>;
+
+    /// Persist a node that has been created or updated by the algorithm.
+    fn save(&mut self, node_key: NodeKey, node: Node<K, V>) -> Result<()>;
+
+    /// Record that the node is no longer part of the tree since the version.
+    fn mark_orphaned(&mut self, orphaned_since_version: u64, node_key: &NodeKey) -> Result<()>;
+
+    /// Delete a node that was saved under the version being written, but is no
+    /// longer part of the tree. This only happens if several batches are
+    /// applied under the same version, as `Tree::apply_streaming` does; the
+    /// default does nothing.
+    fn remove(&mut self, _node_key: &NodeKey) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called whenever a record's value is written or removed, with the value
+    /// before and after. A record can be reported more than once, e.g. if it's
+    /// moved to another node, in which case it's first removed, then written
+    /// again. The default does nothing.
+    fn on_record_change(&mut self, _key: &K, _old: Option<&V>, _new: Option<&V>) {}
+
+    /// The function that gives each record's weight, if the tree tracks the
+    /// aggregate of weights in each subtree. See `Tree::with_aggregation`.
+    fn aggregation(&self) -> Option<fn(&K, &V) -> u128> {
+        None
+    }
+
+    /// When a leaf that is left as the only child of its parent is moved up.
+    /// See `Tree::with_collapse_policy`.
+    fn collapse_policy(&self) -> CollapsePolicy {
+        CollapsePolicy::Always
+    }
+
+    /// Compute the hash of an updated node, which its parent commits to. See
+    /// `TreeHasher`; the default is blake3.
+    fn hash(&self, node: &Node<K, V>) -> Hash
+    where
+        K: AsRef<[u8]>,
+        V: HashableValue,
+    {
+        node.hash()
+    }
+}
+
+/// A `NodeSource` that can be forked, so that `apply_subtree_parallel` can apply
+/// ops to the subtrees of a node's children on different threads, each with a
+/// fork of its own.
+#[cfg(feature = "rayon")]
+pub trait ForkNodeSource<K, V>: NodeSource<K, V> + Send + Sized {
+    /// A source that loads the same nodes as this one, but keeps what is
+    /// written to it to itself until it's joined.
+    fn fork(&self) -> Self;
+
+    /// Write what has been written to the fork to this source, in the same
+    /// order, as if it had been written to this source in the first place.
+    fn join(&mut self, fork: Self) -> Result<()>;
+}
+
+/// When a leaf node that is left as the only child of its parent, e.g. after
+/// its siblings are deleted, is moved up to take the parent's place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CollapsePolicy {
+    /// Always move the leaf up, as far as it goes. This keeps the tree as
+    /// shallow as possible, and its shape depends only on the records it
+    /// contains, not on the order they were inserted and deleted in.
+    Always,
+    /// Never move the leaf up, so that a record's node path only changes if
+    /// another record is inserted under it, or it's deleted. The shape of the
+    /// tree then depends on its history, and proofs may be longer.
+    Never,
+    /// Move the leaf up, but not above the given depth, so that records keep
+    /// their node paths at and below it. `MinDepth(0)` is the same as `Always`.
+    MinDepth(usize),
+}
+
+impl CollapsePolicy {
+    // whether a leaf may be moved up to a node at the given depth
+    fn allows(self, depth: usize) -> bool {
+        match self {
+            CollapsePolicy::Always => true,
+            CollapsePolicy::Never => false,
+            CollapsePolicy::MinDepth(min_depth) => depth >= min_depth,
+        }
+    }
+}
+
+/// Convert a batch into the form accepted by `apply_subtree`: a Vec sorted by
+/// nibble paths, where each key comes with its nibble path. The ops must be
+/// sorted by keys, as those of a `Batch` are.
+///
+/// Errors if any key is empty, or is longer than `MAX_KEY_LEN` bytes, as its
+/// nodes' keys could not be encoded in storage.
+pub fn prepare_batch<K, V, I>(batch: I) -> Result<Vec<(NibblePath, K, Op<V>)>>
+where
+    K: AsRef<[u8]>,
+    I: IntoIterator<Item = (K, Op<V>)>,
+{
+    batch
+        .into_iter()
+        .map(|(key, op)| {
+            let nibble_path = NibblePath::from(&key);
+            check_key(&nibble_path)?;
+            Ok((nibble_path, key, op))
+        })
+        .collect()
+}
+
+// a key must not be empty, as the root node is never a leaf of its own, and
+// the number of nibbles in its nodes' keys must fit in a u16
+pub(crate) fn check_key(nibble_path: &NibblePath) -> Result<()> {
+    if nibble_path.num_nibbles == 0 {
+        return Err(TreeError::EmptyKey);
+    }
+    if nibble_path.num_nibbles > MAX_NIBBLES {
+        return Err(TreeError::KeyTooLong {
+            num_nibbles: nibble_path.num_nibbles,
+        });
+    }
+    Ok(())
+}
+
+/// Apply a batch of ops to the subtree rooted at `current_node_key`, returning
+/// what happened to the subtree's root node. This is the algorithm behind
+/// `Tree::apply`.
+///
+/// - `current_node` can be provided if the caller already has the node at
+///   hand; otherwise it's loaded from `nodes`.
+/// - `batch` must be non-empty, sorted by the nibble paths (see
+///   `prepare_batch`), and every nibble path must start with the current
+///   node's nibble path. An error is returned if two ops have the same nibble
+///   path.
+///
+/// The subtree's child nodes that are updated are written to `nodes`, but the
+/// updated root node of the subtree is not; it's returned to the caller, who
+/// decides where it should go.
+pub fn apply_subtree<K, V, N>(
+    nodes: &mut N,
+    version: u64,
+    current_node_key: &NodeKey,
+    current_node: Option<Node<K, V>>,
+    batch: &[(NibblePath, K, Op<V>)],
+) -> Result<OpResponse<K, V>>
+where
+    K: Clone + PartialEq + AsRef<[u8]>,
+    V: Clone + PartialEq + HashableValue,
+    N: NodeSource<K, V>,
+{
+    let (mut update, batch) = NodeUpdate::begin(nodes, current_node_key, current_node, batch)?;
+
+    // now, if there is only one item left in the batch AND one of the
+    // following is satisfied, then we apply the op at the current node:
+    //
+    // - the current node is a leaf, and the key matches exactly the nibble
+    //   path we want to write to
+    // - the current node has neither any child nor data
+    //
+    // if this condition is not satisfied, we need to dispatch the ops to
+    // the current node's children.
+    if batch.len() == 1 && update.node.is_empty() {
+        apply_op(nodes, &mut update.node, &batch[0]);
+    } else {
+        let nibble_range_iter = NibbleRangeIterator::new(&batch, current_node_key.depth());
+        for NibbleRange { nibble, start, end } in nibble_range_iter {
+            let response = apply_subtree(
+                nodes,
+                version,
+                &update.child_node_key(version, nibble),
+                None,
+                &batch[start..=end],
+            )?;
+            update.update_child(nodes, version, nibble, response)?;
+        }
+    }
+
+    update.finish(nodes, version)
+}
+
+/// Same as `apply_subtree`, but the ops dispatched to each of the node's
+/// children are applied on a different thread, each to a fork of `nodes`. The
+/// forks are then joined in the order of the children's nibbles, so that what
+/// is written to `nodes` is exactly what `apply_subtree` would have written.
+///
+/// Only the children's subtrees are applied in parallel; within each of them,
+/// the ops are applied sequentially.
+#[cfg(feature = "rayon")]
+pub fn apply_subtree_parallel<K, V, N>(
+    nodes: &mut N,
+    version: u64,
+    current_node_key: &NodeKey,
+    current_node: Option<Node<K, V>>,
+    batch: &[(NibblePath, K, Op<V>)],
+) -> Result<OpResponse<K, V>>
+where
+    K: Clone + PartialEq + AsRef<[u8]> + Send + Sync,
+    V: Clone + PartialEq + HashableValue + Send + Sync,
+    N: ForkNodeSource<K, V>,
+{
+    let (mut update, batch) = NodeUpdate::begin(nodes, current_node_key, current_node, batch)?;
+
+    if batch.len() == 1 && update.node.is_empty() {
+        apply_op(nodes, &mut update.node, &batch[0]);
+    } else {
+        let jobs = NibbleRangeIterator::new(&batch, current_node_key.depth())
+            .map(|NibbleRange { nibble, start, end }| {
+                (nibble, update.child_node_key(version, nibble), nodes.fork(), &batch[start..=end])
+            })
+            .collect::<Vec<_>>();
+        let results = jobs
+            .into_par_iter()
+            .map(|(nibble, child_node_key, mut fork, batch)| {
+                let response = apply_subtree(&mut fork, version, &child_node_key, None, batch);
+                (nibble, fork, response)
+            })
+            .collect::<Vec<_>>();
+
+        // if several subtrees failed, the error is the one `apply_subtree`
+        // would have returned, i.e. that of the first one
+        for (nibble, fork, response) in results {
+            let response = response?;
+            nodes.join(fork)?;
+            update.update_child(nodes, version, nibble, response)?;
+        }
+    }
+
+    update.finish(nodes, version)
+}
+
+// ops that are left to be applied, which are cloned only if they need to be
+// changed (see `NodeUpdate::begin`)
+type Ops<'b, K, V> = Cow<'b, [(NibblePath, K, Op<V>)]>;
+
+// a node that a batch is being applied to
+struct NodeUpdate<'k, K, V> {
+    node_key: &'k NodeKey,
+    node: Node<K, V>,
+    // a clone of the node before the ops are executed. after we've executed
+    // the ops, we will compare with it whether the node has been changed
+    node_before: Node<K, V>,
+    // a cache of the node's children that have been changed.
+    // we don't want to write these nodes to store immediately, because if
+    // the node ends up having only one child, we will need to collapse the
+    // path (i.e. delete the node, move the only child one level up)
+    updated_child_nodes: HashMap<Nibble, Node<K, V>>,
+}
+
+impl<'k, K, V> NodeUpdate<'k, K, V>
+where
+    K: Clone + PartialEq + AsRef<[u8]>,
+    V: Clone + PartialEq + HashableValue,
+{
+    // load the node, and apply the op of the batch that is at the node itself,
+    // if any. returns the ops left to be dispatched to the node's children
+    fn begin<'b, N>(
+        nodes: &mut N,
+        current_node_key: &'k NodeKey,
+        current_node: Option<Node<K, V>>,
+        // some basic rust knowledge here: the following are different!
+        //
+        // mut batch: &T
+        // this means that `batch` can be pointed to a different T instance
+        //
+        // batch: &mut T
+        // this means that the T instance that `batch` points to can be mutated
+        mut batch: &'b [(NibblePath, K, Op<V>)],
+    ) -> Result<(Self, Ops<'b, K, V>)>
+    where
+        N: NodeSource<K, V>,
+    {
+        // attempt to load the node. if not found, we simply create a new empty
+        // node (no children, no data)
+        let mut current_node = if let Some(node) = current_node {
+            node
+        } else {
+            nodes.load(current_node_key)?.unwrap_or_else(Node::new)
+        };
+
+        let current_node_before = current_node.clone();
+
+        // if the node has data, and the data's key doesn't exactly equal the
+        // node's nibble path, we take it out and insert it into the batch.
+        // we call this the "dangling_data"
+        let mut dangling_data = None;
+        if let Some(Record { key, .. }) = &current_node.data {
+            // in a previously bugged implementation, here we simply compared
+            // key.as_bytes() and current_nibble_path.bytes; this misses the
+            // case where the nibble path may have odd number of nibbles and
+            // the last nibble in the key may be zero
+            if NibblePath::from(key) != current_node_key.nibble_path {
+                dangling_data = current_node.data.take();
+                if let Some(Record { key, value }) = &dangling_data {
+                    nodes.on_record_change(key, Some(value), None);
+                }
+            }
+        }
+
+        // what this part means is a bit hard to explain...
+        //
+        // basically if there is a key in the batch that is an exact match with
+        // the current node's nibble path, then it is necessarily the first item
+        // in the batch (I don't have a rigorous proof, but empirically this is
+        // true)
+        //
+        // if this is the case, we apply the op at the current node, and remove
+        // this item from the batch.
+        //
+        // additionally, if this node originally had data is will be overwritten
+        // here, we take it out as "dangling data" and insert it later
+        if batch[0].0 == current_node_key.nibble_path {
+            // ops with identical nibble paths are adjacent in the sorted batch,
+            // so a duplicate would be the next item. this can only happen if
+            // distinct keys have the same bytes, e.g. due to a bug in encoding
+            // the keys, or if they have the same canonical form under the
+            // tree's normalizer. only one of them could be applied, and which
+            // one would depend on the order they happen to be in, so we refuse
+            // instead
+            if batch.get(1).is_some_and(|(nibble_path, ..)| *nibble_path == batch[0].0) {
+                return Err(TreeError::DuplicateNibblePath {
+                    nibble_path: batch[0].0.clone(),
+                });
+            }
+            apply_op(nodes, &mut current_node, &batch[0]);
+            batch = &batch[1..];
+        }
+
+        // insert the dangling data into the batch
+        //
+        // note: only insert if the key isn't already in the batch. if it's
+        // already in, it will be overwritten anyways so we just discard it
+        //
+        // this requires copying the batch in memory (slice --> vec) which is
+        // slow, but i don't have a good idea to improve on this
+        // basically we have to do this to allow iteration (if we hash the keys,
+        // there would be no dangling data but no iteration either). it's a
+        // tradeoff between performance and feature, and is one that we're
+        // willing to make (iteration is such as important feature)
+        let batch = if let Some(Record { key, value }) = dangling_data {
+            let nibble_path = NibblePath::from(&key);
+            let mut owned_batch = batch.to_vec();
+            let search =
+                batch.binary_search_by_key(&&nibble_path, |(nibble_path, _, _)| nibble_path);
+            if let Err(pos) = search {
+                owned_batch.insert(pos, (nibble_path, key, Op::Insert(value)));
+            }
+            Cow::Owned(owned_batch)
+        } else {
+            Cow::Borrowed(batch)
+        };
+
+        let update = Self {
+            node_key: current_node_key,
+            node: current_node,
+            node_before: current_node_before,
+            updated_child_nodes: HashMap::new(),
+        };
+        Ok((update, batch))
+    }
+
+    // the key of the child node at the nibble, to which the ops under it are
+    // dispatched. a child that doesn't exist yet is created under the version
+    fn child_node_key(&self, version: u64, nibble: Nibble) -> NodeKey {
+        let child_version = self.node.children.get(nibble).map(|c| c.version).unwrap_or(version);
+        self.node_key.child(child_version, nibble)
+    }
+
+    // update the node's child at the nibble with what happened to it after the
+    // ops under it have been applied
+    fn update_child<N>(
+        &mut self,
+        nodes: &mut N,
+        version: u64,
+        nibble: Nibble,
+        response: OpResponse<K, V>,
+    ) -> Result<()>
+    where
+        N: NodeSource<K, V>,
+    {
+        let child_exists = self.node.children.get(nibble).is_some();
+        let child_node_key = self.child_node_key(version, nibble);
+
+        match response {
+            OpResponse::Updated(updated_child_node) => {
+                self.node.children.insert(Child {
+                    index: nibble,
+                    version,
+                    hash: nodes.hash(&updated_child_node),
+                    aggregate: updated_child_node.aggregate,
+                });
+
+                if child_exists {
+                    retire(nodes, version, &child_node_key)?;
+                }
+
+                self.updated_child_nodes.insert(nibble, updated_child_node);
+            },
+            OpResponse::Deleted => {
+                self.node.children.remove(nibble);
+                if child_exists {
+                    retire(nodes, version, &child_node_key)?;
+                }
+            },
+            OpResponse::Unchanged => (),
+        }
+
+        Ok(())
+    }
+
+    // decide what happens to the node, now that the batch has been applied to
+    // it and its children
+    fn finish<N>(self, nodes: &mut N, version: u64) -> Result<OpResponse<K, V>>
+    where
+        N: NodeSource<K, V>,
+    {
+        let Self {
+            node_key: current_node_key,
+            node: mut current_node,
+            node_before: current_node_before,
+            updated_child_nodes,
+        } = self;
+
+        // if the current node has neither any child nor data, then it should be
+        // deleted
+        if current_node.is_empty() {
+            return Ok(OpResponse::Deleted);
+        }
+
+        // if the current node has no data and exactly 1 child, and this child
+        // is a leaf node, then the path can be collapsed (i.e. the current node
+        // deleted, and that child leaf node moved on level up), unless the
+        // collapse policy keeps the leaf where it is
+        let collapse = nodes.collapse_policy().allows(current_node_key.depth());
+        let only_child = current_node.children.only();
+        if let (true, None, Some(child)) = (collapse, &current_node.data, only_child) {
+            if let Some(child_node) = updated_child_nodes.get(&child.index) {
+                if child_node.is_leaf() {
+                    // the current node may be a collapsed leaf whose data was
+                    // taken out as dangling data, only to be put back exactly
+                    // as it was (e.g. when deleting a key that doesn't exist).
+                    // in that case the subtree is unchanged, and must not be
+                    // rewritten under the new version, which would cascade all
+                    // the way up to the root
+                    if *child_node == current_node_before {
+                        return Ok(OpResponse::Unchanged);
+                    }
+                    return Ok(OpResponse::Updated(child_node.clone()));
+                }
+            } else {
+                let child_node_key = current_node_key.child(child.version, child.index);
+                let child_node = nodes.load(&child_node_key)?.ok_or_else(|| {
+                    TreeError::NonRootNodeNotFound {
+                        node_key: child_node_key.clone(),
+                    }
+                })?;
+                if child_node.is_leaf() {
+                    retire(nodes, version, &child_node_key)?;
+                    return Ok(OpResponse::Updated(child_node));
+                }
+            };
+        }
+
+        // now we know the current node won't be deleted or collapsed,
+        // we can write the updated child nodes
+        for (nibble, node) in updated_child_nodes {
+            let nibble_path = current_node_key.nibble_path.child(nibble);
+            nodes.save(NodeKey::new(version, nibble_path), node)?;
+        }
+
+        if let Some(weight) = nodes.aggregation() {
+            current_node.aggregate = current_node.compute_aggregate(weight)?;
+        }
+
+        if current_node != current_node_before {
+            return Ok(OpResponse::Updated(current_node));
+        }
+
+        Ok(OpResponse::Unchanged)
+    }
+}
+
+// apply the op at the node, reporting the change of the record to `nodes`
+fn apply_op<K, V, N>(nodes: &mut N, node: &mut Node<K, V>, item: &(NibblePath, K, Op<V>))
+where
+    K: Clone,
+    V: Clone,
+    N: NodeSource<K, V>,
+{
+    let (_, key, op) = item;
+    let new = match op {
+        Op::Insert(value) => Some(value),
+        Op::Delete => None,
+    };
+    nodes.on_record_change(key, node.data.as_ref().map(|data| &data.value), new);
+    node.apply_op(item);
+}
+
+// the node under `node_key` is no longer part of the tree as of `version`.
+// nodes of older versions are marked as orphaned, to be pruned later. a node
+// of this same version was written by an earlier batch under this version
+// (see `Tree::apply_streaming`) and never committed, so it's removed right
+// away. it mustn't be marked as orphaned: a node may later be written under
+// the same key, and pruning would then delete it
+fn retire<K, V, N>(nodes: &mut N, version: u64, node_key: &NodeKey) -> Result<()>
+where
+    N: NodeSource<K, V>,
+{
+    if node_key.version < version {
+        nodes.mark_orphaned(version, node_key)
+    } else {
+        nodes.remove(node_key)
+    }
+}
+
+type Result<T> = std::result::Result<T, TreeError>;
@@ -0,0 +1,162 @@
+use {
+    crate::{
+        GetResponse, Hash, HashableValue, NodeCodec, RootResponse, Tree, TreeError, TreeHasher,
+    },
+    cosmwasm_schema::cw_serde,
+    cosmwasm_std::{Binary, StdResult, Storage},
+    serde::{de::DeserializeOwned, ser::Serialize},
+};
+
+/// A statement by whoever serves a tree's state that the tree had the given
+/// root hash at the given version, as of the given time, e.g. for oracles
+/// serving the state to off-chain consumers.
+#[cw_serde]
+pub struct Attestation {
+    pub version: u64,
+    pub root_hash: Hash,
+    /// In whatever unit the signer chooses, e.g. seconds since the epoch.
+    pub timestamp: u64,
+}
+
+impl Attestation {
+    /// The bytes that are signed, defined as:
+    ///
+    /// "tree-attestation" || version || timestamp || root_hash
+    ///
+    /// where `version` and `timestamp` are 64-bit unsigned integers in big
+    /// endian encoding.
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        let mut bytes = b"tree-attestation".to_vec();
+        bytes.extend(self.version.to_be_bytes());
+        bytes.extend(self.timestamp.to_be_bytes());
+        bytes.extend(self.root_hash.as_bytes());
+        bytes
+    }
+}
+
+/// A query response, along with a signed attestation of the root hash that it
+/// is to be verified against.
+#[cw_serde]
+pub struct Signed<T> {
+    pub response: T,
+    pub attestation: Attestation,
+    /// The signature of the attestation's `signing_bytes`, in whatever scheme
+    /// the signer uses.
+    pub signature: Binary,
+}
+
+impl<'a, K, V, C, H> Tree<'a, K, V, C, H>
+where
+    K: Serialize + DeserializeOwned + Clone + PartialEq + AsRef<[u8]>,
+    V: Serialize + DeserializeOwned + Clone + PartialEq + HashableValue,
+    C: NodeCodec<K, V>,
+    H: TreeHasher,
+{
+    /// Same as `root`, but the root hash is attested to as of the given time,
+    /// and the attestation signed using the given callback, which is given the
+    /// attestation's `signing_bytes`. The tree doesn't know how to sign, or
+    /// what the time is; both are up to the host.
+    pub fn root_signed(
+        &self,
+        store: &dyn Storage,
+        version: Option<u64>,
+        timestamp: u64,
+        sign: impl FnOnce(&[u8]) -> StdResult<Binary>,
+    ) -> Result<Signed<RootResponse>> {
+        let response = self.root(store, version)?;
+        let attestation = Attestation {
+            version: response.version,
+            root_hash: response.root_hash.clone(),
+            timestamp,
+        };
+        let signature = sign(&attestation.signing_bytes())?;
+
+        Ok(Signed {
+            response,
+            attestation,
+            signature,
+        })
+    }
+
+    /// Same as `get` with a proof, but along with a signed attestation of the
+    /// root hash that the proof is to be verified against. See `root_signed`.
+    ///
+    /// The proof is `None` if the tree is empty, or if the version has been
+    /// pruned and the record is served without a proof (see
+    /// `PrunedVersionPolicy`); the attestation can't be made in either case,
+    /// so an error is returned instead.
+    pub fn get_signed(
+        &self,
+        store: &dyn Storage,
+        key: &K,
+        version: Option<u64>,
+        timestamp: u64,
+        sign: impl FnOnce(&[u8]) -> StdResult<Binary>,
+    ) -> Result<Signed<GetResponse<K, V>>> {
+        let version = self.version_or_default(store, version)?;
+        let response = self.get(store, key, true, Some(version))?;
+        let Signed {
+            attestation,
+            signature,
+            ..
+        } = self.root_signed(store, Some(version), timestamp, sign)?;
+
+        Ok(Signed {
+            response,
+            attestation,
+            signature,
+        })
+    }
+}
+
+type Result<T> = std::result::Result<T, TreeError>;
+
+// ----------------------------------- tests -----------------------------------
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::Attestation,
+        crate::{verify_get_response, Batch, Op, Tree},
+        cosmwasm_std::{testing::MockStorage, Binary, StdError},
+    };
+
+    const TREE: Tree<Vec<u8>, Vec<u8>> = Tree::new_default();
+
+    // stands in for a real signature scheme
+    fn sign(bytes: &[u8]) -> Result<Binary, StdError> {
+        Ok(blake3::hash(bytes).as_bytes().to_vec().into())
+    }
+
+    #[test]
+    fn signing_responses() {
+        let mut store = MockStorage::new();
+        TREE.apply(&mut store, Batch::from([(b"a".to_vec(), Op::Insert(b"1".to_vec()))])).unwrap();
+        TREE.apply(&mut store, Batch::from([(b"a".to_vec(), Op::Insert(b"2".to_vec()))])).unwrap();
+
+        let root = TREE.root(&store, Some(1)).unwrap();
+        let signed = TREE.root_signed(&store, Some(1), 1234, sign).unwrap();
+        let attestation = Attestation {
+            version: 1,
+            root_hash: root.root_hash.clone(),
+            timestamp: 1234,
+        };
+        assert_eq!(signed.response, root);
+        assert_eq!(signed.signature, sign(&attestation.signing_bytes()).unwrap());
+        assert_eq!(signed.attestation, attestation);
+
+        // the proof is verified against the attested root hash, of the same
+        // version as the record
+        let signed = TREE.get_signed(&store, &b"a".to_vec(), Some(1), 1234, sign).unwrap();
+        assert_eq!(signed.attestation, attestation);
+        assert_eq!(
+            verify_get_response(&signed.attestation.root_hash, &signed.response).unwrap(),
+            Some(&b"1".to_vec()),
+        );
+
+        // errors of the signer are returned as is
+        let result =
+            TREE.root_signed(&store, None, 1234, |_| Err(StdError::generic_err("offline")));
+        assert_eq!(result.unwrap_err().to_string(), "Generic error: offline");
+    }
+}
@@ -0,0 +1,184 @@
+use {
+    crate::{Batch, Hash, Op},
+    cosmwasm_std::{from_slice, to_vec, StdError, StdResult},
+    serde::{de::DeserializeOwned, ser::Serialize},
+    std::any::type_name,
+};
+
+/// Format version of batches encoded by `encode_batch`.
+pub const BATCH_FORMAT: u8 = 1;
+
+const OP_DELETE: u8 = 0;
+const OP_INSERT: u8 = 1;
+
+/// Encode a batch into canonical bytes, e.g. to gossip it between nodes or to
+/// store it for replay. The layout is:
+///
+/// ```plain
+/// format (1 byte) || num_ops (4 bytes)
+/// || (len(key) (4 bytes) || key || op) for each op, in the batch's order
+/// ```
+///
+/// where integers are in big endian encoding, keys and values are encoded as
+/// JSON, and `op` is a zero byte for a deletion, or a one byte followed by
+/// `len(value) (4 bytes) || value` for an insertion. As with `JsonCodec`, the
+/// bytes are stable as long as the key and value types serialize
+/// deterministically, so equal batches are encoded into equal bytes, and share
+/// the same `batch_hash`.
+pub fn encode_batch<K, V>(batch: &Batch<K, V>) -> StdResult<Vec<u8>>
+where
+    K: Serialize,
+    V: Serialize,
+{
+    let mut bytes = vec![BATCH_FORMAT];
+    bytes.extend(len_bytes::<K, V>(batch.len())?);
+
+    for (key, op) in batch {
+        extend_prefixed::<K, V>(&mut bytes, &to_vec(key)?)?;
+        match op {
+            Op::Insert(value) => {
+                bytes.push(OP_INSERT);
+                extend_prefixed::<K, V>(&mut bytes, &to_vec(value)?)?;
+            },
+            Op::Delete => bytes.push(OP_DELETE),
+        }
+    }
+
+    Ok(bytes)
+}
+
+/// Decode a batch encoded by `encode_batch`. Errors unless the bytes are
+/// exactly those `encode_batch` produces for the decoded batch, so that a
+/// batch can't be smuggled in under a different encoding (and thus hash).
+pub fn decode_batch<K, V>(bytes: &[u8]) -> StdResult<Batch<K, V>>
+where
+    K: Serialize + DeserializeOwned + Ord,
+    V: Serialize + DeserializeOwned,
+{
+    let mut rest = bytes;
+    match take::<K, V>(&mut rest, 1)?[0] {
+        BATCH_FORMAT => (),
+        version => return Err(parse_err::<K, V>(format!("unknown batch format: {version}"))),
+    }
+
+    let num_ops = take_len::<K, V>(&mut rest)?;
+    let mut batch = Batch::new();
+    for _ in 0..num_ops {
+        let key_len = take_len::<K, V>(&mut rest)?;
+        let key = from_slice(take::<K, V>(&mut rest, key_len)?)?;
+        let op = match take::<K, V>(&mut rest, 1)?[0] {
+            OP_DELETE => Op::Delete,
+            OP_INSERT => {
+                let value_len = take_len::<K, V>(&mut rest)?;
+                Op::Insert(from_slice(take::<K, V>(&mut rest, value_len)?)?)
+            },
+            _ => return Err(parse_err::<K, V>("invalid op tag")),
+        };
+        batch.insert(key, op);
+    }
+
+    // this also rejects trailing bytes, ops out of order or duplicated, and
+    // JSON that isn't in its canonical form
+    if encode_batch(&batch)? != bytes {
+        return Err(parse_err::<K, V>("batch is not canonically encoded"));
+    }
+
+    Ok(batch)
+}
+
+/// The hash of a batch encoded by `encode_batch`, against which a received or
+/// replayed batch can be checked for integrity.
+pub fn batch_hash(bytes: &[u8]) -> Hash {
+    blake3::hash(bytes).into()
+}
+
+fn extend_prefixed<K, V>(bytes: &mut Vec<u8>, data: &[u8]) -> StdResult<()> {
+    bytes.extend(len_bytes::<K, V>(data.len())?);
+    bytes.extend(data);
+    Ok(())
+}
+
+fn len_bytes<K, V>(len: usize) -> StdResult<[u8; 4]> {
+    u32::try_from(len)
+        .map(u32::to_be_bytes)
+        .map_err(|_| StdError::serialize_err(type_name::<Batch<K, V>>(), "length exceeds u32"))
+}
+
+fn take_len<K, V>(bytes: &mut &[u8]) -> StdResult<usize> {
+    let len = u32::from_be_bytes(take::<K, V>(bytes, 4)?.try_into().unwrap());
+    Ok(len as usize)
+}
+
+// split the first `n` bytes off the slice, or error if there aren't enough
+fn take<'a, K, V>(bytes: &mut &'a [u8], n: usize) -> StdResult<&'a [u8]> {
+    if bytes.len() < n {
+        return Err(parse_err::<K, V>("unexpected end of batch"));
+    }
+
+    let (taken, rest) = bytes.split_at(n);
+    *bytes = rest;
+    Ok(taken)
+}
+
+fn parse_err<K, V>(msg: impl ToString) -> StdError {
+    StdError::parse_err(type_name::<Batch<K, V>>(), msg)
+}
+
+// ----------------------------------- tests -----------------------------------
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{batch_hash, decode_batch, encode_batch},
+        crate::{Batch, Op},
+    };
+
+    fn mock_batch() -> Batch<String, String> {
+        Batch::from([
+            ("foo".to_string(), Op::Insert("bar".to_string())),
+            ("fuzz".to_string(), Op::Delete),
+        ])
+    }
+
+    #[test]
+    fn encoding_batches() {
+        let bytes = encode_batch(&mock_batch()).unwrap();
+        assert_eq!(
+            bytes,
+            [
+                &[1][..],
+                &[0, 0, 0, 2],
+                &[0, 0, 0, 5],
+                b"\"foo\"",
+                &[1, 0, 0, 0, 5],
+                b"\"bar\"",
+                &[0, 0, 0, 6],
+                b"\"fuzz\"",
+                &[0],
+            ]
+            .concat()
+        );
+        assert_eq!(decode_batch::<String, String>(&bytes).unwrap(), mock_batch());
+
+        let mut other = mock_batch();
+        other.insert("fuzz".to_string(), Op::Insert("buzz".to_string()));
+        assert_ne!(batch_hash(&bytes), batch_hash(&encode_batch(&other).unwrap()));
+    }
+
+    #[test]
+    fn rejecting_non_canonical_batches() {
+        let bytes = encode_batch(&mock_batch()).unwrap();
+
+        // truncated, or with trailing bytes
+        assert!(decode_batch::<String, String>(&bytes[..bytes.len() - 1]).is_err());
+        assert!(decode_batch::<String, String>(&[&bytes[..], &[0]].concat()).is_err());
+
+        // ops out of order, which decode into the same batch
+        let swapped = [&bytes[..5], &bytes[24..], &bytes[5..24]].concat();
+        assert!(decode_batch::<String, String>(&swapped).is_err());
+
+        // JSON with whitespace
+        let spaced = [&bytes[..5], &[0, 0, 0, 6], b" \"foo\"", &bytes[14..]].concat();
+        assert!(decode_batch::<String, String>(&spaced).is_err());
+    }
+}
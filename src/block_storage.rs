@@ -0,0 +1,197 @@
+use {
+    crate::{
+        Batch, Blake3, ChangeSet, JsonCodec, NodeCodec, RootResponse, Tree, TreeError, TreeHasher,
+        TreeStorage,
+    },
+    cosmwasm_std::{testing::MockStorage, Order, Record, Storage},
+    std::{cell::RefCell, mem, rc::Rc},
+};
+
+/// A `cosmwasm_std::Storage` backed by a `Tree`, whose writes are committed
+/// block by block, so that contract integration tests run against merklized,
+/// versioned state.
+///
+/// This is a handle to shared state: pass a clone to whatever takes the
+/// storage, e.g. a test harness, and keep the other. Writes made through the
+/// clone are buffered until `commit` is called on the kept handle, typically
+/// between blocks, which applies them to the tree as a new version. The root
+/// hash of each committed version can then be asserted on, and past versions
+/// queried and proven.
+///
+/// Reads always see the latest committed version, plus the buffered writes.
+pub struct BlockStorage<'a, C = JsonCodec, H = Blake3> {
+    inner: Rc<RefCell<Inner<'a, C, H>>>,
+}
+
+// derived Clone would require C: Clone
+impl<'a, C, H> Clone for BlockStorage<'a, C, H> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Rc::clone(&self.inner),
+        }
+    }
+}
+
+struct Inner<'a, C, H> {
+    tree: &'a Tree<'a, Vec<u8>, Vec<u8>, C, H>,
+    store: MockStorage,
+    pending: Batch<Vec<u8>, Vec<u8>>,
+}
+
+impl<'a, C, H> BlockStorage<'a, C, H>
+where
+    C: NodeCodec<Vec<u8>, Vec<u8>>,
+    H: TreeHasher,
+{
+    /// Create a storage backed by an empty tree, whose nodes are kept in
+    /// memory.
+    pub fn new(tree: &'a Tree<'a, Vec<u8>, Vec<u8>, C, H>) -> Self {
+        Self {
+            inner: Rc::new(RefCell::new(Inner {
+                tree,
+                store: MockStorage::new(),
+                pending: Batch::new(),
+            })),
+        }
+    }
+
+    /// Apply the writes buffered since the last commit to the tree. A new
+    /// version is committed only if the tree is changed; see `Tree::apply`.
+    pub fn commit(&self) -> Result<ChangeSet<Vec<u8>>> {
+        let mut inner = self.inner.borrow_mut();
+        let Inner {
+            tree,
+            store,
+            pending,
+        } = &mut *inner;
+
+        // an empty batch can't be applied, but it changes nothing anyways
+        if pending.is_empty() {
+            return Ok(ChangeSet {
+                version: tree.version.may_load(store)?.unwrap_or(0),
+                changes: vec![],
+            });
+        }
+
+        tree.apply(store, mem::take(pending))
+    }
+
+    /// The latest committed version, or zero if nothing has been committed.
+    pub fn version(&self) -> Result<u64> {
+        let inner = self.inner.borrow();
+        Ok(inner.tree.version.may_load(&inner.store)?.unwrap_or(0))
+    }
+
+    /// Same as `Tree::root`. Writes that haven't been committed are ignored.
+    pub fn root(&self, version: Option<u64>) -> Result<RootResponse> {
+        let inner = self.inner.borrow();
+        inner.tree.root(&inner.store, version)
+    }
+
+    /// Run queries against the tree's backing store, e.g. to prove records at
+    /// past versions. Writes that haven't been committed are not visible.
+    pub fn query<R>(&self, f: impl FnOnce(&MockStorage) -> R) -> R {
+        f(&self.inner.borrow().store)
+    }
+
+    // serve a read or write through a TreeStorage of the latest version, with
+    // the buffered writes moved into it and back
+    fn with_storage<R>(&self, f: impl FnOnce(&mut TreeStorage<MockStorage, C, H>) -> R) -> R {
+        let mut inner = self.inner.borrow_mut();
+        let Inner {
+            tree,
+            store,
+            pending,
+        } = &mut *inner;
+
+        let mut storage = tree
+            .version
+            .may_load(store)
+            .map_err(TreeError::from)
+            .and_then(|version| TreeStorage::new(tree, store, version.unwrap_or(0)))
+            .unwrap_or_else(|err| panic!("failed to read from tree: {err}"));
+        storage.extend(mem::take(pending));
+
+        let result = f(&mut storage);
+        *pending = storage.into_batch();
+        result
+    }
+}
+
+impl<'a, C, H> Storage for BlockStorage<'a, C, H>
+where
+    C: NodeCodec<Vec<u8>, Vec<u8>>,
+    H: TreeHasher,
+{
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.with_storage(|storage| storage.get(key))
+    }
+
+    // the records are collected, as the iterator can't borrow the shared state
+    fn range<'b>(
+        &'b self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        order: Order,
+    ) -> Box<dyn Iterator<Item = Record> + 'b> {
+        let records = self.with_storage(|storage| storage.range(start, end, order).collect());
+        Box::new(Vec::into_iter(records))
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8]) {
+        self.with_storage(|storage| storage.set(key, value))
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        self.with_storage(|storage| storage.remove(key))
+    }
+}
+
+type Result<T> = std::result::Result<T, TreeError>;
+
+// ----------------------------------- tests -----------------------------------
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::{verify_membership, BlockStorage, Proof, Tree},
+        cosmwasm_std::{from_binary, Order, Storage},
+    };
+
+    const TREE: Tree<Vec<u8>, Vec<u8>> = Tree::new_default();
+
+    #[test]
+    fn committing_between_blocks() {
+        let storage = BlockStorage::new(&TREE);
+        // the clone that would be handed over to the harness
+        let mut app_storage = storage.clone();
+
+        // block 1
+        app_storage.set(b"balance/alice", b"100");
+        app_storage.set(b"balance/bob", b"50");
+        assert_eq!(app_storage.get(b"balance/alice"), Some(b"100".to_vec()));
+        assert_eq!(storage.commit().unwrap().version, 1);
+
+        // block 2
+        app_storage.set(b"balance/alice", b"70");
+        app_storage.set(b"balance/bob", b"80");
+        app_storage.remove(b"balance/bob");
+        app_storage.set(b"balance/carol", b"30");
+        let records = app_storage.range(Some(b"balance/"), None, Order::Ascending).count();
+        assert_eq!(records, 2);
+        assert_eq!(storage.version().unwrap(), 1);
+        assert_eq!(storage.commit().unwrap().changes.len(), 3);
+
+        // nothing written in block 3
+        assert_eq!(storage.commit().unwrap().version, 2);
+
+        // the states of both blocks can be proven
+        let key = b"balance/alice".to_vec();
+        for (version, value) in [(1, b"100".to_vec()), (2, b"70".to_vec())] {
+            let root_hash = storage.root(Some(version)).unwrap().root_hash;
+            let res = storage.query(|store| TREE.get(store, &key, true, Some(version))).unwrap();
+            let proof: Proof<_, _> = from_binary(&res.proof.unwrap()).unwrap();
+            assert!(verify_membership(&root_hash, &key, &value, &proof).is_ok());
+        }
+    }
+}
@@ -0,0 +1,332 @@
+use {
+    crate::{
+        apply::check_key, stats::StatsDelta, tree::next_version, Child, HashableValue, KeyFilter,
+        NibblePath, Node, NodeCodec, NodeKey, Op, OpResponse, Record, Tree, TreeError, TreeHasher,
+    },
+    cosmwasm_std::Storage,
+    serde::{de::DeserializeOwned, ser::Serialize},
+    std::iter::Peekable,
+};
+
+impl<'a, K, V, C, H> Tree<'a, K, V, C, H>
+where
+    K: Serialize + DeserializeOwned + Clone + PartialEq + AsRef<[u8]>,
+    V: Serialize + DeserializeOwned + Clone + PartialEq + HashableValue,
+    C: NodeCodec<K, V>,
+    H: TreeHasher,
+{
+    /// Load the records of an empty tree from an iterator sorted by keys, and
+    /// commit them as the tree's first version, e.g. for a chain's genesis.
+    ///
+    /// Whereas `apply` and `apply_streaming` walk down the tree from the root
+    /// for each batch, the tree is built bottom-up here: each node is written
+    /// once all records under it have been read, and is never read back. Only
+    /// the nodes on the path to the current record are held in memory. The
+    /// result is the same as inserting all records in one batch.
+    ///
+    /// Keys must be strictly ascending, after they're normalized and mapped if
+    /// the tree has a normalizer or a key transform; otherwise
+    /// `TreeError::KeysNotSorted` is returned. On error, the tree may be left
+    /// partially written; `clear` it before trying again.
+    pub fn initialize_from<I>(&self, store: &mut dyn Storage, records: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        if let Some(latest) = self.version.may_load(store)? {
+            return Err(TreeError::TreeNotEmpty {
+                latest,
+            });
+        }
+        self.check_no_leftovers(store)?;
+
+        let new_version = next_version(0)?;
+        let records = records.into_iter().map(|(key, value)| {
+            let key = self.normalize_owned_key(key);
+            (NibblePath::from(&key), key, value)
+        });
+
+        let mut loader = BulkLoader {
+            tree: self,
+            key_filter: self.key_filter_to_update(store, 0)?,
+            store: &mut *store,
+            version: new_version,
+            records: records.peekable(),
+            nodes_written: 0,
+            stats: self.stats_delta(),
+        };
+        let response = match loader.next_record()? {
+            Some(first) => OpResponse::Updated(loader.build(NibblePath::empty(), first)?),
+            None => OpResponse::Unchanged,
+        };
+        let BulkLoader {
+            key_filter,
+            nodes_written,
+            stats,
+            ..
+        } = loader;
+
+        self.commit_root(
+            store,
+            0,
+            new_version,
+            &response,
+            key_filter.as_ref(),
+            nodes_written,
+            stats,
+        )?;
+
+        Ok(())
+    }
+
+    /// An alias of `initialize_from`, for building a tree's first version from
+    /// key-value pairs sorted by keys.
+    pub fn build_from_sorted<I>(&self, store: &mut dyn Storage, pairs: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        self.initialize_from(store, pairs)
+    }
+}
+
+// builds the nodes of a version bottom-up from sorted records, writing each to
+// the store once it's complete, except for the root, which is returned
+struct BulkLoader<'a, 'b, K, V, C, H, R: Iterator> {
+    tree: &'b Tree<'a, K, V, C, H>,
+    store: &'b mut dyn Storage,
+    version: u64,
+    records: Peekable<R>,
+    key_filter: Option<KeyFilter>,
+    nodes_written: u64,
+    stats: Option<StatsDelta>,
+}
+
+impl<'a, 'b, K, V, C, H, R> BulkLoader<'a, 'b, K, V, C, H, R>
+where
+    K: Serialize + DeserializeOwned + Clone + PartialEq + AsRef<[u8]>,
+    V: Serialize + DeserializeOwned + Clone + PartialEq + HashableValue,
+    C: NodeCodec<K, V>,
+    H: TreeHasher,
+    R: Iterator<Item = (NibblePath, K, V)>,
+{
+    // build the node at the nibble path, given the first record under it, and
+    // taking the other records under it from the iterator. as in `apply`, a
+    // record that is alone in a subtree is held by the subtree's root, as a
+    // leaf
+    fn build(&mut self, nibble_path: NibblePath, first: (NibblePath, K, V)) -> Result<Node<K, V>> {
+        if !self.next_is_under(&nibble_path) {
+            let (_, key, value) = first;
+            return self.with_aggregate(Node::new_leaf(key, value));
+        }
+
+        // a record whose key is exactly the nibble path is the first one, as
+        // the records are sorted
+        let mut node = Node::new();
+        let mut next = None;
+        match first {
+            (first_path, key, value) if first_path == nibble_path => {
+                node.data = Some(Record {
+                    key,
+                    value,
+                });
+            },
+            first => next = Some(first),
+        }
+
+        loop {
+            let record = match next.take() {
+                Some(record) => record,
+                None if self.next_is_under(&nibble_path) => self.next_record()?.unwrap(),
+                None => break,
+            };
+
+            let index = record.0.get_nibble(nibble_path.num_nibbles);
+            let child_nibble_path = nibble_path.child(index);
+            let child_node = self.build(child_nibble_path.clone(), record)?;
+            node.children.insert(Child {
+                index,
+                version: self.version,
+                hash: child_node.hash_using::<H>(),
+                aggregate: child_node.aggregate,
+            });
+            self.save(child_nibble_path, &child_node)?;
+        }
+
+        self.with_aggregate(node)
+    }
+
+    // take the next record, checking that its key is valid and smaller than
+    // that of the record after it, and record that it's inserted
+    fn next_record(&mut self) -> Result<Option<(NibblePath, K, V)>> {
+        let Some((nibble_path, key, value)) = self.records.next() else {
+            return Ok(None);
+        };
+
+        check_key(&nibble_path)?;
+
+        if let Some((_, next_key, _)) = self.records.peek() {
+            if next_key.as_ref() <= key.as_ref() {
+                return Err(TreeError::KeysNotSorted);
+            }
+        }
+
+        if let Some(key_filter) = &mut self.key_filter {
+            key_filter.insert(key.as_ref());
+        }
+
+        if let Some(stats) = &mut self.stats {
+            stats.on_record_change(None, Some(&value));
+        }
+
+        // as an op, to be treated the same as those applied by `apply`
+        let op = [(nibble_path, key, Op::Insert(value))];
+        self.tree.clear_expirations(self.store, &op)?;
+        #[cfg(feature = "journal")]
+        self.tree.journal_batch(self.store, self.version, &op)?;
+
+        let [(nibble_path, key, Op::Insert(value))] = op else {
+            unreachable!("the op is an insertion");
+        };
+        Ok(Some((nibble_path, key, value)))
+    }
+
+    fn next_is_under(&mut self, nibble_path: &NibblePath) -> bool {
+        self.records.peek().is_some_and(|(next_path, ..)| next_path.starts_with(nibble_path))
+    }
+
+    fn with_aggregate(&self, mut node: Node<K, V>) -> Result<Node<K, V>> {
+        if let Some(weight) = self.tree.aggregation {
+            node.aggregate = node.compute_aggregate(weight)?;
+        }
+        Ok(node)
+    }
+
+    fn save(&mut self, nibble_path: NibblePath, node: &Node<K, V>) -> Result<()> {
+        self.nodes_written += 1;
+        if let Some(stats) = &mut self.stats {
+            stats.add_node(node);
+        }
+        let node_key = NodeKey::new(self.version, nibble_path);
+        self.tree.nodes.save(self.store, &node_key, node).map_err(Into::into)
+    }
+}
+
+type Result<T> = std::result::Result<T, TreeError>;
+
+// ----------------------------------- tests -----------------------------------
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::{Batch, CollapsePolicy, Op, Tree, TreeConfig, TreeError},
+        cosmwasm_std::{testing::MockStorage, Order, Storage},
+        rand::{rngs::StdRng, Rng, SeedableRng},
+        std::collections::BTreeMap,
+    };
+
+    const TREE: Tree<Vec<u8>, Vec<u8>> = Tree::with_config(TreeConfig {
+        stats: Some("s"),
+        counters: Some("c"),
+        aggregation: Some(|_, value| value.len() as u128),
+        collapse_policy: CollapsePolicy::Never,
+        ..TreeConfig::new()
+    });
+
+    #[test]
+    fn initializing_in_bulk() {
+        // no version is created without records
+        let mut store = MockStorage::new();
+        TREE.initialize_from(&mut store, []).unwrap();
+        assert_eq!(store.range(None, None, Order::Ascending).count(), 0);
+
+        let mut rng = StdRng::seed_from_u64(9);
+        for num_records in [1, 2, 10, 200] {
+            // keys from a small alphabet, so that many are prefixes of others
+            let batch: Batch<_, _> = (0..num_records)
+                .map(|_| {
+                    let key: Vec<u8> =
+                        (0..rng.gen_range(1..4)).map(|_| rng.gen_range(0..3) * 16).collect();
+                    (key, Op::Insert(vec![rng.gen(); rng.gen_range(1..4)]))
+                })
+                .collect();
+            let records = batch.iter().map(|(key, op)| match op {
+                Op::Insert(value) => (key.clone(), value.clone()),
+                Op::Delete => unreachable!(),
+            });
+
+            let mut store = MockStorage::new();
+            let mut bulk_store = MockStorage::new();
+            TREE.apply(&mut store, batch.clone()).unwrap();
+            TREE.initialize_from(&mut bulk_store, records).unwrap();
+
+            // exactly the same is written, including the stats and counters
+            assert_eq!(
+                store.range(None, None, Order::Ascending).collect::<Vec<_>>(),
+                bulk_store.range(None, None, Order::Ascending).collect::<Vec<_>>(),
+            );
+        }
+    }
+
+    #[test]
+    fn matching_the_incremental_path() {
+        const DEFAULT_TREE: Tree<Vec<u8>, Vec<u8>> = Tree::new_default();
+
+        // records inserted and deleted over many versions end up in the same
+        // nodes as if they had all been inserted at once, so the root hash is
+        // the same
+        let mut rng = StdRng::seed_from_u64(10);
+        let mut store = MockStorage::new();
+        let mut model = BTreeMap::new();
+        for _ in 0..20 {
+            let batch: Batch<_, _> = (0..30)
+                .map(|_| {
+                    let key: Vec<u8> =
+                        (0..rng.gen_range(1..4)).map(|_| rng.gen_range(0..4) * 17).collect();
+                    let op = if rng.gen_bool(0.3) {
+                        Op::Delete
+                    } else {
+                        Op::Insert(vec![rng.gen()])
+                    };
+                    (key, op)
+                })
+                .collect();
+            for (key, op) in &batch {
+                match op {
+                    Op::Insert(value) => model.insert(key.clone(), value.clone()),
+                    Op::Delete => model.remove(key),
+                };
+            }
+            DEFAULT_TREE.apply(&mut store, batch).unwrap();
+        }
+
+        let mut bulk_store = MockStorage::new();
+        DEFAULT_TREE.build_from_sorted(&mut bulk_store, model).unwrap();
+        assert_eq!(
+            DEFAULT_TREE.root(&bulk_store, None).unwrap().root_hash,
+            DEFAULT_TREE.root(&store, None).unwrap().root_hash,
+        );
+    }
+
+    #[test]
+    fn initializing_from_invalid_records() {
+        let mut store = MockStorage::new();
+        let records = [(b"b".to_vec(), b"1".to_vec()), (b"a".to_vec(), b"2".to_vec())];
+        assert!(matches!(
+            TREE.initialize_from(&mut store, records),
+            Err(TreeError::KeysNotSorted),
+        ));
+
+        let mut store = MockStorage::new();
+        let records = [(b"a".to_vec(), b"1".to_vec()), (b"a".to_vec(), b"2".to_vec())];
+        assert!(matches!(
+            TREE.initialize_from(&mut store, records),
+            Err(TreeError::KeysNotSorted),
+        ));
+
+        let mut store = MockStorage::new();
+        TREE.apply(&mut store, Batch::from([(b"a".to_vec(), Op::Insert(b"1".to_vec()))])).unwrap();
+        assert!(matches!(
+            TREE.initialize_from(&mut store, []),
+            Err(TreeError::TreeNotEmpty { latest: 1 }),
+        ));
+    }
+}
@@ -0,0 +1,76 @@
+use {
+    cosmwasm_std::{Order, StdResult, Storage},
+    cw_storage_plus::{Bound, Map},
+};
+
+/// The number of bytes of the nodes orphaned during each epoch, i.e. how much
+/// storage pruning the epoch reclaims. See `Tree::with_churn_counters`.
+pub(crate) struct ChurnCounters<'a> {
+    counters: Map<'a, u64, u64>,
+}
+
+impl<'a> ChurnCounters<'a> {
+    pub const fn new(namespace: &'a str) -> Self {
+        Self {
+            counters: Map::new(namespace),
+        }
+    }
+
+    pub fn namespace(&self) -> &'a [u8] {
+        self.counters.namespace()
+    }
+
+    pub fn load(&self, store: &dyn Storage, epoch: u64) -> StdResult<u64> {
+        self.counters.may_load(store, epoch).map(Option::unwrap_or_default)
+    }
+
+    pub fn add(&self, store: &mut dyn Storage, epoch: u64, bytes: u64) -> StdResult<()> {
+        let total = self.load(store, epoch)?.saturating_add(bytes);
+        self.counters.save(store, epoch, &total)
+    }
+
+    pub fn subtract(&self, store: &mut dyn Storage, epoch: u64, bytes: u64) -> StdResult<()> {
+        match self.load(store, epoch)?.saturating_sub(bytes) {
+            0 => self.counters.remove(store, epoch),
+            total => self.counters.save(store, epoch, &total)?,
+        }
+        Ok(())
+    }
+
+    pub fn remove(&self, store: &mut dyn Storage, epoch: u64) {
+        self.counters.remove(store, epoch)
+    }
+
+    /// Delete the counters of all epochs up to the given one, or all of them.
+    pub fn remove_up_to(&self, store: &mut dyn Storage, epoch: Option<u64>) -> StdResult<()> {
+        let epochs = self
+            .counters
+            .keys(store, None, epoch.map(Bound::inclusive), Order::Ascending)
+            .collect::<StdResult<Vec<_>>>()?;
+
+        for epoch in epochs {
+            self.counters.remove(store, epoch);
+        }
+
+        Ok(())
+    }
+
+    /// Up to `limit` epochs up to the given one, or of all of them, along with
+    /// their counters, largest first. Ties are broken by the older epoch.
+    pub fn largest(
+        &self,
+        store: &dyn Storage,
+        epoch: Option<u64>,
+        limit: usize,
+    ) -> StdResult<Vec<(u64, u64)>> {
+        let mut counters = self
+            .counters
+            .range(store, None, epoch.map(Bound::inclusive), Order::Ascending)
+            .collect::<StdResult<Vec<_>>>()?;
+
+        // stable, so that equal counters remain in ascending order of epochs
+        counters.sort_by(|(_, a), (_, b)| b.cmp(a));
+        counters.truncate(limit);
+        Ok(counters)
+    }
+}
@@ -0,0 +1,375 @@
+use {
+    crate::{Child, Children, Hash, Nibble, Node, Record, HASH_LEN},
+    cosmwasm_std::{from_slice, to_vec, StdError, StdResult},
+    serde::{
+        de::{DeserializeOwned, IgnoredAny},
+        ser::Serialize,
+    },
+    std::any::type_name,
+};
+
+/// Format version of node records encoded as JSON.
+pub const NODE_FORMAT_JSON: u8 = 1;
+
+/// Format version of node records encoded by hand into raw bytes.
+pub const NODE_FORMAT_RAW: u8 = 2;
+
+// records written before format versioning was introduced are plain JSON
+// objects, which always start with this byte. we treat them as "version 0".
+const LEGACY_JSON_PREFIX: u8 = b'{';
+
+// in raw records, flags the num_children byte or a child's index byte if an
+// aggregate follows
+const RAW_HAS_AGGREGATE: u8 = 0x80;
+
+/// Defines how nodes are encoded into bytes before being written to storage.
+///
+/// Every record is prefixed with a format version byte, so that future changes
+/// to the node layout can be rolled out by upgrading nodes lazily (whenever
+/// they are rewritten), rather than migrating the entire node table at once.
+/// Codecs should therefore be able to decode records of older formats.
+pub trait NodeCodec<K, V> {
+    fn encode(node: &Node<K, V>) -> StdResult<Vec<u8>>;
+
+    fn decode(bytes: &[u8]) -> StdResult<Node<K, V>>;
+
+    /// Decode a record without its value, for when only the keys are needed.
+    /// Codecs whose records can be parsed past the value cheaply should
+    /// override this; by default the value is decoded and then dropped.
+    fn decode_without_value(bytes: &[u8]) -> StdResult<Node<K, ()>> {
+        Self::decode(bytes).map(drop_value)
+    }
+
+    /// Convert a record, possibly of an older format or written by another
+    /// tool, into the canonical bytes that `encode` would produce for the same
+    /// node. Two records of the same node are canonicalized to identical bytes,
+    /// which makes e.g. diffing backups meaningful.
+    fn canonicalize(bytes: &[u8]) -> StdResult<Vec<u8>> {
+        Self::encode(&Self::decode(bytes)?)
+    }
+}
+
+/// Encodes nodes as JSON using serde. This is the codec used by default, and
+/// works with any key and value types that implement the cw_serde traits.
+///
+/// The encoding is canonical: fields appear in the order they are declared in,
+/// without whitespace; aggregates are omitted if `None`, and otherwise written
+/// as decimal strings; hashes and nibbles are lowercase hex strings. Nodes
+/// themselves contain no maps or floats, so the bytes are stable as long as the
+/// key and value types serialize deterministically.
+pub struct JsonCodec;
+
+impl<K, V> NodeCodec<K, V> for JsonCodec
+where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+{
+    fn encode(node: &Node<K, V>) -> StdResult<Vec<u8>> {
+        let mut bytes = vec![NODE_FORMAT_JSON];
+        bytes.extend(to_vec(node)?);
+        Ok(bytes)
+    }
+
+    fn decode(bytes: &[u8]) -> StdResult<Node<K, V>> {
+        match bytes.first() {
+            Some(&NODE_FORMAT_JSON) => from_slice(&bytes[1..]),
+            Some(&LEGACY_JSON_PREFIX) => from_slice(bytes),
+            Some(version) => Err(unknown_format::<K, V>(*version)),
+            None => Err(parse_err::<K, V>("node record is empty")),
+        }
+    }
+
+    // the value is still parsed, as JSON has no length prefixes to skip it by,
+    // but nothing is allocated for it
+    fn decode_without_value(bytes: &[u8]) -> StdResult<Node<K, ()>> {
+        let node: Node<K, IgnoredAny> = match bytes.first() {
+            Some(&NODE_FORMAT_JSON) => from_slice(&bytes[1..])?,
+            Some(&LEGACY_JSON_PREFIX) => from_slice(bytes)?,
+            Some(version) => return Err(unknown_format::<K, V>(*version)),
+            None => return Err(parse_err::<K, V>("node record is empty")),
+        };
+        Ok(drop_value(node))
+    }
+}
+
+/// Encodes nodes by hand, skipping serde entirely. Only available for trees
+/// whose keys and values are raw bytes. The layout is:
+///
+/// ```plain
+/// num_children (1 byte) || aggregate (16 bytes)
+/// || (index (1 byte) || aggregate (16 bytes) || version (8 bytes)
+///     || hash (HASH_LEN bytes)) for each child
+/// || has_data (1 byte)
+/// || len(key) (2 bytes) || key || value    -- only if has_data == 1
+/// ```
+///
+/// where integers are in big endian encoding. Aggregates are only present if
+/// tracked, in which case the highest bit of the preceding `num_children` or
+/// `index` byte is set. Records in the JSON format are still readable, so an
+/// existing tree can switch to this codec in place.
+pub struct RawCodec;
+
+impl NodeCodec<Vec<u8>, Vec<u8>> for RawCodec {
+    fn encode(node: &Node<Vec<u8>, Vec<u8>>) -> StdResult<Vec<u8>> {
+        let mut bytes = vec![NODE_FORMAT_RAW];
+        encode_flagged(&mut bytes, node.children.count() as u8, node.aggregate);
+
+        for child in &node.children {
+            encode_flagged(&mut bytes, child.index.byte(), child.aggregate);
+            bytes.extend(child.version.to_be_bytes());
+            bytes.extend(child.hash.as_bytes());
+        }
+
+        if let Some(Record { key, value }) = &node.data {
+            bytes.push(1);
+            bytes.extend((key.len() as u16).to_be_bytes());
+            bytes.extend(key);
+            bytes.extend(value);
+        } else {
+            bytes.push(0);
+        }
+
+        Ok(bytes)
+    }
+
+    fn decode(bytes: &[u8]) -> StdResult<Node<Vec<u8>, Vec<u8>>> {
+        match bytes.first() {
+            Some(&NODE_FORMAT_RAW) => decode_raw(&bytes[1..], <[u8]>::to_vec),
+            Some(_) => JsonCodec::decode(bytes),
+            None => Err(parse_err::<Vec<u8>, Vec<u8>>("node record is empty")),
+        }
+    }
+
+    // the value is the rest of the record, so it's skipped without copying
+    fn decode_without_value(bytes: &[u8]) -> StdResult<Node<Vec<u8>, ()>> {
+        match bytes.first() {
+            Some(&NODE_FORMAT_RAW) => decode_raw(&bytes[1..], |_| ()),
+            Some(_) => <JsonCodec as NodeCodec<_, Vec<u8>>>::decode_without_value(bytes),
+            None => Err(parse_err::<Vec<u8>, Vec<u8>>("node record is empty")),
+        }
+    }
+}
+
+// `value` makes the record's value out of the bytes following its key
+fn decode_raw<V>(mut bytes: &[u8], value: impl FnOnce(&[u8]) -> V) -> StdResult<Node<Vec<u8>, V>> {
+    let (num_children, aggregate) = decode_flagged(&mut bytes)?;
+
+    let mut children = Vec::with_capacity(num_children as usize);
+    for _ in 0..num_children {
+        let (index, child_aggregate) = decode_flagged(&mut bytes)?;
+        let index = Nibble::try_new(index)
+            .map_err(|_| parse_err::<Vec<u8>, Vec<u8>>("child index is not a nibble"))?;
+
+        let version = u64::from_be_bytes(take(&mut bytes, 8)?.try_into().unwrap());
+        let hash = Hash::try_from(take(&mut bytes, HASH_LEN)?).unwrap();
+
+        children.push(Child {
+            index,
+            version,
+            hash,
+            aggregate: child_aggregate,
+        });
+    }
+
+    let data = match take(&mut bytes, 1)?[0] {
+        0 => None,
+        1 => {
+            let key_len = u16::from_be_bytes(take(&mut bytes, 2)?.try_into().unwrap());
+            let key = take(&mut bytes, key_len as usize)?.to_vec();
+            let value = value(std::mem::take(&mut bytes));
+            Some(Record { key, value })
+        },
+        _ => return Err(parse_err::<Vec<u8>, Vec<u8>>("invalid data flag")),
+    };
+
+    if !bytes.is_empty() {
+        return Err(parse_err::<Vec<u8>, Vec<u8>>("unexpected trailing bytes"));
+    }
+
+    Ok(Node {
+        children: Children::new(children),
+        data,
+        aggregate,
+    })
+}
+
+fn drop_value<K, V>(node: Node<K, V>) -> Node<K, ()> {
+    Node {
+        children: node.children,
+        data: node.data.map(|Record { key, .. }| Record { key, value: () }),
+        aggregate: node.aggregate,
+    }
+}
+
+// write a byte, followed by the aggregate if there is one, in which case the
+// byte is flagged
+fn encode_flagged(bytes: &mut Vec<u8>, byte: u8, aggregate: Option<u128>) {
+    if let Some(aggregate) = aggregate {
+        bytes.push(byte | RAW_HAS_AGGREGATE);
+        bytes.extend(aggregate.to_be_bytes());
+    } else {
+        bytes.push(byte);
+    }
+}
+
+fn decode_flagged(bytes: &mut &[u8]) -> StdResult<(u8, Option<u128>)> {
+    let byte = take(bytes, 1)?[0];
+    if byte & RAW_HAS_AGGREGATE == 0 {
+        return Ok((byte, None));
+    }
+
+    let aggregate = u128::from_be_bytes(take(bytes, 16)?.try_into().unwrap());
+    Ok((byte & !RAW_HAS_AGGREGATE, Some(aggregate)))
+}
+
+// split the first `n` bytes off the slice, or error if there aren't enough
+fn take<'a>(bytes: &mut &'a [u8], n: usize) -> StdResult<&'a [u8]> {
+    if bytes.len() < n {
+        return Err(parse_err::<Vec<u8>, Vec<u8>>("unexpected end of node record"));
+    }
+
+    let (taken, rest) = bytes.split_at(n);
+    *bytes = rest;
+    Ok(taken)
+}
+
+fn unknown_format<K, V>(version: u8) -> StdError {
+    parse_err::<K, V>(format!("unknown node format version: {version}"))
+}
+
+fn parse_err<K, V>(msg: impl ToString) -> StdError {
+    StdError::parse_err(type_name::<Node<K, V>>(), msg)
+}
+
+// ----------------------------------- tests -----------------------------------
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{JsonCodec, NodeCodec, RawCodec, NODE_FORMAT_JSON, NODE_FORMAT_RAW},
+        crate::{Child, Hash, Nibble, Node, Record, HASH_LEN},
+        cosmwasm_std::to_vec,
+    };
+
+    fn mock_node() -> Node<Vec<u8>, Vec<u8>> {
+        let mut node = Node::new_leaf(b"foo".to_vec(), b"bar".to_vec());
+        node.aggregate = Some(1000);
+        node.children.insert(Child {
+            index: Nibble::new(0xa),
+            version: 123,
+            hash: Hash::from([7; HASH_LEN]),
+            aggregate: None,
+        });
+        node.children.insert(Child {
+            index: Nibble::new(0xc),
+            version: 456,
+            hash: Hash::from([8; HASH_LEN]),
+            aggregate: Some(789),
+        });
+        node
+    }
+
+    #[test]
+    fn json_encoding_prefixes_format_version() {
+        let node = mock_node();
+        let bytes = JsonCodec::encode(&node).unwrap();
+        assert_eq!(bytes[0], NODE_FORMAT_JSON);
+        assert_eq!(JsonCodec::decode(&bytes).unwrap(), node);
+    }
+
+    #[test]
+    fn raw_encoding_prefixes_format_version() {
+        let node = mock_node();
+        let bytes = RawCodec::encode(&node).unwrap();
+        assert_eq!(bytes[0], NODE_FORMAT_RAW);
+        assert_eq!(RawCodec::decode(&bytes).unwrap(), node);
+    }
+
+    #[test]
+    fn decoding_older_formats() {
+        let node = mock_node();
+
+        let legacy_bytes = to_vec(&node).unwrap();
+        assert_eq!(JsonCodec::decode(&legacy_bytes).unwrap(), node);
+        assert_eq!(RawCodec::decode(&legacy_bytes).unwrap(), node);
+
+        let json_bytes = JsonCodec::encode(&node).unwrap();
+        assert_eq!(RawCodec::decode(&json_bytes).unwrap(), node);
+    }
+
+    #[test]
+    fn rejecting_malformed_records() {
+        let mut bytes = JsonCodec::encode(&mock_node()).unwrap();
+        bytes[0] = 0xff;
+        assert!(<JsonCodec as NodeCodec<Vec<u8>, Vec<u8>>>::decode(&bytes).is_err());
+        assert!(<JsonCodec as NodeCodec<Vec<u8>, Vec<u8>>>::decode(&[]).is_err());
+
+        let bytes = RawCodec::encode(&mock_node()).unwrap();
+        assert!(RawCodec::decode(&bytes[..10]).is_err());
+    }
+
+    #[test]
+    fn decoding_without_values() {
+        let node = mock_node();
+        let expected = Node {
+            children: node.children.clone(),
+            data: node.data.clone().map(|record| Record {
+                key: record.key,
+                value: (),
+            }),
+            aggregate: node.aggregate,
+        };
+
+        let json_bytes = JsonCodec::encode(&node).unwrap();
+        let raw_bytes = RawCodec::encode(&node).unwrap();
+        for bytes in [&json_bytes, &raw_bytes] {
+            assert_eq!(RawCodec::decode_without_value(bytes).unwrap(), expected);
+        }
+        assert_eq!(
+            <JsonCodec as NodeCodec<_, Vec<u8>>>::decode_without_value(&json_bytes).unwrap(),
+            expected,
+        );
+
+        // values that wouldn't decode as the tree's value type are not a
+        // problem, since they are never decoded
+        let bytes = JsonCodec::encode(&Node::new_leaf(b"foo".to_vec(), "bar".to_string())).unwrap();
+        assert!(<JsonCodec as NodeCodec<Vec<u8>, Vec<u8>>>::decode(&bytes).is_err());
+        assert!(<JsonCodec as NodeCodec<Vec<u8>, Vec<u8>>>::decode_without_value(&bytes).is_ok());
+    }
+
+    // if this test fails after upgrading dependencies, stored nodes are no
+    // longer byte-stable, which breaks backup diffing and replay tooling
+    #[cfg(not(feature = "truncated-hash"))]
+    #[test]
+    fn json_encoding_is_byte_stable() {
+        let bytes = JsonCodec::encode(&mock_node()).unwrap();
+        let expected = concat!(
+            r#"{"children":["#,
+            r#"{"index":"a","version":123,"hash":"#,
+            r#""0707070707070707070707070707070707070707070707070707070707070707"},"#,
+            r#"{"index":"c","version":456,"hash":"#,
+            r#""0808080808080808080808080808080808080808080808080808080808080808","#,
+            r#""aggregate":"789"}],"#,
+            r#""data":{"key":[102,111,111],"value":[98,97,114]},"#,
+            r#""aggregate":"1000"}"#,
+        );
+        assert_eq!(bytes[0], NODE_FORMAT_JSON);
+        assert_eq!(std::str::from_utf8(&bytes[1..]).unwrap(), expected);
+    }
+
+    #[test]
+    fn canonicalizing_records() {
+        let node = Node::new_leaf(b"foo".to_vec(), b"bar".to_vec());
+        let canonical = JsonCodec::encode(&node).unwrap();
+
+        // a legacy record with insignificant whitespace and a different field
+        // order
+        let legacy = br#"{ "data": { "value": [98, 97, 114], "key": [102, 111, 111] },
+            "children": [] }"#;
+        for bytes in [legacy.as_slice(), &canonical] {
+            let canonicalized =
+                <JsonCodec as NodeCodec<Vec<u8>, Vec<u8>>>::canonicalize(bytes).unwrap();
+            assert_eq!(canonicalized, canonical);
+        }
+    }
+}
@@ -0,0 +1,257 @@
+use crate::{CollapsePolicy, ProofEncoding, PrunedVersionPolicy, Tree, DEFAULT_PRUNE_BATCH_SIZE};
+
+/// The configuration of a `Tree`, as an alternative to chaining its `with_*`
+/// builders. It can be constructed in const context, overriding only the
+/// options that differ from the defaults:
+///
+/// ```rust
+/// use tree::{Tree, TreeConfig};
+///
+/// const TREE: Tree<Vec<u8>, Vec<u8>> = Tree::with_config(TreeConfig {
+///     domain: Some(b"my-app"),
+///     root_aliases: true,
+///     ..TreeConfig::new()
+/// });
+/// ```
+///
+/// See the builder of each option for what it does. The codec is chosen by
+/// the tree's `C` type parameter instead.
+pub struct TreeConfig<'a, K, V> {
+    pub version_namespace: &'a str,
+    pub node_namespace: &'a str,
+    pub orphan_namespace: &'a str,
+    /// See `Tree::with_orphan_epochs`.
+    pub orphan_epoch_len: u64,
+    /// See `Tree::with_prune_batch_size`.
+    pub prune_batch_size: usize,
+    /// See `Tree::with_churn_counters`.
+    pub churn_counters: Option<&'a str>,
+    /// See `Tree::with_counters`.
+    pub counters: Option<&'a str>,
+    /// See `Tree::with_stats`.
+    pub stats: Option<&'a str>,
+    /// See `Tree::with_aggregation`.
+    pub aggregation: Option<fn(&K, &V) -> u128>,
+    /// See `Tree::with_domain`.
+    pub domain: Option<&'a [u8]>,
+    /// See `Tree::with_root_aliases`.
+    pub root_aliases: bool,
+    /// See `Tree::with_key_filter`.
+    pub key_filter: Option<KeyFilterConfig<'a>>,
+    /// See `Tree::with_pruned_version_policy`.
+    pub pruned_version_policy: PrunedVersionPolicy,
+    /// See `Tree::with_collapse_policy`.
+    pub collapse_policy: CollapsePolicy,
+    /// See `Tree::with_versioned_proofs`.
+    pub versioned_proofs: bool,
+    /// See `Tree::with_proof_encoding`.
+    pub proof_encoding: ProofEncoding,
+    /// See `Tree::with_key_normalizer`.
+    pub key_normalizer: Option<fn(&K) -> K>,
+    /// See `Tree::with_key_transform`.
+    pub key_transform: Option<fn(&K) -> K>,
+    /// See `Tree::with_version_headers`.
+    pub version_headers: Option<&'a str>,
+    /// See `Tree::with_root_history`.
+    pub root_history: Option<RootHistoryConfig<'a>>,
+    /// See `Tree::with_expiration`.
+    pub expiration: Option<ExpirationConfig<'a>>,
+    /// See `Tree::with_journal`.
+    #[cfg(feature = "journal")]
+    pub journal: Option<&'a str>,
+    /// See `Tree::with_proof_metrics`.
+    #[cfg(feature = "proof-metrics")]
+    pub proof_metrics: Option<&'a str>,
+}
+
+/// The arguments of `Tree::with_key_filter`.
+pub struct KeyFilterConfig<'a> {
+    pub namespace: &'a str,
+    pub num_bytes: usize,
+    pub num_hashes: u32,
+}
+
+/// The arguments of `Tree::with_root_history`.
+pub struct RootHistoryConfig<'a> {
+    pub roots_namespace: &'a str,
+    pub nodes_namespace: &'a str,
+}
+
+/// The arguments of `Tree::with_expiration`.
+pub struct ExpirationConfig<'a> {
+    pub times_namespace: &'a str,
+    pub index_namespace: &'a str,
+}
+
+impl<'a, K, V> TreeConfig<'a, K, V> {
+    /// The configuration of `Tree::new_default`.
+    pub const fn new() -> Self {
+        Self {
+            version_namespace: "v",
+            node_namespace: "n",
+            orphan_namespace: "o",
+            orphan_epoch_len: 1,
+            prune_batch_size: DEFAULT_PRUNE_BATCH_SIZE,
+            churn_counters: None,
+            counters: None,
+            stats: None,
+            aggregation: None,
+            domain: None,
+            root_aliases: false,
+            key_filter: None,
+            pruned_version_policy: PrunedVersionPolicy::Error,
+            collapse_policy: CollapsePolicy::Always,
+            versioned_proofs: false,
+            proof_encoding: ProofEncoding::Json,
+            key_normalizer: None,
+            key_transform: None,
+            version_headers: None,
+            root_history: None,
+            expiration: None,
+            #[cfg(feature = "journal")]
+            journal: None,
+            #[cfg(feature = "proof-metrics")]
+            proof_metrics: None,
+        }
+    }
+}
+
+impl<'a, K, V> Default for TreeConfig<'a, K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, K, V, C, H> Tree<'a, K, V, C, H> {
+    /// Create a `Tree` with the given configuration.
+    pub const fn with_config(config: TreeConfig<'a, K, V>) -> Self {
+        let mut tree =
+            Self::new(config.version_namespace, config.node_namespace, config.orphan_namespace)
+                .with_orphan_epochs(config.orphan_epoch_len)
+                .with_prune_batch_size(config.prune_batch_size)
+                .with_pruned_version_policy(config.pruned_version_policy)
+                .with_collapse_policy(config.collapse_policy)
+                .with_proof_encoding(config.proof_encoding);
+
+        if let Some(namespace) = config.churn_counters {
+            tree = tree.with_churn_counters(namespace);
+        }
+
+        if let Some(namespace) = config.counters {
+            tree = tree.with_counters(namespace);
+        }
+
+        if let Some(namespace) = config.stats {
+            tree = tree.with_stats(namespace);
+        }
+
+        if let Some(weight) = config.aggregation {
+            tree = tree.with_aggregation(weight);
+        }
+
+        if let Some(domain) = config.domain {
+            tree = tree.with_domain(domain);
+        }
+
+        if config.root_aliases {
+            tree = tree.with_root_aliases();
+        }
+
+        if config.versioned_proofs {
+            tree = tree.with_versioned_proofs();
+        }
+
+        if let Some(normalize) = config.key_normalizer {
+            tree = tree.with_key_normalizer(normalize);
+        }
+
+        if let Some(transform) = config.key_transform {
+            tree = tree.with_key_transform(transform);
+        }
+
+        if let Some(namespace) = config.version_headers {
+            tree = tree.with_version_headers(namespace);
+        }
+
+        if let Some(root_history) = config.root_history {
+            tree =
+                tree.with_root_history(root_history.roots_namespace, root_history.nodes_namespace);
+        }
+
+        if let Some(expiration) = config.expiration {
+            tree = tree.with_expiration(expiration.times_namespace, expiration.index_namespace);
+        }
+
+        #[cfg(feature = "journal")]
+        if let Some(namespace) = config.journal {
+            tree = tree.with_journal(namespace);
+        }
+
+        #[cfg(feature = "proof-metrics")]
+        if let Some(namespace) = config.proof_metrics {
+            tree = tree.with_proof_metrics(namespace);
+        }
+
+        if let Some(key_filter) = config.key_filter {
+            tree = tree.with_key_filter(
+                key_filter.namespace,
+                key_filter.num_bytes,
+                key_filter.num_hashes,
+            );
+        }
+
+        tree
+    }
+}
+
+// ----------------------------------- tests -----------------------------------
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::{Batch, KeyFilterConfig, Op, Tree, TreeConfig},
+        cosmwasm_std::testing::MockStorage,
+    };
+
+    const CONFIGURED: Tree<Vec<u8>, Vec<u8>> = Tree::with_config(TreeConfig {
+        version_namespace: "a",
+        aggregation: Some(|_, value| value.len() as u128),
+        domain: Some(b"app"),
+        key_filter: Some(KeyFilterConfig {
+            namespace: "f",
+            num_bytes: 64,
+            num_hashes: 3,
+        }),
+        ..TreeConfig::new()
+    });
+
+    const BUILT: Tree<Vec<u8>, Vec<u8>> = Tree::<Vec<u8>, Vec<u8>>::new("a", "n", "o")
+        .with_aggregation(|_, value| value.len() as u128)
+        .with_domain(b"app")
+        .with_key_filter("f", 64, 3);
+
+    #[test]
+    fn configuring_like_builders() {
+        assert_eq!(CONFIGURED.namespaces(), BUILT.namespaces());
+
+        let batch = Batch::from([
+            (b"foo".to_vec(), Op::Insert(b"bar".to_vec())),
+            (b"fuzz".to_vec(), Op::Insert(b"buzz".to_vec())),
+        ]);
+        let mut configured_store = MockStorage::new();
+        let mut built_store = MockStorage::new();
+        CONFIGURED.apply(&mut configured_store, batch.clone()).unwrap();
+        BUILT.apply(&mut built_store, batch).unwrap();
+
+        assert_eq!(
+            CONFIGURED.root(&configured_store, None).unwrap(),
+            BUILT.root(&built_store, None).unwrap(),
+        );
+        let prefix = b"f".to_vec();
+        assert_eq!(
+            CONFIGURED.aggregate(&configured_store, &prefix, false, None).unwrap(),
+            BUILT.aggregate(&built_store, &prefix, false, None).unwrap(),
+        );
+        assert!(CONFIGURED.maybe_contains(&configured_store, &b"foo".to_vec(), None).unwrap());
+    }
+}
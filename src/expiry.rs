@@ -0,0 +1,66 @@
+use {
+    crate::Set,
+    cosmwasm_std::{Order, StdResult, Storage},
+    cw_storage_plus::{Map, PrefixBound},
+    serde::{de::DeserializeOwned, ser::Serialize},
+};
+
+/// When the keys of a tree expire, indexed by time so that the expired ones
+/// can be found without scanning all keys. See `Tree::with_expiration`.
+pub(crate) struct Expirations<'a, K> {
+    // the expiration time of each key, along with the key itself, as it can't
+    // be recovered from its bytes
+    times: Map<'a, &'a [u8], (u64, K)>,
+    // the keys ordered by expiration time
+    index: Set<'a, (u64, &'a [u8])>,
+}
+
+impl<'a, K> Expirations<'a, K> {
+    pub const fn new(times_namespace: &'a str, index_namespace: &'a str) -> Self {
+        Self {
+            times: Map::new(times_namespace),
+            index: Set::new(index_namespace),
+        }
+    }
+
+    pub fn namespaces(&self) -> [&'a [u8]; 2] {
+        [self.times.namespace(), self.index.namespace()]
+    }
+}
+
+impl<'a, K> Expirations<'a, K>
+where
+    K: Serialize + DeserializeOwned + Clone + AsRef<[u8]>,
+{
+    pub fn may_load(&self, store: &dyn Storage, key: &K) -> StdResult<Option<u64>> {
+        let time = self.times.may_load(store, key.as_ref())?;
+        Ok(time.map(|(time, _)| time))
+    }
+
+    pub fn set(&self, store: &mut dyn Storage, key: &K, time: u64) -> StdResult<()> {
+        self.clear(store, key)?;
+        self.times.save(store, key.as_ref(), &(time, key.clone()))?;
+        self.index.insert(store, (time, key.as_ref()))
+    }
+
+    pub fn clear(&self, store: &mut dyn Storage, key: &K) -> StdResult<()> {
+        if let Some((time, _)) = self.times.may_load(store, key.as_ref())? {
+            self.index.remove(store, (time, key.as_ref()));
+            self.times.remove(store, key.as_ref());
+        }
+        Ok(())
+    }
+
+    /// Up to `limit` keys that expire at or before `now`, earliest first.
+    pub fn expired(&self, store: &dyn Storage, now: u64, limit: usize) -> StdResult<Vec<K>> {
+        self.index
+            .prefix_range(store, None, Some(PrefixBound::inclusive(now)), Order::Ascending)
+            .take(limit)
+            .map(|item| {
+                let (_, key) = item?;
+                let (_, key) = self.times.load(store, &key)?;
+                Ok(key)
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,185 @@
+use {
+    crate::{
+        Blake3, HashableValue, IteratorCheckpoint, JsonCodec, NibblePath, NodeCodec, NodeKey, Tree,
+        TreeError, TreeHasher,
+    },
+    cosmwasm_std::{Binary, Order, Storage},
+    serde::{de::DeserializeOwned, ser::Serialize},
+};
+
+/// The number of records an `Exporter` produces per chunk, unless set using
+/// `Exporter::with_chunk_size`.
+pub const DEFAULT_EXPORT_CHUNK_SIZE: usize = 100;
+
+impl<'a, K, V, C, H> Tree<'a, K, V, C, H>
+where
+    K: Serialize + DeserializeOwned + Clone + PartialEq + AsRef<[u8]>,
+    V: Serialize + DeserializeOwned + Clone + PartialEq + HashableValue,
+    C: NodeCodec<K, V>,
+    H: TreeHasher,
+{
+    /// Export all records of the given version, or of the latest one if not
+    /// specified, in ascending order of keys, e.g. to serve the full state to a
+    /// peer that is syncing. See `Exporter`.
+    pub fn export<S: Storage>(
+        &'a self,
+        store: &S,
+        version: Option<u64>,
+    ) -> Result<Exporter<'a, K, V, C, H>> {
+        let checkpoint = self.iterate(store, Order::Ascending, None, None, version)?.checkpoint();
+        Ok(self.resume_export(checkpoint))
+    }
+
+    /// Resume an export from a checkpoint taken by `Exporter::checkpoint`, e.g.
+    /// after a restart. Proofs and the chunk size need to be set again.
+    pub fn resume_export(&'a self, checkpoint: IteratorCheckpoint) -> Exporter<'a, K, V, C, H> {
+        Exporter {
+            tree: self,
+            checkpoint,
+            prove: false,
+            chunk_size: DEFAULT_EXPORT_CHUNK_SIZE,
+        }
+    }
+}
+
+/// Produces the records of a version of a tree in chunks, each along with its
+/// proof if requested. Created by `Tree::export`.
+///
+/// Unlike a `TreeIterator`, the exporter doesn't hold on to the store between
+/// chunks, only to its position in the tree. So a host loop can yield between
+/// chunks, e.g. to serve other queries or to `.await`, and the chunk size
+/// bounds how long each call blocks for:
+///
+/// ```rust,ignore
+/// let mut exporter = tree.export(&store, None)?.with_proofs();
+/// loop {
+///     let chunk = exporter.next_chunk(&store)?;
+///     if chunk.is_empty() {
+///         break;
+///     }
+///     send(chunk).await;
+/// }
+/// ```
+///
+/// The version must not be pruned before the export is finished.
+pub struct Exporter<'a, K, V, C = JsonCodec, H = Blake3> {
+    tree: &'a Tree<'a, K, V, C, H>,
+    checkpoint: IteratorCheckpoint,
+    prove: bool,
+    chunk_size: usize,
+}
+
+impl<'a, K, V, C, H> Exporter<'a, K, V, C, H>
+where
+    K: Serialize + DeserializeOwned + Clone + PartialEq + AsRef<[u8]>,
+    V: Serialize + DeserializeOwned + Clone + PartialEq + HashableValue,
+    C: NodeCodec<K, V>,
+    H: TreeHasher,
+{
+    /// Include the proof of each record against the version's root hash,
+    /// which makes the export considerably larger and slower.
+    pub fn with_proofs(self) -> Self {
+        Self {
+            prove: true,
+            ..self
+        }
+    }
+
+    /// Produce up to `chunk_size` records per chunk.
+    pub fn with_chunk_size(self, chunk_size: usize) -> Self {
+        assert!(chunk_size > 0, "chunk size must be positive");
+        Self {
+            chunk_size,
+            ..self
+        }
+    }
+
+    /// The position of the exporter, from which the export can be resumed
+    /// using `Tree::resume_export`. It can be persisted.
+    pub fn checkpoint(&self) -> &IteratorCheckpoint {
+        &self.checkpoint
+    }
+
+    /// Produce the next chunk of records, along with their proofs if
+    /// requested. An empty chunk means the export is complete.
+    pub fn next_chunk<S: Storage>(&mut self, store: &S) -> Result<Vec<(K, V, Option<Binary>)>> {
+        let version = self.checkpoint.version();
+        let mut iter = self.tree.resume_iterate(store, &self.checkpoint)?;
+        let mut chunk = Vec::with_capacity(self.chunk_size);
+
+        while chunk.len() < self.chunk_size {
+            let Some(item) = iter.next() else {
+                break;
+            };
+            let (key, value) = item?;
+
+            // the key is in the form it's stored under, so it's looked up as
+            // is, rather than through `get`
+            let proof = if self.prove {
+                let nibble_path = NibblePath::from(&key);
+                let root_node_key = NodeKey::root(version);
+                let (_, proof) =
+                    self.tree.get_at(store, root_node_key, &mut nibble_path.nibbles(), true)?;
+                Some(self.tree.encode_proof(&proof)?)
+            } else {
+                None
+            };
+
+            chunk.push((key, value, proof));
+        }
+
+        self.checkpoint = iter.checkpoint();
+        Ok(chunk)
+    }
+}
+
+type Result<T> = std::result::Result<T, TreeError>;
+
+// ----------------------------------- tests -----------------------------------
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::{verify_membership, Batch, Op, Proof, Tree},
+        cosmwasm_std::{from_binary, from_slice, testing::MockStorage, to_vec, Order},
+    };
+
+    const TREE: Tree<String, String> = Tree::new_default();
+
+    #[test]
+    fn exporting_in_chunks() {
+        let mut store = MockStorage::new();
+        let batch: Batch<_, _> =
+            (0..25).map(|i| (format!("key{i}"), Op::Insert(format!("value{i}")))).collect();
+        TREE.apply(&mut store, batch).unwrap();
+        let root_hash = TREE.root(&store, None).unwrap().root_hash;
+
+        let mut exporter = TREE.export(&store, None).unwrap().with_proofs().with_chunk_size(10);
+        let mut records = vec![];
+        let mut chunk_sizes = vec![];
+        loop {
+            // writes between chunks don't affect the exported version
+            TREE.apply(&mut store, Batch::from([("key0".to_string(), Op::Delete)])).unwrap();
+
+            let chunk = exporter.next_chunk(&store).unwrap();
+            chunk_sizes.push(chunk.len());
+            if chunk.is_empty() {
+                break;
+            }
+
+            for (key, value, proof) in chunk {
+                let proof: Proof<String, String> = from_binary(&proof.unwrap()).unwrap();
+                verify_membership(&root_hash, &key, &value, &proof).unwrap();
+                records.push((key, value));
+            }
+
+            // round trip the checkpoint, as if it was persisted
+            let checkpoint = from_slice(&to_vec(exporter.checkpoint()).unwrap()).unwrap();
+            exporter = TREE.resume_export(checkpoint).with_proofs().with_chunk_size(10);
+        }
+        assert_eq!(chunk_sizes, [10, 10, 5, 0]);
+
+        let all = TREE.iterate(&store, Order::Ascending, None, None, Some(1)).unwrap();
+        assert_eq!(records, all.collect::<Result<Vec<_>, _>>().unwrap());
+    }
+}
@@ -0,0 +1,231 @@
+use {
+    cosmwasm_std::{Order, Record, Storage},
+    std::cell::Cell,
+};
+
+/// The gas charged for each kind of storage access, in Cosmos SDK gas units.
+///
+/// The default is the SDK's `KVGasConfig`, which is what contracts are charged
+/// for storage access on most CosmWasm chains.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GasConfig {
+    pub delete_cost: u64,
+    pub read_cost_flat: u64,
+    pub read_cost_per_byte: u64,
+    pub write_cost_flat: u64,
+    pub write_cost_per_byte: u64,
+    pub iter_next_cost_flat: u64,
+}
+
+impl Default for GasConfig {
+    fn default() -> Self {
+        Self {
+            delete_cost: 1000,
+            read_cost_flat: 1000,
+            read_cost_per_byte: 3,
+            write_cost_flat: 2000,
+            write_cost_per_byte: 30,
+            iter_next_cost_flat: 30,
+        }
+    }
+}
+
+/// The storage accesses counted by a `GasMeter`, and the gas they cost in
+/// total.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct GasReport {
+    /// Number of reads, including those that found nothing.
+    pub reads: u64,
+    /// Number of records yielded by iterators.
+    pub iterations: u64,
+    /// Number of bytes of keys and values read, by reads and iterators.
+    pub read_bytes: u64,
+    pub writes: u64,
+    /// Number of bytes of keys and values written.
+    pub written_bytes: u64,
+    pub deletes: u64,
+    pub gas: u64,
+}
+
+/// Wraps a storage, and counts the gas that accessing it would cost on-chain.
+///
+/// This is intended for benchmarks, so that design choices (such as the codec
+/// or the batch size) can be compared by the gas cost, rather than the time
+/// taken in native code, which correlates with gas poorly:
+///
+/// ```rust
+/// use {
+///     cosmwasm_std::testing::MockStorage,
+///     tree::{Batch, GasMeter, Op, Tree},
+/// };
+///
+/// const TREE: Tree<Vec<u8>, Vec<u8>> = Tree::new_default();
+///
+/// let mut store = GasMeter::new(MockStorage::new());
+/// let batch = Batch::from([(b"foo".to_vec(), Op::Insert(b"bar".to_vec()))]);
+/// TREE.apply(&mut store, batch).unwrap();
+///
+/// let report = store.take_report();
+/// println!("apply used {} gas in {} writes", report.gas, report.writes);
+/// ```
+///
+/// The gas is charged in the same way as the Cosmos SDK's `GasKVStore`. Note
+/// that CosmWasm multiplies it by a constant factor when converting it to
+/// CosmWasm gas, and that the gas for executing the contract's own code isn't
+/// accounted for.
+pub struct GasMeter<S> {
+    inner: S,
+    config: GasConfig,
+    report: Cell<GasReport>,
+}
+
+impl<S> GasMeter<S> {
+    pub fn new(inner: S) -> Self {
+        Self::with_config(inner, GasConfig::default())
+    }
+
+    pub fn with_config(inner: S, config: GasConfig) -> Self {
+        Self {
+            inner,
+            config,
+            report: Cell::new(GasReport::default()),
+        }
+    }
+
+    /// The accesses counted since the meter was created or last reset.
+    pub fn report(&self) -> GasReport {
+        self.report.get()
+    }
+
+    /// Return the accesses counted so far, and reset the counters. Call this
+    /// after each `apply` or `get` to get the gas used by each.
+    pub fn take_report(&self) -> GasReport {
+        self.report.take()
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    fn charge(&self, f: impl FnOnce(&mut GasReport, &GasConfig)) {
+        let mut report = self.report.get();
+        f(&mut report, &self.config);
+        self.report.set(report);
+    }
+}
+
+impl<S: Storage> Storage for GasMeter<S> {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        let value = self.inner.get(key);
+        let len = (key.len() + value.as_ref().map_or(0, Vec::len)) as u64;
+        self.charge(|report, config| {
+            report.reads += 1;
+            report.read_bytes += len;
+            report.gas += config.read_cost_flat + config.read_cost_per_byte * len;
+        });
+        value
+    }
+
+    fn range<'a>(
+        &'a self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        order: Order,
+    ) -> Box<dyn Iterator<Item = Record> + 'a> {
+        // as in the SDK, creating an iterator is free, but each record yielded
+        // is charged
+        let iter = self.inner.range(start, end, order).inspect(|(key, value)| {
+            let len = (key.len() + value.len()) as u64;
+            self.charge(|report, config| {
+                report.iterations += 1;
+                report.read_bytes += len;
+                report.gas += config.iter_next_cost_flat + config.read_cost_per_byte * len;
+            });
+        });
+        Box::new(iter)
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8]) {
+        let len = (key.len() + value.len()) as u64;
+        self.charge(|report, config| {
+            report.writes += 1;
+            report.written_bytes += len;
+            report.gas += config.write_cost_flat + config.write_cost_per_byte * len;
+        });
+        self.inner.set(key, value)
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        self.charge(|report, config| {
+            report.deletes += 1;
+            report.gas += config.delete_cost;
+        });
+        self.inner.remove(key)
+    }
+}
+
+// ----------------------------------- tests -----------------------------------
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::{Batch, GasMeter, GasReport, Op, RawTree, Tree},
+        cosmwasm_std::{testing::MockStorage, Order, Storage},
+    };
+
+    const TREE: Tree<Vec<u8>, Vec<u8>> = Tree::new_default();
+    const RAW_TREE: RawTree = RawTree::new_default();
+
+    fn batch() -> Batch<Vec<u8>, Vec<u8>> {
+        (0..20u8).map(|i| (vec![i; 4], Op::Insert(vec![i; 32]))).collect()
+    }
+
+    #[test]
+    fn metering_storage_access() {
+        let mut store = GasMeter::new(MockStorage::new());
+
+        store.set(b"foo", b"bar");
+        assert_eq!(store.get(b"foo"), Some(b"bar".to_vec()));
+        store.remove(b"foo");
+        assert_eq!(store.range(None, None, Order::Ascending).count(), 0);
+
+        assert_eq!(
+            store.take_report(),
+            GasReport {
+                reads: 1,
+                iterations: 0,
+                read_bytes: 6,
+                writes: 1,
+                written_bytes: 6,
+                deletes: 1,
+                gas: (2000 + 30 * 6) + (1000 + 3 * 6) + 1000,
+            },
+        );
+        assert_eq!(store.report(), GasReport::default());
+    }
+
+    #[test]
+    fn comparing_codecs() {
+        let mut json_store = GasMeter::new(MockStorage::new());
+        TREE.apply(&mut json_store, batch()).unwrap();
+        let json_apply = json_store.take_report();
+        TREE.get(&json_store, &vec![7; 4], true, None).unwrap();
+        let json_get = json_store.take_report();
+
+        let mut raw_store = GasMeter::new(MockStorage::new());
+        RAW_TREE.apply(&mut raw_store, batch()).unwrap();
+        let raw_apply = raw_store.take_report();
+        RAW_TREE.get(&raw_store, &vec![7; 4], true, None).unwrap();
+        let raw_get = raw_store.take_report();
+
+        // both trees have the same shape, so they access storage equally often,
+        // but raw nodes are smaller, so they cost less
+        assert_eq!(json_apply.writes, raw_apply.writes);
+        assert!(json_apply.gas > raw_apply.gas);
+        assert_eq!(json_get.reads, raw_get.reads);
+        assert!(json_get.gas > raw_get.gas);
+
+        // a get doesn't write
+        assert_eq!(json_get.writes, 0);
+    }
+}
@@ -0,0 +1,72 @@
+use {
+    crate::Hash,
+    cosmwasm_schema::cw_serde,
+    cosmwasm_std::{Order, StdResult, Storage},
+    cw_storage_plus::{Bound, Map},
+};
+
+/// The metadata of a version, recorded when the version is committed. Each
+/// header includes the root hash of the previous version, so that the headers
+/// form a hash chain of the tree's commitments. See `verify_version_chain`.
+#[cw_serde]
+pub struct VersionHeader {
+    pub version: u64,
+    /// `None` if the tree is empty at this version.
+    pub root_hash: Option<Hash>,
+    /// The version committed before this one, usually `version - 1`, unless
+    /// versions have been skipped using `Tree::apply_at`. Zero if this is the
+    /// first version.
+    pub prev_version: u64,
+    /// The root hash of the previous version. `None` if this is the first
+    /// version, or if the tree was empty at the previous one.
+    pub prev_root_hash: Option<Hash>,
+}
+
+/// The headers of a tree's versions.
+pub(crate) struct VersionHeaders<'a> {
+    headers: Map<'a, u64, VersionHeader>,
+}
+
+impl<'a> VersionHeaders<'a> {
+    pub const fn new(namespace: &'a str) -> Self {
+        Self {
+            headers: Map::new(namespace),
+        }
+    }
+
+    pub fn namespace(&self) -> &'a [u8] {
+        self.headers.namespace()
+    }
+
+    pub fn save(&self, store: &mut dyn Storage, header: &VersionHeader) -> StdResult<()> {
+        self.headers.save(store, header.version, header)
+    }
+
+    /// Delete the headers of all versions newer than the given one. Returns
+    /// whether there were any.
+    pub fn remove_after(&self, store: &mut dyn Storage, version: u64) -> StdResult<bool> {
+        let versions = self
+            .headers
+            .keys(store, Some(Bound::exclusive(version)), None, Order::Ascending)
+            .collect::<StdResult<Vec<_>>>()?;
+
+        for version in &versions {
+            self.headers.remove(store, *version);
+        }
+
+        Ok(!versions.is_empty())
+    }
+
+    pub fn range(
+        &self,
+        store: &dyn Storage,
+        start_after: Option<u64>,
+        limit: usize,
+    ) -> StdResult<Vec<VersionHeader>> {
+        self.headers
+            .range(store, start_after.map(Bound::exclusive), None, Order::Ascending)
+            .take(limit)
+            .map(|item| item.map(|(_, header)| header))
+            .collect()
+    }
+}
@@ -0,0 +1,236 @@
+use {
+    crate::{Hash, HashableValue, NibblePath, Op, VerificationError},
+    blake3::Hasher,
+    cosmwasm_schema::cw_serde,
+    cosmwasm_std::{Order, StdResult, Storage},
+    cw_storage_plus::{Bound, Map},
+    serde::{de::DeserializeOwned, ser::Serialize},
+};
+
+pub(crate) const DEFAULT_JOURNAL_LIMIT: usize = 10;
+
+/// An op applied to the tree, as recorded in its journal. See
+/// `Tree::with_journal`.
+#[cw_serde]
+pub struct JournalEntry<K, V> {
+    /// The position of the entry in the journal, starting from zero.
+    pub index: u64,
+    /// The version that the op was committed in.
+    pub version: u64,
+    pub key: K,
+    pub op: Op<V>,
+    /// Commits to this entry and all entries before it. See
+    /// `journal_entry_hash`.
+    pub hash: Hash,
+}
+
+/// Compute the hash of a journal entry, which is defined as:
+///
+/// hash(prev_hash || version || len(key) || key || op)
+///
+/// where `prev_hash` is the hash of the previous entry (omitted for the first
+/// entry), `version` is a 64-bit unsigned integer in big endian encoding,
+/// `len()` returns a 16-bit unsigned integer in big endian encoding, and `op`
+/// is a zero byte for a deletion, or a one byte followed by the value's
+/// `HashableValue::hash_bytes` for an insertion.
+pub fn journal_entry_hash<K, V>(prev_hash: Option<&Hash>, version: u64, key: &K, op: &Op<V>) -> Hash
+where
+    K: AsRef<[u8]>,
+    V: HashableValue,
+{
+    let mut hasher = Hasher::new();
+    if let Some(prev_hash) = prev_hash {
+        hasher.update(prev_hash.as_bytes());
+    }
+    hasher.update(&version.to_be_bytes());
+    hasher.update(&(key.as_ref().len() as u16).to_be_bytes());
+    hasher.update(key.as_ref());
+    match op {
+        Op::Insert(value) => {
+            hasher.update(&[1]);
+            hasher.update(&value.hash_bytes());
+        },
+        Op::Delete => {
+            hasher.update(&[0]);
+        },
+    }
+    hasher.finalize().into()
+}
+
+/// Verify that consecutive journal entries, e.g. a page returned by
+/// `Tree::journal`, are intact: their indexes are contiguous and each hash
+/// chains onto the previous one. `prev_hash` is the hash of the entry before
+/// the first one, or `None` if the first one is the start of the journal.
+///
+/// This detects entries that have been altered, inserted or removed, provided
+/// that the hash of the last entry is known from a trusted source.
+pub fn verify_journal<K, V>(
+    prev_hash: Option<&Hash>,
+    entries: &[JournalEntry<K, V>],
+) -> Result<(), VerificationError>
+where
+    K: AsRef<[u8]>,
+    V: HashableValue,
+{
+    let mut prev_hash = prev_hash;
+    for (i, entry) in entries.iter().enumerate() {
+        let contiguous = i == 0 || entry.index == entries[i - 1].index + 1;
+        let hash = journal_entry_hash(prev_hash, entry.version, &entry.key, &entry.op);
+        if !contiguous || hash != entry.hash {
+            return Err(VerificationError::JournalBroken {
+                index: entry.index,
+            });
+        }
+        prev_hash = Some(&entry.hash);
+    }
+
+    Ok(())
+}
+
+/// An append-only log of the ops applied to a tree, keyed by index.
+pub(crate) struct Journal<'a, K, V> {
+    entries: Map<'a, u64, JournalEntry<K, V>>,
+}
+
+impl<'a, K, V> Journal<'a, K, V> {
+    pub const fn new(namespace: &'a str) -> Self {
+        Self {
+            entries: Map::new(namespace),
+        }
+    }
+
+    pub fn namespace(&self) -> &'a [u8] {
+        self.entries.namespace()
+    }
+}
+
+impl<'a, K, V> Journal<'a, K, V>
+where
+    K: Serialize + DeserializeOwned + Clone + AsRef<[u8]>,
+    V: Serialize + DeserializeOwned + Clone + HashableValue,
+{
+    pub fn last(&self, store: &dyn Storage) -> StdResult<Option<JournalEntry<K, V>>> {
+        self.entries
+            .range(store, None, None, Order::Descending)
+            .next()
+            .transpose()
+            .map(|item| item.map(|(_, entry)| entry))
+    }
+
+    /// Record the ops of a prepared batch, committed in the given version.
+    pub fn append(
+        &self,
+        store: &mut dyn Storage,
+        version: u64,
+        batch: &[(NibblePath, K, Op<V>)],
+    ) -> StdResult<()> {
+        let last = self.last(store)?;
+        let first_index = last.as_ref().map_or(0, |entry| entry.index + 1);
+        let mut prev_hash = last.map(|entry| entry.hash);
+
+        for (index, (_, key, op)) in (first_index..).zip(batch) {
+            let hash = journal_entry_hash(prev_hash.as_ref(), version, key, op);
+            let entry = JournalEntry {
+                index,
+                version,
+                key: key.clone(),
+                op: op.clone(),
+                hash: hash.clone(),
+            };
+            self.entries.save(store, index, &entry)?;
+            prev_hash = Some(hash);
+        }
+
+        Ok(())
+    }
+
+    pub fn entries(
+        &self,
+        store: &dyn Storage,
+        start_after: Option<u64>,
+        limit: usize,
+    ) -> StdResult<Vec<JournalEntry<K, V>>> {
+        self.entries
+            .range(store, start_after.map(Bound::exclusive), None, Order::Ascending)
+            .take(limit)
+            .map(|item| item.map(|(_, entry)| entry))
+            .collect()
+    }
+
+    /// Delete the entries of versions newer than the given one, which have
+    /// been recorded by an apply that didn't commit. Returns whether there were
+    /// any. Entries are in ascending order of versions, so these are the last
+    /// ones.
+    pub fn truncate(&self, store: &mut dyn Storage, latest_version: u64) -> StdResult<bool> {
+        let mut truncated = false;
+        while let Some(entry) = self.last(store)? {
+            if entry.version <= latest_version {
+                break;
+            }
+            self.entries.remove(store, entry.index);
+            truncated = true;
+        }
+        Ok(truncated)
+    }
+}
+
+// ----------------------------------- tests -----------------------------------
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::{prepare_batch, verify_journal, Batch, Op, Tree, VerificationError},
+        cosmwasm_std::testing::MockStorage,
+    };
+
+    const TREE: Tree<Vec<u8>, Vec<u8>> = Tree::new_default().with_journal("j");
+
+    #[test]
+    fn journaling_ops() {
+        let mut store = MockStorage::new();
+        let batch = Batch::from([
+            (b"a".to_vec(), Op::Insert(b"1".to_vec())),
+            (b"b".to_vec(), Op::Insert(b"2".to_vec())),
+        ]);
+        TREE.apply(&mut store, batch).unwrap();
+        let batch = Batch::from([(b"a".to_vec(), Op::Delete)]);
+        TREE.apply(&mut store, batch).unwrap();
+
+        // ops that don't create a version are not journaled
+        let batch = Batch::from([(b"c".to_vec(), Op::Delete)]);
+        TREE.apply(&mut store, batch.clone()).unwrap();
+        TREE.apply_streaming(&mut store, batch, 1).unwrap();
+
+        let entries = TREE.journal(&store, None, None).unwrap();
+        let summary = entries.iter().map(|entry| (entry.index, entry.version, entry.key.clone()));
+        assert_eq!(
+            summary.collect::<Vec<_>>(),
+            [(0, 1, b"a".to_vec()), (1, 1, b"b".to_vec()), (2, 2, b"a".to_vec())],
+        );
+        assert_eq!(entries[2].op, Op::Delete);
+        assert_eq!(TREE.journal_head(&store).unwrap().as_ref(), entries.last());
+        assert!(verify_journal(None, &entries).is_ok());
+
+        // pages are verified against the hash of the entry before them
+        let page = TREE.journal(&store, Some(0), Some(1)).unwrap();
+        assert_eq!(page, entries[1..2]);
+        assert!(verify_journal(Some(&entries[0].hash), &page).is_ok());
+        assert!(verify_journal(None, &page).is_err());
+
+        let mut tampered = entries.clone();
+        tampered[1].op = Op::Insert(b"3".to_vec());
+        assert!(matches!(
+            verify_journal(None, &tampered),
+            Err(VerificationError::JournalBroken {
+                index: 1,
+            }),
+        ));
+        assert!(verify_journal(None, &[entries[0].clone(), entries[2].clone()]).is_err());
+
+        // entries recorded by an apply that didn't commit are cleaned up
+        let batch = Batch::from([(b"d".to_vec(), Op::Insert(b"4".to_vec()))]);
+        TREE.journal_batch(&mut store, 3, &prepare_batch(batch).unwrap()).unwrap();
+        assert!(TREE.recover(&mut store).unwrap());
+        assert_eq!(TREE.journal(&store, None, None).unwrap(), entries);
+    }
+}
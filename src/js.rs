@@ -0,0 +1,121 @@
+use {
+    crate::{GetResponse, Hash, Proof},
+    cosmwasm_std::{from_slice, StdError, StdResult},
+    wasm_bindgen::prelude::*,
+};
+
+// bindings for verifying proofs in the browser, e.g. by light clients, using
+// this crate compiled to WebAssembly rather than a reimplementation.
+//
+// keys and values are strings, as those of a `Tree<String, String>`. root
+// hashes are in hex, and proofs and responses are JSON, in the same format as
+// those returned by the contract's queries.
+
+/// Verify that the key exists in the tree with the given root hash, and has
+/// the given value.
+#[wasm_bindgen(js_name = verifyMembership)]
+pub fn verify_membership(
+    root_hash: &str,
+    key: String,
+    value: String,
+    proof: &str,
+) -> Result<(), JsError> {
+    let proof = from_slice::<Proof<String, String>>(proof.as_bytes())?;
+    crate::verify_membership(&parse_hash(root_hash)?, &key, &value, &proof)?;
+    Ok(())
+}
+
+/// Verify that the key doesn't exist in the tree with the given root hash.
+#[wasm_bindgen(js_name = verifyNonMembership)]
+pub fn verify_non_membership(root_hash: &str, key: String, proof: &str) -> Result<(), JsError> {
+    let proof = from_slice::<Proof<String, String>>(proof.as_bytes())?;
+    crate::verify_non_membership(&parse_hash(root_hash)?, &key, &proof)?;
+    Ok(())
+}
+
+/// Verify the JSON of a `GetResponse` that includes a proof. Returns the value
+/// if the proof shows that the key exists, or `undefined` if it shows that the
+/// key doesn't exist.
+#[wasm_bindgen(js_name = verifyGetResponse)]
+pub fn verify_get_response(root_hash: &str, response: &str) -> Result<Option<String>, JsError> {
+    let res = from_slice::<GetResponse<String, String>>(response.as_bytes())?;
+    let value = crate::verify_get_response(&parse_hash(root_hash)?, &res)?;
+    Ok(value.cloned())
+}
+
+/// The root hash, in hex, that a proof of membership proves the key and value
+/// against.
+#[wasm_bindgen(js_name = computeRootFromMembership)]
+pub fn compute_root_from_membership(
+    key: String,
+    value: String,
+    proof: &str,
+) -> Result<String, JsError> {
+    let proof = from_slice::<Proof<String, String>>(proof.as_bytes())?;
+    let root_hash = crate::compute_root_from_membership(&key, &value, &proof)?;
+    Ok(root_hash.to_string())
+}
+
+/// The root hash, in hex, that a proof of non-membership proves the key
+/// against.
+#[wasm_bindgen(js_name = computeRootFromNonMembership)]
+pub fn compute_root_from_non_membership(key: String, proof: &str) -> Result<String, JsError> {
+    let proof = from_slice::<Proof<String, String>>(proof.as_bytes())?;
+    let root_hash = crate::compute_root_from_non_membership(&key, &proof)?;
+    Ok(root_hash.to_string())
+}
+
+fn parse_hash(hex_str: &str) -> StdResult<Hash> {
+    let bytes = hex::decode(hex_str).map_err(|err| StdError::parse_err("Hash", err))?;
+    Hash::try_from(bytes.as_slice()).map_err(|err| StdError::parse_err("Hash", err))
+}
+
+// ----------------------------------- tests -----------------------------------
+
+// only the happy paths are tested, as errors are converted into JS values,
+// which panics outside of WebAssembly
+#[cfg(test)]
+mod tests {
+    use {
+        super::{
+            compute_root_from_membership, compute_root_from_non_membership, verify_get_response,
+            verify_membership, verify_non_membership,
+        },
+        crate::{Batch, Op, Tree},
+        cosmwasm_std::{testing::MockStorage, to_vec},
+    };
+
+    const TREE: Tree<String, String> = Tree::new_default();
+
+    #[test]
+    fn verifying_from_json() {
+        let mut store = MockStorage::new();
+        let batch = Batch::from([
+            ("foo".to_string(), Op::Insert("bar".to_string())),
+            ("fuzz".to_string(), Op::Insert("buzz".to_string())),
+        ]);
+        TREE.apply(&mut store, batch).unwrap();
+        let root_hash = TREE.root(&store, None).unwrap().root_hash.to_string();
+
+        let res = TREE.get(&store, &"foo".to_string(), true, None).unwrap();
+        let proof = res.proof.clone().unwrap();
+        let response = String::from_utf8(to_vec(&res).unwrap()).unwrap();
+        assert_eq!(verify_get_response(&root_hash, &response).unwrap(), Some("bar".to_string()));
+
+        let proof = String::from_utf8(proof.0).unwrap();
+        verify_membership(&root_hash, "foo".into(), "bar".into(), &proof).unwrap();
+        assert_eq!(
+            compute_root_from_membership("foo".into(), "bar".into(), &proof).unwrap(),
+            root_hash
+        );
+
+        let res = TREE.get(&store, &"fizz".to_string(), true, None).unwrap();
+        let proof = res.proof.clone().unwrap();
+        let response = String::from_utf8(to_vec(&res).unwrap()).unwrap();
+        assert_eq!(verify_get_response(&root_hash, &response).unwrap(), None);
+
+        let proof = String::from_utf8(proof.0).unwrap();
+        verify_non_membership(&root_hash, "fizz".into(), &proof).unwrap();
+        assert_eq!(compute_root_from_non_membership("fizz".into(), &proof).unwrap(), root_hash);
+    }
+}
@@ -0,0 +1,143 @@
+use {
+    crate::{NibblePath, Op},
+    cosmwasm_schema::cw_serde,
+    cosmwasm_std::{Binary, Order, StdResult, Storage},
+    cw_storage_plus::{Bound, Map},
+};
+
+const PRUNE_BATCH_SIZE: usize = 10;
+
+/// A bloom filter over the keys in a tree. It may report that a key is present
+/// when it isn't (a false positive), but never that a key is absent when it is
+/// present.
+///
+/// Keys are never removed from the filter, as a bloom filter can't forget a
+/// key, so keys that have been deleted from the tree remain "maybe present".
+#[cw_serde]
+pub struct KeyFilter {
+    num_hashes: u32,
+    bits: Binary,
+}
+
+impl KeyFilter {
+    pub fn new(num_bytes: usize, num_hashes: u32) -> Self {
+        Self {
+            num_hashes,
+            bits: Binary(vec![0; num_bytes]),
+        }
+    }
+
+    pub fn insert(&mut self, key: &[u8]) {
+        for index in self.bit_indexes(key) {
+            self.bits.0[index / 8] |= 1 << (index % 8);
+        }
+    }
+
+    // add the keys inserted by a prepared batch
+    pub(crate) fn insert_batch<K, V>(&mut self, batch: &[(NibblePath, K, Op<V>)])
+    where
+        K: AsRef<[u8]>,
+    {
+        for (_, key, op) in batch {
+            if let Op::Insert(_) = op {
+                self.insert(key.as_ref());
+            }
+        }
+    }
+
+    /// Whether the key may have been inserted into the filter. If `false`, it
+    /// definitely hasn't.
+    pub fn maybe_contains(&self, key: &[u8]) -> bool {
+        self.bit_indexes(key).all(|index| self.bits.0[index / 8] & (1 << (index % 8)) != 0)
+    }
+
+    // the k bit indexes of a key are derived from a single hash, using double
+    // hashing: index_i = h1 + i * h2 mod m
+    fn bit_indexes(&self, key: &[u8]) -> impl Iterator<Item = usize> {
+        let hash = blake3::hash(key);
+        let bytes = hash.as_bytes();
+        let h1 = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+        // odd, so that the indexes don't cycle early if m is a power of 2
+        let h2 = u64::from_le_bytes(bytes[8..16].try_into().unwrap()) | 1;
+        let num_bits = self.bits.len() as u64 * 8;
+        (0..self.num_hashes as u64)
+            .map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % num_bits) as usize)
+    }
+}
+
+/// The key filters of a tree, one per version, each containing all keys ever
+/// inserted up to that version.
+pub(crate) struct KeyFilters<'a> {
+    filters: Map<'a, u64, KeyFilter>,
+    num_bytes: usize,
+    num_hashes: u32,
+}
+
+impl<'a> KeyFilters<'a> {
+    pub const fn new(namespace: &'a str, num_bytes: usize, num_hashes: u32) -> Self {
+        assert!(num_bytes > 0, "key filter must be at least one byte");
+        assert!(num_hashes > 0, "key filter must use at least one hash");
+        Self {
+            filters: Map::new(namespace),
+            num_bytes,
+            num_hashes,
+        }
+    }
+
+    pub fn namespace(&self) -> &'a [u8] {
+        self.filters.namespace()
+    }
+
+    pub fn may_load(&self, store: &dyn Storage, version: u64) -> StdResult<Option<KeyFilter>> {
+        self.filters.may_load(store, version)
+    }
+
+    /// The filter that the keys inserted on top of `old_version` are to be
+    /// added to, which is that of `old_version`. Returns `None` if the tree
+    /// existed before key filters were enabled, as a new filter would then miss
+    /// the existing keys; such a tree is never filtered.
+    pub fn to_update(&self, store: &dyn Storage, old_version: u64) -> StdResult<Option<KeyFilter>> {
+        if old_version == 0 {
+            return Ok(Some(KeyFilter::new(self.num_bytes, self.num_hashes)));
+        }
+        self.filters.may_load(store, old_version)
+    }
+
+    pub fn save(&self, store: &mut dyn Storage, version: u64, filter: &KeyFilter) -> StdResult<()> {
+        self.filters.save(store, version, filter)
+    }
+
+    /// Delete the filters of all versions newer than the given one. Returns
+    /// whether there were any.
+    pub fn remove_after(&self, store: &mut dyn Storage, version: u64) -> StdResult<bool> {
+        let versions = self
+            .filters
+            .keys(store, Some(Bound::exclusive(version)), None, Order::Ascending)
+            .collect::<StdResult<Vec<_>>>()?;
+
+        for version in &versions {
+            self.filters.remove(store, *version);
+        }
+
+        Ok(!versions.is_empty())
+    }
+
+    /// Delete the filters of all versions older than the given one.
+    pub fn prune(&self, store: &mut dyn Storage, before_version: u64) -> StdResult<()> {
+        loop {
+            let versions = self
+                .filters
+                .keys(store, None, Some(Bound::exclusive(before_version)), Order::Ascending)
+                .take(PRUNE_BATCH_SIZE)
+                .collect::<StdResult<Vec<_>>>()?;
+
+            for version in &versions {
+                self.filters.remove(store, *version);
+            }
+
+            if versions.len() < PRUNE_BATCH_SIZE {
+                return Ok(());
+            }
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use {
+    cosmwasm_std::{Order, Record, Storage},
+    std::{collections::BTreeMap, ops::Bound},
+};
+
+/// A minimal key-value store, which is all a `Tree` needs of its storage. Wrap
+/// a store in `KvStorage` to use it as a tree's `Storage`, e.g. to run the same
+/// tree off-chain on RocksDB or sled, for indexers and light-client servers.
+///
+/// As with `Storage`, the methods can't return errors, so a store that fails to
+/// read or write must panic.
+pub trait KvStore {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>>;
+
+    fn set(&mut self, key: &[u8], value: &[u8]);
+
+    fn remove(&mut self, key: &[u8]);
+
+    /// Yield the records with `min <= key < max` in the given order, in which
+    /// keys are compared as bytes. Nothing is yielded if `min >= max`.
+    fn scan<'a>(
+        &'a self,
+        min: Option<&[u8]>,
+        max: Option<&[u8]>,
+        order: Order,
+    ) -> Box<dyn Iterator<Item = Record> + 'a>;
+}
+
+/// An in-memory store, e.g. for tests, or for trees rebuilt from a snapshot on
+/// every start.
+impl KvStore for BTreeMap<Vec<u8>, Vec<u8>> {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        BTreeMap::get(self, key).cloned()
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8]) {
+        self.insert(key.to_vec(), value.to_vec());
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        BTreeMap::remove(self, key);
+    }
+
+    fn scan<'a>(
+        &'a self,
+        min: Option<&[u8]>,
+        max: Option<&[u8]>,
+        order: Order,
+    ) -> Box<dyn Iterator<Item = Record> + 'a> {
+        // BTreeMap::range panics if min > max, whereas stores return nothing
+        if let (Some(min), Some(max)) = (min, max) {
+            if min >= max {
+                return Box::new(std::iter::empty());
+            }
+        }
+
+        let range = self
+            .range::<[u8], _>((
+                min.map_or(Bound::Unbounded, Bound::Included),
+                max.map_or(Bound::Unbounded, Bound::Excluded),
+            ))
+            .map(|(key, value)| (key.clone(), value.clone()));
+        match order {
+            Order::Ascending => Box::new(range),
+            Order::Descending => Box::new(range.rev()),
+        }
+    }
+}
+
+/// Exposes a `KvStore` as a `cosmwasm_std::Storage`, so that a `Tree` can be
+/// read and written through it like through any other storage.
+#[derive(Default)]
+pub struct KvStorage<T>(pub T);
+
+impl<T> KvStorage<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T: KvStore> Storage for KvStorage<T> {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.0.get(key)
+    }
+
+    fn range<'a>(
+        &'a self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        order: Order,
+    ) -> Box<dyn Iterator<Item = Record> + 'a> {
+        self.0.scan(start, end, order)
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8]) {
+        self.0.set(key, value);
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        self.0.remove(key);
+    }
+}
+
+// ----------------------------------- tests -----------------------------------
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::KvStorage,
+        crate::{Batch, Op, Tree},
+        cosmwasm_std::{testing::MockStorage, Order, Storage},
+        rand::{rngs::StdRng, Rng, SeedableRng},
+        std::collections::BTreeMap,
+    };
+
+    const TREE: Tree<Vec<u8>, Vec<u8>> = Tree::new_default();
+
+    #[test]
+    fn running_on_kv_stores() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut store = KvStorage(BTreeMap::new());
+        let mut mock_store = MockStorage::new();
+        for _ in 0..10 {
+            let batch: Batch<_, _> = (0..10)
+                .map(|_| {
+                    let key = vec![rng.gen_range(0..8), rng.gen()];
+                    let op = if rng.gen_bool(0.3) {
+                        Op::Delete
+                    } else {
+                        Op::Insert(vec![rng.gen()])
+                    };
+                    (key, op)
+                })
+                .collect();
+            TREE.apply(&mut store, batch.clone()).unwrap();
+            TREE.apply(&mut mock_store, batch).unwrap();
+        }
+        TREE.prune(&mut store, None).unwrap();
+        TREE.prune(&mut mock_store, None).unwrap();
+
+        // the same records are stored, and scanned in the same order
+        assert_eq!(TREE.root(&store, None).unwrap(), TREE.root(&mock_store, None).unwrap());
+        for order in [Order::Ascending, Order::Descending] {
+            for (min, max) in [(None, None), (Some(&[0, 1][..]), Some(&[0, 1, b'z'][..]))] {
+                assert_eq!(
+                    store.range(min, max, order).collect::<Vec<_>>(),
+                    mock_store.range(min, max, order).collect::<Vec<_>>(),
+                );
+            }
+        }
+        assert_eq!(store.range(Some(b"b"), Some(b"a"), Order::Ascending).count(), 0);
+    }
+}
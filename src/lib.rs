@@ -1,11 +1,140 @@
+#[cfg(feature = "storage")]
+mod append;
+#[cfg(feature = "storage")]
+mod apply;
+#[cfg(feature = "storage")]
+mod attest;
+#[cfg(feature = "storage")]
+mod batch_codec;
+#[cfg(feature = "block-storage")]
+mod block_storage;
+#[cfg(feature = "storage")]
+mod bulk_load;
+#[cfg(feature = "storage")]
+mod churn;
+#[cfg(feature = "storage")]
+mod codec;
+#[cfg(feature = "storage")]
+mod config;
+#[cfg(feature = "storage")]
+mod expiry;
+#[cfg(feature = "storage")]
+mod export;
+#[cfg(feature = "storage")]
+mod gas;
+#[cfg(feature = "storage")]
+mod header;
+#[cfg(feature = "js")]
+mod js;
+#[cfg(feature = "journal")]
+mod journal;
+#[cfg(feature = "storage")]
+mod key_filter;
+#[cfg(feature = "storage")]
+mod kv_store;
+#[cfg(feature = "storage")]
+mod migrate;
+#[cfg(feature = "storage")]
+mod multi;
+#[cfg(feature = "storage")]
+mod negative_cache;
+#[cfg(feature = "storage")]
+mod node_map;
+#[cfg(feature = "storage")]
+mod overlay;
+#[cfg(feature = "rayon")]
+mod parallel;
+#[cfg(feature = "storage")]
+mod proof_codec;
+#[cfg(feature = "proof-metrics")]
+mod proof_metrics;
+#[cfg(feature = "storage")]
+mod prover;
+#[cfg(feature = "rocksdb")]
+mod rocks_store;
+#[cfg(feature = "storage")]
+mod root_history;
+#[cfg(feature = "storage")]
 mod set;
+#[cfg(feature = "storage")]
+mod smt;
+#[cfg(feature = "storage")]
+mod snapshot;
+#[cfg(feature = "storage")]
+mod staged;
+#[cfg(feature = "storage")]
+mod stats;
+#[cfg(feature = "storage")]
+mod storage;
+#[cfg(feature = "subscribe")]
+mod subscribe;
+#[cfg(feature = "storage")]
 mod tree;
 mod types;
 mod verify;
+#[cfg(feature = "storage")]
+mod version;
 
 pub use crate::{
-    set::Set,
-    tree::{Tree, TreeError, TreeIterator},
     types::*,
-    verify::{verify_membership, verify_non_membership, VerificationError},
+    verify::{
+        compute_root_from_membership, compute_root_from_membership_using,
+        compute_root_from_non_membership, compute_root_from_non_membership_using, proof_preimages,
+        verify_aggregate, verify_aggregate_in_domain, verify_aggregate_using,
+        verify_batch_membership, verify_batch_membership_in_domain, verify_batch_membership_using,
+        verify_membership, verify_membership_in_domain, verify_membership_using,
+        verify_multi_membership, verify_multi_non_membership, verify_non_membership,
+        verify_non_membership_in_domain, verify_non_membership_using, verify_proof_versions,
+        verify_subtree, verify_subtree_in_domain, verify_subtree_using, VerificationError,
+    },
+};
+#[cfg(feature = "storage")]
+pub use crate::{
+    append::Appender,
+    apply::{apply_subtree, prepare_batch, CollapsePolicy, NodeSource},
+    attest::{Attestation, Signed},
+    batch_codec::{batch_hash, decode_batch, encode_batch, BATCH_FORMAT},
+    codec::{JsonCodec, NodeCodec, RawCodec, NODE_FORMAT_JSON, NODE_FORMAT_RAW},
+    config::{ExpirationConfig, KeyFilterConfig, RootHistoryConfig, TreeConfig},
+    export::{Exporter, DEFAULT_EXPORT_CHUNK_SIZE},
+    gas::{GasConfig, GasMeter, GasReport},
+    header::VersionHeader,
+    key_filter::KeyFilter,
+    kv_store::{KvStorage, KvStore},
+    multi::MultiTree,
+    negative_cache::NegativeCache,
+    node_map::NodeMap,
+    overlay::Overlay,
+    proof_codec::{decode_proof, ProofBytes, PROOF_FORMAT},
+    prover::{prove_queries, DEFAULT_RANGE_LIMIT},
+    root_history::{
+        verify_historical_root, verify_historical_root_using, HistoricalRootProof,
+        HistoricalRootResponse,
+    },
+    set::Set,
+    snapshot::{snapshot_chunk_hash, Snapshot},
+    staged::{StagedIterator, StagedTree},
+    stats::TreeStats,
+    storage::TreeStorage,
+    tree::{
+        check_namespaces, HashedTree, Keys, ProofEncoding, PrunedVersionPolicy, RawTree, Tree,
+        TreeError, TreeIterator, Values, DEFAULT_PRUNE_BATCH_SIZE,
+    },
+    verify::{
+        verify_get_many_response, verify_get_response, verify_get_response_in_domain,
+        verify_get_response_using, verify_version_chain,
+    },
+    version::Version,
 };
+#[cfg(feature = "block-storage")]
+pub use crate::block_storage::BlockStorage;
+#[cfg(feature = "journal")]
+pub use crate::journal::{journal_entry_hash, verify_journal, JournalEntry};
+#[cfg(feature = "proof-metrics")]
+pub use crate::proof_metrics::ProofMetrics;
+#[cfg(feature = "rayon")]
+pub use crate::apply::{apply_subtree_parallel, ForkNodeSource};
+#[cfg(feature = "rocksdb")]
+pub use crate::rocks_store::RocksStore;
+#[cfg(feature = "subscribe")]
+pub use crate::subscribe::{Notifier, Subscription};
@@ -0,0 +1,159 @@
+use {
+    crate::{Batch, ChangeSet, HashableValue, NodeCodec, Op, Tree, TreeError, TreeHasher},
+    cosmwasm_std::{Order, StdResult, Storage},
+    cw_storage_plus::Map,
+    serde::{de::DeserializeOwned, ser::Serialize},
+};
+
+impl<'a, K, V, C, H> Tree<'a, K, V, C, H>
+where
+    K: Serialize + DeserializeOwned + Clone + PartialEq + Ord + AsRef<[u8]>,
+    V: Serialize + DeserializeOwned + Clone + PartialEq + HashableValue,
+    C: NodeCodec<K, V>,
+    H: TreeHasher,
+{
+    /// Load all entries of a cw-storage-plus `Map` under the given namespace in
+    /// the same storage into the tree, committed as a single new version. This
+    /// upgrades a deployed contract's state to a merklized one in place, e.g.
+    /// in its `migrate` entry point.
+    ///
+    /// `transform` converts each entry into a record of the tree. It's given
+    /// the raw key as stored by the map, which for single-part keys, such as
+    /// `&str`, `Addr` or `&[u8]`, is the key's bytes. Composite keys can be
+    /// decoded using `KeyDeserialize::from_vec`.
+    ///
+    /// All entries are held in memory at once. The map is left intact; clear
+    /// it once it's no longer needed, as it's not kept in sync with the tree.
+    pub fn import_map<T, F>(
+        &self,
+        store: &mut dyn Storage,
+        map_namespace: &str,
+        mut transform: F,
+    ) -> Result<ChangeSet<K>>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnMut(Vec<u8>, T) -> StdResult<(K, V)>,
+    {
+        let map: Map<Vec<u8>, T> = Map::new(map_namespace);
+        let batch = map
+            .range(store, None, None, Order::Ascending)
+            .map(|item| {
+                let (raw_key, value) = item?;
+                let (key, value) = transform(raw_key, value)?;
+                Ok((key, Op::Insert(value)))
+            })
+            .collect::<StdResult<Batch<K, V>>>()?;
+
+        if batch.is_empty() {
+            return self.unchanged(store);
+        }
+
+        self.apply(store, batch)
+    }
+
+    /// The reverse of `import_map`: write all records of the latest version
+    /// into a cw-storage-plus `Map` under the given namespace, e.g. for a
+    /// contract that no longer needs its state to be versioned or merklized.
+    /// Returns the number of entries written.
+    ///
+    /// `transform` converts each record into an entry of the map, given as the
+    /// raw key to store it under, which for single-part keys is the key's
+    /// bytes, and the value.
+    ///
+    /// All records are held in memory at once. The tree is left intact.
+    pub fn export_map<S, T, F>(
+        &'a self,
+        store: &mut S,
+        map_namespace: &str,
+        mut transform: F,
+    ) -> Result<usize>
+    where
+        S: Storage,
+        T: Serialize + DeserializeOwned,
+        F: FnMut(K, V) -> StdResult<(Vec<u8>, T)>,
+    {
+        if self.version.may_load(store)?.is_none() {
+            return Ok(0);
+        }
+
+        let entries = self
+            .iterate(store, Order::Ascending, None, None, None)?
+            .map(|item| {
+                let (key, value) = item?;
+                transform(key, value).map_err(Into::into)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let map: Map<&[u8], T> = Map::new(map_namespace);
+        for (key, value) in &entries {
+            map.save(store, key, value)?;
+        }
+
+        Ok(entries.len())
+    }
+}
+
+type Result<T> = std::result::Result<T, TreeError>;
+
+// ----------------------------------- tests -----------------------------------
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::{Batch, Op, Tree},
+        cosmwasm_std::{testing::MockStorage, Order, StdResult},
+        cw_storage_plus::Map,
+    };
+
+    const TREE: Tree<String, String> = Tree::new_default();
+    const BALANCES: Map<&str, u64> = Map::new("balances");
+
+    #[test]
+    fn importing_maps() {
+        let mut store = MockStorage::new();
+        // an empty map doesn't create a version
+        let changes = TREE.import_map(&mut store, "balances", |_, _: u64| unreachable!()).unwrap();
+        assert_eq!(changes.version, 0);
+
+        BALANCES.save(&mut store, "alice", &100).unwrap();
+        BALANCES.save(&mut store, "bob", &50).unwrap();
+        let changes = TREE
+            .import_map(&mut store, "balances", |key, amount: u64| {
+                Ok((String::from_utf8(key).unwrap(), amount.to_string()))
+            })
+            .unwrap();
+        assert_eq!((changes.version, changes.changes.len()), (1, 2));
+
+        let iter = TREE.iterate(&store, Order::Ascending, None, None, None).unwrap();
+        assert_eq!(
+            iter.collect::<Result<Vec<_>, _>>().unwrap(),
+            [("alice".to_string(), "100".to_string()), ("bob".to_string(), "50".to_string())],
+        );
+        // the map is left intact
+        assert_eq!(BALANCES.load(&store, "alice").unwrap(), 100);
+    }
+
+    #[test]
+    fn exporting_maps() {
+        let mut store = MockStorage::new();
+        let export = |store: &mut MockStorage| {
+            TREE.export_map(store, "balances", |key, amount| {
+                Ok((key.into_bytes(), amount.parse::<u64>().unwrap()))
+            })
+        };
+        assert_eq!(export(&mut store).unwrap(), 0);
+
+        let batch = Batch::from([
+            ("alice".to_string(), Op::Insert("100".to_string())),
+            ("bob".to_string(), Op::Insert("50".to_string())),
+        ]);
+        TREE.apply(&mut store, batch).unwrap();
+        assert_eq!(export(&mut store).unwrap(), 2);
+
+        let entries = BALANCES.range(&store, None, None, Order::Ascending);
+        assert_eq!(
+            entries.collect::<StdResult<Vec<_>>>().unwrap(),
+            [("alice".to_string(), 100), ("bob".to_string(), 50)],
+        );
+    }
+}
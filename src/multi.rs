@@ -0,0 +1,326 @@
+use {
+    crate::{
+        tree::next_version, Batch, Blake3, Hash, HashableValue, JsonCodec, MultiGetResponse,
+        MultiProof, NibblePath, NodeCodec, NodeKey, Op, RootResponse, Tree, TreeError, TreeHasher,
+    },
+    cosmwasm_std::{to_binary, Order, Storage},
+    cw_storage_plus::{Bound, Map},
+    serde::{de::DeserializeOwned, ser::Serialize},
+    std::collections::BTreeMap,
+};
+
+// a store's name, and the store itself
+type Store<'a, K, V, C, H> = (&'a str, &'a Tree<'a, K, V, C, H>);
+
+/// Manages several named `Tree`s (stores), and commits their root hashes into
+/// a top-level tree, whose root hash commits to the state of all of them. This
+/// is the layout of the Cosmos SDK's multistore.
+///
+/// Each store must be a distinct `Tree` using its own namespaces, which must
+/// also differ from those used by the `MultiTree` itself (see `namespaces`):
+///
+/// ```rust
+/// use tree::{MultiTree, Tree};
+///
+/// const BANK: Tree<Vec<u8>, Vec<u8>> = Tree::new("bank/v", "bank/n", "bank/o");
+/// const STAKING: Tree<Vec<u8>, Vec<u8>> = Tree::new("staking/v", "staking/n", "staking/o");
+/// const MULTI: MultiTree<Vec<u8>, Vec<u8>> =
+///     MultiTree::new_default(&[("bank", &BANK), ("staking", &STAKING)]);
+/// ```
+///
+/// The top-level tree maps each store's name to its root hash. Its version is
+/// the version of the multistore, and increments whenever any of the stores
+/// is changed. An empty store is not included in the top-level tree.
+pub struct MultiTree<'a, K, V, C = JsonCodec, H = Blake3> {
+    stores: &'a [Store<'a, K, V, C, H>],
+    roots: Tree<'a, String, Hash, JsonCodec, H>,
+    // (store name, multistore version) => store version. a record is written
+    // whenever the store is changed.
+    store_versions: Map<'a, (&'a str, u64), u64>,
+}
+
+impl<'a, K, V, C, H> MultiTree<'a, K, V, C, H> {
+    pub const fn new(
+        stores: &'a [Store<'a, K, V, C, H>],
+        version_namespace: &'a str,
+        node_namespace: &'a str,
+        orphan_namespace: &'a str,
+        store_version_namespace: &'a str,
+    ) -> Self {
+        Self {
+            stores,
+            roots: Tree::new(version_namespace, node_namespace, orphan_namespace),
+            store_versions: Map::new(store_version_namespace),
+        }
+    }
+
+    /// Create a `MultiTree` using the default namespaces for the top-level
+    /// tree.
+    pub const fn new_default(stores: &'a [Store<'a, K, V, C, H>]) -> Self {
+        Self::new(stores, "multi/v", "multi/n", "multi/o", "multi/s")
+    }
+
+    /// The namespaces used by the `MultiTree` itself, followed by those of each
+    /// of the stores. Pass them to `check_namespaces` to verify that the stores
+    /// are indeed distinct.
+    pub fn namespaces(&self) -> Vec<&[u8]> {
+        let mut namespaces = self.roots.namespaces().to_vec();
+        namespaces.push(self.store_versions.namespace());
+        for (_, store) in self.stores {
+            namespaces.extend(store.namespaces());
+        }
+        namespaces
+    }
+
+    // find the store by name, returning its name with the lifetime 'a, as well
+    // as the store itself
+    fn store(&self, name: &str) -> Result<Store<'a, K, V, C, H>> {
+        self.stores
+            .iter()
+            .find(|(store_name, _)| *store_name == name)
+            .copied()
+            .ok_or_else(|| TreeError::UnknownStore {
+                name: name.to_string(),
+            })
+    }
+}
+
+impl<'a, K, V, C, H> MultiTree<'a, K, V, C, H>
+where
+    K: Serialize + DeserializeOwned + Clone + PartialEq + AsRef<[u8]>,
+    V: Serialize + DeserializeOwned + Clone + PartialEq + HashableValue,
+    C: NodeCodec<K, V>,
+    H: TreeHasher,
+{
+    /// Apply a batch of ops to each of the given stores, then commit the new
+    /// root hashes of the stores to the top-level tree.
+    pub fn apply(
+        &self,
+        store: &mut dyn Storage,
+        batches: BTreeMap<String, Batch<K, V>>,
+    ) -> Result<()> {
+        let new_version = next_version(self.roots.version.may_load(store)?.unwrap_or(0))?;
+        let mut root_batch = Batch::new();
+
+        for (name, batch) in batches {
+            let (store_name, tree) = self.store(&name)?;
+
+            let old_store_version = tree.version.may_load(store)?.unwrap_or(0);
+            let store_version = tree.apply(store, batch)?.version;
+
+            // the store's root didn't change
+            if store_version == old_store_version {
+                continue;
+            }
+
+            self.store_versions.save(store, (store_name, new_version), &store_version)?;
+
+            // commit the hash of the store's root node, not tagged with the
+            // store's domain if it has one. the store's name in the top-level
+            // tree already serves the purpose.
+            match tree.nodes.may_load(store, &NodeKey::root(store_version))? {
+                Some(root_node) => root_batch.insert(name, Op::Insert(root_node.hash_using::<H>())),
+                None => root_batch.insert(name, Op::Delete),
+            };
+        }
+
+        self.roots.apply(store, root_batch)?;
+
+        Ok(())
+    }
+
+    /// Query the root hash of the top-level tree, which commits to the state of
+    /// all stores.
+    pub fn root(&self, store: &dyn Storage, version: Option<u64>) -> Result<RootResponse> {
+        self.roots.root(store, version)
+    }
+
+    /// Query the version of the given store as of the given multistore version,
+    /// or `None` if the store hadn't been written to by then.
+    pub fn store_version(
+        &self,
+        store: &dyn Storage,
+        name: &str,
+        version: Option<u64>,
+    ) -> Result<Option<u64>> {
+        let (store_name, _) = self.store(name)?;
+
+        let version = match version {
+            Some(version) => version,
+            None => self.roots.version.load(store)?,
+        };
+
+        self.store_versions
+            .prefix(store_name)
+            .range(store, None, Some(Bound::inclusive(version)), Order::Descending)
+            .next()
+            .transpose()
+            .map(|record| record.map(|(_, store_version)| store_version))
+            .map_err(Into::into)
+    }
+
+    /// Query the value associated with the key in the given store, optionally
+    /// with a proof against the root hash of the top-level tree. The proof can
+    /// be verified using `verify_multi_membership` or
+    /// `verify_multi_non_membership`.
+    pub fn get(
+        &self,
+        store: &dyn Storage,
+        name: &str,
+        key: &K,
+        prove: bool,
+        version: Option<u64>,
+    ) -> Result<MultiGetResponse<K, V>> {
+        let (_, tree) = self.store(name)?;
+        let key = tree.normalize_key(key);
+
+        let version = match version {
+            Some(version) => version,
+            None => self.roots.version.load(store)?,
+        };
+
+        let (store_root_hash, store_proof) = self.roots.get_at(
+            store,
+            NodeKey::root(version),
+            &mut NibblePath::from(name).nibbles(),
+            prove,
+        )?;
+
+        // if the store isn't in the top-level tree, it is empty, so the key
+        // doesn't exist. the store-level proof suffices to prove this.
+        let store_version = self.store_version(store, name, Some(version))?;
+        let (value, key_proof) = match (&store_root_hash, store_version) {
+            (Some(_), Some(store_version)) => {
+                let key_path = NibblePath::from(&*key);
+                tree.get_at(store, NodeKey::root(store_version), &mut key_path.nibbles(), prove)?
+            },
+            _ => (None, vec![]),
+        };
+
+        let proof = if prove {
+            Some(to_binary(&MultiProof {
+                store_root_hash,
+                store_proof,
+                key_proof,
+            })?)
+        } else {
+            None
+        };
+
+        Ok(MultiGetResponse {
+            key: key.into_owned(),
+            value,
+            proof,
+        })
+    }
+}
+
+type Result<T> = std::result::Result<T, TreeError>;
+
+// ----------------------------------- tests -----------------------------------
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::{
+            check_namespaces, verify_multi_membership, verify_multi_non_membership, Batch,
+            MultiProof, MultiTree, Op, Tree, TreeError,
+        },
+        cosmwasm_std::{from_binary, testing::MockStorage},
+        std::collections::BTreeMap,
+    };
+
+    const BANK: Tree<String, String> = Tree::new("bank/v", "bank/n", "bank/o");
+    const STAKING: Tree<String, String> = Tree::new("staking/v", "staking/n", "staking/o");
+    const MULTI: MultiTree<String, String> =
+        MultiTree::new_default(&[("bank", &BANK), ("staking", &STAKING)]);
+
+    fn batches(ops: &[(&str, &str, Option<&str>)]) -> BTreeMap<String, Batch<String, String>> {
+        let mut batches = BTreeMap::<String, Batch<_, _>>::new();
+        for (name, key, value) in ops {
+            let op = value.map(|v| Op::Insert(v.to_string())).unwrap_or(Op::Delete);
+            batches.entry(name.to_string()).or_default().insert(key.to_string(), op);
+        }
+        batches
+    }
+
+    fn check(store: &MockStorage, name: &str, key: &str, value: Option<&str>, version: u64) {
+        let root_hash = MULTI.root(store, Some(version)).unwrap().root_hash;
+        let res = MULTI.get(store, name, &key.to_string(), true, Some(version)).unwrap();
+        let proof: MultiProof<String, String> = from_binary(&res.proof.unwrap()).unwrap();
+
+        assert_eq!(res.value.as_deref(), value);
+        match value {
+            Some(value) => {
+                let value = value.to_string();
+                let key = &res.key;
+                assert!(verify_multi_membership(&root_hash, name, key, &value, &proof).is_ok());
+                assert!(verify_multi_non_membership(&root_hash, name, key, &proof).is_err());
+            },
+            None => {
+                assert!(verify_multi_non_membership(&root_hash, name, &res.key, &proof).is_ok());
+            },
+        }
+    }
+
+    #[test]
+    fn proving_across_stores() {
+        let mut store = MockStorage::new();
+
+        MULTI
+            .apply(
+                &mut store,
+                batches(&[
+                    ("bank", "alice", Some("100")),
+                    ("bank", "bob", Some("50")),
+                    ("staking", "alice", Some("20")),
+                ]),
+            )
+            .unwrap();
+
+        // only bank is changed. staking's version stays at 1
+        MULTI.apply(&mut store, batches(&[("bank", "bob", Some("70"))])).unwrap();
+        assert_eq!(MULTI.store_version(&store, "bank", None).unwrap(), Some(2));
+        assert_eq!(MULTI.store_version(&store, "staking", None).unwrap(), Some(1));
+
+        // staking is emptied
+        MULTI.apply(&mut store, batches(&[("staking", "alice", None)])).unwrap();
+
+        check(&store, "bank", "alice", Some("100"), 3);
+        check(&store, "bank", "bob", Some("70"), 3);
+        check(&store, "bank", "charlie", None, 3);
+        check(&store, "staking", "alice", None, 3);
+
+        // historical queries
+        check(&store, "bank", "bob", Some("50"), 1);
+        check(&store, "staking", "alice", Some("20"), 2);
+    }
+
+    #[test]
+    fn rejecting_unknown_store() {
+        let mut store = MockStorage::new();
+        let err = MULTI.apply(&mut store, batches(&[("gov", "proposal", Some("1"))])).unwrap_err();
+        assert_eq!(
+            err,
+            TreeError::UnknownStore {
+                name: "gov".into(),
+            },
+        );
+    }
+
+    #[test]
+    fn checking_namespaces() {
+        assert!(check_namespaces(&MULTI.namespaces()).is_ok());
+
+        // a store that mistakenly reuses the bank's node namespace
+        const GOV: Tree<String, String> = Tree::new("gov/v", "bank/n", "gov/o");
+        const BAD_MULTI: MultiTree<String, String> =
+            MultiTree::new_default(&[("bank", &BANK), ("gov", &GOV)]);
+        assert_eq!(
+            check_namespaces(&BAD_MULTI.namespaces()),
+            Err(TreeError::NamespaceCollision {
+                namespace: "bank/n".into(),
+            }),
+        );
+    }
+}
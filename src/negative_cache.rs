@@ -0,0 +1,151 @@
+use {
+    crate::{
+        Blake3, GetResponse, HashableValue, JsonCodec, NodeCodec, Tree, TreeError, TreeHasher,
+    },
+    cosmwasm_std::{Binary, Storage},
+    serde::{de::DeserializeOwned, ser::Serialize},
+    std::collections::{BTreeMap, HashMap},
+};
+
+/// Answers `get` queries at the latest version like `Tree::get`, remembering
+/// the keys found to be absent, along with their non-membership proofs. This is
+/// intended for off-chain proof servers, where repeated queries of the same
+/// missing keys (e.g. spam lookups) can dominate the workload.
+///
+/// Up to `capacity` keys are remembered; beyond that, the least recently
+/// queried one is forgotten. All are forgotten once a new version is committed,
+/// as the keys may have been inserted since.
+pub struct NegativeCache<'a, K, V, C = JsonCodec, H = Blake3> {
+    tree: &'a Tree<'a, K, V, C, H>,
+    capacity: usize,
+    // the version the remembered keys are absent in
+    version: Option<u64>,
+    // the proof of each key, if one has been requested, and when it was last
+    // queried
+    entries: HashMap<Vec<u8>, (Option<Binary>, u64)>,
+    // the keys ordered by when they were last queried, for eviction
+    recency: BTreeMap<u64, Vec<u8>>,
+    tick: u64,
+}
+
+impl<'a, K, V, C, H> NegativeCache<'a, K, V, C, H>
+where
+    K: Serialize + DeserializeOwned + Clone + PartialEq + AsRef<[u8]>,
+    V: Serialize + DeserializeOwned + Clone + PartialEq + HashableValue,
+    C: NodeCodec<K, V>,
+    H: TreeHasher,
+{
+    pub fn new(tree: &'a Tree<'a, K, V, C, H>, capacity: usize) -> Self {
+        assert!(capacity > 0, "capacity must be non-zero");
+        Self {
+            tree,
+            capacity,
+            version: None,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    /// Same as `Tree::get` at the latest version, except that keys remembered
+    /// to be absent are answered without loading any node.
+    pub fn get(&mut self, store: &dyn Storage, key: &K, prove: bool) -> Result<GetResponse<K, V>> {
+        let version = self.tree.version.load(store)?;
+        if self.version != Some(version) {
+            self.entries.clear();
+            self.recency.clear();
+            self.version = Some(version);
+        }
+
+        self.tick += 1;
+        if let Some((proof, last_queried)) = self.entries.get_mut(key.as_ref()) {
+            // the key may have been remembered without a proof
+            if !prove || proof.is_some() {
+                self.recency.remove(last_queried);
+                self.recency.insert(self.tick, key.as_ref().to_vec());
+                *last_queried = self.tick;
+                return Ok(GetResponse {
+                    key: key.clone(),
+                    value: None,
+                    proof: proof.clone().filter(|_| prove),
+                });
+            }
+        }
+
+        let res = self.tree.get(store, key, prove, Some(version))?;
+        if res.value.is_none() {
+            self.remember(key.as_ref().to_vec(), res.proof.clone());
+        }
+
+        Ok(res)
+    }
+
+    fn remember(&mut self, key: Vec<u8>, proof: Option<Binary>) {
+        if let Some((_, last_queried)) = self.entries.remove(&key) {
+            self.recency.remove(&last_queried);
+        } else if self.entries.len() >= self.capacity {
+            if let Some((_, evicted)) = self.recency.pop_first() {
+                self.entries.remove(&evicted);
+            }
+        }
+
+        self.recency.insert(self.tick, key.clone());
+        self.entries.insert(key, (proof, self.tick));
+    }
+}
+
+type Result<T> = std::result::Result<T, TreeError>;
+
+// ----------------------------------- tests -----------------------------------
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::{Batch, GasMeter, NegativeCache, Op, Tree},
+        cosmwasm_std::testing::MockStorage,
+    };
+
+    const TREE: Tree<Vec<u8>, Vec<u8>> = Tree::new_default();
+
+    fn insert(store: &mut GasMeter<MockStorage>, key: &[u8]) {
+        let batch = Batch::from([(key.to_vec(), Op::Insert(b"value".to_vec()))]);
+        TREE.apply(store, batch).unwrap();
+    }
+
+    #[test]
+    fn caching_absent_keys() {
+        let mut store = GasMeter::new(MockStorage::new());
+        insert(&mut store, b"foo");
+        let mut cache = NegativeCache::new(&TREE, 2);
+
+        // reads needed to answer a query, besides that of the version
+        let node_reads = |cache: &mut NegativeCache<_, _>, key: &[u8], prove| {
+            store.take_report();
+            let res = cache.get(&store, &key.to_vec(), prove).unwrap();
+            assert_eq!(res.proof.is_some(), prove);
+            store.take_report().reads - 1
+        };
+
+        assert!(node_reads(&mut cache, b"bar", false) > 0);
+        assert_eq!(node_reads(&mut cache, b"bar", false), 0);
+        // the proof wasn't requested at first, so it's yet to be generated
+        assert!(node_reads(&mut cache, b"bar", true) > 0);
+        assert_eq!(node_reads(&mut cache, b"bar", true), 0);
+
+        // existing keys are not remembered
+        assert!(node_reads(&mut cache, b"foo", true) > 0);
+        assert!(node_reads(&mut cache, b"foo", true) > 0);
+
+        // the least recently queried key is forgotten first
+        node_reads(&mut cache, b"baz", true);
+        node_reads(&mut cache, b"bar", true);
+        node_reads(&mut cache, b"qux", true);
+        assert_eq!(node_reads(&mut cache, b"bar", true), 0);
+        assert!(node_reads(&mut cache, b"baz", true) > 0);
+
+        // all are forgotten once a new version is committed
+        insert(&mut store, b"bar");
+        let res = cache.get(&store, &b"bar".to_vec(), true).unwrap();
+        assert_eq!(res.value, Some(b"value".to_vec()));
+    }
+}
@@ -0,0 +1,120 @@
+use {
+    crate::{JsonCodec, Node, NodeCodec, NodeKey},
+    cosmwasm_std::{Empty, Order, StdError, StdResult, Storage},
+    cw_storage_plus::{Bound, Key, KeyDeserialize, Path, Prefix, PrimaryKey},
+    serde::{de::DeserializeOwned, ser::Serialize},
+    std::{any::type_name, marker::PhantomData},
+};
+
+type NodeRecord<K, V> = (NodeKey, Node<K, V>);
+
+/// Similar to cw-storage-plus' `Map<&NodeKey, Node<K, V>>`, but records are
+/// encoded by a `NodeCodec` instead of always using JSON.
+pub struct NodeMap<'a, K, V, C = JsonCodec> {
+    namespace: &'a [u8],
+    node_type: PhantomData<Node<K, V>>,
+    codec_type: PhantomData<C>,
+}
+
+impl<'a, K, V, C> NodeMap<'a, K, V, C> {
+    pub const fn new(namespace: &'a str) -> Self {
+        NodeMap {
+            namespace: namespace.as_bytes(),
+            node_type: PhantomData,
+            codec_type: PhantomData,
+        }
+    }
+
+    pub fn namespace(&self) -> &'a [u8] {
+        self.namespace
+    }
+
+    /// The number of bytes the node takes up in storage, if it exists.
+    pub fn encoded_len(&self, store: &dyn Storage, node_key: &NodeKey) -> Option<usize> {
+        store.get(&self.key(node_key)).map(|bytes| bytes.len())
+    }
+
+    fn key(&self, node_key: &NodeKey) -> Path<Empty> {
+        Path::new(
+            self.namespace,
+            &node_key.key().iter().map(Key::as_ref).collect::<Vec<_>>(),
+        )
+    }
+}
+
+impl<'a, K, V, C> NodeMap<'a, K, V, C>
+where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+    C: NodeCodec<K, V>,
+{
+    fn no_prefix(&self) -> Prefix<&'a NodeKey, Node<K, V>, &'a NodeKey> {
+        Prefix::with_deserialization_functions(
+            self.namespace,
+            &[],
+            &[],
+            |_, _, (k, v)| Ok((<&NodeKey>::from_vec(k)?, C::decode(&v)?)),
+            |_, _, (k, v)| Ok((k, C::decode(&v)?)),
+        )
+    }
+
+    pub fn save(
+        &self,
+        store: &mut dyn Storage,
+        node_key: &NodeKey,
+        node: &Node<K, V>,
+    ) -> StdResult<()> {
+        store.set(&self.key(node_key), &C::encode(node)?);
+        Ok(())
+    }
+
+    pub fn remove(&self, store: &mut dyn Storage, node_key: &NodeKey) {
+        store.remove(&self.key(node_key))
+    }
+
+    pub fn may_load(
+        &self,
+        store: &dyn Storage,
+        node_key: &NodeKey,
+    ) -> StdResult<Option<Node<K, V>>> {
+        store.get(&self.key(node_key)).map(|bytes| C::decode(&bytes)).transpose()
+    }
+
+    pub fn load(&self, store: &dyn Storage, node_key: &NodeKey) -> StdResult<Node<K, V>> {
+        self.may_load(store, node_key)?
+            .ok_or_else(|| StdError::not_found(type_name::<Node<K, V>>()))
+    }
+
+    /// Same as `may_load`, but the node's value, if any, isn't decoded.
+    pub fn may_load_without_value(
+        &self,
+        store: &dyn Storage,
+        node_key: &NodeKey,
+    ) -> StdResult<Option<Node<K, ()>>> {
+        store.get(&self.key(node_key)).map(|bytes| C::decode_without_value(&bytes)).transpose()
+    }
+
+    /// Same as `load`, but the node's value, if any, isn't decoded.
+    pub fn load_without_value(
+        &self,
+        store: &dyn Storage,
+        node_key: &NodeKey,
+    ) -> StdResult<Node<K, ()>> {
+        self.may_load_without_value(store, node_key)?
+            .ok_or_else(|| StdError::not_found(type_name::<Node<K, V>>()))
+    }
+
+    pub fn range<'c>(
+        &self,
+        store: &'c dyn Storage,
+        min: Option<Bound<'a, &'a NodeKey>>,
+        max: Option<Bound<'a, &'a NodeKey>>,
+        order: Order,
+    ) -> Box<dyn Iterator<Item = StdResult<NodeRecord<K, V>>> + 'c>
+    where
+        K: 'c,
+        V: 'c,
+    {
+        self.no_prefix().range(store, min, max, order)
+    }
+}
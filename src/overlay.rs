@@ -0,0 +1,282 @@
+use {
+    crate::{
+        apply_subtree, tree::next_version, Batch, Blake3, CollapsePolicy, Hash, HashableValue,
+        JsonCodec, Node, NodeCodec, NodeKey, NodeSource, Op, OpResponse, Tree, TreeError,
+        TreeHasher,
+    },
+    cosmwasm_std::Storage,
+    serde::{de::DeserializeOwned, ser::Serialize},
+    std::collections::HashMap,
+};
+
+/// Layers uncommitted changes over a version of a `Tree`, without writing
+/// anything to storage.
+///
+/// Reads fall through to the parent overlay (if any), and eventually to the
+/// tree; writes stay in memory. The root hash that the tree would have if the
+/// changes were committed can be computed on demand.
+///
+/// This is intended for speculative execution, e.g. simulating candidate blocks
+/// in the mempool against the last committed state. To commit the changes, use
+/// `into_batch` and apply the batch to the tree.
+///
+/// Overlays can be nested using `nest`. To merge a child overlay's changes into
+/// its parent, drop the child by `into_batch` and `extend` the parent with it.
+pub struct Overlay<'a, K, V, C = JsonCodec, H = Blake3> {
+    tree: &'a Tree<'a, K, V, C, H>,
+    store: &'a dyn Storage,
+    parent: Option<&'a Overlay<'a, K, V, C, H>>,
+    version: u64,
+    // under canonical keys, if the tree has a normalizer, so that keys that
+    // only differ in form overwrite each other as they would in the tree. they
+    // are not mapped by the tree's key transform until the root is computed,
+    // so that the batch can still be applied to the tree
+    pending: Batch<K, V>,
+}
+
+impl<'a, K, V, C, H> Overlay<'a, K, V, C, H>
+where
+    K: Serialize + DeserializeOwned + Clone + Ord + AsRef<[u8]>,
+    V: Serialize + DeserializeOwned + Clone + PartialEq + HashableValue,
+    C: NodeCodec<K, V>,
+    H: TreeHasher,
+{
+    /// Create an overlay over the given version of the tree. `version` of zero
+    /// means the tree is empty.
+    pub fn new(tree: &'a Tree<'a, K, V, C, H>, store: &'a dyn Storage, version: u64) -> Self {
+        Self {
+            tree,
+            store,
+            parent: None,
+            version,
+            pending: Batch::new(),
+        }
+    }
+
+    /// Create a child overlay, whose reads fall through to this one.
+    pub fn nest(&self) -> Overlay<'_, K, V, C, H> {
+        Overlay {
+            tree: self.tree,
+            store: self.store,
+            parent: Some(self),
+            version: self.version,
+            pending: Batch::new(),
+        }
+    }
+
+    /// The version of the tree this overlay is layered on.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        self.pending.insert(self.tree.canonical_owned_key(key), Op::Insert(value));
+    }
+
+    pub fn delete(&mut self, key: K) {
+        self.pending.insert(self.tree.canonical_owned_key(key), Op::Delete);
+    }
+
+    /// Add a batch of ops to the overlay. Ops in the batch overwrite existing
+    /// pending ops under the same keys.
+    pub fn extend(&mut self, batch: Batch<K, V>) {
+        for (key, op) in batch {
+            self.pending.insert(self.tree.canonical_owned_key(key), op);
+        }
+    }
+
+    /// Consume the overlay, returning its own pending ops (excluding those of
+    /// its parents).
+    pub fn into_batch(self) -> Batch<K, V> {
+        self.pending
+    }
+
+    pub fn get(&self, key: &K) -> Result<Option<V>> {
+        if let Some(op) = self.pending.get(&*self.tree.canonical_key(key)) {
+            return match op {
+                Op::Insert(value) => Ok(Some(value.clone())),
+                Op::Delete => Ok(None),
+            };
+        }
+
+        if let Some(parent) = self.parent {
+            return parent.get(key);
+        }
+
+        if self.version == 0 {
+            return Ok(None);
+        }
+
+        self.tree.get(self.store, key, false, Some(self.version)).map(|res| res.value)
+    }
+
+    /// Compute the root hash the tree would have if the pending ops of this
+    /// overlay and all of its parents were committed. `None` if the tree would
+    /// be empty.
+    pub fn root_hash(&self) -> Result<Option<Hash>> {
+        let batch = self.tree.normalize_batch(self.merged_batch())?;
+        if batch.is_empty() {
+            return self.base_root_hash();
+        }
+
+        let new_version = next_version(self.version)?;
+        let mut nodes = OverlayNodes {
+            tree: self.tree,
+            store: self.store,
+            new_version,
+            written: HashMap::new(),
+        };
+
+        let root_node_key = NodeKey::root(self.version);
+        match apply_subtree(&mut nodes, new_version, &root_node_key, None, &batch)? {
+            OpResponse::Updated(root_node) => Ok(Some(self.tree.root_hash(&root_node))),
+            OpResponse::Deleted => Ok(None),
+            OpResponse::Unchanged => self.base_root_hash(),
+        }
+    }
+
+    // the pending ops of this overlay, on top of those of all of its parents
+    fn merged_batch(&self) -> Batch<K, V> {
+        let mut batch = self.parent.map(Overlay::merged_batch).unwrap_or_default();
+        batch.extend(self.pending.clone());
+        batch
+    }
+
+    fn base_root_hash(&self) -> Result<Option<Hash>> {
+        let root_node = self.tree.nodes.may_load(self.store, &NodeKey::root(self.version))?;
+        Ok(root_node.map(|node| self.tree.root_hash(&node)))
+    }
+}
+
+// a source of nodes where reads fall through to the tree, but writes stay in
+// memory
+struct OverlayNodes<'a, K, V, C, H> {
+    tree: &'a Tree<'a, K, V, C, H>,
+    store: &'a dyn Storage,
+    new_version: u64,
+    written: HashMap<NodeKey, Node<K, V>>,
+}
+
+impl<'a, K, V, C, H> NodeSource<K, V> for OverlayNodes<'a, K, V, C, H>
+where
+    K: Serialize + DeserializeOwned + Clone,
+    V: Serialize + DeserializeOwned + Clone,
+    C: NodeCodec<K, V>,
+    H: TreeHasher,
+{
+    fn load(&self, node_key: &NodeKey) -> Result<Option<Node<K, V>>> {
+        if let Some(node) = self.written.get(node_key) {
+            return Ok(Some(node.clone()));
+        }
+
+        // if the overlay is layered on an older version, the tree may already
+        // contain nodes under the version we're writing to. these must not be
+        // mistaken as ours.
+        if node_key.version >= self.new_version {
+            return Ok(None);
+        }
+
+        self.tree.nodes.may_load(self.store, node_key).map_err(Into::into)
+    }
+
+    fn save(&mut self, node_key: NodeKey, node: Node<K, V>) -> Result<()> {
+        self.written.insert(node_key, node);
+        Ok(())
+    }
+
+    fn mark_orphaned(&mut self, _: u64, _: &NodeKey) -> Result<()> {
+        // nothing is pruned from an overlay, so no need to keep track of this
+        Ok(())
+    }
+
+    fn aggregation(&self) -> Option<fn(&K, &V) -> u128> {
+        self.tree.aggregation
+    }
+
+    fn collapse_policy(&self) -> CollapsePolicy {
+        self.tree.collapse_policy
+    }
+
+    fn hash(&self, node: &Node<K, V>) -> Hash
+    where
+        K: AsRef<[u8]>,
+        V: HashableValue,
+    {
+        node.hash_using::<H>()
+    }
+}
+
+type Result<T> = std::result::Result<T, TreeError>;
+
+// ----------------------------------- tests -----------------------------------
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::{Batch, Op, Overlay, Tree},
+        cosmwasm_std::testing::MockStorage,
+    };
+
+    const TREE: Tree<String, String> = Tree::new_default();
+
+    fn batch(ops: &[(&str, Option<&str>)]) -> Batch<String, String> {
+        ops.iter()
+            .map(|(key, value)| {
+                let op = value.map(|v| Op::Insert(v.to_string())).unwrap_or(Op::Delete);
+                (key.to_string(), op)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn overlay_matches_committed_tree() {
+        let first = batch(&[
+            ("food", Some("ramen")),
+            ("fuzz", Some("buzz")),
+            ("larry", Some("engineer")),
+        ]);
+        let second = batch(&[("fuzz", None), ("pumpkin", Some("cat"))]);
+        let third = batch(&[("food", Some("sushi")), ("satoshi", Some("nakamoto"))]);
+
+        let mut store = MockStorage::new();
+        TREE.apply(&mut store, first.clone()).unwrap();
+
+        let mut overlay = Overlay::new(&TREE, &store, 1);
+        overlay.extend(second.clone());
+        assert_eq!(overlay.get(&"fuzz".into()).unwrap(), None);
+        assert_eq!(overlay.get(&"food".into()).unwrap(), Some("ramen".into()));
+
+        let mut nested = overlay.nest();
+        nested.extend(third.clone());
+        assert_eq!(nested.get(&"food".into()).unwrap(), Some("sushi".into()));
+        assert_eq!(nested.get(&"pumpkin".into()).unwrap(), Some("cat".into()));
+
+        let overlay_root = overlay.root_hash().unwrap();
+        let nested_root = nested.root_hash().unwrap();
+
+        // the base tree must not have been touched
+        assert_eq!(TREE.root(&store, None).unwrap().version, 1);
+
+        let mut expected_store = MockStorage::new();
+        TREE.apply(&mut expected_store, first).unwrap();
+        TREE.apply(&mut expected_store, second).unwrap();
+        assert_eq!(overlay_root, Some(TREE.root(&expected_store, None).unwrap().root_hash));
+        TREE.apply(&mut expected_store, third).unwrap();
+        assert_eq!(nested_root, Some(TREE.root(&expected_store, None).unwrap().root_hash));
+    }
+
+    #[test]
+    fn overlay_over_empty_tree() {
+        let store = MockStorage::new();
+
+        let mut overlay = Overlay::new(&TREE, &store, 0);
+        assert_eq!(overlay.root_hash().unwrap(), None);
+        assert_eq!(overlay.get(&"food".into()).unwrap(), None);
+
+        overlay.insert("food".into(), "ramen".into());
+        assert!(overlay.root_hash().unwrap().is_some());
+
+        overlay.delete("food".into());
+        assert_eq!(overlay.root_hash().unwrap(), None);
+    }
+}
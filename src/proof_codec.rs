@@ -0,0 +1,262 @@
+use {
+    crate::{Hash, Nibble, Proof, ProofChild, ProofNode, Record, HASH_LEN},
+    cosmwasm_std::{from_slice, to_vec, StdError, StdResult},
+    serde::{de::DeserializeOwned, ser::Serialize},
+    std::any::type_name,
+};
+
+/// Format version of proofs encoded by `ProofBytes::to_bytes`.
+pub const PROOF_FORMAT: u8 = 1;
+
+// proofs encoded as JSON are arrays, which always start with this byte
+const JSON_PREFIX: u8 = b'[';
+
+// flags of a proof node, telling which of its optional fields follow
+const HAS_DATA: u8 = 0x01;
+const HAS_AGGREGATE: u8 = 0x02;
+const HAS_VERSION: u8 = 0x04;
+
+/// Encodes a `Proof` into compact bytes, as an alternative to JSON, which is
+/// several times larger as it spells out field names and hex-encodes hashes.
+/// The layout is:
+///
+/// ```plain
+/// format (1 byte) || num_nodes (4 bytes) || node for each node
+///
+/// node := flags (1 byte) || num_children (1 byte)
+///     || (index (1 byte) || hash (HASH_LEN bytes)) for each child
+///     || len(key) (4 bytes) || key || len(value) (4 bytes) || value  -- if has data
+///     || aggregate (16 bytes)                                        -- if has aggregate
+///     || version (8 bytes)                                           -- if has version
+/// ```
+///
+/// where integers are in big endian encoding, keys and values are encoded as
+/// JSON, as in `encode_batch`, and `flags` tell which of the optional fields
+/// are present. Nodes are in the same order as in the proof.
+///
+/// Use `Tree::with_proof_encoding` to have queries return proofs encoded this
+/// way, and `decode_proof` to decode proofs of either encoding.
+pub trait ProofBytes: Sized {
+    fn to_bytes(&self) -> StdResult<Vec<u8>>;
+
+    /// Errors unless the bytes are exactly those `to_bytes` produces for the
+    /// decoded proof.
+    fn from_bytes(bytes: &[u8]) -> StdResult<Self>;
+}
+
+impl<K, V> ProofBytes for Proof<K, V>
+where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+{
+    fn to_bytes(&self) -> StdResult<Vec<u8>> {
+        let mut bytes = vec![PROOF_FORMAT];
+        bytes.extend(len_bytes::<K, V>(self.len())?);
+
+        for node in self {
+            let flags = node.data.as_ref().map_or(0, |_| HAS_DATA)
+                | node.aggregate.map_or(0, |_| HAS_AGGREGATE)
+                | node.version.map_or(0, |_| HAS_VERSION);
+            bytes.push(flags);
+
+            bytes.push(node.children.len() as u8);
+            for child in &node.children {
+                bytes.push(child.index.byte());
+                bytes.extend(child.hash.as_bytes());
+            }
+
+            if let Some(Record { key, value }) = &node.data {
+                extend_prefixed::<K, V>(&mut bytes, &to_vec(key)?)?;
+                extend_prefixed::<K, V>(&mut bytes, &to_vec(value)?)?;
+            }
+            if let Some(aggregate) = node.aggregate {
+                bytes.extend(aggregate.to_be_bytes());
+            }
+            if let Some(version) = node.version {
+                bytes.extend(version.to_be_bytes());
+            }
+        }
+
+        Ok(bytes)
+    }
+
+    fn from_bytes(bytes: &[u8]) -> StdResult<Self> {
+        let mut rest = bytes;
+        match take::<K, V>(&mut rest, 1)?[0] {
+            PROOF_FORMAT => (),
+            version => return Err(parse_err::<K, V>(format!("unknown proof format: {version}"))),
+        }
+
+        let num_nodes = take_len::<K, V>(&mut rest)?;
+        let mut proof = vec![];
+        for _ in 0..num_nodes {
+            let flags = take::<K, V>(&mut rest, 1)?[0];
+            if flags & !(HAS_DATA | HAS_AGGREGATE | HAS_VERSION) != 0 {
+                return Err(parse_err::<K, V>("invalid proof node flags"));
+            }
+
+            let num_children = take::<K, V>(&mut rest, 1)?[0];
+            if num_children > 16 {
+                return Err(parse_err::<K, V>("proof node has more than 16 children"));
+            }
+            let mut children = vec![];
+            for _ in 0..num_children {
+                children.push(ProofChild {
+                    index: Nibble::try_new(take::<K, V>(&mut rest, 1)?[0])?,
+                    hash: Hash::try_from(take::<K, V>(&mut rest, HASH_LEN)?).unwrap(),
+                });
+            }
+
+            let data = if flags & HAS_DATA != 0 {
+                let key_len = take_len::<K, V>(&mut rest)?;
+                let key = from_slice(take::<K, V>(&mut rest, key_len)?)?;
+                let value_len = take_len::<K, V>(&mut rest)?;
+                let value = from_slice(take::<K, V>(&mut rest, value_len)?)?;
+                Some(Record { key, value })
+            } else {
+                None
+            };
+            let aggregate = if flags & HAS_AGGREGATE != 0 {
+                Some(u128::from_be_bytes(take::<K, V>(&mut rest, 16)?.try_into().unwrap()))
+            } else {
+                None
+            };
+            let version = if flags & HAS_VERSION != 0 {
+                Some(u64::from_be_bytes(take::<K, V>(&mut rest, 8)?.try_into().unwrap()))
+            } else {
+                None
+            };
+
+            proof.push(ProofNode {
+                children,
+                data,
+                aggregate,
+                version,
+            });
+        }
+
+        // this also rejects trailing bytes, and JSON that isn't in its
+        // canonical form
+        if proof.to_bytes()? != bytes {
+            return Err(parse_err::<K, V>("proof is not canonically encoded"));
+        }
+
+        Ok(proof)
+    }
+}
+
+/// Decode a proof that was encoded either as JSON or by `ProofBytes::to_bytes`,
+/// telling them apart by the first byte.
+pub fn decode_proof<K, V>(bytes: &[u8]) -> StdResult<Proof<K, V>>
+where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+{
+    match bytes.first() {
+        Some(&JSON_PREFIX) => from_slice(bytes),
+        _ => Proof::from_bytes(bytes),
+    }
+}
+
+fn extend_prefixed<K, V>(bytes: &mut Vec<u8>, data: &[u8]) -> StdResult<()> {
+    bytes.extend(len_bytes::<K, V>(data.len())?);
+    bytes.extend(data);
+    Ok(())
+}
+
+fn len_bytes<K, V>(len: usize) -> StdResult<[u8; 4]> {
+    u32::try_from(len)
+        .map(u32::to_be_bytes)
+        .map_err(|_| StdError::serialize_err(type_name::<Proof<K, V>>(), "length exceeds u32"))
+}
+
+fn take_len<K, V>(bytes: &mut &[u8]) -> StdResult<usize> {
+    let len = u32::from_be_bytes(take::<K, V>(bytes, 4)?.try_into().unwrap());
+    Ok(len as usize)
+}
+
+// split the first `n` bytes off the slice, or error if there aren't enough
+fn take<'a, K, V>(bytes: &mut &'a [u8], n: usize) -> StdResult<&'a [u8]> {
+    if bytes.len() < n {
+        return Err(parse_err::<K, V>("unexpected end of proof"));
+    }
+
+    let (taken, rest) = bytes.split_at(n);
+    *bytes = rest;
+    Ok(taken)
+}
+
+fn parse_err<K, V>(msg: impl ToString) -> StdError {
+    StdError::parse_err(type_name::<Proof<K, V>>(), msg)
+}
+
+// ----------------------------------- tests -----------------------------------
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{decode_proof, ProofBytes},
+        crate::{Hash, Nibble, Proof, ProofChild, ProofNode, Record, HASH_LEN},
+        cosmwasm_std::to_vec,
+    };
+
+    fn mock_proof() -> Proof<String, String> {
+        vec![
+            ProofNode {
+                children: vec![ProofChild {
+                    index: Nibble::new(3),
+                    hash: Hash::from([1; HASH_LEN]),
+                }],
+                data: Some(Record {
+                    key: "foo".to_string(),
+                    value: "bar".to_string(),
+                }),
+                aggregate: None,
+                version: Some(2),
+            },
+            ProofNode {
+                children: vec![],
+                data: None,
+                aggregate: Some(5),
+                version: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn encoding_proofs() {
+        let proof = mock_proof();
+        let bytes = proof.to_bytes().unwrap();
+        assert_eq!(Proof::from_bytes(&bytes).unwrap(), proof);
+        assert!(bytes.len() * 2 < to_vec(&proof).unwrap().len());
+
+        // either encoding can be decoded
+        assert_eq!(decode_proof::<String, String>(&bytes).unwrap(), proof);
+        assert_eq!(decode_proof::<String, String>(&to_vec(&proof).unwrap()).unwrap(), proof);
+
+        let empty: Proof<String, String> = vec![];
+        assert_eq!(empty.to_bytes().unwrap(), [1, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn rejecting_non_canonical_proofs() {
+        let bytes = mock_proof().to_bytes().unwrap();
+
+        // truncated, or with trailing bytes
+        assert!(Proof::<String, String>::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Proof::<String, String>::from_bytes(&[&bytes[..], &[0]].concat()).is_err());
+
+        // unknown flags, or a child index that isn't a nibble
+        let mut flagged = bytes.clone();
+        flagged[5] |= 0x80;
+        assert!(Proof::<String, String>::from_bytes(&flagged).is_err());
+        let mut indexed = bytes.clone();
+        indexed[7] = 0x10;
+        assert!(Proof::<String, String>::from_bytes(&indexed).is_err());
+
+        // an unknown format
+        let mut versioned = bytes;
+        versioned[0] = 2;
+        assert!(Proof::<String, String>::from_bytes(&versioned).is_err());
+    }
+}
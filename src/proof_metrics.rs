@@ -0,0 +1,126 @@
+use {
+    crate::{Tree, TreeError},
+    cosmwasm_schema::cw_serde,
+    cosmwasm_std::{from_slice, Binary, Storage},
+    serde::de::IgnoredAny,
+};
+
+/// Histograms of the sizes of the proofs recorded using `Tree::record_proof`.
+/// See `Tree::with_proof_metrics`.
+///
+/// A proof has one node per level of the tree it passes through, so a shift
+/// towards larger proofs indicates that the tree has grown deeper, e.g. due to
+/// keys crafted to share long prefixes.
+#[cw_serde]
+#[derive(Default)]
+pub struct ProofMetrics {
+    /// The number of proofs recorded
+    pub proofs: u64,
+    /// The number of proofs by their number of nodes, which is the index
+    pub nodes: Vec<u64>,
+    /// The number of proofs by their serialized size, in buckets of powers of
+    /// two: the `i`-th bucket counts proofs of more than `2^(i-1)` and up to
+    /// `2^i` bytes
+    pub bytes: Vec<u64>,
+    /// The serialized size of the largest proof recorded
+    pub max_bytes: u64,
+}
+
+impl ProofMetrics {
+    fn record(&mut self, num_nodes: usize, num_bytes: usize) {
+        self.proofs += 1;
+        increment_bucket(&mut self.nodes, num_nodes);
+        increment_bucket(&mut self.bytes, num_bytes.next_power_of_two().trailing_zeros() as usize);
+        self.max_bytes = self.max_bytes.max(num_bytes as u64);
+    }
+}
+
+impl<'a, K, V, C, H> Tree<'a, K, V, C, H> {
+    /// Record the size of a proof returned by `get`, `get_many` or `aggregate`
+    /// in the histograms of proof sizes, if `with_proof_metrics` is enabled.
+    ///
+    /// Queries can't write to storage, so this is up to whoever serves the
+    /// proofs, e.g. a node's query server, or an execute handler that returns
+    /// proofs to other contracts.
+    pub fn record_proof(&self, store: &mut dyn Storage, proof: &Binary) -> Result<()> {
+        let Some(proof_metrics) = &self.proof_metrics else {
+            return Ok(());
+        };
+
+        // these proofs are all lists of nodes
+        let num_nodes = from_slice::<Vec<IgnoredAny>>(proof)?.len();
+
+        let mut metrics = proof_metrics.may_load(store)?.unwrap_or_default();
+        metrics.record(num_nodes, proof.len());
+        proof_metrics.save(store, &metrics).map_err(Into::into)
+    }
+
+    /// Return the histograms of the sizes of the proofs recorded. See
+    /// `with_proof_metrics`.
+    pub fn proof_metrics(&self, store: &dyn Storage) -> Result<ProofMetrics> {
+        let proof_metrics = self.proof_metrics.as_ref().ok_or(TreeError::ProofMetricsNotTracked)?;
+        Ok(proof_metrics.may_load(store)?.unwrap_or_default())
+    }
+}
+
+fn increment_bucket(buckets: &mut Vec<u64>, index: usize) {
+    if buckets.len() <= index {
+        buckets.resize(index + 1, 0);
+    }
+    buckets[index] += 1;
+}
+
+type Result<T> = std::result::Result<T, TreeError>;
+
+// ----------------------------------- tests -----------------------------------
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::{Batch, Op, Tree, TreeError},
+        cosmwasm_std::testing::MockStorage,
+    };
+
+    const TREE: Tree<String, String> = Tree::new_default().with_proof_metrics("m");
+
+    #[test]
+    fn recording_proof_sizes() {
+        let mut store = MockStorage::new();
+        let batch = Batch::from([
+            ("foo".to_string(), Op::Insert("bar".to_string())),
+            ("fuzz".to_string(), Op::Insert("buzz".to_string())),
+            ("larry".to_string(), Op::Insert("engineer".to_string())),
+        ]);
+        TREE.apply(&mut store, batch).unwrap();
+
+        // the keys all start with nibble 6. "larry" branches off at the second
+        // nibble, so its proof has 3 nodes, and "foo" at the third, so 4. the
+        // batch proof of "foo" and "fuzz" has both of their leaves, so 5
+        let mut sizes = vec![];
+        for key in ["foo", "larry"] {
+            let proof = TREE.get(&store, &key.to_string(), true, None).unwrap().proof.unwrap();
+            TREE.record_proof(&mut store, &proof).unwrap();
+            sizes.push(proof.len() as u64);
+        }
+        let keys = ["foo".to_string(), "fuzz".to_string()];
+        let proof = TREE.get_many(&store, &keys, true, None).unwrap().proof.unwrap();
+        TREE.record_proof(&mut store, &proof).unwrap();
+        sizes.push(proof.len() as u64);
+
+        let metrics = TREE.proof_metrics(&store).unwrap();
+        assert_eq!(metrics.proofs, 3);
+        assert_eq!(metrics.nodes, [0, 0, 0, 1, 1, 1]);
+        assert_eq!(metrics.bytes.iter().sum::<u64>(), 3);
+        assert_eq!(metrics.max_bytes, *sizes.iter().max().unwrap());
+        for size in sizes {
+            let bucket = size.next_power_of_two().trailing_zeros() as usize;
+            assert!(metrics.bytes[bucket] > 0);
+        }
+
+        // not a proof
+        assert!(TREE.record_proof(&mut store, &b"{}".into()).is_err());
+
+        let untracked: Tree<String, String> = Tree::new_default();
+        assert!(matches!(untracked.proof_metrics(&store), Err(TreeError::ProofMetricsNotTracked)));
+    }
+}
@@ -0,0 +1,186 @@
+use {
+    crate::{
+        GetResponse, HashableValue, NibblePath, Node, NodeCodec, NodeKey, ProverRequest, Tree,
+        TreeError, TreeHasher,
+    },
+    cosmwasm_std::{Order, Storage},
+    serde::{de::DeserializeOwned, ser::Serialize},
+    std::collections::HashMap,
+};
+
+/// The default maximum number of items returned for a range request.
+pub const DEFAULT_RANGE_LIMIT: u32 = 30;
+
+/// Answer many queries against the same version of the tree in one pass, e.g.
+/// for an RPC backend serving proofs. Nodes loaded while answering one query
+/// are reused for the rest, so proofs for nearby keys, which share most of
+/// their nodes, are cheap.
+///
+/// Responses are in the same order as the requests. A `Get` request results in
+/// one response; a `Range` request results in one response for each key-value
+/// pair in the range, up to the limit.
+pub fn prove_queries<K, V, C, S, H>(
+    tree: &Tree<K, V, C, H>,
+    store: &S,
+    requests: Vec<ProverRequest<K>>,
+    version: Option<u64>,
+) -> Result<Vec<GetResponse<K, V>>>
+where
+    K: Serialize + DeserializeOwned + Clone + PartialEq + AsRef<[u8]>,
+    V: Serialize + DeserializeOwned + Clone + PartialEq + HashableValue,
+    C: NodeCodec<K, V>,
+    H: TreeHasher,
+    S: Storage,
+{
+    let version = tree.version_or_default(store, version)?;
+    let mut prover = Prover {
+        tree,
+        store,
+        version,
+        cache: HashMap::new(),
+    };
+
+    let mut responses = vec![];
+    for request in requests {
+        match request {
+            ProverRequest::Get {
+                key,
+                prove,
+            } => {
+                responses.push(prover.get(key, prove)?);
+            },
+            ProverRequest::Range {
+                min,
+                max,
+                limit,
+                prove,
+            } => {
+                let limit = limit.unwrap_or(DEFAULT_RANGE_LIMIT) as usize;
+                let items = tree
+                    .iterate(store, Order::Ascending, min.as_ref(), max.as_ref(), Some(version))?
+                    .take(limit)
+                    .collect::<Result<Vec<_>>>()?;
+
+                for (key, value) in items {
+                    // no need to walk the tree again if no proof is requested
+                    if prove {
+                        responses.push(prover.get(key, true)?);
+                    } else {
+                        responses.push(GetResponse {
+                            key,
+                            value: Some(value),
+                            proof: None,
+                        });
+                    }
+                }
+            },
+        }
+    }
+
+    Ok(responses)
+}
+
+struct Prover<'a, 'b, K, V, C, H> {
+    tree: &'b Tree<'a, K, V, C, H>,
+    store: &'b dyn Storage,
+    version: u64,
+    cache: HashMap<NodeKey, Option<Node<K, V>>>,
+}
+
+impl<'a, 'b, K, V, C, H> Prover<'a, 'b, K, V, C, H>
+where
+    K: Serialize + DeserializeOwned + Clone + PartialEq + AsRef<[u8]>,
+    V: Serialize + DeserializeOwned + Clone + PartialEq + HashableValue,
+    C: NodeCodec<K, V>,
+    H: TreeHasher,
+{
+    fn get(&mut self, key: K, prove: bool) -> Result<GetResponse<K, V>> {
+        let Self {
+            tree,
+            store,
+            cache,
+            ..
+        } = self;
+
+        let mut load = |node_key: &NodeKey| -> Result<Option<Node<K, V>>> {
+            if let Some(node) = cache.get(node_key) {
+                return Ok(node.clone());
+            }
+            let node = tree.nodes.may_load(*store, node_key)?;
+            cache.insert(node_key.clone(), node.clone());
+            Ok(node)
+        };
+
+        let key = tree.normalize_owned_key(key);
+        let nibble_path = NibblePath::from(&key);
+        let (value, proof) = tree.get_at_with(
+            *store,
+            &mut load,
+            NodeKey::root(self.version),
+            &mut nibble_path.nibbles(),
+            prove,
+        )?;
+
+        let proof = if prove {
+            Some(tree.encode_proof(&proof)?)
+        } else {
+            None
+        };
+
+        Ok(GetResponse {
+            key,
+            value,
+            proof,
+        })
+    }
+}
+
+type Result<T> = std::result::Result<T, TreeError>;
+
+// ----------------------------------- tests -----------------------------------
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::{prove_queries, Batch, Op, ProverRequest, Tree},
+        cosmwasm_std::testing::MockStorage,
+    };
+
+    const TREE: Tree<String, String> = Tree::new_default();
+
+    #[test]
+    fn proving_mixed_queries() {
+        let mut store = MockStorage::new();
+        let batch: Batch<_, _> = [("food", "ramen"), ("fuzz", "buzz"), ("larry", "engineer")]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), Op::Insert(v.to_string())))
+            .collect();
+        TREE.apply(&mut store, batch).unwrap();
+
+        let requests = vec![
+            ProverRequest::Get {
+                key: "fuzz".to_string(),
+                prove: true,
+            },
+            ProverRequest::Get {
+                key: "foo".to_string(),
+                prove: false,
+            },
+            ProverRequest::Range {
+                min: Some("f".to_string()),
+                max: Some("g".to_string()),
+                limit: None,
+                prove: true,
+            },
+        ];
+        let responses = prove_queries(&TREE, &store, requests, None).unwrap();
+
+        // the responses should be identical to those of individual queries
+        let expected = ["fuzz", "foo", "food", "fuzz"]
+            .into_iter()
+            .zip([true, false, true, true])
+            .map(|(key, prove)| TREE.get(&store, &key.to_string(), prove, None).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(responses, expected);
+    }
+}
@@ -0,0 +1,151 @@
+use {
+    crate::KvStore,
+    cosmwasm_std::{Order, Record},
+    rocksdb::{IteratorMode, Options, ReadOptions, DB},
+    std::path::Path,
+};
+
+/// A `KvStore` backed by RocksDB, so that node operators can run a tree as a
+/// standalone state commitment database. Wrap it in `KvStorage` to use it as
+/// the tree's storage:
+///
+/// ```rust,ignore
+/// let mut store = KvStorage(RocksStore::open("./data")?);
+/// TREE.apply(&mut store, batch)?;
+/// ```
+///
+/// Keys are compared by RocksDB's default bytewise comparator, so records are
+/// scanned in the same order as in `MockStorage`.
+///
+/// Every write is committed to RocksDB on its own, so a crash while applying a
+/// batch leaves the new version partially written. Call `Tree::recover` when
+/// opening the database to clean it up.
+pub struct RocksStore {
+    db: DB,
+}
+
+impl RocksStore {
+    /// Open the database at the given path, creating it if it doesn't exist.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, rocksdb::Error> {
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        DB::open(&opts, path).map(Self::new)
+    }
+
+    /// Use a database opened with custom options. It must use the default
+    /// comparator.
+    pub fn new(db: DB) -> Self {
+        Self {
+            db,
+        }
+    }
+
+    pub fn db(&self) -> &DB {
+        &self.db
+    }
+
+    pub fn into_db(self) -> DB {
+        self.db
+    }
+}
+
+// as `KvStore` can't return errors, those of RocksDB cause a panic
+impl KvStore for RocksStore {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.db.get(key).unwrap_or_else(|err| panic!("failed to read from RocksDB: {err}"))
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8]) {
+        self.db.put(key, value).unwrap_or_else(|err| panic!("failed to write to RocksDB: {err}"));
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        self.db.delete(key).unwrap_or_else(|err| panic!("failed to write to RocksDB: {err}"));
+    }
+
+    fn scan<'a>(
+        &'a self,
+        min: Option<&[u8]>,
+        max: Option<&[u8]>,
+        order: Order,
+    ) -> Box<dyn Iterator<Item = Record> + 'a> {
+        if let (Some(min), Some(max)) = (min, max) {
+            if min >= max {
+                return Box::new(std::iter::empty());
+            }
+        }
+
+        // the lower bound is inclusive and the upper bound exclusive, as those
+        // of `KvStore::scan`
+        let mut opts = ReadOptions::default();
+        if let Some(min) = min {
+            opts.set_iterate_lower_bound(min);
+        }
+        if let Some(max) = max {
+            opts.set_iterate_upper_bound(max);
+        }
+
+        let mode = match order {
+            Order::Ascending => IteratorMode::Start,
+            Order::Descending => IteratorMode::End,
+        };
+        Box::new(self.db.iterator_opt(mode, opts).map(|item| {
+            let (key, value) =
+                item.unwrap_or_else(|err| panic!("failed to read from RocksDB: {err}"));
+            (key.into_vec(), value.into_vec())
+        }))
+    }
+}
+
+// ----------------------------------- tests -----------------------------------
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::RocksStore,
+        crate::{Batch, KvStorage, Op, Tree},
+        cosmwasm_std::{testing::MockStorage, Order, Storage},
+        rand::{rngs::StdRng, Rng, SeedableRng},
+        rocksdb::{Options, DB},
+        std::env,
+    };
+
+    const TREE: Tree<Vec<u8>, Vec<u8>> = Tree::new_default();
+
+    #[test]
+    fn running_on_rocksdb() {
+        let path = env::temp_dir().join(format!("tree-rocks-store-{}", std::process::id()));
+        let mut store = KvStorage(RocksStore::open(&path).unwrap());
+        let mut mock_store = MockStorage::new();
+
+        let mut rng = StdRng::seed_from_u64(8);
+        for _ in 0..10 {
+            let batch: Batch<_, _> = (0..10)
+                .map(|_| (vec![rng.gen_range(0..8), rng.gen()], Op::Insert(vec![rng.gen()])))
+                .collect();
+            TREE.apply(&mut store, batch.clone()).unwrap();
+            TREE.apply(&mut mock_store, batch).unwrap();
+        }
+        TREE.prune(&mut store, None).unwrap();
+        TREE.prune(&mut mock_store, None).unwrap();
+
+        // the same records are stored, and scanned in the same order
+        for order in [Order::Ascending, Order::Descending] {
+            for (min, max) in [(None, None), (Some(&[0, 1][..]), Some(&[0, 1, b'z'][..]))] {
+                assert_eq!(
+                    store.range(min, max, order).collect::<Vec<_>>(),
+                    mock_store.range(min, max, order).collect::<Vec<_>>(),
+                );
+            }
+        }
+
+        // the tree survives reopening the database
+        let root = TREE.root(&store, None).unwrap();
+        drop(store);
+        let store = KvStorage(RocksStore::open(&path).unwrap());
+        assert_eq!(TREE.root(&store, None).unwrap(), root);
+
+        drop(store);
+        DB::destroy(&Options::default(), &path).unwrap();
+    }
+}
@@ -0,0 +1,306 @@
+use {
+    crate::{Blake3, Hash, TreeHasher, VerificationError},
+    cosmwasm_schema::cw_serde,
+    cosmwasm_std::{Order, StdResult, Storage},
+    cw_storage_plus::{Bound, Map},
+};
+
+/// Returned by `Tree::prove_historical_root`: the root hash committed at a
+/// version, and a proof of it against the tree's history root.
+#[cw_serde]
+pub struct HistoricalRootResponse {
+    pub version: u64,
+    /// `None` if the tree was empty at the version.
+    pub root_hash: Option<Hash>,
+    pub proof: HistoricalRootProof,
+}
+
+/// A proof that a root hash was committed at a version, to be verified with
+/// `verify_historical_root` against a history root (see `Tree::history_root`).
+///
+/// The versions recorded form the leaves of a merkle mountain range: a list
+/// of perfect binary trees ("mountains") of decreasing sizes, one for each bit
+/// set in the number of leaves. The proof consists of the siblings on the path
+/// from the version's leaf up to the peak of its mountain, plus the peaks of
+/// the other mountains.
+#[cw_serde]
+pub struct HistoricalRootProof {
+    /// The position of the version among the versions recorded, from zero.
+    pub index: u64,
+    /// The number of versions recorded when the proof was made, which the
+    /// history root commits to.
+    pub num_versions: u64,
+    /// The siblings of the nodes on the path from the leaf to its peak, from
+    /// the bottom up.
+    pub siblings: Vec<Hash>,
+    /// The peaks of the mountains other than the one holding the leaf, from
+    /// the largest mountain to the smallest.
+    pub peaks: Vec<Hash>,
+}
+
+/// Verify that the given root hash was committed at the given version, using a
+/// proof returned by `Tree::prove_historical_root`, against a history root
+/// from a trusted source, e.g. one committed to in a block header.
+///
+/// The leaf of a version is defined as:
+///
+/// hash(0 || version || root_hash)
+///
+/// where `version` is a 64-bit unsigned integer in big endian encoding, and
+/// `root_hash` is omitted if the tree was empty at the version. Each inner node
+/// is hash(1 || left || right), and the history root is:
+///
+/// hash(2 || num_versions || peaks)
+///
+/// where the peaks are ordered from the largest mountain to the smallest.
+pub fn verify_historical_root(
+    history_root: &Hash,
+    version: u64,
+    root_hash: Option<&Hash>,
+    proof: &HistoricalRootProof,
+) -> Result<(), VerificationError> {
+    verify_historical_root_using::<Blake3>(history_root, version, root_hash, proof)
+}
+
+/// Same as `verify_historical_root`, for a tree that uses the given hash
+/// function.
+pub fn verify_historical_root_using<H: TreeHasher>(
+    history_root: &Hash,
+    version: u64,
+    root_hash: Option<&Hash>,
+    proof: &HistoricalRootProof,
+) -> Result<(), VerificationError> {
+    if proof.index >= proof.num_versions {
+        return Err(VerificationError::HistoryProofMalformed);
+    }
+
+    let mountains = mountains(proof.num_versions);
+    let (position, &(height, start)) = mountains
+        .iter()
+        .enumerate()
+        .find(|(_, &(height, start))| proof.index < start + (1 << height))
+        .ok_or(VerificationError::HistoryProofMalformed)?;
+    if proof.siblings.len() != height as usize || proof.peaks.len() != mountains.len() - 1 {
+        return Err(VerificationError::HistoryProofMalformed);
+    }
+
+    let mut hash = leaf_hash::<H>(version, root_hash);
+    let mut index = proof.index - start;
+    for sibling in &proof.siblings {
+        hash = if index & 1 == 0 {
+            inner_hash::<H>(&hash, sibling)
+        } else {
+            inner_hash::<H>(sibling, &hash)
+        };
+        index >>= 1;
+    }
+
+    let mut peaks = proof.peaks.clone();
+    peaks.insert(position, hash);
+    let computed = bag_peaks::<H>(proof.num_versions, &peaks);
+    if computed != *history_root {
+        return Err(VerificationError::RootHashMismatch {
+            given: history_root.clone(),
+            computed,
+        });
+    }
+
+    Ok(())
+}
+
+/// Where a version is among the leaves of the history, along with its root.
+#[cw_serde]
+struct RecordedRoot {
+    index: u64,
+    root_hash: Option<Hash>,
+}
+
+/// The root hashes of a tree's versions, accumulated in a merkle mountain
+/// range. Nodes are keyed by their height and their index at that height,
+/// with the leaves at height zero.
+pub(crate) struct RootHistory<'a> {
+    roots: Map<'a, u64, RecordedRoot>,
+    nodes: Map<'a, (u8, u64), Hash>,
+}
+
+impl<'a> RootHistory<'a> {
+    pub const fn new(roots_namespace: &'a str, nodes_namespace: &'a str) -> Self {
+        Self {
+            roots: Map::new(roots_namespace),
+            nodes: Map::new(nodes_namespace),
+        }
+    }
+
+    pub fn namespaces(&self) -> [&'a [u8]; 2] {
+        [self.roots.namespace(), self.nodes.namespace()]
+    }
+
+    // the number of versions recorded, which are indexed consecutively
+    fn len(&self, store: &dyn Storage) -> StdResult<u64> {
+        let last = self.roots.range(store, None, None, Order::Descending).next().transpose()?;
+        Ok(last.map_or(0, |(_, root)| root.index + 1))
+    }
+
+    /// Record the root hash committed at a version, which must be newer than
+    /// all versions recorded before.
+    pub fn append<H: TreeHasher>(
+        &self,
+        store: &mut dyn Storage,
+        version: u64,
+        root_hash: Option<&Hash>,
+    ) -> StdResult<()> {
+        let index = self.len(store)?;
+        self.roots.save(
+            store,
+            version,
+            &RecordedRoot {
+                index,
+                root_hash: root_hash.cloned(),
+            },
+        )?;
+
+        // each time the new leaf completes a pair, the pair is merged into a
+        // node one level up
+        let mut hash = leaf_hash::<H>(version, root_hash);
+        let (mut height, mut index) = (0, index);
+        self.nodes.save(store, (height, index), &hash)?;
+        while index & 1 == 1 {
+            let left = self.nodes.load(store, (height, index - 1))?;
+            hash = inner_hash::<H>(&left, &hash);
+            height += 1;
+            index >>= 1;
+            self.nodes.save(store, (height, index), &hash)?;
+        }
+
+        Ok(())
+    }
+
+    /// The history root, or `None` if no version has been recorded.
+    pub fn root<H: TreeHasher>(&self, store: &dyn Storage) -> StdResult<Option<Hash>> {
+        let len = self.len(store)?;
+        if len == 0 {
+            return Ok(None);
+        }
+
+        let peaks = mountains(len)
+            .into_iter()
+            .map(|(height, start)| self.nodes.load(store, (height, start >> height)))
+            .collect::<StdResult<Vec<_>>>()?;
+        Ok(Some(bag_peaks::<H>(len, &peaks)))
+    }
+
+    /// Prove the root hash of a version, or return `None` if the version hasn't
+    /// been recorded.
+    pub fn prove(
+        &self,
+        store: &dyn Storage,
+        version: u64,
+    ) -> StdResult<Option<HistoricalRootResponse>> {
+        let Some(RecordedRoot {
+            index,
+            root_hash,
+        }) = self.roots.may_load(store, version)?
+        else {
+            return Ok(None);
+        };
+
+        let len = self.len(store)?;
+        let mut siblings = vec![];
+        let mut peaks = vec![];
+        for (height, start) in mountains(len) {
+            if index >= start && index < start + (1 << height) {
+                for level in 0..height {
+                    siblings.push(self.nodes.load(store, (level, (index >> level) ^ 1))?);
+                }
+            } else {
+                peaks.push(self.nodes.load(store, (height, start >> height))?);
+            }
+        }
+
+        Ok(Some(HistoricalRootResponse {
+            version,
+            root_hash,
+            proof: HistoricalRootProof {
+                index,
+                num_versions: len,
+                siblings,
+                peaks,
+            },
+        }))
+    }
+
+    /// Delete the roots of all versions newer than the given one, along with
+    /// the nodes that include them. Returns whether there were any.
+    pub fn remove_after(&self, store: &mut dyn Storage, version: u64) -> StdResult<bool> {
+        let removed = self
+            .roots
+            .range(store, Some(Bound::exclusive(version)), None, Order::Ascending)
+            .collect::<StdResult<Vec<_>>>()?;
+        let Some((_, first)) = removed.first() else {
+            return Ok(false);
+        };
+
+        // the nodes at each height that only cover the remaining leaves are
+        // kept
+        let len = first.index;
+        for height in 0..u64::BITS as u8 {
+            let nodes = self
+                .nodes
+                .prefix(height)
+                .keys(store, Some(Bound::inclusive(len >> height)), None, Order::Ascending)
+                .collect::<StdResult<Vec<_>>>()?;
+            for index in nodes {
+                self.nodes.remove(store, (height, index));
+            }
+        }
+
+        for (version, _) in &removed {
+            self.roots.remove(store, *version);
+        }
+
+        Ok(true)
+    }
+}
+
+// the height of each mountain and the index of its first leaf, from the largest
+// mountain to the smallest
+fn mountains(len: u64) -> Vec<(u8, u64)> {
+    let mut start = 0;
+    (0..u64::BITS as u8)
+        .rev()
+        .filter(|height| len & (1 << height) != 0)
+        .map(|height| {
+            let mountain = (height, start);
+            start += 1 << height;
+            mountain
+        })
+        .collect()
+}
+
+fn leaf_hash<H: TreeHasher>(version: u64, root_hash: Option<&Hash>) -> Hash {
+    let mut hasher = H::default();
+    hasher.update(&[0]);
+    hasher.update(&version.to_be_bytes());
+    if let Some(root_hash) = root_hash {
+        hasher.update(root_hash.as_bytes());
+    }
+    hasher.finalize()
+}
+
+fn inner_hash<H: TreeHasher>(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = H::default();
+    hasher.update(&[1]);
+    hasher.update(left.as_bytes());
+    hasher.update(right.as_bytes());
+    hasher.finalize()
+}
+
+fn bag_peaks<H: TreeHasher>(len: u64, peaks: &[Hash]) -> Hash {
+    let mut hasher = H::default();
+    hasher.update(&[2]);
+    hasher.update(&len.to_be_bytes());
+    for peak in peaks {
+        hasher.update(peak.as_bytes());
+    }
+    hasher.finalize()
+}
@@ -22,6 +22,10 @@ where
         }
     }
 
+    pub fn namespace(&self) -> &'a [u8] {
+        self.namespace
+    }
+
     fn key(&self, item: T) -> Path<Empty> {
         Path::new(
             self.namespace,
@@ -0,0 +1,203 @@
+use {
+    crate::{Hash, HashableValue, Node, NodeCodec, NodeKey, Tree, TreeError, TreeHasher, HASH_LEN},
+    cosmwasm_std::Storage,
+    serde::{de::DeserializeOwned, ser::Serialize},
+};
+
+impl<'a, K, V, C, H> Tree<'a, K, V, C, H>
+where
+    K: Serialize + DeserializeOwned + Clone + PartialEq + AsRef<[u8]>,
+    V: Serialize + DeserializeOwned + Clone + PartialEq + HashableValue,
+    C: NodeCodec<K, V>,
+    H: TreeHasher,
+{
+    /// The root hash that a binary sparse merkle tree holding the same records
+    /// as the tree at the given version would have, as with the SMT store of
+    /// the Cosmos SDK (ICS-23's `SmtSpec`), if the tree hashes its keys with
+    /// SHA-256 (a `HashedTree` using `Sha256`). Defaults to the latest version.
+    ///
+    /// In a sparse merkle tree, a record is placed at the first bit of its key
+    /// that isn't shared with any other key, and its leaf is:
+    ///
+    /// hash(0 || key || hash(value))
+    ///
+    /// Each inner node is hash(1 || left || right), where an empty subtree is
+    /// represented by `HASH_LEN` zero bytes, which is also the root hash of an
+    /// empty tree.
+    ///
+    /// This doesn't change how the tree's own nodes are hashed, so its proofs
+    /// still can't be verified against this root. It's computed by reading all
+    /// nodes of the version, e.g. to check that the tree matches the store it's
+    /// migrated from. Note that an IAVL store's root hash can't be reproduced,
+    /// as it depends on the shape of its AVL tree and the versions its nodes
+    /// were written at. All keys must be of the same length, else
+    /// `TreeError::KeyLengthsDiffer` is returned.
+    pub fn smt_root(&self, store: &dyn Storage, version: Option<u64>) -> Result<Hash> {
+        let version = self.version_or_default(store, version)?;
+        let Some(root_node) = self.root_node_if_any(store, version)? else {
+            return Ok(Subtree::Empty.hash());
+        };
+
+        let mut key_len = None;
+        let subtree = self.smt_subtree(store, &NodeKey::root(version), root_node, &mut key_len)?;
+        Ok(subtree.hash())
+    }
+
+    // fold the children of a node into the binary subtree of the sparse merkle
+    // tree below the node's nibble path, one bit of the child index at a time
+    fn smt_subtree(
+        &self,
+        store: &dyn Storage,
+        node_key: &NodeKey,
+        node: Node<K, V>,
+        key_len: &mut Option<usize>,
+    ) -> Result<Subtree> {
+        if let Some(record) = node.data {
+            let key = record.key.as_ref();
+            if !node.children.is_empty() || key_len.is_some_and(|len| len != key.len()) {
+                return Err(TreeError::KeyLengthsDiffer);
+            }
+            *key_len = Some(key.len());
+            return Ok(Subtree::Leaf(leaf_hash::<H>(key, &record.value.hash_bytes())));
+        }
+
+        let mut slots = vec![Subtree::Empty; 16];
+        for child in &node.children {
+            let child_node_key = node_key.child(child.version, child.index);
+            let child_node = self.load_child(store, &child_node_key)?;
+            slots[usize::from(child.index.byte())] =
+                self.smt_subtree(store, &child_node_key, child_node, key_len)?;
+        }
+
+        while slots.len() > 1 {
+            slots = slots.chunks(2).map(|pair| Subtree::join::<H>(&pair[0], &pair[1])).collect();
+        }
+        Ok(slots.remove(0))
+    }
+}
+
+// a subtree of the sparse merkle tree. a subtree holding a single record is
+// represented by the record's leaf, wherever it is
+#[derive(Clone)]
+enum Subtree {
+    Empty,
+    Leaf(Hash),
+    Inner(Hash),
+}
+
+impl Subtree {
+    fn join<H: TreeHasher>(left: &Self, right: &Self) -> Self {
+        match (left, right) {
+            (Self::Empty, Self::Empty) => Self::Empty,
+            (Self::Leaf(hash), Self::Empty) | (Self::Empty, Self::Leaf(hash)) => {
+                Self::Leaf(hash.clone())
+            },
+            _ => Self::Inner(inner_hash::<H>(&left.hash(), &right.hash())),
+        }
+    }
+
+    fn hash(&self) -> Hash {
+        match self {
+            Self::Empty => Hash::from([0; HASH_LEN]),
+            Self::Leaf(hash) | Self::Inner(hash) => hash.clone(),
+        }
+    }
+}
+
+fn leaf_hash<H: TreeHasher>(key: &[u8], value: &[u8]) -> Hash {
+    let mut value_hasher = H::default();
+    value_hasher.update(value);
+    let mut hasher = H::default();
+    hasher.update(&[0]);
+    hasher.update(key);
+    hasher.update(value_hasher.finalize().as_bytes());
+    hasher.finalize()
+}
+
+fn inner_hash<H: TreeHasher>(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = H::default();
+    hasher.update(&[1]);
+    hasher.update(left.as_bytes());
+    hasher.update(right.as_bytes());
+    hasher.finalize()
+}
+
+type Result<T> = std::result::Result<T, TreeError>;
+
+// ----------------------------------- tests -----------------------------------
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{inner_hash, leaf_hash},
+        crate::{Batch, Blake3, Hash, HashedKey, HashedTree, Op, Tree, TreeError, HASH_LEN},
+        cosmwasm_std::testing::MockStorage,
+        rand::{rngs::StdRng, Rng, SeedableRng},
+        std::collections::BTreeMap,
+    };
+
+    const TREE: HashedTree<Vec<u8>, Vec<u8>> = Tree::new_default();
+
+    // the root of a sparse merkle tree built from its definition, from records
+    // sorted by key, splitting them by one bit of their keys at each level
+    fn reference_root(records: &[(Vec<u8>, Vec<u8>)], bit: usize) -> Hash {
+        match records {
+            [] => Hash::from([0; HASH_LEN]),
+            [(key, value)] => leaf_hash::<Blake3>(key, value),
+            _ => {
+                let split =
+                    records.partition_point(|(key, _)| key[bit / 8] & (0x80 >> (bit % 8)) == 0);
+                let left = reference_root(&records[..split], bit + 1);
+                let right = reference_root(&records[split..], bit + 1);
+                inner_hash::<Blake3>(&left, &right)
+            },
+        }
+    }
+
+    #[test]
+    fn computing_smt_roots() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let mut store = MockStorage::new();
+        let mut model = BTreeMap::new();
+        for _ in 0..30 {
+            let batch: Batch<_, _> = (0..rng.gen_range(1..20))
+                .map(|_| {
+                    let key = HashedKey::new(vec![rng.gen_range(0..50)]);
+                    let op = if rng.gen_bool(0.3) {
+                        Op::Delete
+                    } else {
+                        Op::Insert(vec![rng.gen()])
+                    };
+                    (key, op)
+                })
+                .collect();
+            for (key, op) in &batch {
+                let path = key.hash().as_bytes().to_vec();
+                match op {
+                    Op::Insert(value) => model.insert(path, value.clone()),
+                    Op::Delete => model.remove(&path),
+                };
+            }
+            TREE.apply(&mut store, batch).unwrap();
+
+            let records = model.clone().into_iter().collect::<Vec<_>>();
+            assert_eq!(TREE.smt_root(&store, None).unwrap(), reference_root(&records, 0));
+        }
+    }
+
+    #[test]
+    fn computing_smt_roots_of_keys_of_different_lengths() {
+        const RAW_TREE: Tree<Vec<u8>, Vec<u8>> = Tree::new_default();
+
+        let mut store = MockStorage::new();
+        let batch = Batch::from([
+            (b"a".to_vec(), Op::Insert(b"1".to_vec())),
+            (b"ab".to_vec(), Op::Insert(b"2".to_vec())),
+        ]);
+        RAW_TREE.apply(&mut store, batch).unwrap();
+        assert!(matches!(
+            RAW_TREE.smt_root(&store, None),
+            Err(TreeError::KeyLengthsDiffer),
+        ));
+    }
+}
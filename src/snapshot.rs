@@ -0,0 +1,266 @@
+use {
+    crate::{
+        prepare_batch, Hash, HashableValue, NibblePath, NodeCodec, Op, Tree, TreeError, TreeHasher,
+    },
+    cosmwasm_schema::cw_serde,
+    cosmwasm_std::{to_vec, StdResult, Storage},
+    serde::{de::DeserializeOwned, ser::Serialize},
+    std::iter,
+};
+
+/// The manifest of a snapshot of a version of a tree, for state sync. Created
+/// by `Tree::snapshot`.
+///
+/// A new node fetches the manifest from a peer, then the chunks, each of which
+/// can be checked against its hash in the manifest as soon as it arrives, and
+/// imports them using `Tree::import`. The manifest itself is checked once all
+/// chunks are imported, against the root hash of the version, which must be
+/// obtained from a trusted source, e.g. a light client.
+#[cw_serde]
+pub struct Snapshot {
+    pub version: u64,
+    pub root_hash: Hash,
+    /// The number of records in each chunk, except the last one, which may
+    /// have fewer
+    pub chunk_size: u64,
+    /// The hash of each chunk; see `snapshot_chunk_hash`
+    pub chunk_hashes: Vec<Hash>,
+}
+
+/// The hash of a chunk of a snapshot, which is the blake3 hash of its records
+/// encoded as JSON, as those of an `Exporter`'s chunk without the proofs.
+pub fn snapshot_chunk_hash<K, V>(records: &[(K, V)]) -> StdResult<Hash>
+where
+    K: Serialize,
+    V: Serialize,
+{
+    Ok(blake3::hash(&to_vec(records)?).into())
+}
+
+impl<'a, K, V, C, H> Tree<'a, K, V, C, H>
+where
+    K: Serialize + DeserializeOwned + Clone + PartialEq + AsRef<[u8]>,
+    V: Serialize + DeserializeOwned + Clone + PartialEq + HashableValue,
+    C: NodeCodec<K, V>,
+    H: TreeHasher,
+{
+    /// Create the manifest of a snapshot of the given version, or of the latest
+    /// one if not specified, split into chunks of `chunk_size` records. This
+    /// reads the whole version once, but holds only one chunk in memory.
+    ///
+    /// The chunks themselves are those of an `Exporter` with the same chunk
+    /// size, and can thus be served one at a time, or from a checkpoint:
+    ///
+    /// ```rust,ignore
+    /// let snapshot = tree.snapshot(&store, None, 1000)?;
+    /// let mut exporter = tree.export(&store, Some(snapshot.version))?.with_chunk_size(1000);
+    /// ```
+    ///
+    /// The version must not be pruned before the chunks are served.
+    pub fn snapshot<S: Storage>(
+        &'a self,
+        store: &S,
+        version: Option<u64>,
+        chunk_size: usize,
+    ) -> Result<Snapshot> {
+        let mut exporter = self.export(store, version)?.with_chunk_size(chunk_size);
+        let version = exporter.checkpoint().version();
+
+        let mut chunk_hashes = vec![];
+        loop {
+            let chunk = exporter.next_chunk(store)?;
+            if chunk.is_empty() {
+                break;
+            }
+
+            let records = chunk.into_iter().map(|(key, value, _)| (key, value)).collect::<Vec<_>>();
+            chunk_hashes.push(snapshot_chunk_hash(&records)?);
+        }
+
+        Ok(Snapshot {
+            version,
+            root_hash: self.root(store, Some(version))?.root_hash,
+            chunk_size: chunk_size as u64,
+            chunk_hashes,
+        })
+    }
+
+    /// Reconstruct the version of a tree that a snapshot was taken of, from the
+    /// snapshot's chunks in order, such that it has the same root hash. The
+    /// tree must be empty, and is committed at the snapshot's version, so the
+    /// node continues from the same version as its peers.
+    ///
+    /// As with `apply_streaming`, chunks are applied one at a time, so only one
+    /// of them is held in memory. A chunk that doesn't match its hash in the
+    /// manifest is rejected before it's applied.
+    ///
+    /// The tree must be configured as the one the snapshot was taken of, e.g.
+    /// with the same domain and aggregation, or the root hash won't match. For
+    /// the same reason, snapshots can't be taken of trees that don't collapse
+    /// leaves (see `with_collapse_policy`), as their structure depends on the
+    /// order in which records were inserted and deleted.
+    ///
+    /// Records are imported as they are stored, so keys are not normalized or
+    /// mapped again. On error, the tree may be left partially imported; clear
+    /// the storage before trying again.
+    pub fn import<I>(&self, store: &mut dyn Storage, snapshot: &Snapshot, chunks: I) -> Result<()>
+    where
+        I: IntoIterator<Item = Vec<(K, V)>>,
+    {
+        if let Some(latest) = self.version.may_load(store)? {
+            return Err(TreeError::TreeNotEmpty {
+                latest,
+            });
+        }
+
+        let mut chunks = chunks.into_iter();
+        let mut num_chunks = 0;
+        let prepared_chunks = iter::from_fn(|| {
+            let records = chunks.next()?;
+            let index = num_chunks;
+            num_chunks += 1;
+            Some(prepare_snapshot_chunk(snapshot, index, records))
+        });
+        self.apply_chunks(store, prepared_chunks, 0, snapshot.version)?;
+
+        if num_chunks != snapshot.chunk_hashes.len() {
+            return Err(TreeError::SnapshotIncomplete {
+                expected: snapshot.chunk_hashes.len(),
+                received: num_chunks,
+            });
+        }
+
+        let root_hash = self.root(store, Some(snapshot.version))?.root_hash;
+        if root_hash != snapshot.root_hash {
+            return Err(TreeError::SnapshotRootMismatch {
+                expected: snapshot.root_hash.clone(),
+                computed: root_hash,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+// check a chunk against its hash in the manifest, and prepare its records to
+// be inserted
+fn prepare_snapshot_chunk<K, V>(
+    snapshot: &Snapshot,
+    index: usize,
+    mut records: Vec<(K, V)>,
+) -> Result<Vec<(NibblePath, K, Op<V>)>>
+where
+    K: Serialize + AsRef<[u8]>,
+    V: Serialize,
+{
+    let expected = snapshot.chunk_hashes.get(index);
+    if expected != Some(&snapshot_chunk_hash(&records)?) {
+        return Err(TreeError::SnapshotChunkMismatch {
+            index,
+        });
+    }
+
+    // the records of a chunk that matches are in the order they're exported
+    // in, unless the manifest itself is forged, which the root hash catches.
+    // either way, the ops must be sorted to be applied
+    records.sort_by(|(a, _), (b, _)| a.as_ref().cmp(b.as_ref()));
+    prepare_batch(records.into_iter().map(|(key, value)| (key, Op::Insert(value))))
+}
+
+type Result<T> = std::result::Result<T, TreeError>;
+
+// ----------------------------------- tests -----------------------------------
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::{snapshot_chunk_hash, Batch, Op, Tree, TreeError},
+        cosmwasm_std::{testing::MockStorage, Order},
+    };
+
+    const TREE: Tree<String, String> = Tree::new_default();
+
+    fn export_chunks(
+        store: &MockStorage,
+        version: u64,
+        chunk_size: usize,
+    ) -> Vec<Vec<(String, String)>> {
+        let mut exporter = TREE.export(store, Some(version)).unwrap().with_chunk_size(chunk_size);
+        let mut chunks = vec![];
+        loop {
+            let chunk = exporter.next_chunk(store).unwrap();
+            if chunk.is_empty() {
+                return chunks;
+            }
+            chunks.push(chunk.into_iter().map(|(key, value, _)| (key, value)).collect());
+        }
+    }
+
+    #[test]
+    fn syncing_from_snapshots() {
+        let mut store = MockStorage::new();
+        let batch: Batch<_, _> =
+            (0..25).map(|i| (format!("key{i}"), Op::Insert(format!("value{i}")))).collect();
+        TREE.apply(&mut store, batch).unwrap();
+        let batch: Batch<_, _> =
+            (0..25).step_by(3).map(|i| (format!("key{i}"), Op::Delete)).collect();
+        TREE.apply(&mut store, batch).unwrap();
+
+        let snapshot = TREE.snapshot(&store, None, 5).unwrap();
+        assert_eq!((snapshot.version, snapshot.chunk_hashes.len()), (2, 4));
+
+        // newer versions don't affect the snapshot, and the chunks can be
+        // served afterwards
+        TREE.apply(&mut store, Batch::from([("key1".to_string(), Op::Delete)])).unwrap();
+        let chunks = export_chunks(&store, 2, 5);
+        for (chunk, hash) in chunks.iter().zip(&snapshot.chunk_hashes) {
+            assert_eq!(snapshot_chunk_hash(chunk).unwrap(), *hash);
+        }
+
+        let mut new_store = MockStorage::new();
+        TREE.import(&mut new_store, &snapshot, chunks.clone()).unwrap();
+        assert_eq!(TREE.root(&new_store, None).unwrap(), TREE.root(&store, Some(2)).unwrap());
+        let records = |store, version| {
+            let iter = TREE.iterate(store, Order::Ascending, None, None, Some(version)).unwrap();
+            iter.collect::<Result<Vec<_>, _>>().unwrap()
+        };
+        assert_eq!(records(&new_store, 2), records(&store, 2));
+
+        // the tree must be empty
+        assert!(matches!(
+            TREE.import(&mut new_store, &snapshot, chunks.clone()),
+            Err(TreeError::TreeNotEmpty {
+                latest: 2
+            }),
+        ));
+
+        // a tampered chunk is rejected
+        let mut tampered = chunks.clone();
+        tampered[1][0].1 = "tampered".to_string();
+        assert!(matches!(
+            TREE.import(&mut MockStorage::new(), &snapshot, tampered),
+            Err(TreeError::SnapshotChunkMismatch {
+                index: 1
+            }),
+        ));
+
+        // as are missing chunks
+        assert!(matches!(
+            TREE.import(&mut MockStorage::new(), &snapshot, chunks[..3].to_vec()),
+            Err(TreeError::SnapshotIncomplete {
+                expected: 4,
+                received: 3
+            }),
+        ));
+
+        // a forged manifest is caught by the root hash
+        let mut forged = snapshot.clone();
+        let mut forged_chunks = chunks;
+        forged_chunks[1][0].1 = "forged".to_string();
+        forged.chunk_hashes[1] = snapshot_chunk_hash(&forged_chunks[1]).unwrap();
+        assert!(matches!(
+            TREE.import(&mut MockStorage::new(), &forged, forged_chunks),
+            Err(TreeError::SnapshotRootMismatch { .. }),
+        ));
+    }
+}
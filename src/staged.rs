@@ -0,0 +1,294 @@
+use {
+    crate::{
+        Batch, Blake3, ChangeSet, Hash, HashableValue, JsonCodec, NodeCodec, Op, Overlay, Tree,
+        TreeError, TreeHasher, TreeIterator,
+    },
+    cosmwasm_std::{Order, Storage},
+    serde::{de::DeserializeOwned, ser::Serialize},
+    std::{cmp::Ordering, iter::Peekable, vec},
+};
+
+impl<'a, K, V, C, H> Tree<'a, K, V, C, H>
+where
+    K: Serialize + DeserializeOwned + Clone + Ord + AsRef<[u8]>,
+    V: Serialize + DeserializeOwned + Clone + PartialEq + HashableValue,
+    C: NodeCodec<K, V>,
+    H: TreeHasher,
+{
+    /// Start staging changes to the latest version of the tree in memory, e.g.
+    /// those of the transactions of a block. See `StagedTree`.
+    pub fn stage(&'a self) -> StagedTree<'a, K, V, C, H> {
+        StagedTree {
+            tree: self,
+            pending: Batch::new(),
+        }
+    }
+}
+
+/// An in-memory write batch over the latest version of a `Tree`, whose reads
+/// reflect the pending changes. Created by `Tree::stage`.
+///
+/// Nothing is written to storage until `commit`, which applies all pending
+/// changes as a single batch, creating one new version. Dropping the staged
+/// tree discards them.
+///
+/// Unlike an `Overlay`, the staged tree doesn't hold on to the store, so that
+/// the same store can be written to when committing:
+///
+/// ```rust,ignore
+/// let mut staged = tree.stage();
+/// for tx in block.txs {
+///     execute(&mut staged, &store, tx)?;
+/// }
+/// staged.commit(&mut store)?;
+/// ```
+///
+/// The tree must not be written to while changes are staged, or reads would
+/// reflect a mix of versions.
+pub struct StagedTree<'a, K, V, C = JsonCodec, H = Blake3> {
+    tree: &'a Tree<'a, K, V, C, H>,
+    // under canonical keys, as those of an `Overlay`
+    pending: Batch<K, V>,
+}
+
+impl<'a, K, V, C, H> StagedTree<'a, K, V, C, H>
+where
+    K: Serialize + DeserializeOwned + Clone + Ord + AsRef<[u8]>,
+    V: Serialize + DeserializeOwned + Clone + PartialEq + HashableValue,
+    C: NodeCodec<K, V>,
+    H: TreeHasher,
+{
+    pub fn insert(&mut self, key: K, value: V) {
+        self.pending.insert(self.tree.canonical_owned_key(key), Op::Insert(value));
+    }
+
+    pub fn delete(&mut self, key: K) {
+        self.pending.insert(self.tree.canonical_owned_key(key), Op::Delete);
+    }
+
+    /// Add a batch of ops to the pending changes. Ops in the batch overwrite
+    /// pending ops under the same keys.
+    pub fn extend(&mut self, batch: Batch<K, V>) {
+        for (key, op) in batch {
+            self.pending.insert(self.tree.canonical_owned_key(key), op);
+        }
+    }
+
+    /// The pending changes, which `commit` would apply.
+    pub fn pending(&self) -> &Batch<K, V> {
+        &self.pending
+    }
+
+    /// The value of a key as of the latest version, with the pending changes
+    /// applied.
+    pub fn get(&self, store: &dyn Storage, key: &K) -> Result<Option<V>> {
+        if let Some(op) = self.pending.get(&*self.tree.canonical_key(key)) {
+            return match op {
+                Op::Insert(value) => Ok(Some(value.clone())),
+                Op::Delete => Ok(None),
+            };
+        }
+
+        if self.tree.version.may_load(store)?.is_none() {
+            return Ok(None);
+        }
+
+        self.tree.get(store, key, false, None).map(|res| res.value)
+    }
+
+    /// Enumerate the records of the latest version, with the pending changes
+    /// applied, in the same order and within the same bounds as `Tree::iterate`
+    /// would after committing them. Keys are yielded in the form they're stored
+    /// under.
+    pub fn iterate<'c, S: Storage>(
+        &'c self,
+        store: &'c S,
+        order: Order,
+        min: Option<&K>,
+        max: Option<&K>,
+    ) -> Result<StagedIterator<'c, K, V, S, C, H>>
+    where
+        'a: 'c,
+    {
+        let records = match self.tree.version.may_load(store)? {
+            Some(version) => match self.tree.iterate(store, order, min, max, Some(version)) {
+                Ok(iter) => Some(iter.peekable()),
+                // all records have been deleted
+                Err(TreeError::RootNodeNotFound {
+                    ..
+                }) => None,
+                Err(err) => return Err(err),
+            },
+            None => None,
+        };
+
+        // the pending ops, in the form and the order the tree iterates in.
+        // bounds are mapped, but not normalized, as in `Tree::iterate`
+        let min = min.map(|min| self.tree.mapped_owned_key(min.clone()));
+        let max = max.map(|max| self.tree.mapped_owned_key(max.clone()));
+        let mut pending = self
+            .pending
+            .iter()
+            .map(|(key, op)| (self.tree.mapped_owned_key(key.clone()), op.clone()))
+            .filter(|(key, _)| min.as_ref().map_or(true, |min| key.as_ref() >= min.as_ref()))
+            .filter(|(key, _)| max.as_ref().map_or(true, |max| key.as_ref() < max.as_ref()))
+            .collect::<Vec<_>>();
+        pending.sort_by(|(a, _), (b, _)| a.as_ref().cmp(b.as_ref()));
+        if let Order::Descending = order {
+            pending.reverse();
+        }
+
+        Ok(StagedIterator {
+            records,
+            pending: pending.into_iter().peekable(),
+            order,
+        })
+    }
+
+    /// Compute the root hash the tree would have if the pending changes were
+    /// committed. `None` if the tree would be empty.
+    pub fn root_hash(&self, store: &dyn Storage) -> Result<Option<Hash>> {
+        let version = self.tree.version.may_load(store)?.unwrap_or(0);
+        let mut overlay = Overlay::new(self.tree, store, version);
+        overlay.extend(self.pending.clone());
+        overlay.root_hash()
+    }
+
+    /// Apply the pending changes to the tree as a single batch. See
+    /// `Tree::apply`.
+    pub fn commit(self, store: &mut dyn Storage) -> Result<ChangeSet<K>> {
+        self.tree.apply(store, self.pending)
+    }
+}
+
+/// Yields the records of a `StagedTree`, merging the pending changes into the
+/// records of the tree. Created by `StagedTree::iterate`.
+pub struct StagedIterator<'a, K, V, S, C = JsonCodec, H = Blake3>
+where
+    S: Storage,
+    K: Serialize + DeserializeOwned + Clone + AsRef<[u8]>,
+    V: Serialize + DeserializeOwned + Clone,
+    C: NodeCodec<K, V>,
+    H: TreeHasher,
+{
+    // none if the tree is empty
+    records: Option<Records<'a, K, V, S, C, H>>,
+    pending: Peekable<vec::IntoIter<(K, Op<V>)>>,
+    order: Order,
+}
+
+impl<'a, K, V, S, C, H> Iterator for StagedIterator<'a, K, V, S, C, H>
+where
+    S: Storage,
+    K: Serialize + DeserializeOwned + Clone + AsRef<[u8]>,
+    V: Serialize + DeserializeOwned + Clone,
+    C: NodeCodec<K, V>,
+    H: TreeHasher,
+{
+    type Item = Result<(K, V)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            // whether the next pending op comes before the next record of the
+            // tree, in the order of iteration
+            let ordering =
+                match (self.pending.peek(), self.records.as_mut().and_then(Peekable::peek)) {
+                    (_, Some(Err(_))) => return self.records.as_mut()?.next(),
+                    (None, None) => return None,
+                    (Some(_), None) => Ordering::Less,
+                    (None, Some(_)) => Ordering::Greater,
+                    (Some((a, _)), Some(Ok((b, _)))) => match self.order {
+                        Order::Ascending => a.as_ref().cmp(b.as_ref()),
+                        Order::Descending => b.as_ref().cmp(a.as_ref()),
+                    },
+                };
+
+            if ordering == Ordering::Greater {
+                return self.records.as_mut()?.next();
+            }
+
+            // the pending op overrides the record under the same key, if any
+            if ordering == Ordering::Equal {
+                self.records.as_mut()?.next();
+            }
+            if let Some((key, Op::Insert(value))) = self.pending.next() {
+                return Some(Ok((key, value)));
+            }
+        }
+    }
+}
+
+type Records<'a, K, V, S, C, H> = Peekable<TreeIterator<'a, K, V, S, C, H>>;
+
+type Result<T> = std::result::Result<T, TreeError>;
+
+// ----------------------------------- tests -----------------------------------
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::{Batch, Op, Tree},
+        cosmwasm_std::{testing::MockStorage, Order},
+    };
+
+    const TREE: Tree<String, String> = Tree::new_default();
+
+    fn batch(ops: &[(&str, Option<&str>)]) -> Batch<String, String> {
+        ops.iter()
+            .map(|(key, value)| {
+                let op = value.map(|v| Op::Insert(v.to_string())).unwrap_or(Op::Delete);
+                (key.to_string(), op)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn staging_changes() {
+        let mut store = MockStorage::new();
+        TREE.apply(&mut store, batch(&[("a", Some("1")), ("c", Some("3")), ("e", Some("5"))]))
+            .unwrap();
+
+        let mut staged = TREE.stage();
+        staged.extend(batch(&[("b", Some("2")), ("c", None), ("e", Some("50")), ("f", None)]));
+        staged.insert("g".into(), "7".into());
+        assert_eq!(staged.get(&store, &"a".into()).unwrap(), Some("1".into()));
+        assert_eq!(staged.get(&store, &"c".into()).unwrap(), None);
+        assert_eq!(staged.get(&store, &"e".into()).unwrap(), Some("50".into()));
+
+        let records = |order, min: Option<&str>, max: Option<&str>| {
+            let (min, max) = (min.map(String::from), max.map(String::from));
+            staged
+                .iterate(&store, order, min.as_ref(), max.as_ref())
+                .unwrap()
+                .map(|item| item.map(|(key, value)| format!("{key}={value}")))
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap()
+        };
+        assert_eq!(records(Order::Ascending, None, None), ["a=1", "b=2", "e=50", "g=7"]);
+        assert_eq!(records(Order::Descending, None, None), ["g=7", "e=50", "b=2", "a=1"]);
+        assert_eq!(records(Order::Ascending, Some("b"), Some("g")), ["b=2", "e=50"]);
+
+        // nothing is written until committed
+        let root_hash = staged.root_hash(&store).unwrap();
+        assert_eq!(TREE.root(&store, None).unwrap().version, 1);
+        staged.commit(&mut store).unwrap();
+        let root = TREE.root(&store, None).unwrap();
+        assert_eq!((root.version, Some(root.root_hash)), (2, root_hash));
+    }
+
+    #[test]
+    fn staging_over_empty_tree() {
+        let mut store = MockStorage::new();
+
+        let mut staged = TREE.stage();
+        assert_eq!(staged.get(&store, &"a".into()).unwrap(), None);
+        assert_eq!(staged.root_hash(&store).unwrap(), None);
+
+        staged.insert("a".into(), "1".into());
+        let records = staged.iterate(&store, Order::Ascending, None, None).unwrap();
+        assert_eq!(records.collect::<Result<Vec<_>, _>>().unwrap(), [("a".into(), "1".into())]);
+
+        staged.commit(&mut store).unwrap();
+        assert_eq!(TREE.get(&store, &"a".into(), false, None).unwrap().value, Some("1".into()));
+    }
+}
@@ -0,0 +1,255 @@
+use {
+    crate::{HashableValue, Node, NodeCodec, Tree, TreeError, TreeHasher},
+    cosmwasm_schema::cw_serde,
+    cosmwasm_std::{Order, StdResult, Storage},
+    cw_storage_plus::{Bound, Map},
+    serde::{de::DeserializeOwned, ser::Serialize},
+};
+
+/// The size of a version of a tree. See `Tree::with_stats`.
+///
+/// These are not part of the tree, so they are neither hashed nor proven, and
+/// a tree's peers may disagree on them if they enabled stats at different
+/// versions.
+#[cw_serde]
+#[derive(Default)]
+pub struct TreeStats {
+    /// The number of records
+    pub records: u64,
+    /// The number of nodes without children, each of which holds a record
+    pub leaves: u64,
+    /// The number of nodes with children, some of which also hold a record,
+    /// if its key is a prefix of other keys
+    pub internal_nodes: u64,
+    /// The total size of the values, as given by `HashableValue::hash_bytes`,
+    /// which for bytes and strings is their length
+    pub value_bytes: u64,
+}
+
+impl TreeStats {
+    fn apply(self, delta: &StatsDelta) -> Self {
+        Self {
+            records: self.records.saturating_add_signed(delta.records),
+            leaves: self.leaves.saturating_add_signed(delta.leaves),
+            internal_nodes: self.internal_nodes.saturating_add_signed(delta.internal_nodes),
+            value_bytes: self.value_bytes.saturating_add_signed(delta.value_bytes),
+        }
+    }
+}
+
+/// The stats of a tree's versions. Only versions that were committed while
+/// stats were enabled have them.
+pub(crate) struct VersionStats<'a> {
+    stats: Map<'a, u64, TreeStats>,
+}
+
+impl<'a> VersionStats<'a> {
+    pub const fn new(namespace: &'a str) -> Self {
+        Self {
+            stats: Map::new(namespace),
+        }
+    }
+
+    pub fn namespace(&self) -> &'a [u8] {
+        self.stats.namespace()
+    }
+
+    /// The stats of the given version, which are those of the newest version
+    /// that has them, up to the given one. Versions that didn't change the
+    /// tree have none of their own.
+    pub fn load(&self, store: &dyn Storage, version: u64) -> StdResult<TreeStats> {
+        let max = Some(Bound::inclusive(version));
+        match self.stats.range(store, None, max, Order::Descending).next() {
+            Some(item) => item.map(|(_, stats)| stats),
+            None => Ok(TreeStats::default()),
+        }
+    }
+
+    /// Save the stats of a new version, given the changes made by the batch
+    /// committed as that version on top of the previous one.
+    pub fn save(
+        &self,
+        store: &mut dyn Storage,
+        old_version: u64,
+        new_version: u64,
+        delta: &StatsDelta,
+    ) -> StdResult<()> {
+        let stats = self.load(store, old_version)?.apply(delta);
+        self.stats.save(store, new_version, &stats)
+    }
+
+    /// Delete the stats of all versions newer than the given one. Returns
+    /// whether there were any.
+    pub fn remove_after(&self, store: &mut dyn Storage, version: u64) -> StdResult<bool> {
+        let versions = self
+            .stats
+            .keys(store, Some(Bound::exclusive(version)), None, Order::Ascending)
+            .collect::<StdResult<Vec<_>>>()?;
+
+        for version in &versions {
+            self.stats.remove(store, *version);
+        }
+
+        Ok(!versions.is_empty())
+    }
+}
+
+/// The changes made to a tree's stats while applying a batch, as the nodes
+/// and records are reported to the node source.
+#[derive(Default)]
+pub(crate) struct StatsDelta {
+    records: i64,
+    leaves: i64,
+    internal_nodes: i64,
+    value_bytes: i64,
+}
+
+impl StatsDelta {
+    pub fn add_node<K, V>(&mut self, node: &Node<K, V>) {
+        self.count_node(node, 1);
+    }
+
+    pub fn remove_node<K, V>(&mut self, node: &Node<K, V>) {
+        self.count_node(node, -1);
+    }
+
+    pub fn on_record_change<V: HashableValue>(&mut self, old: Option<&V>, new: Option<&V>) {
+        let value_bytes = |value: Option<&V>| value.map_or(0, |v| v.hash_bytes().len() as i64);
+        self.records += new.is_some() as i64 - old.is_some() as i64;
+        self.value_bytes += value_bytes(new) - value_bytes(old);
+    }
+
+    fn count_node<K, V>(&mut self, node: &Node<K, V>, count: i64) {
+        if node.children.is_empty() {
+            self.leaves += count;
+        } else {
+            self.internal_nodes += count;
+        }
+    }
+}
+
+impl<'a, K, V, C, H> Tree<'a, K, V, C, H>
+where
+    K: Serialize + DeserializeOwned + Clone + PartialEq + AsRef<[u8]>,
+    V: Serialize + DeserializeOwned + Clone + PartialEq + HashableValue,
+    C: NodeCodec<K, V>,
+    H: TreeHasher,
+{
+    /// Return the stats of the given version, or of the latest one if not
+    /// specified. See `with_stats`.
+    pub fn stats(&self, store: &dyn Storage, version: Option<u64>) -> Result<TreeStats> {
+        let stats = self.stats.as_ref().ok_or(TreeError::StatsNotTracked)?;
+        let version = match version {
+            Some(version) => self.version_or_default(store, Some(version))?,
+            None => self.version.may_load(store)?.unwrap_or(0),
+        };
+        stats.load(store, version).map_err(Into::into)
+    }
+
+    // a tally of the changes to the stats of the version being written, if
+    // stats are enabled
+    pub(crate) fn stats_delta(&self) -> Option<StatsDelta> {
+        self.stats.as_ref().map(|_| StatsDelta::default())
+    }
+}
+
+type Result<T> = std::result::Result<T, TreeError>;
+
+// ----------------------------------- tests -----------------------------------
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::{Appender, Batch, NodeKey, Op, Tree, TreeError, TreeStats},
+        cosmwasm_std::{testing::MockStorage, Order, Storage},
+        rand::{rngs::StdRng, Rng, SeedableRng},
+    };
+
+    const TREE: Tree<Vec<u8>, Vec<u8>> = Tree::new_default().with_stats("s");
+
+    // the stats of a version, counted by walking all of its nodes
+    fn scan(store: &dyn Storage, version: u64) -> TreeStats {
+        let mut stats = TreeStats::default();
+        let mut stack = vec![];
+        if let Some(root_node) = TREE.nodes.may_load(store, &NodeKey::root(version)).unwrap() {
+            stack.push((NodeKey::root(version), root_node));
+        }
+
+        while let Some((node_key, node)) = stack.pop() {
+            if let Some(record) = &node.data {
+                stats.records += 1;
+                stats.value_bytes += record.value.len() as u64;
+            }
+            if node.children.is_empty() {
+                stats.leaves += 1;
+            } else {
+                stats.internal_nodes += 1;
+            }
+            for child in node.children.as_ref() {
+                let child_node_key = node_key.child(child.version, child.index);
+                let child_node = TREE.nodes.load(store, &child_node_key).unwrap();
+                stack.push((child_node_key, child_node));
+            }
+        }
+
+        stats
+    }
+
+    #[test]
+    fn tracking_stats() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut store = MockStorage::new();
+        assert_eq!(TREE.stats(&store, None).unwrap(), TreeStats::default());
+
+        for _ in 0..30 {
+            let mut batch = Batch::new();
+            for _ in 0..rng.gen_range(1..20) {
+                // short keys, so that many are prefixes of others
+                let key = (0..rng.gen_range(1..3)).map(|_| rng.gen_range(0..4)).collect();
+                if rng.gen_bool(0.3) {
+                    batch.insert(key, Op::Delete);
+                } else {
+                    batch.insert(key, Op::Insert(vec![0; rng.gen_range(0..10)]));
+                }
+            }
+            if rng.gen_bool(0.5) {
+                TREE.apply(&mut store, batch).unwrap();
+            } else {
+                TREE.apply_streaming(&mut store, batch, 3).unwrap();
+            }
+        }
+
+        let latest = TREE.root(&store, None).unwrap().version;
+        for version in 1..=latest {
+            assert_eq!(TREE.stats(&store, Some(version)).unwrap(), scan(&store, version));
+        }
+
+        // deleting everything
+        let keys = TREE
+            .iterate(&store, Order::Ascending, None, None, None)
+            .unwrap()
+            .map(|item| item.map(|(key, _)| (key, Op::Delete)))
+            .collect::<Result<Batch<_, _>, _>>()
+            .unwrap();
+        TREE.apply(&mut store, keys).unwrap();
+        assert_eq!(TREE.stats(&store, None).unwrap(), TreeStats::default());
+
+        let untracked: Tree<Vec<u8>, Vec<u8>> = Tree::new_default();
+        assert!(matches!(untracked.stats(&store, None), Err(TreeError::StatsNotTracked)));
+    }
+
+    #[test]
+    fn tracking_stats_when_appending() {
+        let mut store = MockStorage::new();
+        let mut appender = Appender::new(&TREE);
+        for i in 0..10u32 {
+            let batch = (i * 20..i * 20 + 20)
+                .map(|id| (id.to_be_bytes().to_vec(), Op::Insert(vec![0; id as usize % 5])))
+                .collect();
+            appender.apply(&mut store, batch).unwrap();
+        }
+
+        assert_eq!(TREE.stats(&store, None).unwrap(), scan(&store, 10));
+        assert_eq!(TREE.stats(&store, None).unwrap().records, 200);
+    }
+}
@@ -0,0 +1,284 @@
+use {
+    crate::{
+        Batch, Blake3, JsonCodec, Node, NodeCodec, NodeKey, Op, Tree, TreeError, TreeHasher,
+        TreeIterator,
+    },
+    cosmwasm_std::{Order, Record, Storage},
+    std::{cmp::Ordering, iter::Peekable, ops::Bound},
+};
+
+/// Exposes a version of a `Tree` of raw bytes as a `cosmwasm_std::Storage`, so
+/// that the tree can back the storage layers of testing frameworks, such as
+/// the storage of each contract in cw-multi-test.
+///
+/// Reads are served from the tree, with the same semantics as any other
+/// `Storage`: `range` yields the records with `start <= key < end` in the
+/// given order. Writes are buffered in memory on top of the tree, and are seen
+/// by subsequent reads; to commit them, use `into_batch` and apply the batch to
+/// the tree.
+///
+/// As `Storage` can't return errors, errors from the tree cause a panic. These
+/// only happen if the tree is corrupted, or the version has been pruned.
+pub struct TreeStorage<'a, S, C = JsonCodec, H = Blake3> {
+    tree: &'a Tree<'a, Vec<u8>, Vec<u8>, C, H>,
+    store: &'a S,
+    version: u64,
+    root_node: Option<Node<Vec<u8>, Vec<u8>>>,
+    pending: Batch<Vec<u8>, Vec<u8>>,
+}
+
+impl<'a, S, C, H> TreeStorage<'a, S, C, H>
+where
+    S: Storage,
+    C: NodeCodec<Vec<u8>, Vec<u8>>,
+    H: TreeHasher,
+{
+    /// Expose the given version of the tree. `version` of zero means the tree
+    /// is empty.
+    pub fn new(
+        tree: &'a Tree<'a, Vec<u8>, Vec<u8>, C, H>,
+        store: &'a S,
+        version: u64,
+    ) -> Result<Self> {
+        // the tree may have been emptied at this version, in which case there's
+        // no root node
+        let root_node = if version > 0 {
+            tree.nodes.may_load(store, &NodeKey::root(version))?
+        } else {
+            None
+        };
+
+        Ok(Self {
+            tree,
+            store,
+            version,
+            root_node,
+            pending: Batch::new(),
+        })
+    }
+
+    /// The version of the tree this storage exposes.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Buffer a batch of writes on top of the tree. Ops in the batch overwrite
+    /// writes already buffered under the same keys.
+    pub fn extend(&mut self, batch: Batch<Vec<u8>, Vec<u8>>) {
+        self.pending.extend(batch);
+    }
+
+    /// Consume the storage, returning the writes buffered on top of the tree.
+    pub fn into_batch(self) -> Batch<Vec<u8>, Vec<u8>> {
+        self.pending
+    }
+}
+
+impl<'a, S, C, H> Storage for TreeStorage<'a, S, C, H>
+where
+    S: Storage,
+    C: NodeCodec<Vec<u8>, Vec<u8>>,
+    H: TreeHasher,
+{
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        if let Some(op) = self.pending.get(key) {
+            return match op {
+                Op::Insert(value) => Some(value.clone()),
+                Op::Delete => None,
+            };
+        }
+
+        self.root_node.as_ref()?;
+
+        self.tree
+            .get(self.store, &key.to_vec(), false, Some(self.version))
+            .unwrap_or_else(|err| panic!("failed to read from tree: {err}"))
+            .value
+    }
+
+    fn range<'b>(
+        &'b self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        order: Order,
+    ) -> Box<dyn Iterator<Item = Record> + 'b> {
+        let start = start.map(<[u8]>::to_vec);
+        let end = end.map(<[u8]>::to_vec);
+
+        let committed = self.root_node.clone().map(|root_node| {
+            let (min, max) = (start.as_ref(), end.as_ref());
+            TreeIterator::new(self.tree, self.store, self.version, order, min, max, root_node)
+        });
+
+        // BTreeMap::range panics if start > end, whereas storages return nothing
+        let pending: PendingOps<'b> = match (&start, &end) {
+            (Some(start), Some(end)) if start >= end => Box::new(std::iter::empty()),
+            _ => {
+                let range = self.pending.range::<Vec<u8>, _>((
+                    start.map_or(Bound::Unbounded, Bound::Included),
+                    end.map_or(Bound::Unbounded, Bound::Excluded),
+                ));
+                match order {
+                    Order::Ascending => Box::new(range),
+                    Order::Descending => Box::new(range.rev()),
+                }
+            },
+        };
+
+        Box::new(MergedRange {
+            committed: committed.into_iter().flatten().peekable(),
+            pending: pending.peekable(),
+            order,
+        })
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8]) {
+        self.pending.insert(key.to_vec(), Op::Insert(value.to_vec()));
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        self.pending.insert(key.to_vec(), Op::Delete);
+    }
+}
+
+type PendingOps<'b> = Box<dyn Iterator<Item = (&'b Vec<u8>, &'b Op<Vec<u8>>)> + 'b>;
+
+// merges the records committed to the tree with the pending ops, both sorted
+// in the same order. where both have the same key, the pending op wins
+struct MergedRange<'b, I, P>
+where
+    I: Iterator,
+    P: Iterator<Item = (&'b Vec<u8>, &'b Op<Vec<u8>>)>,
+{
+    committed: Peekable<I>,
+    pending: Peekable<P>,
+    order: Order,
+}
+
+impl<'b, I, P> Iterator for MergedRange<'b, I, P>
+where
+    I: Iterator<Item = Result<Record>>,
+    P: Iterator<Item = (&'b Vec<u8>, &'b Op<Vec<u8>>)>,
+{
+    type Item = Record;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let committed_key = match self.committed.peek() {
+                Some(Ok((key, _))) => Some(key),
+                Some(Err(err)) => panic!("failed to read from tree: {err}"),
+                None => None,
+            };
+
+            // which comes first: the committed record, or the pending op
+            let ordering = match (committed_key, self.pending.peek()) {
+                (None, None) => return None,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(committed_key), Some((pending_key, _))) => match self.order {
+                    Order::Ascending => committed_key.cmp(*pending_key),
+                    Order::Descending => (*pending_key).cmp(committed_key),
+                },
+            };
+
+            if ordering == Ordering::Less {
+                return self.committed.next().map(|item| item.unwrap());
+            }
+
+            // the committed record, if any, is overwritten by the pending op
+            if ordering == Ordering::Equal {
+                self.committed.next();
+            }
+
+            if let Some((key, Op::Insert(value))) = self.pending.next() {
+                return Some((key.clone(), value.clone()));
+            }
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, TreeError>;
+
+// ----------------------------------- tests -----------------------------------
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::{Batch, Op, Tree, TreeStorage},
+        cosmwasm_std::{testing::MockStorage, Order, Storage},
+        rand::{rngs::StdRng, Rng, SeedableRng},
+    };
+
+    const TREE: Tree<Vec<u8>, Vec<u8>> = Tree::new_default();
+
+    fn rand_key<R: Rng>(rng: &mut R) -> Vec<u8> {
+        let len = rng.gen_range(1..=3);
+        (0..len).map(|_| [0x00, 0x01, 0x10, 0xff][rng.gen_range(0..4)]).collect()
+    }
+
+    // a TreeStorage must be indistinguishable from a MockStorage that the same
+    // records have been written to
+    #[test]
+    fn matching_storage_semantics() {
+        let mut rng = StdRng::seed_from_u64(1234);
+        let mut store = MockStorage::new();
+        let mut expected = MockStorage::new();
+
+        let batch: Batch<_, _> =
+            (0..30).map(|_| (rand_key(&mut rng), Op::Insert(vec![rng.gen()]))).collect();
+        for (key, op) in &batch {
+            if let Op::Insert(value) = op {
+                expected.set(key, value);
+            }
+        }
+        TREE.apply(&mut store, batch).unwrap();
+
+        let mut storage = TreeStorage::new(&TREE, &store, 1).unwrap();
+        for _ in 0..30 {
+            let key = rand_key(&mut rng);
+            if rng.gen_bool(0.5) {
+                let value = vec![rng.gen()];
+                storage.set(&key, &value);
+                expected.set(&key, &value);
+            } else {
+                storage.remove(&key);
+                expected.remove(&key);
+            }
+        }
+
+        for _ in 0..100 {
+            let key = rand_key(&mut rng);
+            assert_eq!(storage.get(&key), expected.get(&key));
+
+            let start = rng.gen_bool(0.7).then(|| rand_key(&mut rng));
+            let end = rng.gen_bool(0.7).then(|| rand_key(&mut rng));
+            for order in [Order::Ascending, Order::Descending] {
+                assert_eq!(
+                    storage.range(start.as_deref(), end.as_deref(), order).collect::<Vec<_>>(),
+                    expected.range(start.as_deref(), end.as_deref(), order).collect::<Vec<_>>(),
+                );
+            }
+        }
+
+        // committing the buffered writes results in the same records
+        let batch = storage.into_batch();
+        TREE.apply(&mut store, batch).unwrap();
+        let storage = TreeStorage::new(&TREE, &store, 2).unwrap();
+        assert_eq!(
+            storage.range(None, None, Order::Ascending).collect::<Vec<_>>(),
+            expected.range(None, None, Order::Ascending).collect::<Vec<_>>(),
+        );
+    }
+
+    #[test]
+    fn empty_tree_storage() {
+        let store = MockStorage::new();
+        let mut storage = TreeStorage::new(&TREE, &store, 0).unwrap();
+        assert_eq!(storage.get(b"foo"), None);
+        assert_eq!(storage.range(None, None, Order::Ascending).count(), 0);
+
+        storage.set(b"foo", b"bar");
+        assert_eq!(storage.get(b"foo"), Some(b"bar".to_vec()));
+        assert_eq!(storage.range(None, None, Order::Descending).count(), 1);
+    }
+}
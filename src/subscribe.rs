@@ -0,0 +1,121 @@
+use {
+    crate::{
+        Batch, Blake3, ChangeSet, HashableValue, JsonCodec, NodeCodec, Tree, TreeError, TreeHasher,
+    },
+    cosmwasm_std::Storage,
+    serde::{de::DeserializeOwned, ser::Serialize},
+    std::sync::mpsc::{channel, Receiver, Sender},
+};
+
+/// Applies batches to a `Tree`, and notifies subscribers whenever a new version
+/// is committed. This is intended for off-chain services built on the tree,
+/// such as proof servers, which need to invalidate caches promptly.
+///
+/// Only batches applied through the notifier are noticed. If the tree is also
+/// updated by other means, those versions are reported with the next batch
+/// applied through the notifier.
+pub struct Notifier<'a, K, V, C = JsonCodec, H = Blake3> {
+    tree: &'a Tree<'a, K, V, C, H>,
+    senders: Vec<Sender<u64>>,
+}
+
+impl<'a, K, V, C, H> Notifier<'a, K, V, C, H>
+where
+    K: Serialize + DeserializeOwned + Clone + PartialEq + AsRef<[u8]>,
+    V: Serialize + DeserializeOwned + Clone + PartialEq + HashableValue,
+    C: NodeCodec<K, V>,
+    H: TreeHasher,
+{
+    pub fn new(tree: &'a Tree<'a, K, V, C, H>) -> Self {
+        Self {
+            tree,
+            senders: vec![],
+        }
+    }
+
+    /// Register a new subscriber, which will be notified of every version
+    /// committed from now on.
+    pub fn subscribe(&mut self) -> Subscription {
+        let (sender, receiver) = channel();
+        self.senders.push(sender);
+        Subscription {
+            receiver,
+        }
+    }
+
+    /// Same as `Tree::apply`, but additionally notifies subscribers if a new
+    /// version is committed.
+    pub fn apply(&mut self, store: &mut dyn Storage, batch: Batch<K, V>) -> Result<ChangeSet<K>> {
+        let old_version = self.tree.version.may_load(store)?.unwrap_or(0);
+        let changes = self.tree.apply(store, batch)?;
+
+        // the version isn't incremented if the root node is unchanged
+        if changes.version != old_version {
+            // drop the subscribers that have gone away
+            self.senders.retain(|sender| sender.send(changes.version).is_ok());
+        }
+
+        Ok(changes)
+    }
+}
+
+/// Receives the versions committed by a `Notifier`, in ascending order.
+pub struct Subscription {
+    receiver: Receiver<u64>,
+}
+
+impl Subscription {
+    /// Block until a version no older than the given one is committed, and
+    /// return that version. Returns `None` if the notifier has been dropped.
+    pub fn wait_for_version(&self, version: u64) -> Option<u64> {
+        self.receiver.iter().find(|committed| *committed >= version)
+    }
+
+    /// Return the latest version committed since the last call, if any,
+    /// without blocking.
+    pub fn latest_version(&self) -> Option<u64> {
+        self.receiver.try_iter().last()
+    }
+}
+
+type Result<T> = std::result::Result<T, TreeError>;
+
+// ----------------------------------- tests -----------------------------------
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::{Batch, Notifier, Op, Tree},
+        cosmwasm_std::testing::MockStorage,
+    };
+
+    const TREE: Tree<String, String> = Tree::new_default();
+
+    fn batch(key: &str, value: &str) -> Batch<String, String> {
+        Batch::from([(key.to_string(), Op::Insert(value.to_string()))])
+    }
+
+    #[test]
+    fn notifying_new_versions() {
+        let mut store = MockStorage::new();
+        let mut notifier = Notifier::new(&TREE);
+        let subscription = notifier.subscribe();
+
+        notifier.apply(&mut store, batch("foo", "bar")).unwrap();
+        notifier.apply(&mut store, batch("fuzz", "buzz")).unwrap();
+        assert_eq!(subscription.wait_for_version(2), Some(2));
+
+        // nothing changed, so no version is committed
+        notifier.apply(&mut store, batch("foo", "bar")).unwrap();
+        assert_eq!(subscription.latest_version(), None);
+
+        // dropping the subscription doesn't affect the notifier
+        let another = notifier.subscribe();
+        drop(subscription);
+        notifier.apply(&mut store, batch("foo", "baz")).unwrap();
+        assert_eq!(another.latest_version(), Some(3));
+
+        drop(notifier);
+        assert_eq!(another.wait_for_version(4), None);
+    }
+}
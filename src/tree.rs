@@ -1,22 +1,34 @@
 use {
     crate::{
-        Batch, Child, GetResponse, Nibble, NibbleIterator, NibblePath, NibbleRange,
-        NibbleRangeIterator, Node, NodeKey, Op, OpResponse, Proof, ProofNode, Record, RootResponse,
-        Set,
+        apply::check_key, apply_subtree, churn::ChurnCounters, domain_root_hash_using,
+        expiry::Expirations, header::VersionHeaders, key_filter::KeyFilters, prepare_batch,
+        root_history::RootHistory, stats::StatsDelta, stats::VersionStats, value_hash_using,
+        AggregateResponse, Batch, BatchProof, BatchProofNode, Blake3, Change, ChangeSet,
+        CollapsePolicy, Counters, GetManyResponse, GetResponse, Hash, HashableValue, HashedKey,
+        HistoricalRootResponse, IterateResponse, IteratorCheckpoint, JsonCodec, KeyFilter, Nibble,
+        NibbleIterator, NibblePath, Node, NodeCodec, NodeKey, NodeMap, NodeSource, Op, OpResponse,
+        Proof, ProofBytes, ProofNode, PruneStatus, RawCodec, Record, RootResponse, Set, StopReason,
+        SubtreeRootResponse, TreeHasher, Version, VersionHeader, MAX_NIBBLES,
     },
-    cosmwasm_std::{to_binary, Order, StdResult, Storage},
-    cw_storage_plus::{Item, Map, PrefixBound},
+    cosmwasm_std::{from_slice, to_binary, to_vec, Binary, Order, StdResult, Storage},
+    cw_storage_plus::{Bound, Item, PrefixBound},
     serde::{de::DeserializeOwned, ser::Serialize},
-    std::{cmp::Ordering, collections::HashMap},
+    std::{borrow::Cow, cmp::Ordering, collections::BTreeMap, iter, marker::PhantomData},
 };
 #[cfg(feature = "debug")]
-use {
-    crate::{NodeResponse, OrphanResponse},
-    cw_storage_plus::Bound,
+use crate::{NodeResponse, OrphanResponse, PathNodeResponse};
+#[cfg(feature = "journal")]
+use crate::{
+    journal::{Journal, DEFAULT_JOURNAL_LIMIT},
+    JournalEntry,
 };
+#[cfg(feature = "proof-metrics")]
+use crate::ProofMetrics;
+
+/// The number of orphaned nodes pruning reads from the orphan index at a time,
+/// unless set using `Tree::with_prune_batch_size`.
+pub const DEFAULT_PRUNE_BATCH_SIZE: usize = 10;
 
-const PRUNE_BATCH_SIZE: usize = 10;
-#[cfg(feature = "debug")]
 const DEFAULT_QUERY_BATCH_SIZE: usize = 10;
 
 /// A versioned and merklized key-value store, based on a radix tree data
@@ -45,29 +57,601 @@ const DEFAULT_QUERY_BATCH_SIZE: usize = 10;
 /// | `root`    | query the root node hash                                                      |
 /// | `get`     | query the value associated with the given key, optionally with a Merkle proof |
 /// | `iterate` | enumerate key-value pairs stored in the tree                                  |
-pub struct Tree<'a, K, V> {
-    version: Item<'a, u64>,
-    nodes: Map<'a, &'a NodeKey, Node<K, V>>,
+///
+/// By default, nodes are persisted as JSON. The codec can be swapped out using
+/// the `C` type parameter; see `RawTree` for example. Likewise, nodes are
+/// hashed using blake3, which can be swapped out using the `H` type parameter;
+/// see `TreeHasher`.
+///
+/// Additionally, the tree can maintain an aggregate in each node, such as the
+/// number of records in its subtree, or the sum of a numeric field of the
+/// values. This enables `rank`, `select` and provable `aggregate` queries. It
+/// is opt-in, using `with_aggregation` or `with_subtree_counts`.
+pub struct Tree<'a, K, V, C = JsonCodec, H = Blake3> {
+    pub(crate) version: Item<'a, u64>,
+    pub(crate) nodes: NodeMap<'a, K, V, C>,
     orphans: Set<'a, (u64, &'a NodeKey)>,
+    pub(crate) aggregation: Option<fn(&K, &V) -> u128>,
+    orphan_epoch_len: u64,
+    prune_batch_size: usize,
+    churn_counters: Option<ChurnCounters<'a>>,
+    counters: Option<Item<'a, Counters>>,
+    pub(crate) stats: Option<VersionStats<'a>>,
+    domain: Option<&'a [u8]>,
+    root_aliases: bool,
+    key_filters: Option<KeyFilters<'a>>,
+    pruned_version_policy: PrunedVersionPolicy,
+    pub(crate) collapse_policy: CollapsePolicy,
+    versioned_proofs: bool,
+    proof_encoding: ProofEncoding,
+    key_normalizer: Option<fn(&K) -> K>,
+    key_transform: Option<fn(&K) -> K>,
+    version_headers: Option<VersionHeaders<'a>>,
+    root_history: Option<RootHistory<'a>>,
+    expirations: Option<Expirations<'a, K>>,
+    #[cfg(feature = "journal")]
+    journal: Option<Journal<'a, K, V>>,
+    #[cfg(feature = "proof-metrics")]
+    pub(crate) proof_metrics: Option<Item<'a, ProofMetrics>>,
+    hasher: PhantomData<H>,
+}
+
+/// What `Tree::get` does when queried at a version that has been pruned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrunedVersionPolicy {
+    /// Return `TreeError::VersionPruned`.
+    Error,
+    /// Serve the value without a proof if the record is known to have been
+    /// the same at that version as it is at the latest version, i.e. the node
+    /// holding it was written at or before the queried version. Otherwise,
+    /// return `TreeError::VersionPruned`.
+    ServeWithoutProof,
+}
+
+/// How the proofs of single keys returned by `Tree::get` and similar queries are
+/// encoded. `verify_get_response` accepts either.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProofEncoding {
+    /// As JSON, which any client can decode.
+    Json,
+    /// In the compact binary format of `ProofBytes`, which is several times
+    /// smaller, but must be decoded with `decode_proof` or `Proof::from_bytes`.
+    Binary,
 }
 
-impl<'a, K, V> Default for Tree<'a, K, V> {
+/// A `Tree` whose keys and values are raw bytes, and whose nodes are encoded
+/// by hand instead of through serde. Use this when performance matters and
+/// typed keys and values are not needed.
+///
+/// Run `cargo run --example raw_tree --release` to compare the performance of
+/// this against the default `Tree`.
+pub type RawTree<'a> = Tree<'a, Vec<u8>, Vec<u8>, RawCodec>;
+
+/// A `Tree` whose keys are placed by their hashes, as in Diem's Jellyfish
+/// Merkle tree, so that it stays balanced and its proofs stay short even if
+/// the keys are chosen adversarially. Keys are hashed with the same function
+/// as the tree's nodes. See `HashedKey` for the tradeoffs.
+///
+/// ```rust
+/// use tree::{HashedKey, HashedTree, Tree};
+/// const TREE: HashedTree<String, String> = Tree::new_default();
+/// let key: HashedKey<String> = "foo".to_string().into();
+/// ```
+pub type HashedTree<'a, K, V, C = JsonCodec, H = Blake3> = Tree<'a, HashedKey<K, H>, V, C, H>;
+
+impl<'a, K, V, C, H> Default for Tree<'a, K, V, C, H> {
     fn default() -> Self {
         Self::new_default()
     }
 }
 
-impl<'a, K, V> Tree<'a, K, V> {
+impl<'a, K, V, C, H> Tree<'a, K, V, C, H> {
     pub const fn new(
         version_namespace: &'a str,
         node_namespace: &'a str,
         orphan_namespace: &'a str,
     ) -> Self {
+        assert!(
+            !same_namespace(version_namespace, node_namespace)
+                && !same_namespace(version_namespace, orphan_namespace)
+                && !same_namespace(node_namespace, orphan_namespace),
+            "a tree's namespaces must be distinct",
+        );
         Tree {
             version: Item::new(version_namespace),
-            nodes: Map::new(node_namespace),
+            nodes: NodeMap::new(node_namespace),
             orphans: Set::new(orphan_namespace),
+            aggregation: None,
+            orphan_epoch_len: 1,
+            prune_batch_size: DEFAULT_PRUNE_BATCH_SIZE,
+            churn_counters: None,
+            counters: None,
+            stats: None,
+            domain: None,
+            root_aliases: false,
+            key_filters: None,
+            pruned_version_policy: PrunedVersionPolicy::Error,
+            collapse_policy: CollapsePolicy::Always,
+            versioned_proofs: false,
+            proof_encoding: ProofEncoding::Json,
+            key_normalizer: None,
+            key_transform: None,
+            version_headers: None,
+            root_history: None,
+            expirations: None,
+            #[cfg(feature = "journal")]
+            journal: None,
+            #[cfg(feature = "proof-metrics")]
+            proof_metrics: None,
+            hasher: PhantomData,
+        }
+    }
+
+    /// Group orphaned nodes into epochs of `epoch_len` versions each, so that
+    /// all nodes orphaned during an epoch can be pruned at once with
+    /// `prune_epoch`, which scans a single prefix of the orphan index instead
+    /// of a range of versions.
+    ///
+    /// The tradeoff is granularity: `prune` can then only prune whole epochs.
+    /// By default, each epoch consists of a single version. This should not be
+    /// changed on an existing tree.
+    pub const fn with_orphan_epochs(self, epoch_len: u64) -> Self {
+        assert!(epoch_len > 0, "epoch length must be non-zero");
+        Self {
+            orphan_epoch_len: epoch_len,
+            ..self
+        }
+    }
+
+    /// Read up to `batch_size` orphaned nodes at a time from the orphan index
+    /// when pruning, before deleting them, and likewise up to `batch_size`
+    /// records at a time when clearing the tree. Larger batches take fewer
+    /// range queries, but more memory. This doesn't bound how many nodes a
+    /// prune deletes; see `prune_with_limit` for that.
+    pub const fn with_prune_batch_size(self, batch_size: usize) -> Self {
+        assert!(batch_size > 0, "prune batch size must be non-zero");
+        Self {
+            prune_batch_size: batch_size,
+            ..self
+        }
+    }
+
+    /// Count the bytes of the nodes orphaned during each epoch under the given
+    /// namespace, i.e. how much storage pruning the epoch would reclaim. This
+    /// enables `prune_largest`, which prunes the epochs with the most bytes
+    /// first, so that a bounded prune reclaims the most storage per unit of
+    /// gas. Counters can be queried with `orphaned_bytes`.
+    ///
+    /// This costs one extra read and one write per orphaned node. It should be
+    /// enabled from the start; epochs orphaned before are never counted.
+    pub const fn with_churn_counters(self, namespace: &'a str) -> Self {
+        Self {
+            churn_counters: Some(ChurnCounters::new(namespace)),
+            ..self
+        }
+    }
+
+    /// Maintain cumulative counters of the tree's activity under the given
+    /// namespace, which `counters` returns, as basic telemetry for long-running
+    /// deployments. See `Counters` for what is counted.
+    ///
+    /// This costs one read and one write per committed version, and per prune.
+    /// Counting starts when this is enabled.
+    pub const fn with_counters(self, namespace: &'a str) -> Self {
+        Self {
+            counters: Some(Item::new(namespace)),
+            ..self
+        }
+    }
+
+    /// Maintain stats of the size of each version under the given namespace,
+    /// which `stats` returns, so that operators can tell how big the tree is
+    /// without scanning it. See `TreeStats`.
+    ///
+    /// Stats are updated incrementally as batches are applied, which costs one
+    /// extra read per node orphaned, and one read and one write per committed
+    /// version. They should be enabled from the start, as the stats of a tree
+    /// that already has records would start from zero.
+    pub const fn with_stats(self, namespace: &'a str) -> Self {
+        Self {
+            stats: Some(VersionStats::new(namespace)),
+            ..self
+        }
+    }
+
+    /// Maintain in each node the sum of the weights of all records in its
+    /// subtree, where `weight` gives the weight of each record. For example,
+    /// the weight can be the amount of tokens staked, so that the total amount
+    /// staked under any key prefix can be queried and proven.
+    ///
+    /// The aggregate is part of the node's hash, so enabling this changes the
+    /// root hash. It should be enabled from the start. If enabled on an
+    /// existing tree, aggregates become available only for subtrees that have
+    /// been rewritten since.
+    pub const fn with_aggregation(self, weight: fn(&K, &V) -> u128) -> Self {
+        Self {
+            aggregation: Some(weight),
+            ..self
+        }
+    }
+
+    /// Maintain in each node the number of records in its subtree. This is
+    /// `with_aggregation` where every record weighs 1.
+    pub const fn with_subtree_counts(self) -> Self {
+        self.with_aggregation(count_one)
+    }
+
+    /// Tag the root hash with a domain, e.g. the name of the application, so
+    /// that two trees with identical contents but different domains have
+    /// different root hashes. This prevents a proof against one tree from
+    /// being replayed against the other.
+    ///
+    /// The root hash becomes `domain_root_hash(domain, root_node_hash)`. Proofs
+    /// must be verified using the `*_in_domain` verification functions. Node
+    /// hashes are not affected, so this can be enabled on an existing tree.
+    pub const fn with_domain(self, domain: &'a [u8]) -> Self {
+        assert!(domain.len() <= u16::MAX as usize, "domain must be no longer than 65535 bytes");
+        Self {
+            domain: Some(domain),
+            ..self
+        }
+    }
+
+    /// Commit a new version on every `apply`, even if the batch doesn't change
+    /// the tree, so that the tree's version can track e.g. the block height.
+    ///
+    /// By default, the version isn't incremented if the root node is unchanged,
+    /// so the versions skipped have no root. With this enabled, a copy of the
+    /// root node, which we call a root alias, is saved under the new version
+    /// instead, so that every version can be queried and proven as usual. The
+    /// alias costs one node write, and the node under the old version becomes
+    /// orphaned and can be pruned, the same as if the root had been updated.
+    pub const fn with_root_aliases(self) -> Self {
+        Self {
+            root_aliases: true,
+            ..self
+        }
+    }
+
+    /// Maintain a bloom filter of the keys in each version, `num_bytes` large
+    /// and using `num_hashes` hash functions, stored under the given namespace.
+    /// `maybe_contains` then tells whether a key may exist at a version without
+    /// loading any node, so that e.g. a proof server can skip obviously absent
+    /// keys. Each version's filter is rewritten in full when it's committed,
+    /// so this costs `num_bytes` of storage writes per version.
+    ///
+    /// The filters only grow: deleted keys remain "maybe present". Filters are
+    /// pruned along with the versions they belong to. This should be enabled
+    /// from the start; on an existing tree, no filter is ever built.
+    pub const fn with_key_filter(
+        self,
+        namespace: &'a str,
+        num_bytes: usize,
+        num_hashes: u32,
+    ) -> Self {
+        Self {
+            key_filters: Some(KeyFilters::new(namespace, num_bytes, num_hashes)),
+            ..self
+        }
+    }
+
+    /// Set what `get` does when queried at a version that has been pruned. By
+    /// default, it returns an error. Applications that prefer to degrade
+    /// gracefully can serve values without proofs where possible instead; see
+    /// `PrunedVersionPolicy`.
+    pub const fn with_pruned_version_policy(self, policy: PrunedVersionPolicy) -> Self {
+        Self {
+            pruned_version_policy: policy,
+            ..self
+        }
+    }
+
+    /// Choose when a leaf that is left as the only child of its parent is
+    /// moved up to take the parent's place. By default, it always is, which
+    /// keeps the tree shallow; proof consumers that prefer stable node paths
+    /// can restrict this. See `CollapsePolicy`.
+    ///
+    /// The policy determines the shape of the tree, and thus the root hash, so
+    /// it must not be changed on an existing tree, and must be the same across
+    /// all nodes that need to agree on the root hash.
+    pub const fn with_collapse_policy(self, policy: CollapsePolicy) -> Self {
+        Self {
+            collapse_policy: policy,
+            ..self
+        }
+    }
+
+    /// Include the version of each node in proofs, so that verifiers can check
+    /// with `verify_proof_versions` that versions never increase from the root
+    /// down. This helps catch implementation bugs, at the cost of larger
+    /// proofs. Versions are not hashed, so this doesn't change any hash.
+    pub const fn with_versioned_proofs(self) -> Self {
+        Self {
+            versioned_proofs: true,
+            ..self
+        }
+    }
+
+    /// Encode the proofs returned by `get`, `get_with_budget`, `next_key`,
+    /// `prev_key` and `sample`, as well as those of `Get` requests to
+    /// `prove_queries` and of an `Exporter`, in the given encoding. Defaults to
+    /// JSON. Proofs of multiple keys, of aggregates or of subtrees are always
+    /// JSON.
+    pub const fn with_proof_encoding(self, encoding: ProofEncoding) -> Self {
+        Self {
+            proof_encoding: encoding,
+            ..self
+        }
+    }
+
+    /// Bring keys into a canonical form, e.g. lowercase, or Unicode NFC for
+    /// strings, before they are written or looked up. Keys that only differ in
+    /// form then refer to the same record, instead of becoming distinct records
+    /// by mistake.
+    ///
+    /// `apply` refuses a batch in which two keys have the same canonical form,
+    /// as it's ambiguous which op should win. Responses carry the canonical
+    /// key, which is what proofs are verified against. Range bounds and
+    /// prefixes are not normalized.
+    ///
+    /// Normalizing a canonical key must return it unchanged. This should be set
+    /// from the start, as existing keys are not normalized retroactively.
+    pub const fn with_key_normalizer(self, normalize: fn(&K) -> K) -> Self {
+        Self {
+            key_normalizer: Some(normalize),
+            ..self
+        }
+    }
+
+    /// Map every key given to the tree before it's used, e.g. to put all keys
+    /// under a static prefix, or to replace them with a digest. The mapping is
+    /// applied consistently by `apply`, `get` and other queries, and after
+    /// normalizing, if the tree has a normalizer. Responses carry the mapped
+    /// key, which is what proofs are verified against.
+    ///
+    /// Unlike a normalizer, the mapping needn't return mapped keys unchanged,
+    /// as it's only ever applied once. It's also applied to the bounds of
+    /// `iterate`, which is only meaningful if it preserves the order of keys,
+    /// as a prefix does but a digest doesn't. Other prefixes are not mapped.
+    ///
+    /// If two keys of a batch are mapped to the same key, `apply` refuses it.
+    /// An `Overlay` holds its keys unmapped, so its batch can be applied to the
+    /// tree. This should be set from the start, as existing keys are not mapped
+    /// retroactively.
+    pub const fn with_key_transform(self, transform: fn(&K) -> K) -> Self {
+        Self {
+            key_transform: Some(transform),
+            ..self
+        }
+    }
+
+    // the proof node of a node under the given version. the version is only
+    // included if proofs are versioned
+    pub(crate) fn proof_node(
+        &self,
+        version: u64,
+        node: Node<K, V>,
+        drop_child_at_index: Option<Nibble>,
+        drop_data: bool,
+    ) -> ProofNode<K, V> {
+        let proof_node = ProofNode::from_node(node, drop_child_at_index, drop_data);
+        if self.versioned_proofs {
+            proof_node.with_version(version)
+        } else {
+            proof_node
+        }
+    }
+
+    // a proof of a single key, in the tree's proof encoding
+    pub(crate) fn encode_proof(&self, proof: &Proof<K, V>) -> StdResult<Binary>
+    where
+        K: Serialize + DeserializeOwned,
+        V: Serialize + DeserializeOwned,
+    {
+        match self.proof_encoding {
+            ProofEncoding::Json => to_binary(proof),
+            ProofEncoding::Binary => proof.to_bytes().map(Binary),
+        }
+    }
+
+    // the root hash of the tree given its root node, which is the root node's
+    // hash, tagged with the domain if there is one
+    pub(crate) fn root_hash(&self, root_node: &Node<K, V>) -> Hash
+    where
+        K: AsRef<[u8]>,
+        V: HashableValue,
+        H: TreeHasher,
+    {
+        let root_node_hash = root_node.hash_using::<H>();
+        match self.domain {
+            Some(domain) => domain_root_hash_using::<H>(domain, &root_node_hash),
+            None => root_node_hash,
+        }
+    }
+
+    // orphans are indexed by the epoch during which they became orphaned
+    pub(crate) fn mark_node_as_orphaned(
+        &self,
+        store: &mut dyn Storage,
+        orphaned_since_version: u64,
+        node_key: &NodeKey,
+    ) -> StdResult<()> {
+        let epoch = orphaned_since_version / self.orphan_epoch_len;
+        if let Some(churn_counters) = &self.churn_counters {
+            let bytes = self.nodes.encoded_len(store, node_key).unwrap_or(0);
+            churn_counters.add(store, epoch, bytes as u64)?;
+        }
+        self.orphans.insert(store, (epoch, node_key))
+    }
+
+    /// Record a header for every version committed, under the given namespace.
+    /// Each header includes the root hash of the previous version, forming a
+    /// hash chain of the tree's commitments, which auditors can check for
+    /// continuity using `verify_version_chain`.
+    ///
+    /// This costs one node read and one write per version. Headers are not
+    /// pruned along with the versions, so the chain remains complete.
+    pub const fn with_version_headers(self, namespace: &'a str) -> Self {
+        Self {
+            version_headers: Some(VersionHeaders::new(namespace)),
+            ..self
+        }
+    }
+
+    /// Accumulate the root hash of every version committed in a merkle mountain
+    /// range, whose nodes are kept under the second namespace, indexed by
+    /// version under the first. Its root, the history root, commits to the
+    /// root hashes of all versions so far, so a chain can include it in block
+    /// headers, and later show with `prove_historical_root` that an old root,
+    /// e.g. one referenced by a fraud proof, was indeed committed.
+    ///
+    /// This costs two writes per version, plus one for each node merged, which
+    /// is one on average. Only the versions committed after this is enabled
+    /// are recorded, and the history is not pruned along with the versions.
+    pub const fn with_root_history(
+        self,
+        roots_namespace: &'a str,
+        nodes_namespace: &'a str,
+    ) -> Self {
+        Self {
+            root_history: Some(RootHistory::new(roots_namespace, nodes_namespace)),
+            ..self
+        }
+    }
+
+    /// Allow keys to be given an expiration time using `set_expiration`, after
+    /// which `expire` deletes them, e.g. for sessions or allowances that
+    /// shouldn't accumulate forever. Expiration times are kept under the first
+    /// namespace, and indexed by time under the second.
+    ///
+    /// Times are in whatever unit the application chooses, e.g. block time in
+    /// seconds. They are not part of the tree, so they are neither versioned
+    /// nor proven. Every op applied to a key clears its expiration time, which
+    /// costs one storage read per op.
+    pub const fn with_expiration(self, times_namespace: &'a str, index_namespace: &'a str) -> Self {
+        Self {
+            expirations: Some(Expirations::new(times_namespace, index_namespace)),
+            ..self
+        }
+    }
+
+    /// Record every op applied to the tree in a journal under the given
+    /// namespace, along with the version it was committed in, as an audit
+    /// trail. Entries are hash-chained, so that altering, inserting or removing
+    /// any of them changes the hash of the latest one; see `verify_journal`.
+    ///
+    /// Ops of batches that don't change the tree create no version, so they
+    /// are not recorded. The journal is never pruned, so it grows with every op.
+    /// This should be enabled from the start, as ops applied before are not
+    /// recorded.
+    #[cfg(feature = "journal")]
+    pub const fn with_journal(self, namespace: &'a str) -> Self {
+        Self {
+            journal: Some(Journal::new(namespace)),
+            ..self
+        }
+    }
+
+    /// Keep histograms of the sizes of proofs under the given namespace, which
+    /// `proof_metrics` returns, so that operators can tell from the proofs
+    /// actually served whether the tree is growing deeper than expected. See
+    /// `ProofMetrics`.
+    ///
+    /// Proofs are only counted when passed to `record_proof`, which costs one
+    /// read and one write each.
+    #[cfg(feature = "proof-metrics")]
+    pub const fn with_proof_metrics(self, namespace: &'a str) -> Self {
+        Self {
+            proof_metrics: Some(Item::new(namespace)),
+            ..self
+        }
+    }
+
+    // the canonical form of a key, if the tree has a normalizer
+    pub(crate) fn canonical_key<'k>(&self, key: &'k K) -> Cow<'k, K>
+    where
+        K: Clone,
+    {
+        match self.key_normalizer {
+            Some(normalize) => Cow::Owned(normalize(key)),
+            None => Cow::Borrowed(key),
+        }
+    }
+
+    // same as `canonical_key`, for owned keys
+    pub(crate) fn canonical_owned_key(&self, key: K) -> K {
+        match self.key_normalizer {
+            Some(normalize) => normalize(&key),
+            None => key,
+        }
+    }
+
+    // the form a key is stored under: its canonical form, then mapped if the
+    // tree has a key transform. unlike normalizing, this must only be done
+    // once, so it's not for keys that are already stored
+    pub(crate) fn normalize_key<'k>(&self, key: &'k K) -> Cow<'k, K>
+    where
+        K: Clone,
+    {
+        let key = self.canonical_key(key);
+        match self.key_transform {
+            Some(transform) => Cow::Owned(transform(&key)),
+            None => key,
+        }
+    }
+
+    // same as `normalize_key`, for owned keys
+    pub(crate) fn normalize_owned_key(&self, key: K) -> K {
+        self.mapped_owned_key(self.canonical_owned_key(key))
+    }
+
+    // the form a key that is already canonical is stored under, i.e. mapped if
+    // the tree has a key transform
+    pub(crate) fn mapped_owned_key(&self, key: K) -> K {
+        match self.key_transform {
+            Some(transform) => transform(&key),
+            None => key,
+        }
+    }
+
+    /// The namespaces under which the tree's version, nodes and orphans are
+    /// stored, in that order, followed by those of the key filters, the version
+    /// headers, the root history, the expiration times, the churn counters, the counters, the
+    /// stats, the journal and the proof metrics if enabled.
+    /// See `check_namespaces`.
+    pub fn namespaces(&self) -> Vec<&[u8]> {
+        let mut namespaces =
+            vec![self.version.as_slice(), self.nodes.namespace(), self.orphans.namespace()];
+        if let Some(key_filters) = &self.key_filters {
+            namespaces.push(key_filters.namespace());
+        }
+        if let Some(version_headers) = &self.version_headers {
+            namespaces.push(version_headers.namespace());
+        }
+        if let Some(root_history) = &self.root_history {
+            namespaces.extend(root_history.namespaces());
+        }
+        if let Some(expirations) = &self.expirations {
+            namespaces.extend(expirations.namespaces());
+        }
+        if let Some(churn_counters) = &self.churn_counters {
+            namespaces.push(churn_counters.namespace());
+        }
+        if let Some(counters) = &self.counters {
+            namespaces.push(counters.as_slice());
+        }
+        if let Some(stats) = &self.stats {
+            namespaces.push(stats.namespace());
         }
+        #[cfg(feature = "journal")]
+        if let Some(journal) = &self.journal {
+            namespaces.push(journal.namespace());
+        }
+        #[cfg(feature = "proof-metrics")]
+        if let Some(proof_metrics) = &self.proof_metrics {
+            namespaces.push(proof_metrics.as_slice());
+        }
+        namespaces
     }
 
     /// Create a `Tree` using the default namespaces.
@@ -82,15 +666,19 @@ impl<'a, K, V> Tree<'a, K, V> {
 
 // note: whereas other common storage primitives (such as Item, Map) only
 // requires K, V to implement cw_serde traits (namely Serialize + DeserializedOwned)
-// we additionally require AsRef<[u8]>. there are two uses for this:
+// we additionally require K: AsRef<[u8]> and V: HashableValue. these are used
+// for:
 // - conversion of K into NibblePath
 // - hashing K and V
 // most types that you'll typically use implement AsRef<[u8]>, such as Vec<u8>
-// and String.
-impl<'a, K, V> Tree<'a, K, V>
+// and String, which implies HashableValue. for other value types, such as
+// structs, implement HashableValue to define how they're committed.
+impl<'a, K, V, C, H> Tree<'a, K, V, C, H>
 where
     K: Serialize + DeserializeOwned + Clone + PartialEq + AsRef<[u8]>,
-    V: Serialize + DeserializeOwned + Clone + PartialEq + AsRef<[u8]>,
+    V: Serialize + DeserializeOwned + Clone + PartialEq + HashableValue,
+    C: NodeCodec<K, V>,
+    H: TreeHasher,
 {
     /// Apply a batch of ops to the tree. Each op can be either 1) inserting a
     /// value at a key, or 2) deleting a key.
@@ -106,710 +694,5210 @@ where
     ///   method to write the changes to to disk, while resetting its in-memory
     ///   to empty, getting ready for the next block.
     ///
-    /// Note: keys must not be empty, but we don't assert it here.
-    pub fn apply(&self, store: &mut dyn Storage, batch: Batch<K, V>) -> Result<()> {
+    /// `stage` provides such an in-memory batch, which reads reflect.
+    ///
+    /// Returns the records that have been changed, e.g. to be emitted as
+    /// events.
+    ///
+    /// Errors with `TreeError::EmptyKey` if any key is empty, or with
+    /// `TreeError::KeyTooLong` if any is longer than `MAX_KEY_LEN` bytes.
+    pub fn apply(&self, store: &mut dyn Storage, batch: Batch<K, V>) -> Result<ChangeSet<K>> {
         let old_version = self.version.may_load(store)?.unwrap_or(0);
-        let old_root_key = NodeKey::root(old_version);
+        self.apply_as(store, batch, old_version, next_version(old_version)?)
+    }
+
+    /// Same as `apply`, but the batch is committed as the given version instead
+    /// of the one after the latest, e.g. so that the tree's versions equal the
+    /// chain's block heights, even if the tree is created at a later height.
+    /// The version must be newer than the latest one; versions in between are
+    /// skipped, and can't be queried.
+    ///
+    /// As with `apply`, no version is created if the batch doesn't change the
+    /// tree, unless the tree is created using `with_root_aliases`.
+    pub fn apply_at<T: Version>(
+        &self,
+        store: &mut dyn Storage,
+        batch: Batch<K, V>,
+        version: T,
+    ) -> Result<ChangeSet<K>> {
+        let old_version = self.version.may_load(store)?.unwrap_or(0);
+        let new_version = version.to_u64();
+        if new_version <= old_version {
+            return Err(TreeError::VersionNotNewer {
+                latest: old_version,
+                version: new_version,
+            });
+        }
+        self.apply_as(store, batch, old_version, new_version)
+    }
+
+    /// Return the latest version as the application's version type. See
+    /// `Version`.
+    pub fn latest_version<T: Version>(&self, store: &dyn Storage) -> Result<Option<T>> {
+        let Some(version) = self.version.may_load(store)? else {
+            return Ok(None);
+        };
+        T::from_u64(version).map(Some).ok_or(TreeError::VersionNotRepresentable {
+            version,
+        })
+    }
+
+    // apply a batch on top of the old version, committing it as the new one
+    fn apply_as(
+        &self,
+        store: &mut dyn Storage,
+        batch: Batch<K, V>,
+        old_version: u64,
+        new_version: u64,
+    ) -> Result<ChangeSet<K>> {
+        // collect the batch into a sorted Vec, also normalizing the keys and
+        // converting them to NibblePaths
+        let batch = self.normalize_batch(batch)?;
+        self.apply_prepared(store, batch, old_version, new_version)
+    }
 
+    // same as `apply_as`, for a batch whose keys are already in the form they
+    // are stored under
+    fn apply_prepared(
+        &self,
+        store: &mut dyn Storage,
+        batch: Vec<(NibblePath, K, Op<V>)>,
+        old_version: u64,
+        new_version: u64,
+    ) -> Result<ChangeSet<K>> {
         // note: we don't save the new version to store just yet, unless we know
         // the root node has been changed.
-        let new_version = old_version + 1;
-
-        // collect the batch into a sorted Vec, also converting the string keys
-        // to NibblePaths
-        let batch = batch
-            .into_iter()
-            .map(|(key, op)| (NibblePath::from(&key), key, op))
-            .collect::<Vec<_>>();
+        let old_root_key = NodeKey::root(old_version);
 
         // recursively apply the batch, starting from the root (depth = 0)
-        match self.apply_at(
-            store,
-            new_version,
-            &old_root_key,
-            None,
-            &batch,
-        )? {
-            OpResponse::Updated(updated_root_node) => {
-                self.set_version(store, new_version)?;
-                self.create_node(store, new_version, NibblePath::empty(), &updated_root_node)?;
-                if old_version > 0 {
-                    self.mark_node_as_orphaned(store, new_version, &old_root_key)?;
-                }
-            },
-            OpResponse::Deleted => {
-                self.set_version(store, new_version)?;
-                if old_version > 0 {
-                    self.mark_node_as_orphaned(store, new_version, &old_root_key)?;
-                }
-            },
-            OpResponse::Unchanged => {
-                // do nothing. note that we don't increment the version if the
-                // root node is not changed.
-            },
-        }
-
-        Ok(())
+        self.apply_prepared_with(store, batch, old_version, new_version, |nodes, batch| {
+            apply_subtree(nodes, new_version, &old_root_key, None, batch)
+        })
     }
 
-    fn apply_at(
+    // same as `apply_prepared`, where the batch is applied to the tree's nodes
+    // by the given function, given the nodes and the batch
+    pub(crate) fn apply_prepared_with<F>(
         &self,
         store: &mut dyn Storage,
-        version: u64,
-        current_node_key: &NodeKey,
-        current_node: Option<Node<K, V>>,
-        // some basic rust knowledge here: the following are different!
-        //
-        // mut batch: &T
-        // this means that `batch` can be pointed to a different T instance
-        //
-        // batch: &mut T
-        // this means that the T instance that `batch` points to can be mutated
-        mut batch: &[(NibblePath, K, Op<V>)],
-    ) -> Result<OpResponse<K, V>> {
-        // attempt to load the node. if not found, we simply create a new empty
-        // node (no children, no data)
-        let mut current_node = if let Some(node) = current_node {
-            node
-        } else {
-            self.nodes.may_load(store, current_node_key)?.unwrap_or_else(Node::new)
-        };
-
-        // make a mutable clone of the current node. after we've executed the
-        // ops, we will compare with the original whether it has been changed
-        let current_node_before = current_node.clone();
-
-        // a cache of the current node's children that have been changed.
-        // we don't want to write these nodes to store immediately, because if
-        // the current node ends up having only one child, we will need to
-        // collapse the path (i.e. delete the current node, move the only child
-        // one level up)
-        let mut updated_child_nodes = HashMap::new();
-
-        // if the node has data, and the data's key doesn't exactly equal the
-        // node's nibble path, we take it out and insert it into the batch.
-        // we call this the "dangling_data"
-        let mut dangling_data = None;
-        if let Some(Record { key, .. }) = &current_node.data {
-            // in a previously bugged implementation, here we simply compared
-            // key.as_bytes() and current_nibble_path.bytes; this misses the
-            // case where the nibble path may have odd number of nibbles and
-            // the last nibble in the key may be zero
-            if NibblePath::from(key) != current_node_key.nibble_path {
-                dangling_data = current_node.data.take();
-            }
-        }
-
-        // what this part means is a bit hard to explain...
-        //
-        // basically if there is a key in the batch that is an exact match with
-        // the current node's nibble path, then it is necessarily the first item
-        // in the batch (I don't have a rigorous proof, but empirically this is
-        // true)
-        //
-        // if this is the case, we apply the op at the current node, and remove
-        // this item from the batch.
-        //
-        // additionally, if this node originally had data is will be overwritten
-        // here, we take it out as "dangling data" and insert it later
-        if batch[0].0 == current_node_key.nibble_path {
-            current_node.apply_op(&batch[0]);
-            batch = &batch[1..];
-        }
-
-        // insert the dangling data into the batch
-        //
-        // note: only insert if the key isn't already in the batch. if it's
-        // already in, it will be overwritten anyways so we just discard it
-        //
-        // this requires copying the batch in memory (slice --> vec) which is
-        // slow, but i don't have a good idea to improve on this
-        // basically we have to do this to allow iteration (if we hash the keys,
-        // there would be no dangling data but no iteration either). it's a
-        // tradeoff between performance and feature, and is one that we're
-        // willing to make (iteration is such as important feature)
-        let mut owned_batch;
-        let batch = if let Some(Record { key, value }) = dangling_data {
-            let nibble_path = NibblePath::from(&key);
-            owned_batch = batch.to_vec();
-            if let Err(pos) = batch.binary_search_by_key(&&nibble_path, |(nibble_path, _, _)| nibble_path) {
-                owned_batch.insert(pos, (nibble_path, key, Op::Insert(value)));
-            }
-            owned_batch.as_slice()
-        } else {
-            batch
-        };
-
-        // now, if there is only one item left in the batch AND one of the
-        // following is satisfied, then we apply the op at the current node:
-        //
-        // - the current node is a leaf, and the key matches exactly the nibble
-        //   path we want to write to
-        // - the current node has neither any child nor data
-        //
-        // if this condition is not satisfied, we need to dispatch the ops to
-        // the current node's children.
-        if batch.len() == 1 && current_node.is_empty() {
-            current_node.apply_op(&batch[0]);
-        } else {
-            let nibble_range_iter = NibbleRangeIterator::new(batch, current_node_key.depth());
-            for NibbleRange { nibble, start, end } in nibble_range_iter {
-                let child = current_node.children.get(nibble);
-                let child_version = child.map(|c| c.version).unwrap_or(version);
-                let child_node_key = current_node_key.child(child_version, nibble);
-
-                match self.apply_at(
-                    store,
-                    version,
-                    &child_node_key,
-                    updated_child_nodes.remove(&nibble),
-                    &batch[start..=end],
-                )? {
-                    OpResponse::Updated(updated_child_node) => {
-                        current_node.children.insert(Child {
-                            index: nibble,
-                            version,
-                            hash: updated_child_node.hash(),
-                        });
-
-                        if child_node_key.version < version {
-                            self.mark_node_as_orphaned(store, version, &child_node_key)?;
-                        }
-
-                        updated_child_nodes.insert(nibble, updated_child_node);
-                    },
-                    OpResponse::Deleted => {
-                        current_node.children.remove(nibble);
-                        if child_node_key.version < version {
-                            self.mark_node_as_orphaned(store, version, &child_node_key)?;
-                        }
-                    },
-                    OpResponse::Unchanged => (),
-                }
-            }
+        batch: Vec<(NibblePath, K, Op<V>)>,
+        old_version: u64,
+        new_version: u64,
+        apply: F,
+    ) -> Result<ChangeSet<K>>
+    where
+        F: FnOnce(
+            &mut TreeNodes<'a, '_, K, V, C, H>,
+            &[(NibblePath, K, Op<V>)],
+        ) -> Result<OpResponse<K, V>>,
+    {
+        if old_version == 0 {
+            self.check_no_leftovers(store)?;
         }
 
-        // if the current node has neither any child nor data, then it should be
-        // deleted
-        if current_node.is_empty() {
-            return Ok(OpResponse::Deleted);
+        let mut key_filter = self.key_filter_to_update(store, old_version)?;
+        if let Some(key_filter) = &mut key_filter {
+            key_filter.insert_batch(&batch);
         }
 
-        // if the current node has no data and exactly 1 child, and this child
-        // is a leaf node, then the path can be collapsed (i.e. the current node
-        // deleted, and that child leaf node moved on level up)
-        if current_node.data.is_none() && current_node.children.count() == 1 {
-            let child = current_node.children.get_only();
-            if let Some(child_node) = updated_child_nodes.get(&child.index) {
-                if child_node.is_leaf() {
-                    return Ok(OpResponse::Updated(child_node.clone()));
-                }
-            } else {
-                let child_node_key = current_node_key.child(child.version, child.index);
-                let child_node = self.nodes.load(store, &child_node_key)?;
-                if child_node.is_leaf() {
-                    self.mark_node_as_orphaned(store, version, &child_node_key)?;
-                    return Ok(OpResponse::Updated(child_node));
-                }
-            };
-        }
+        self.clear_expirations(store, &batch)?;
 
-        // now we know the current node won't be deleted or collapsed,
-        // we can write the updated child nodes
-        for (nibble, node) in updated_child_nodes {
-            let nibble_path = current_node_key.nibble_path.child(nibble);
-            self.create_node(store, version, nibble_path, &node)?;
-        }
+        let mut nodes = TreeNodes {
+            tree: self,
+            store: &mut *store,
+            changes: Some(BTreeMap::new()),
+            nodes_written: 0,
+            stats: self.stats_delta(),
+        };
+        let response = apply(&mut nodes, &batch)?;
+        let nodes_written = nodes.nodes_written;
+        let stats = nodes.stats;
+        let changes = nodes.changes.unwrap_or_default();
 
-        if current_node != current_node_before {
-            return Ok(OpResponse::Updated(current_node));
+        #[cfg(feature = "journal")]
+        if !matches!(response, OpResponse::Unchanged) {
+            self.journal_batch(store, new_version, &batch)?;
         }
 
-        Ok(OpResponse::Unchanged)
-    }
-
-    pub fn prune(&self, store: &mut dyn Storage, up_to_version: Option<u64>) -> Result<()> {
-        let end = up_to_version.map(PrefixBound::inclusive);
+        let version = self.commit_root(
+            store,
+            old_version,
+            new_version,
+            &response,
+            key_filter.as_ref(),
+            nodes_written,
+            stats,
+        )?;
 
-        loop {
-            let batch = self
-                .orphans
-                .prefix_range(store, None, end.clone(), Order::Ascending)
-                .take(PRUNE_BATCH_SIZE)
-                .collect::<StdResult<Vec<_>>>()?;
+        // a record that has been moved is reported as removed, then written
+        // again with the same value
+        let changes = changes
+            .into_values()
+            .filter(|change| change.old_value_hash != change.new_value_hash)
+            .collect();
 
-            for (stale_since_version, node_key) in &batch {
-                self.nodes.remove(store, node_key);
-                self.orphans.remove(store, (*stale_since_version, node_key));
-            }
+        Ok(ChangeSet {
+            version,
+            changes,
+        })
+    }
 
-            if batch.len() < PRUNE_BATCH_SIZE {
-                break;
-            }
+    /// Same as `prepare_batch`, but with the keys normalized and mapped first,
+    /// if the tree has a normalizer or a key transform. As that may change
+    /// their order, the batch is sorted again; keys that end up the same are
+    /// adjacent, for `apply_subtree` to refuse as duplicates.
+    pub(crate) fn normalize_batch(
+        &self,
+        batch: Batch<K, V>,
+    ) -> Result<Vec<(NibblePath, K, Op<V>)>> {
+        if self.key_normalizer.is_none() && self.key_transform.is_none() {
+            return prepare_batch(batch);
         }
 
-        Ok(())
+        let mut ops = batch
+            .into_iter()
+            .map(|(key, op)| (self.normalize_owned_key(key), op))
+            .collect::<Vec<_>>();
+        // the sort is stable, so which op comes first among duplicates doesn't
+        // depend on anything but the order of the original keys
+        ops.sort_by(|(a, _), (b, _)| a.as_ref().cmp(b.as_ref()));
+        prepare_batch(ops)
     }
 
-    fn version_or_default(&self, store: &dyn Storage, version: Option<u64>) -> StdResult<u64> {
-        if let Some(version) = version {
-            Ok(version)
-        } else {
-            self.version.load(store)
-        }
+    /// Same as `apply`, but for batches too large to be held in memory at once,
+    /// such as genesis imports or airdrops. Ops are taken from the iterator
+    /// `chunk_size` at a time, and each chunk is applied on top of the previous
+    /// one before the next is read; the nodes it updates are flushed to
+    /// storage, so only one chunk and the root node are kept in memory.
+    ///
+    /// All chunks are committed as a single new version, with the same result
+    /// as applying all ops in one batch. If a key occurs more than once, the
+    /// last op wins. This includes keys with the same canonical form, if the
+    /// tree has a normalizer, and keys mapped to the same key by its key
+    /// transform.
+    ///
+    /// Ops should be sorted by keys. This isn't required for correctness, but
+    /// otherwise chunks touch overlapping parts of the tree, and the same nodes
+    /// are rewritten many times.
+    pub fn apply_streaming<I>(
+        &self,
+        store: &mut dyn Storage,
+        ops: I,
+        chunk_size: usize,
+    ) -> Result<()>
+    where
+        K: Ord,
+        I: IntoIterator<Item = (K, Op<V>)>,
+    {
+        assert!(chunk_size > 0, "chunk size must be non-zero");
+
+        let old_version = self.version.may_load(store)?.unwrap_or(0);
+        let new_version = next_version(old_version)?;
+
+        let mut ops =
+            ops.into_iter().map(|(key, op)| (self.normalize_owned_key(key), op)).peekable();
+        let chunks = iter::from_fn(|| {
+            ops.peek()?;
+            Some(prepare_batch(ops.by_ref().take(chunk_size).collect::<Batch<_, _>>()))
+        });
+
+        self.apply_chunks(store, chunks, old_version, new_version)
     }
 
-    fn set_version(&self, store: &mut dyn Storage, version: u64) -> StdResult<()> {
-        self.version.save(store, &version)
+    // apply chunks of prepared ops on top of `old_version` one by one, as
+    // described in `apply_streaming`, and commit them as `new_version`
+    pub(crate) fn apply_chunks<I>(
+        &self,
+        store: &mut dyn Storage,
+        chunks: I,
+        old_version: u64,
+        new_version: u64,
+    ) -> Result<()>
+    where
+        I: IntoIterator<Item = Result<Vec<(NibblePath, K, Op<V>)>>>,
+    {
+        // what has happened to the root node so far. until it's changed, each
+        // chunk is applied to the root of the old version; from then on, to
+        // the updated root, which is kept in memory and only saved at the end
+        let mut response = OpResponse::Unchanged;
+
+        if old_version == 0 {
+            self.check_no_leftovers(store)?;
+        }
+
+        let mut key_filter = self.key_filter_to_update(store, old_version)?;
+        let mut nodes_written = 0;
+        let mut stats = self.stats_delta();
+
+        for batch in chunks {
+            let batch = batch?;
+            if let Some(key_filter) = &mut key_filter {
+                key_filter.insert_batch(&batch);
+            }
+
+            self.clear_expirations(store, &batch)?;
+
+            // whether the new version is created is only known at the end, so
+            // the chunk is journaled now, and taken back if it isn't
+            #[cfg(feature = "journal")]
+            self.journal_batch(store, new_version, &batch)?;
+
+            let (root_node_key, root_node) = match &response {
+                OpResponse::Unchanged => (NodeKey::root(old_version), None),
+                OpResponse::Updated(root_node) => {
+                    (NodeKey::root(new_version), Some(root_node.clone()))
+                },
+                // the root node under the new version doesn't exist in storage,
+                // so it's loaded as an empty node
+                OpResponse::Deleted => (NodeKey::root(new_version), None),
+            };
+
+            let mut nodes = TreeNodes {
+                tree: self,
+                store: &mut *store,
+                // not collected, as they would take memory proportional to the
+                // number of ops
+                changes: None,
+                nodes_written: 0,
+                stats: stats.take(),
+            };
+            match apply_subtree(&mut nodes, new_version, &root_node_key, root_node, &batch)? {
+                OpResponse::Unchanged => (),
+                changed => response = changed,
+            }
+            nodes_written += nodes.nodes_written;
+            stats = nodes.stats;
+        }
+
+        #[cfg(feature = "journal")]
+        if let (Some(journal), OpResponse::Unchanged) = (&self.journal, &response) {
+            journal.truncate(store, old_version)?;
+        }
+
+        self.commit_root(
+            store,
+            old_version,
+            new_version,
+            &response,
+            key_filter.as_ref(),
+            nodes_written,
+            stats,
+        )?;
+
+        Ok(())
     }
 
-    fn create_node(
+    /// Given what has happened to the root node after applying a batch on top
+    /// of `old_version`, save the new root node and version (usually the one
+    /// after `old_version`) if necessary,
+    /// along with the key filter of the new version, if any. Returns the
+    /// latest version after the commit.
+    ///
+    /// `nodes_written` is the number of nodes the batch has written besides
+    /// the root, to be counted if `with_counters` is enabled. Likewise, `stats`
+    /// are the changes the batch has made to the stats besides the root, if
+    /// `with_stats` is enabled.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn commit_root(
         &self,
         store: &mut dyn Storage,
-        version: u64,
-        nibble_path: NibblePath,
-        node: &Node<K, V>,
-    ) -> StdResult<()> {
-        self.nodes.save(store, &NodeKey::new(version, nibble_path), node)
+        old_version: u64,
+        new_version: u64,
+        response: &OpResponse<K, V>,
+        key_filter: Option<&KeyFilter>,
+        nodes_written: u64,
+        stats: Option<StatsDelta>,
+    ) -> Result<u64> {
+        let old_root_key = NodeKey::root(old_version);
+        let mut root_written = false;
+
+        // the version is bumped last, making it the commit point: until then,
+        // the nodes written under the new version are unreachable, so if the
+        // host crashes halfway, the latest version is still intact. see
+        // `recover` on how the partial writes are cleaned up
+        match response {
+            OpResponse::Updated(updated_root_node) => {
+                self.create_node(store, new_version, NibblePath::empty(), updated_root_node)?;
+                root_written = true;
+                if old_version > 0 {
+                    self.mark_node_as_orphaned(store, new_version, &old_root_key)?;
+                }
+            },
+            OpResponse::Deleted => {
+                if old_version > 0 {
+                    self.mark_node_as_orphaned(store, new_version, &old_root_key)?;
+                }
+            },
+            OpResponse::Unchanged => {
+                // by default, we don't increment the version if the root node
+                // is not changed
+                if !self.root_aliases {
+                    return Ok(old_version);
+                }
+                // the tree may be empty, in which case there's no root to alias
+                if let Some(root_node) = self.nodes.may_load(store, &old_root_key)? {
+                    self.create_node(store, new_version, NibblePath::empty(), &root_node)?;
+                    self.mark_node_as_orphaned(store, new_version, &old_root_key)?;
+                    root_written = true;
+                }
+            },
+        }
+
+        self.update_counters(store, |counters| {
+            counters.applies += 1;
+            counters.nodes_written += nodes_written + root_written as u64;
+        })?;
+
+        if let (Some(key_filters), Some(key_filter)) = (&self.key_filters, key_filter) {
+            key_filters.save(store, new_version, key_filter)?;
+        }
+
+        // an aliased root node replaces the old one, so only an updated or
+        // deleted root changes the stats
+        if let (Some(version_stats), Some(mut stats)) = (&self.stats, stats) {
+            if !matches!(response, OpResponse::Unchanged) {
+                if let Some(old_root_node) = self.nodes.may_load(store, &old_root_key)? {
+                    stats.remove_node(&old_root_node);
+                }
+            }
+            if let OpResponse::Updated(updated_root_node) = response {
+                stats.add_node(updated_root_node);
+            }
+            version_stats.save(store, old_version, new_version, &stats)?;
+        }
+
+        if self.version_headers.is_some() || self.root_history.is_some() {
+            let prev_root_hash = self.root_hash_at(store, old_version)?;
+            let root_hash = match response {
+                OpResponse::Updated(updated_root_node) => Some(self.root_hash(updated_root_node)),
+                OpResponse::Deleted => None,
+                // the root node has been aliased, if any
+                OpResponse::Unchanged => prev_root_hash.clone(),
+            };
+            if let Some(root_history) = &self.root_history {
+                root_history.append::<H>(store, new_version, root_hash.as_ref())?;
+            }
+            if let Some(version_headers) = &self.version_headers {
+                version_headers.save(store, &VersionHeader {
+                    version: new_version,
+                    root_hash,
+                    prev_version: old_version,
+                    prev_root_hash,
+                })?;
+            }
+        }
+
+        self.set_version(store, new_version)?;
+
+        Ok(new_version)
+    }
+
+    /// Delete nodes that are no longer part of the tree since `up_to_version`
+    /// or earlier. If orphans are grouped into epochs, only epochs that end at
+    /// or before `up_to_version` are pruned.
+    ///
+    /// Only orphaned nodes are deleted, which by definition aren't reachable
+    /// from the root of any version after the one they were orphaned in. So
+    /// reads at the retained versions are unaffected, including iterations
+    /// resumed from checkpoints taken before the prune. Nodes are deleted in
+    /// ascending order of the versions they were created in, so a prune that
+    /// is interrupted leaves the oldest versions broken, never newer ones.
+    ///
+    /// In a native multi-threaded embedding, this holds for readers of the
+    /// retained versions that share the storage backend with the pruning
+    /// thread, e.g. through separate RocksDB handles, as long as each deletion
+    /// is atomic. Readers of the versions being pruned may find nodes missing,
+    /// so the application should stop serving them before pruning.
+    pub fn prune(&self, store: &mut dyn Storage, up_to_version: Option<u64>) -> Result<()> {
+        let end = match up_to_version {
+            Some(version) => match self.last_epoch_ended(version) {
+                Some(epoch) => Some(epoch),
+                None => return Ok(()),
+            },
+            None => None,
+        };
+
+        self.prune_range(store, None, end.map(PrefixBound::inclusive), usize::MAX)?;
+        self.finish_prune(store, end)
     }
 
-    fn mark_node_as_orphaned(
+    /// Same as `prune`, but delete at most `max_nodes` nodes, oldest first, so
+    /// that a contract can prune incrementally across blocks without exceeding
+    /// the gas limit. Returns whether orphaned nodes up to `up_to_version`
+    /// remain to be pruned; call it again until it returns `false`.
+    ///
+    /// Only the oldest remaining entry of the orphan index is read to tell, so
+    /// the cost of a call doesn't grow with the backlog; use `orphan_count` to
+    /// count the remaining orphans. The churn counter of an epoch that is only
+    /// partially pruned is removed once the epoch is fully pruned, and so are
+    /// key filters once all epochs up to `up_to_version` are.
+    pub fn prune_with_limit(
         &self,
         store: &mut dyn Storage,
-        orphaned_since_version: u64,
-        node_key: &NodeKey,
-    ) -> StdResult<()> {
-        self.orphans.insert(store, (orphaned_since_version, node_key))
+        up_to_version: Option<u64>,
+        max_nodes: usize,
+    ) -> Result<bool> {
+        let end = match up_to_version {
+            Some(version) => match self.last_epoch_ended(version) {
+                Some(epoch) => Some(epoch),
+                None => return Ok(false),
+            },
+            None => None,
+        };
+
+        let bound = end.map(PrefixBound::inclusive);
+        self.prune_range(store, None, bound.clone(), max_nodes)?;
+
+        let first_remaining = self
+            .orphans
+            .prefix_range(store, None, bound, Order::Ascending)
+            .next()
+            .transpose()?;
+        let Some((first_epoch, _)) = first_remaining else {
+            self.finish_prune(store, end)?;
+            return Ok(false);
+        };
+
+        // the epochs before that of the oldest remaining orphan are complete
+        if let Some(churn_counters) = &self.churn_counters {
+            if let Some(epoch) = first_epoch.checked_sub(1) {
+                churn_counters.remove_up_to(store, Some(epoch))?;
+            }
+        }
+
+        Ok(true)
     }
 
-    pub fn root(&self, store: &dyn Storage, version: Option<u64>) -> Result<RootResponse> {
-        let version = self.version_or_default(store, version)?;
-        let root_node = self.root_node(store, version)?;
+    /// Count the orphaned nodes that are pending deletion: those that `prune`
+    /// would delete given the same `up_to_version`, and all of them. This reads
+    /// every entry of the orphan index, but not the nodes themselves.
+    pub fn orphan_count(
+        &self,
+        store: &dyn Storage,
+        up_to_version: Option<u64>,
+    ) -> Result<PruneStatus> {
+        let end = match up_to_version {
+            Some(version) => self.last_epoch_ended(version),
+            None => Some(u64::MAX),
+        };
 
-        Ok(RootResponse {
-            version,
-            root_hash: root_node.hash(),
-        })
+        let mut status = PruneStatus {
+            prunable: 0,
+            orphans: 0,
+        };
+        for item in self.orphans.items(store, None, None, Order::Ascending) {
+            let (epoch, _) = item?;
+            if end.is_some_and(|end| epoch <= end) {
+                status.prunable += 1;
+            }
+            status.orphans += 1;
+        }
+
+        Ok(status)
     }
 
-    fn root_node(&self, store: &dyn Storage, version: u64) -> Result<Node<K, V>> {
-        let root_node_key = NodeKey::root(version);
-        self.nodes
-            .may_load(store, &root_node_key)?
-            .ok_or(TreeError::RootNodeNotFound { version })
+    // once all orphans of the epochs up to the given one, or of all epochs,
+    // have been pruned, delete what only concerned the pruned versions
+    fn finish_prune(&self, store: &mut dyn Storage, end: Option<u64>) -> Result<()> {
+        if let Some(churn_counters) = &self.churn_counters {
+            churn_counters.remove_up_to(store, end)?;
+        }
+
+        // only the latest version remains if all orphans have been pruned
+        let first_remaining_version = match end {
+            Some(epoch) => self.first_remaining_version(epoch),
+            None => self.version.may_load(store)?.unwrap_or(0),
+        };
+        self.prune_key_filters(store, first_remaining_version)
     }
 
-    pub fn get(
+    /// Delete all nodes that became orphaned during the given epoch. See
+    /// `with_orphan_epochs`.
+    ///
+    /// Note that this doesn't check whether earlier epochs have been pruned.
+    /// Typically this is called on the oldest remaining epoch.
+    pub fn prune_epoch(&self, store: &mut dyn Storage, epoch: u64) -> Result<()> {
+        let bound = Some(PrefixBound::inclusive(epoch));
+        self.prune_range(store, bound.clone(), bound, usize::MAX)?;
+        if let Some(churn_counters) = &self.churn_counters {
+            churn_counters.remove(store, epoch);
+        }
+        self.prune_key_filters(store, self.first_remaining_version(epoch))
+    }
+
+    /// Prune up to `max_epochs` of the epochs that end at or before
+    /// `up_to_version`, choosing those whose orphaned nodes take up the most
+    /// bytes first. Returns the pruned epochs, in the order they were pruned.
+    /// See `with_churn_counters`.
+    ///
+    /// As epochs may be pruned out of order, the versions before the latest
+    /// pruned epoch should no longer be queried. Key filters are only pruned
+    /// by `prune` and `prune_epoch`, as the remaining versions aren't
+    /// contiguous.
+    pub fn prune_largest(
+        &self,
+        store: &mut dyn Storage,
+        up_to_version: Option<u64>,
+        max_epochs: usize,
+    ) -> Result<Vec<u64>> {
+        let churn_counters = self.churn_counters.as_ref().ok_or(TreeError::ChurnNotTracked)?;
+        let end = match up_to_version {
+            Some(version) => match self.last_epoch_ended(version) {
+                Some(epoch) => Some(epoch),
+                None => return Ok(vec![]),
+            },
+            None => None,
+        };
+
+        let epochs = churn_counters
+            .largest(store, end, max_epochs)?
+            .into_iter()
+            .map(|(epoch, _)| epoch)
+            .collect::<Vec<_>>();
+
+        for epoch in &epochs {
+            let bound = Some(PrefixBound::inclusive(*epoch));
+            self.prune_range(store, bound.clone(), bound, usize::MAX)?;
+            churn_counters.remove(store, *epoch);
+        }
+
+        Ok(epochs)
+    }
+
+    /// Return the number of bytes of the nodes orphaned during the given epoch
+    /// that are yet to be pruned. See `with_churn_counters`.
+    pub fn orphaned_bytes(&self, store: &dyn Storage, epoch: u64) -> Result<u64> {
+        let churn_counters = self.churn_counters.as_ref().ok_or(TreeError::ChurnNotTracked)?;
+        churn_counters.load(store, epoch).map_err(Into::into)
+    }
+
+    /// Whether the key may exist in the tree at the given version, according
+    /// to the key filter (see `with_key_filter`). If `false`, the key
+    /// definitely doesn't exist, and no node needs to be loaded to prove it.
+    ///
+    /// Returns `true` if there's no filter for the version, e.g. if it's been
+    /// pruned.
+    pub fn maybe_contains(
         &self,
         store: &dyn Storage,
         key: &K,
-        prove: bool,
         version: Option<u64>,
-    ) -> Result<GetResponse<K, V>> {
+    ) -> Result<bool> {
+        let key_filters = self.key_filters.as_ref().ok_or(TreeError::KeyFilterNotTracked)?;
         let version = self.version_or_default(store, version)?;
-        let nibble_path = NibblePath::from(&key);
+        let key = self.normalize_key(key);
+        match key_filters.may_load(store, version)? {
+            Some(key_filter) => Ok(key_filter.maybe_contains((*key).as_ref())),
+            None => Ok(true),
+        }
+    }
 
-        let (value, proof) = self.get_at(
-            store,
-            NodeKey::root(version),
-            &mut nibble_path.nibbles(),
-            prove,
-        )?;
+    /// Set the time at which a key expires, or clear it with `None`. The key is
+    /// deleted by the first call to `expire` at or after that time, unless an
+    /// op is applied to it in the meantime. See `with_expiration`.
+    pub fn set_expiration(
+        &self,
+        store: &mut dyn Storage,
+        key: &K,
+        expires_at: Option<u64>,
+    ) -> Result<()> {
+        let expirations = self.expirations.as_ref().ok_or(TreeError::ExpirationNotTracked)?;
+        let key = self.normalize_key(key);
+        match expires_at {
+            Some(time) => expirations.set(store, &key, time)?,
+            None => expirations.clear(store, &key)?,
+        }
+        Ok(())
+    }
 
-        let proof = if prove {
-            Some(to_binary(&proof)?)
-        } else {
-            None
-        };
+    /// Return the time at which a key expires, if any.
+    pub fn expiration(&self, store: &dyn Storage, key: &K) -> Result<Option<u64>> {
+        let expirations = self.expirations.as_ref().ok_or(TreeError::ExpirationNotTracked)?;
+        expirations.may_load(store, &self.normalize_key(key)).map_err(Into::into)
+    }
+
+    /// Delete up to `limit` keys that expire at or before `now`, earliest
+    /// first, by applying a batch as usual. Call this periodically, e.g. at
+    /// the end of each block, with a limit that bounds the gas spent. If no key
+    /// has expired, nothing is applied and no version is created.
+    pub fn expire(&self, store: &mut dyn Storage, now: u64, limit: usize) -> Result<ChangeSet<K>> {
+        let expirations = self.expirations.as_ref().ok_or(TreeError::ExpirationNotTracked)?;
+        let expired = expirations.expired(store, now, limit)?;
+        if expired.is_empty() {
+            return self.unchanged(store);
+        }
 
-        Ok(GetResponse { key: key.clone(), value, proof })
+        // the keys are stored ones, so they mustn't be mapped again. applying
+        // the batch also clears their expiration times
+        let mut ops = expired.into_iter().map(|key| (key, Op::Delete)).collect::<Vec<_>>();
+        ops.sort_by(|(a, _), (b, _)| a.as_ref().cmp(b.as_ref()));
+        let old_version = self.version.may_load(store)?.unwrap_or(0);
+        self.apply_prepared(store, prepare_batch(ops)?, old_version, next_version(old_version)?)
+    }
+
+    // the change set of an apply that is skipped, as it has no ops, which
+    // `apply_subtree` doesn't accept
+    pub(crate) fn unchanged(&self, store: &dyn Storage) -> Result<ChangeSet<K>> {
+        Ok(ChangeSet {
+            version: self.version.may_load(store)?.unwrap_or(0),
+            changes: vec![],
+        })
     }
 
-    fn get_at(
+    // clear the expiration times of the keys that a prepared batch applies to
+    pub(crate) fn clear_expirations(
         &self,
-        store: &dyn Storage,
-        current_node_key: NodeKey,
-        nibble_iter: &mut NibbleIterator,
-        prove: bool,
-    ) -> Result<(Option<V>, Proof<K, V>)> {
-        let Some(current_node) = self.nodes.may_load(store, &current_node_key)? else {
-            // Node is not found. There are a few circumstances:
-            // - if the node is the root,
-            //   - and it's older than the latest version: it may simply be that
-            //     that version has been pruned
-            //   - and it's the current version: it may simply be that the current
-            //     tree is empty
-            //   - and it's newer than the latest version: this query is illegal
-            // - if the node is not the root: database corrupted
-            if current_node_key.nibble_path.is_empty() {
-                let latest_version = self.version.load(store)?;
-                return match current_node_key.version.cmp(&latest_version) {
-                    Ordering::Equal => {
-                        Ok((None, vec![]))
-                    },
-                    Ordering::Less => {
-                        Err(TreeError::RootNodeNotFound {
-                            version: current_node_key.version,
-                        })
-                    },
-                    Ordering::Greater => {
-                        Err(TreeError::VersionNewerThanLatest {
-                            latest: latest_version,
-                            querying: current_node_key.version,
-                        })
-                    },
-                };
-            } else {
-                return Err(TreeError::NonRootNodeNotFound {
-                    node_key: current_node_key,
-                });
+        store: &mut dyn Storage,
+        batch: &[(NibblePath, K, Op<V>)],
+    ) -> Result<()> {
+        if let Some(expirations) = &self.expirations {
+            for (_, key, _) in batch {
+                expirations.clear(store, key)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Delete everything the tree has stored: its nodes, orphans and version,
+    /// along with everything tracked with them, such as the stats, version
+    /// headers and journal if enabled. The tree is then empty, as if it had
+    /// never been written to, e.g. to be reinitialized using `initialize_from`.
+    ///
+    /// Records are deleted in batches of `prune_batch_size` (see
+    /// `with_prune_batch_size`), so memory use is bounded however large the
+    /// tree is. The version is deleted first, so the tree reads as empty right
+    /// away; if this is interrupted, call it again to delete the rest. Until
+    /// then, writing to the tree errors with `TreeError::LeftoverRecords`.
+    pub fn clear(&self, store: &mut dyn Storage) -> Result<()> {
+        // items are stored under their namespaces, whereas the entries of maps
+        // are stored under their length-prefixed namespaces
+        let mut items = vec![self.version.as_slice()];
+        items.extend(self.counters.as_ref().map(Item::as_slice));
+        #[cfg(feature = "proof-metrics")]
+        items.extend(self.proof_metrics.as_ref().map(Item::as_slice));
+
+        for namespace in self.namespaces() {
+            if items.contains(&namespace) {
+                store.remove(namespace);
+                continue;
+            }
+
+            let prefix = map_prefix(namespace);
+            loop {
+                let keys = store
+                    .range(Some(&prefix), None, Order::Ascending)
+                    .take_while(|(key, _)| key.starts_with(&prefix))
+                    .take(self.prune_batch_size)
+                    .map(|(key, _)| key)
+                    .collect::<Vec<_>>();
+                for key in &keys {
+                    store.remove(key);
+                }
+                if keys.len() < self.prune_batch_size {
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // a tree without a version may still hold nodes and orphans, if a clear or
+    // the first apply was interrupted. writing a new version on top of them
+    // isn't safe: pruning would delete the new version's nodes that have the
+    // same keys as stale orphaned ones
+    pub(crate) fn check_no_leftovers(&self, store: &dyn Storage) -> Result<()> {
+        for namespace in [self.nodes.namespace(), self.orphans.namespace()] {
+            let prefix = map_prefix(namespace);
+            let mut records = store.range(Some(&prefix), None, Order::Ascending);
+            if records.next().is_some_and(|(key, _)| key.starts_with(&prefix)) {
+                return Err(TreeError::LeftoverRecords);
             }
+        }
+
+        Ok(())
+    }
+
+    /// Clean up after an `apply` that was interrupted halfway, e.g. by a crash
+    /// of a host whose storage backend doesn't commit writes atomically, such
+    /// as a native node using RocksDB. Call this on startup, before applying
+    /// or pruning anything. Returns whether anything needed to be cleaned up.
+    ///
+    /// `apply` bumps the version only after all nodes and orphan records are
+    /// written, so an interrupted apply leaves the latest version intact. But
+    /// the records it has written remain: nodes under the next version, which
+    /// would be overwritten anyways, and orphan records of nodes that are still
+    /// part of the latest version, which would have them pruned. Both are
+    /// deleted here.
+    ///
+    /// Contracts don't need this, as the writes of a transaction are committed
+    /// atomically.
+    pub fn recover(&self, store: &mut dyn Storage) -> Result<bool> {
+        let latest_version = self.version.may_load(store)?.unwrap_or(0);
+        let Some(staged_version) = latest_version.checked_add(1) else {
+            // no apply can have been attempted beyond the maximum version
+            return Ok(false);
         };
 
-        // if the node has data and the key matches the request key, then we
-        // have found it
-        if let Some(Record { key, value }) = current_node.data.clone() {
-            if NibblePath::from(&key) == nibble_iter.nibble_path() {
-                let proof = if prove {
-                    vec![ProofNode::from_node(current_node.clone(), None, true)]
-                } else {
-                    vec![]
-                };
-                return Ok((Some(value), proof));
+        // nodes are keyed by version first, so those of the staged version
+        // (or any newer one) come after all others
+        let staged_root_key = NodeKey::root(staged_version);
+        let staged_nodes = self
+            .nodes
+            .range(store, Some(Bound::inclusive(&staged_root_key)), None, Order::Ascending)
+            .map(|item| item.map(|(node_key, _)| node_key))
+            .collect::<StdResult<Vec<_>>>()?;
+
+        // orphans are keyed by epoch, in which those recorded by the staged
+        // version can't be told apart from those of committed versions. the
+        // former are those that are still part of the latest version
+        let staged_epoch = Some(PrefixBound::inclusive(staged_version / self.orphan_epoch_len));
+        let mut staged_orphans = vec![];
+        for item in self.orphans.prefix_range(store, staged_epoch, None, Order::Ascending) {
+            let (epoch, node_key) = item?;
+            if node_key.version > latest_version
+                || self.is_reachable(store, latest_version, &node_key)?
+            {
+                staged_orphans.push((epoch, node_key));
             }
         }
 
-        // otherwise, if we have already reached the last nibble, then key is
-        // not found
-        let Some(index) = nibble_iter.next() else {
-            let proof = if prove {
-                vec![ProofNode::from_node(current_node, None, false)]
-            } else {
-                vec![]
-            };
-            return Ok((None, proof));
+        for node_key in &staged_nodes {
+            self.nodes.remove(store, node_key);
+        }
+
+        for (epoch, node_key) in &staged_orphans {
+            // the nodes are still part of the latest version, so they exist
+            if let Some(churn_counters) = &self.churn_counters {
+                let bytes = self.nodes.encoded_len(store, node_key).unwrap_or(0);
+                churn_counters.subtract(store, *epoch, bytes as u64)?;
+            }
+            self.orphans.remove(store, (*epoch, node_key));
+        }
+
+        // the staged version may be any newer than the latest, if it's been
+        // applied using `apply_at`
+        let staged_key_filter = match &self.key_filters {
+            Some(key_filters) => key_filters.remove_after(store, latest_version)?,
+            None => false,
         };
 
-        // if there're still more nibbles, but the current node doesn't have the
-        // corresponding child, then key is not found
-        let Some(child) = current_node.children.get(index) else {
-            let proof = if prove {
-                vec![ProofNode::from_node(current_node, None, false)]
+        let staged_header = match &self.version_headers {
+            Some(version_headers) => version_headers.remove_after(store, latest_version)?,
+            None => false,
+        };
+
+        let staged_root = match &self.root_history {
+            Some(root_history) => root_history.remove_after(store, latest_version)?,
+            None => false,
+        };
+
+        let staged_stats = match &self.stats {
+            Some(stats) => stats.remove_after(store, latest_version)?,
+            None => false,
+        };
+
+        #[cfg(feature = "journal")]
+        let staged_journal = match &self.journal {
+            Some(journal) => journal.truncate(store, latest_version)?,
+            None => false,
+        };
+        #[cfg(not(feature = "journal"))]
+        let staged_journal = false;
+
+        Ok(!staged_nodes.is_empty()
+            || !staged_orphans.is_empty()
+            || staged_key_filter
+            || staged_header
+            || staged_root
+            || staged_stats
+            || staged_journal)
+    }
+
+    // whether the node is part of the tree at the given version, i.e. it is
+    // reached by walking down the tree from the root along its nibble path
+    fn is_reachable(&self, store: &dyn Storage, version: u64, node_key: &NodeKey) -> Result<bool> {
+        let mut current_node_key = NodeKey::root(version);
+        while current_node_key.depth() < node_key.depth() {
+            let Some(node) = self.nodes.may_load(store, &current_node_key)? else {
+                return Ok(false);
+            };
+            let index = node_key.nibble_path.get_nibble(current_node_key.depth());
+            let Some(child) = node.children.get(index) else {
+                return Ok(false);
+            };
+            current_node_key = current_node_key.child(child.version, index);
+        }
+
+        Ok(current_node_key == *node_key)
+    }
+
+    // delete up to `limit` of the nodes orphaned during the epochs in range,
+    // oldest first
+    fn prune_range(
+        &self,
+        store: &mut dyn Storage,
+        start: Option<PrefixBound<u64>>,
+        end: Option<PrefixBound<u64>>,
+        limit: usize,
+    ) -> Result<()> {
+        let mut orphans_pruned = 0;
+        while orphans_pruned < limit {
+            let batch_size = self.prune_batch_size.min(limit - orphans_pruned);
+            let batch = self
+                .orphans
+                .prefix_range(store, start.clone(), end.clone(), Order::Ascending)
+                .take(batch_size)
+                .collect::<StdResult<Vec<_>>>()?;
+
+            for (epoch, node_key) in &batch {
+                self.nodes.remove(store, node_key);
+                self.orphans.remove(store, (*epoch, node_key));
+            }
+            orphans_pruned += batch.len();
+
+            if batch.len() < batch_size {
+                break;
+            }
+        }
+
+        self.update_counters(store, |counters| counters.orphans_pruned += orphans_pruned as u64)
+    }
+
+    // update the counters, if `with_counters` is enabled
+    fn update_counters(
+        &self,
+        store: &mut dyn Storage,
+        update: impl FnOnce(&mut Counters),
+    ) -> Result<()> {
+        if let Some(counters_item) = &self.counters {
+            let mut counters = counters_item.may_load(store)?.unwrap_or_default();
+            update(&mut counters);
+            counters_item.save(store, &counters)?;
+        }
+        Ok(())
+    }
+
+    /// Return the cumulative counters of the tree's activity. See
+    /// `with_counters`.
+    pub fn counters(&self, store: &dyn Storage) -> Result<Counters> {
+        let counters = self.counters.as_ref().ok_or(TreeError::CountersNotTracked)?;
+        Ok(counters.may_load(store)?.unwrap_or_default())
+    }
+
+    // the last epoch that ends at or before the given version, if any. computed
+    // in u128 so that version = u64::MAX doesn't overflow
+    fn last_epoch_ended(&self, version: u64) -> Option<u64> {
+        let epochs_ended = (version as u128 + 1) / self.orphan_epoch_len as u128;
+        epochs_ended.checked_sub(1).map(|epoch| epoch as u64)
+    }
+
+    // once the orphans of all epochs up to the given one are pruned, the nodes
+    // of all versions before the last one of the epoch are gone
+    fn first_remaining_version(&self, epoch: u64) -> u64 {
+        let epoch_end = (epoch as u128 + 1) * self.orphan_epoch_len as u128 - 1;
+        epoch_end.min(u64::MAX as u128) as u64
+    }
+
+    fn prune_key_filters(&self, store: &mut dyn Storage, before_version: u64) -> Result<()> {
+        if let Some(key_filters) = &self.key_filters {
+            key_filters.prune(store, before_version)?;
+        }
+        Ok(())
+    }
+
+    // the filter that keys inserted on top of old_version are to be added to,
+    // if key filters are enabled
+    pub(crate) fn key_filter_to_update(
+        &self,
+        store: &dyn Storage,
+        old_version: u64,
+    ) -> Result<Option<KeyFilter>> {
+        match &self.key_filters {
+            Some(key_filters) => key_filters.to_update(store, old_version).map_err(Into::into),
+            None => Ok(None),
+        }
+    }
+
+    // the root hash of a version, or `None` if the tree is empty at it
+    fn root_hash_at(&self, store: &dyn Storage, version: u64) -> Result<Option<Hash>> {
+        if version == 0 {
+            return Ok(None);
+        }
+        let root_node = self.nodes.may_load(store, &NodeKey::root(version))?;
+        Ok(root_node.map(|root_node| self.root_hash(&root_node)))
+    }
+
+    /// Return the headers of the versions after the given one, in ascending
+    /// order, e.g. to export them for `verify_version_chain`. See
+    /// `with_version_headers`.
+    pub fn version_headers(
+        &self,
+        store: &dyn Storage,
+        start_after: Option<u64>,
+        limit: Option<usize>,
+    ) -> Result<Vec<VersionHeader>> {
+        let version_headers =
+            self.version_headers.as_ref().ok_or(TreeError::VersionHeadersNotTracked)?;
+        let limit = limit.unwrap_or(DEFAULT_QUERY_BATCH_SIZE);
+        version_headers.range(store, start_after, limit).map_err(Into::into)
+    }
+
+    /// Return the history root, which commits to the root hashes of all
+    /// versions recorded so far, or `None` if none has been. See
+    /// `with_root_history`.
+    pub fn history_root(&self, store: &dyn Storage) -> Result<Option<Hash>> {
+        let root_history = self.root_history.as_ref().ok_or(TreeError::RootHistoryNotTracked)?;
+        root_history.root::<H>(store).map_err(Into::into)
+    }
+
+    /// Return the root hash committed at the given version, with a proof of it
+    /// against the current history root, to be verified with
+    /// `verify_historical_root`. The version may have been pruned, but must
+    /// have been committed while the root history was enabled. See
+    /// `with_root_history`.
+    pub fn prove_historical_root(
+        &self,
+        store: &dyn Storage,
+        version: u64,
+    ) -> Result<HistoricalRootResponse> {
+        let root_history = self.root_history.as_ref().ok_or(TreeError::RootHistoryNotTracked)?;
+        root_history.prove(store, version)?.ok_or(TreeError::VersionNotInRootHistory {
+            version,
+        })
+    }
+
+    // record the ops of a prepared batch in the journal, if enabled
+    #[cfg(feature = "journal")]
+    pub(crate) fn journal_batch(
+        &self,
+        store: &mut dyn Storage,
+        version: u64,
+        batch: &[(NibblePath, K, Op<V>)],
+    ) -> Result<()> {
+        if let Some(journal) = &self.journal {
+            journal.append(store, version, batch)?;
+        }
+        Ok(())
+    }
+
+    /// Return the entries of the journal in the order they were recorded,
+    /// starting after the given index. See `with_journal`.
+    #[cfg(feature = "journal")]
+    pub fn journal(
+        &self,
+        store: &dyn Storage,
+        start_after: Option<u64>,
+        limit: Option<usize>,
+    ) -> Result<Vec<JournalEntry<K, V>>> {
+        let journal = self.journal.as_ref().ok_or(TreeError::JournalNotTracked)?;
+        let limit = limit.unwrap_or(DEFAULT_JOURNAL_LIMIT);
+        journal.entries(store, start_after, limit).map_err(Into::into)
+    }
+
+    /// Return the latest entry of the journal, if any. Its hash commits to all
+    /// entries, so it can be published (e.g. along with the root hash) for the
+    /// journal to be verified against later.
+    #[cfg(feature = "journal")]
+    pub fn journal_head(&self, store: &dyn Storage) -> Result<Option<JournalEntry<K, V>>> {
+        let journal = self.journal.as_ref().ok_or(TreeError::JournalNotTracked)?;
+        journal.last(store).map_err(Into::into)
+    }
+
+    // if a version is given, make sure it's not newer than the latest version;
+    // otherwise, use the latest version
+    pub(crate) fn version_or_default(
+        &self,
+        store: &dyn Storage,
+        version: Option<u64>,
+    ) -> Result<u64> {
+        let Some(version) = version else {
+            return self.version.load(store).map_err(Into::into);
+        };
+
+        let latest_version = self.version.may_load(store)?.unwrap_or(0);
+        if version > latest_version {
+            return Err(TreeError::VersionNewerThanLatest {
+                latest: latest_version,
+                querying: version,
+            });
+        }
+
+        Ok(version)
+    }
+
+    fn set_version(&self, store: &mut dyn Storage, version: u64) -> StdResult<()> {
+        self.version.save(store, &version)
+    }
+
+    fn create_node(
+        &self,
+        store: &mut dyn Storage,
+        version: u64,
+        nibble_path: NibblePath,
+        node: &Node<K, V>,
+    ) -> StdResult<()> {
+        self.nodes.save(store, &NodeKey::new(version, nibble_path), node)
+    }
+
+    pub fn root(&self, store: &dyn Storage, version: Option<u64>) -> Result<RootResponse> {
+        let version = self.version_or_default(store, version)?;
+        let root_node = self.root_node(store, version)?;
+
+        Ok(RootResponse {
+            version,
+            root_hash: self.root_hash(&root_node),
+        })
+    }
+
+    fn root_node(&self, store: &dyn Storage, version: u64) -> Result<Node<K, V>> {
+        let root_node_key = NodeKey::root(version);
+        self.nodes
+            .may_load(store, &root_node_key)?
+            .ok_or(TreeError::RootNodeNotFound { version })
+    }
+
+    pub fn get(
+        &self,
+        store: &dyn Storage,
+        key: &K,
+        prove: bool,
+        version: Option<u64>,
+    ) -> Result<GetResponse<K, V>> {
+        let version = self.version_or_default(store, version)?;
+        let key = self.normalize_key(key);
+        let nibble_path = NibblePath::from(&*key);
+        check_key(&nibble_path)?;
+
+        let (value, proof) = match self.get_at(
+            store,
+            NodeKey::root(version),
+            &mut nibble_path.nibbles(),
+            prove,
+        ) {
+            Err(TreeError::RootNodeNotFound { version }) => {
+                return self.get_pruned(store, &key, version);
+            },
+            result => result?,
+        };
+
+        let proof = if prove {
+            Some(self.encode_proof(&proof)?)
+        } else {
+            None
+        };
+
+        Ok(GetResponse { key: key.into_owned(), value, proof })
+    }
+
+    // the version's nodes have been pruned, so the record can neither be read
+    // nor proven. depending on the policy, it may be served from the latest
+    // version instead
+    fn get_pruned(&self, store: &dyn Storage, key: &K, version: u64) -> Result<GetResponse<K, V>> {
+        if let PrunedVersionPolicy::ServeWithoutProof = self.pruned_version_policy {
+            if let Some(value) = self.unchanged_since(store, key, version)? {
+                return Ok(GetResponse {
+                    key: key.clone(),
+                    value: Some(value),
+                    proof: None,
+                });
+            }
+        }
+
+        Err(TreeError::VersionPruned {
+            version,
+        })
+    }
+
+    // the value of the key at the latest version, if its record is known to
+    // have been the same at the given version. nodes are never modified once
+    // written, so if the node holding the record was written at or before the
+    // given version and is still part of the tree, the record hasn't changed
+    // in between
+    fn unchanged_since(&self, store: &dyn Storage, key: &K, version: u64) -> Result<Option<V>> {
+        let mut node_key = NodeKey::root(self.version.load(store)?);
+        let Some(mut node) = self.nodes.may_load(store, &node_key)? else {
+            return Ok(None);
+        };
+
+        let nibble_path = NibblePath::from(key);
+        let mut nibble_iter = nibble_path.nibbles();
+        loop {
+            if let Some(record) = node.data {
+                if record.key.as_ref() == key.as_ref() {
+                    return Ok((node_key.version <= version).then_some(record.value));
+                }
+            }
+
+            let Some(index) = nibble_iter.next() else {
+                return Ok(None);
+            };
+            let Some(child) = node.children.get(index) else {
+                return Ok(None);
+            };
+            node_key = node_key.child(child.version, index);
+            node = self.load_child(store, &node_key)?;
+        }
+    }
+
+    /// Same as `get`, but aborts with `TreeError::ReadBudgetExceeded` if more
+    /// than `max_reads` nodes, including the root, would need to be loaded.
+    /// This protects public query endpoints from queries that are costly to
+    /// answer, e.g. of keys deep in the tree.
+    pub fn get_with_budget(
+        &self,
+        store: &dyn Storage,
+        key: &K,
+        prove: bool,
+        version: Option<u64>,
+        max_reads: u64,
+    ) -> Result<GetResponse<K, V>> {
+        let version = self.version_or_default(store, version)?;
+        let key = self.normalize_key(key);
+        let nibble_path = NibblePath::from(&*key);
+        check_key(&nibble_path)?;
+
+        let mut reads_left = Some(max_reads);
+        let mut load = |node_key: &NodeKey| {
+            spend_read(&mut reads_left)?;
+            Ok(self.nodes.may_load(store, node_key)?)
+        };
+        let (value, proof) = self.get_at_with(
+            store,
+            &mut load,
+            NodeKey::root(version),
+            &mut nibble_path.nibbles(),
+            prove,
+        )?;
+
+        let proof = if prove {
+            Some(self.encode_proof(&proof)?)
+        } else {
+            None
+        };
+
+        Ok(GetResponse {
+            key: key.into_owned(),
+            value,
+            proof,
+        })
+    }
+
+    /// Same as `get`, but for multiple keys at once. If proofs are requested,
+    /// the keys are proven by a single `BatchProof`, in which the nodes that
+    /// their paths share are included only once, so it's much smaller than
+    /// separate proofs of nearby keys. Verify it using
+    /// `verify_get_many_response` or `verify_batch_membership`.
+    ///
+    /// Errors if the version has been pruned, regardless of the tree's pruned
+    /// version policy.
+    pub fn get_many(
+        &self,
+        store: &dyn Storage,
+        keys: &[K],
+        prove: bool,
+        version: Option<u64>,
+    ) -> Result<GetManyResponse<K, V>> {
+        let version = self.version_or_default(store, version)?;
+        let mut keys = keys
+            .iter()
+            .map(|key| {
+                let key = self.normalize_key(key).into_owned();
+                let nibble_path = NibblePath::from(&key);
+                check_key(&nibble_path)?;
+                Ok((nibble_path, key))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        keys.sort_by(|(_, a), (_, b)| a.as_ref().cmp(b.as_ref()));
+        keys.dedup_by(|(_, a), (_, b)| a.as_ref() == b.as_ref());
+
+        let mut values = vec![None; keys.len()];
+        let root_node_key = NodeKey::root(version);
+        let proof = match self.nodes.may_load(store, &root_node_key)? {
+            Some(root_node) => {
+                let mut proof = vec![];
+                self.get_many_at(
+                    store,
+                    root_node_key,
+                    root_node,
+                    &keys,
+                    &mut values,
+                    prove,
+                    &mut proof,
+                )?;
+                prove.then(|| to_binary(&proof)).transpose()?
+            },
+            // as in `get_at_with`, the tree is empty, unless the version is
+            // older than the latest one, in which case it has been pruned
+            None if version < self.version.load(store)? => {
+                return Err(TreeError::VersionPruned {
+                    version,
+                });
+            },
+            None => None,
+        };
+
+        Ok(GetManyResponse {
+            records: keys.into_iter().map(|(_, key)| key).zip(values).collect(),
+            proof,
+        })
+    }
+
+    // find the values of the given keys, which are sorted and all start with
+    // the node's nibble path, in the node's subtree. values that aren't found
+    // are left as they are. if proving, the node and those visited below it
+    // are appended to the proof, in depth-first order
+    #[allow(clippy::too_many_arguments)]
+    fn get_many_at(
+        &self,
+        store: &dyn Storage,
+        node_key: NodeKey,
+        node: Node<K, V>,
+        keys: &[(NibblePath, K)],
+        values: &mut [Option<V>],
+        prove: bool,
+        proof: &mut BatchProof<K, V>,
+    ) -> Result<()> {
+        let depth = node_key.nibble_path.num_nibbles;
+
+        // the keys whose records are held by the node itself
+        let mut found = vec![false; keys.len()];
+        if let Some(Record { key, value }) = &node.data {
+            for (i, (_, k)) in keys.iter().enumerate() {
+                if k.as_ref() == key.as_ref() {
+                    values[i] = Some(value.clone());
+                    found[i] = true;
+                }
+            }
+        }
+
+        let position = proof.len();
+        if prove {
+            proof.push(BatchProofNode {
+                node: self.proof_node(node_key.version, node.clone(), None, false),
+                proven_children: vec![],
+            });
+        }
+
+        // the other keys continue into the node's children. as the keys are
+        // sorted, those continuing into the same child are next to each other
+        let mut proven_children = vec![];
+        let mut start = 0;
+        while start < keys.len() {
+            let Some(index) = keys[start].0.checked_get_nibble(depth) else {
+                start += 1;
+                continue;
+            };
+            let end = keys[start..]
+                .iter()
+                .position(|(nibble_path, _)| nibble_path.checked_get_nibble(depth) != Some(index))
+                .map_or(keys.len(), |len| start + len);
+
+            if let Some(child) = node.children.get(index) {
+                if found[start..end].contains(&false) {
+                    let child_node_key = node_key.child(child.version, index);
+                    let child_node = self.load_child(store, &child_node_key)?;
+                    self.get_many_at(
+                        store,
+                        child_node_key,
+                        child_node,
+                        &keys[start..end],
+                        &mut values[start..end],
+                        prove,
+                        proof,
+                    )?;
+                    proven_children.push(index);
+                }
+            }
+
+            start = end;
+        }
+
+        if prove {
+            let proof_node = &mut proof[position];
+            proof_node.node.children.retain(|child| !proven_children.contains(&child.index));
+            proof_node.proven_children = proven_children;
+        }
+
+        Ok(())
+    }
+
+    /// Compare the records of the tree at `version_a` in `store_a` with those
+    /// at `version_b` in `store_b`, e.g. to validate a migration or a replica.
+    /// The stores may be the same, to compare two versions of one tree.
+    ///
+    /// Returns the smallest key whose record differs, i.e. that exists on one
+    /// side only or has different values, or `None` if the records are equal.
+    /// Only subtrees whose hashes differ are descended into, so this is much
+    /// faster than exporting both sides when they are mostly equal.
+    pub fn equal_at(
+        &self,
+        store_a: &dyn Storage,
+        version_a: Option<u64>,
+        store_b: &dyn Storage,
+        version_b: Option<u64>,
+    ) -> Result<Option<K>> {
+        let version_a = self.version_or_default(store_a, version_a)?;
+        let version_b = self.version_or_default(store_b, version_b)?;
+        let root_a = self.root_node_if_any(store_a, version_a)?;
+        let root_b = self.root_node_if_any(store_b, version_b)?;
+
+        match (root_a, root_b) {
+            (Some(node_a), Some(node_b)) => {
+                let root_key_a = NodeKey::root(version_a);
+                let root_key_b = NodeKey::root(version_b);
+                self.first_difference(store_a, root_key_a, node_a, store_b, root_key_b, node_b)
+            },
+            (Some(_), None) => self.first_key(store_a, NodeKey::root(version_a)),
+            (None, Some(_)) => self.first_key(store_b, NodeKey::root(version_b)),
+            (None, None) => Ok(None),
+        }
+    }
+
+    // the smallest key whose record differs between the subtrees of two nodes
+    // at the same nibble path
+    fn first_difference(
+        &self,
+        store_a: &dyn Storage,
+        node_key_a: NodeKey,
+        node_a: Node<K, V>,
+        store_b: &dyn Storage,
+        node_key_b: NodeKey,
+        node_b: Node<K, V>,
+    ) -> Result<Option<K>> {
+        if node_a.hash_using::<H>() == node_b.hash_using::<H>() {
+            return Ok(None);
+        }
+
+        // a leaf that has been collapsed into the node holds a key longer than
+        // the node's nibble path, so it may be held by a different node on the
+        // other side, e.g. if the trees' collapse policies differ. as such a
+        // leaf is the only record in its subtree, compare the subtrees' records
+        // one by one instead
+        let nibble_path = &node_key_a.nibble_path;
+        let is_collapsed = |node: &Node<K, V>| {
+            node.data.as_ref().is_some_and(|data| NibblePath::from(&data.key) != *nibble_path)
+        };
+        if is_collapsed(&node_a) || is_collapsed(&node_b) {
+            let records_a = self.subtree_records(store_a, &node_key_a, node_a)?;
+            let records_b = self.subtree_records(store_b, &node_key_b, node_b)?;
+            return Ok(first_different_record(records_a, records_b));
+        }
+
+        // otherwise, the nodes' records are keyed by the nibble path, which
+        // comes before all keys in the nodes' subtrees
+        match (node_a.data, node_b.data) {
+            (Some(a), Some(b)) if a.value != b.value => return Ok(Some(a.key)),
+            (Some(a), None) => return Ok(Some(a.key)),
+            (None, Some(b)) => return Ok(Some(b.key)),
+            _ => (),
+        }
+
+        for index in 0..16 {
+            let index = Nibble::new(index);
+            match (node_a.children.get(index), node_b.children.get(index)) {
+                (Some(a), Some(b)) if a.hash == b.hash => (),
+                (Some(a), Some(b)) => {
+                    let child_node_key_a = node_key_a.child(a.version, index);
+                    let child_node_key_b = node_key_b.child(b.version, index);
+                    let child_node_a = self.load_child(store_a, &child_node_key_a)?;
+                    let child_node_b = self.load_child(store_b, &child_node_key_b)?;
+                    let difference = self.first_difference(
+                        store_a,
+                        child_node_key_a,
+                        child_node_a,
+                        store_b,
+                        child_node_key_b,
+                        child_node_b,
+                    )?;
+                    if difference.is_some() {
+                        return Ok(difference);
+                    }
+                },
+                (Some(a), None) => {
+                    return self.first_key(store_a, node_key_a.child(a.version, index));
+                },
+                (None, Some(b)) => {
+                    return self.first_key(store_b, node_key_b.child(b.version, index));
+                },
+                (None, None) => (),
+            }
+        }
+
+        Ok(None)
+    }
+
+    // the smallest key in the subtree of a node, which exists on one side only
+    fn first_key(&self, store: &dyn Storage, node_key: NodeKey) -> Result<Option<K>> {
+        let record = self.extreme_record(store, node_key, Order::Ascending)?;
+        Ok(Some(record.key))
+    }
+
+    // all records in the subtree of a node, in ascending order of keys
+    fn subtree_records(
+        &self,
+        store: &dyn Storage,
+        node_key: &NodeKey,
+        node: Node<K, V>,
+    ) -> Result<Vec<(K, V)>> {
+        let mut records = vec![];
+        if let Some(Record { key, value }) = node.data {
+            records.push((key, value));
+        }
+
+        for child in node.children {
+            let child_node_key = node_key.child(child.version, child.index);
+            let child_node = self.load_child(store, &child_node_key)?;
+            records.extend(self.subtree_records(store, &child_node_key, child_node)?);
+        }
+
+        Ok(records)
+    }
+
+    pub(crate) fn get_at(
+        &self,
+        store: &dyn Storage,
+        current_node_key: NodeKey,
+        nibble_iter: &mut NibbleIterator,
+        prove: bool,
+    ) -> Result<(Option<V>, Proof<K, V>)> {
+        let mut load = |node_key: &NodeKey| Ok(self.nodes.may_load(store, node_key)?);
+        self.get_at_with(store, &mut load, current_node_key, nibble_iter, prove)
+    }
+
+    // same as `get_at`, but nodes are loaded using the given function, e.g. so
+    // that they can be cached across queries
+    pub(crate) fn get_at_with(
+        &self,
+        store: &dyn Storage,
+        load: &mut LoadNode<K, V>,
+        current_node_key: NodeKey,
+        nibble_iter: &mut NibbleIterator,
+        prove: bool,
+    ) -> Result<(Option<V>, Proof<K, V>)> {
+        let Some(current_node) = load(&current_node_key)? else {
+            // Node is not found. There are a few circumstances:
+            // - if the node is the root,
+            //   - and it's older than the latest version: it may simply be that
+            //     that version has been pruned
+            //   - and it's the current version: it may simply be that the current
+            //     tree is empty
+            //   - and it's newer than the latest version: this query is illegal
+            // - if the node is not the root: database corrupted
+            if current_node_key.nibble_path.is_empty() {
+                let latest_version = self.version.load(store)?;
+                return match current_node_key.version.cmp(&latest_version) {
+                    Ordering::Equal => {
+                        Ok((None, vec![]))
+                    },
+                    Ordering::Less => {
+                        Err(TreeError::RootNodeNotFound {
+                            version: current_node_key.version,
+                        })
+                    },
+                    Ordering::Greater => {
+                        Err(TreeError::VersionNewerThanLatest {
+                            latest: latest_version,
+                            querying: current_node_key.version,
+                        })
+                    },
+                };
+            } else {
+                return Err(TreeError::NonRootNodeNotFound {
+                    node_key: current_node_key,
+                });
+            }
+        };
+
+        // if the node has data and the key matches the request key, then we
+        // have found it
+        if let Some(Record { key, value }) = current_node.data.clone() {
+            if NibblePath::from(&key) == nibble_iter.nibble_path() {
+                let proof = if prove {
+                    let node = current_node.clone();
+                    vec![self.proof_node(current_node_key.version, node, None, true)]
+                } else {
+                    vec![]
+                };
+                return Ok((Some(value), proof));
+            }
+        }
+
+        // otherwise, if we have already reached the last nibble, then key is
+        // not found
+        let Some(index) = nibble_iter.next() else {
+            let proof = if prove {
+                vec![self.proof_node(current_node_key.version, current_node, None, false)]
+            } else {
+                vec![]
+            };
+            return Ok((None, proof));
+        };
+
+        // if there're still more nibbles, but the current node doesn't have the
+        // corresponding child, then key is not found
+        let Some(child) = current_node.children.get(index) else {
+            let proof = if prove {
+                vec![self.proof_node(current_node_key.version, current_node, None, false)]
+            } else {
+                vec![]
+            };
+            return Ok((None, proof));
+        };
+
+        let (value, mut proof) = self.get_at_with(
+            store,
+            load,
+            current_node_key.child(child.version, index),
+            nibble_iter,
+            prove,
+        )?;
+
+        if prove {
+            proof.push(self.proof_node(current_node_key.version, current_node, Some(index), false));
+        }
+
+        Ok((value, proof))
+    }
+
+    /// Return the number of keys in the tree that are smaller than the given
+    /// key, regardless of whether the key itself exists. Requires the tree to
+    /// track aggregates (see `with_aggregation`).
+    ///
+    /// If the aggregation is something other than counts, this is the total
+    /// weight of the smaller keys instead.
+    ///
+    /// This only loads the nodes on the path to the key, i.e. O(depth).
+    pub fn rank(&self, store: &dyn Storage, key: &K, version: Option<u64>) -> Result<u128> {
+        let weight = self.aggregation.ok_or(TreeError::AggregationNotTracked)?;
+        let version = self.version_or_default(store, version)?;
+        let mut node_key = NodeKey::root(version);
+        let mut node = self.root_node(store, version)?;
+        let normalized = self.normalize_key(key);
+        let key = &*normalized;
+        let nibble_path = NibblePath::from(key);
+        let mut rank = 0u128;
+
+        loop {
+            // if the node has children, its data's key equals its nibble path,
+            // so is smaller than every other key in the subtree. if the node
+            // is a leaf, its key may be longer than the nibble path.
+            if let Some(Record { key: data_key, value }) = &node.data {
+                if data_key.as_ref() < key.as_ref() {
+                    let weight = weight(data_key, value);
+                    rank = rank.checked_add(weight).ok_or(TreeError::AggregateOverflow)?;
+                }
+            }
+
+            // all keys below this point have the key as a prefix, so are
+            // greater than it
+            if node_key.depth() == nibble_path.num_nibbles {
+                return Ok(rank);
+            }
+
+            let index = nibble_path.get_nibble(node_key.depth());
+            let mut next_child = None;
+            for child in &node.children {
+                if child.index < index {
+                    let aggregate = child.aggregate.ok_or(TreeError::AggregationNotTracked)?;
+                    rank = rank.checked_add(aggregate).ok_or(TreeError::AggregateOverflow)?;
+                } else if child.index == index {
+                    next_child = Some(node_key.child(child.version, child.index));
+                }
+            }
+
+            let Some(child_node_key) = next_child else {
+                return Ok(rank);
+            };
+
+            node = self.load_child(store, &child_node_key)?;
+            node_key = child_node_key;
+        }
+    }
+
+    /// Return the `n`-th smallest (zero-indexed) key and its value, or `None`
+    /// if the tree contains no more than `n` keys. Requires the tree to track
+    /// aggregates (see `with_aggregation`).
+    ///
+    /// If the aggregation is something other than counts, this is the key at
+    /// which the running total of weights (in ascending order of keys) first
+    /// exceeds `n`. Picking `n` uniformly at random thus samples keys in
+    /// proportion to their weights.
+    ///
+    /// This only loads the nodes on the path to the key, i.e. O(depth).
+    pub fn select(
+        &self,
+        store: &dyn Storage,
+        mut n: u128,
+        version: Option<u64>,
+    ) -> Result<Option<(K, V)>> {
+        let weight = self.aggregation.ok_or(TreeError::AggregationNotTracked)?;
+        let version = self.version_or_default(store, version)?;
+        let mut node_key = NodeKey::root(version);
+        let mut node = self.root_node(store, version)?;
+
+        'outer: loop {
+            // the node's data is the smallest key in its subtree
+            if let Some(Record { key, value }) = node.data {
+                let data_weight = weight(&key, &value);
+                if n < data_weight {
+                    return Ok(Some((key, value)));
+                }
+                n -= data_weight;
+            }
+
+            for child in &node.children {
+                let aggregate = child.aggregate.ok_or(TreeError::AggregationNotTracked)?;
+                if n < aggregate {
+                    let child_node_key = node_key.child(child.version, child.index);
+                    node = self.load_child(store, &child_node_key)?;
+                    node_key = child_node_key;
+                    continue 'outer;
+                }
+                n -= aggregate;
+            }
+
+            return Ok(None);
+        }
+    }
+
+    /// Return the record with the smallest key greater than the given one, if
+    /// any, optionally with a membership proof of that record. The key itself
+    /// doesn't need to exist in the tree.
+    ///
+    /// This takes a single descent from the root along the key's path, instead
+    /// of setting up an iterator, e.g. to find the next price level in an
+    /// orderbook. Note that the proof only proves that the record exists, not
+    /// that no key lies between it and the given key.
+    pub fn next_key(
+        &self,
+        store: &dyn Storage,
+        key: &K,
+        prove: bool,
+        version: Option<u64>,
+    ) -> Result<Option<GetResponse<K, V>>> {
+        self.adjacent_key(store, key, prove, version, Order::Ascending)
+    }
+
+    /// Return the record with the greatest key smaller than the given one, if
+    /// any. See `next_key`.
+    pub fn prev_key(
+        &self,
+        store: &dyn Storage,
+        key: &K,
+        prove: bool,
+        version: Option<u64>,
+    ) -> Result<Option<GetResponse<K, V>>> {
+        self.adjacent_key(store, key, prove, version, Order::Descending)
+    }
+
+    // the record next to the key in the given order. while descending along
+    // the key's path, keep track of the deepest subtree (or record) that lies
+    // entirely beyond the key: the adjacent record is either found on the path
+    // itself, or it's the first record of that subtree
+    fn adjacent_key(
+        &self,
+        store: &dyn Storage,
+        key: &K,
+        prove: bool,
+        version: Option<u64>,
+        order: Order,
+    ) -> Result<Option<GetResponse<K, V>>> {
+        let version = self.version_or_default(store, version)?;
+        let Some(mut node) = self.root_node_if_any(store, version)? else {
+            return Ok(None);
+        };
+
+        let normalized = self.normalize_key(key);
+        let key = &*normalized;
+        let nibble_path = NibblePath::from(key);
+        let mut nibble_iter = nibble_path.nibbles();
+        let mut node_key = NodeKey::root(version);
+        let mut adjacent = None;
+
+        loop {
+            // the node's data is the smallest key in its subtree
+            if let Some(record) = &node.data {
+                match (order, record.key.as_ref().cmp(key.as_ref())) {
+                    (Order::Ascending, Ordering::Greater) => {
+                        adjacent = Some(Adjacent::Record(record.clone()));
+                        break;
+                    },
+                    (Order::Descending, Ordering::Less) => {
+                        adjacent = Some(Adjacent::Record(record.clone()));
+                    },
+                    _ => (),
+                }
+            }
+
+            // all keys in the node's subtree other than the data are greater
+            // than the key, as the key is a prefix of them
+            let Some(index) = nibble_iter.next() else {
+                if let (Order::Ascending, Some(child)) = (order, node.children.as_ref().first()) {
+                    adjacent = Some(Adjacent::Subtree(node_key.child(child.version, child.index)));
+                }
+                break;
+            };
+
+            // the children on the far side of the key's path
+            let beyond = match order {
+                Order::Ascending => node.children.as_ref().iter().find(|child| child.index > index),
+                Order::Descending => {
+                    node.children.as_ref().iter().rev().find(|child| child.index < index)
+                },
+            };
+            if let Some(child) = beyond {
+                adjacent = Some(Adjacent::Subtree(node_key.child(child.version, child.index)));
+            }
+
+            let Some(child) = node.children.get(index) else {
+                break;
+            };
+            node_key = node_key.child(child.version, index);
+            node = self.load_child(store, &node_key)?;
+        }
+
+        let record = match adjacent {
+            Some(Adjacent::Record(record)) => record,
+            Some(Adjacent::Subtree(node_key)) => self.extreme_record(store, node_key, order)?,
+            None => return Ok(None),
+        };
+
+        let proof = if prove {
+            let (_, proof) = self.get_at(
+                store,
+                NodeKey::root(version),
+                &mut NibblePath::from(&record.key).nibbles(),
+                true,
+            )?;
+            Some(self.encode_proof(&proof)?)
+        } else {
+            None
+        };
+
+        Ok(Some(GetResponse {
+            key: record.key,
+            value: Some(record.value),
+            proof,
+        }))
+    }
+
+    /// Deterministically sample `n` records, each with a membership proof, for
+    /// random audits of the tree's state. Each sample is found by descending
+    /// from the root, making a choice between the data and the children of
+    /// each node, derived from the seed using the tree's hash function. The
+    /// same seed, `n` and version always give the same samples, so a verifier
+    /// can pick a seed, e.g. a block hash, that the prover can't predict.
+    ///
+    /// Records are sampled with replacement, so the same record may occur more
+    /// than once. Records are not sampled uniformly: those in sparse subtrees
+    /// are more likely to be chosen. Returns no records if the tree is empty.
+    pub fn sample(
+        &self,
+        store: &dyn Storage,
+        seed: &[u8],
+        n: u32,
+        version: Option<u64>,
+    ) -> Result<Vec<GetResponse<K, V>>> {
+        let version = self.version_or_default(store, version)?;
+        let Some(root_node) = self.root_node_if_any(store, version)? else {
+            return Ok(vec![]);
+        };
+
+        (0..n)
+            .map(|i| {
+                let mut node_key = NodeKey::root(version);
+                let mut node = root_node.clone();
+                let record = loop {
+                    // a node always has data or children, unless corrupted
+                    let num_choices = node.children.count() + node.data.is_some() as usize;
+                    let choice = sample_choice::<H>(seed, i, node_key.nibble_path.num_nibbles);
+                    let choice = (choice % num_choices.max(1) as u64) as usize;
+
+                    // the data, if any, is the last choice
+                    let Some(child) = node.children.as_ref().get(choice) else {
+                        break node.data.ok_or(TreeError::NonRootNodeNotFound {
+                            node_key,
+                        })?;
+                    };
+                    node_key = node_key.child(child.version, child.index);
+                    node = self.load_child(store, &node_key)?;
+                };
+
+                let (_, proof) = self.get_at(
+                    store,
+                    NodeKey::root(version),
+                    &mut NibblePath::from(&record.key).nibbles(),
+                    true,
+                )?;
+
+                Ok(GetResponse {
+                    key: record.key,
+                    value: Some(record.value),
+                    proof: Some(self.encode_proof(&proof)?),
+                })
+            })
+            .collect()
+    }
+
+    // the root node at the given version, or None if the tree is empty at that
+    // version. a missing root of an older version means it's been pruned
+    pub(crate) fn root_node_if_any(
+        &self,
+        store: &dyn Storage,
+        version: u64,
+    ) -> Result<Option<Node<K, V>>> {
+        let root_node = self.nodes.may_load(store, &NodeKey::root(version))?;
+        if root_node.is_none() && version != self.version.load(store)? {
+            return Err(TreeError::RootNodeNotFound {
+                version,
+            });
+        }
+        Ok(root_node)
+    }
+
+    // the first record of the subtree in the given order
+    fn extreme_record(
+        &self,
+        store: &dyn Storage,
+        mut node_key: NodeKey,
+        order: Order,
+    ) -> Result<Record<K, V>> {
+        loop {
+            let node = self.load_child(store, &node_key)?;
+            let child = match order {
+                Order::Ascending => {
+                    if let Some(record) = node.data {
+                        return Ok(record);
+                    }
+                    node.children.as_ref().first()
+                },
+                Order::Descending => node.children.as_ref().last(),
+            };
+
+            // a node without children is a leaf, which always has data
+            let Some(child) = child else {
+                return node.data.ok_or(TreeError::NonRootNodeNotFound {
+                    node_key,
+                });
+            };
+            node_key = node_key.child(child.version, child.index);
+        }
+    }
+
+    /// Return the total weight of all keys that start with the given prefix,
+    /// optionally with a proof that can be verified with `verify_aggregate`.
+    /// Requires the tree to track aggregates (see `with_aggregation`).
+    pub fn aggregate(
+        &self,
+        store: &dyn Storage,
+        prefix: &[u8],
+        prove: bool,
+        version: Option<u64>,
+    ) -> Result<AggregateResponse> {
+        self.aggregation.ok_or(TreeError::AggregationNotTracked)?;
+
+        let version = self.version_or_default(store, version)?;
+        let nibble_path = NibblePath::from(prefix);
+        let mut node_key = NodeKey::root(version);
+        let mut node = self.root_node(store, version)?;
+        let mut ancestors = vec![];
+
+        let total = loop {
+            // the subtree of the node at this depth contains exactly the keys
+            // that start with the prefix
+            if node_key.depth() == nibble_path.num_nibbles {
+                break node.aggregate.ok_or(TreeError::AggregationNotTracked)?;
+            }
+
+            // a leaf above this depth may have been collapsed, in which case
+            // its key may or may not start with the prefix
+            if node.is_leaf() {
+                let starts_with_prefix = node
+                    .data
+                    .as_ref()
+                    .is_some_and(|Record { key, .. }| key.as_ref().starts_with(prefix));
+                if starts_with_prefix {
+                    break node.aggregate.ok_or(TreeError::AggregationNotTracked)?;
+                } else {
+                    break 0;
+                }
+            }
+
+            let index = nibble_path.get_nibble(node_key.depth());
+            let Some(child) = node.children.get(index) else {
+                break 0;
+            };
+
+            let child_node_key = node_key.child(child.version, index);
+            let child_node = self.load_child(store, &child_node_key)?;
+            ancestors.push((node_key.version, std::mem::replace(&mut node, child_node), index));
+            node_key = child_node_key;
+        };
+
+        let proof = if prove {
+            let mut proof = vec![self.proof_node(node_key.version, node, None, false)];
+            for (version, ancestor, index) in ancestors.into_iter().rev() {
+                proof.push(self.proof_node(version, ancestor, Some(index), false));
+            }
+            Some(to_binary(&proof)?)
+        } else {
+            None
+        };
+
+        Ok(AggregateResponse { total, proof })
+    }
+
+    /// Return the hash of the subtree holding the keys under the given nibble
+    /// prefix, with a proof linking it to the root hash that can be verified
+    /// with `verify_subtree`. This lets an application that keeps sub-stores
+    /// under prefixes of the same tree, e.g. one per module, commit to and
+    /// prove each of them independently.
+    ///
+    /// A leaf may be collapsed above the prefix, in which case there's no node
+    /// at the prefix; if the leaf's key is under the prefix, it's the subtree's
+    /// only record, and the subtree's hash is the leaf's.
+    pub fn subtree_root(
+        &self,
+        store: &dyn Storage,
+        prefix: &NibblePath,
+        version: Option<u64>,
+    ) -> Result<SubtreeRootResponse> {
+        let version = self.version_or_default(store, version)?;
+        let mut node_key = NodeKey::root(version);
+        let mut node = self.root_node(store, version)?;
+        let mut ancestors = vec![];
+
+        let hash = loop {
+            if node_key.depth() == prefix.num_nibbles {
+                break Some(node.hash_using::<H>());
+            }
+
+            if node.is_leaf() {
+                let under_prefix = node
+                    .data
+                    .as_ref()
+                    .is_some_and(|Record { key, .. }| NibblePath::from(key).starts_with(prefix));
+                break under_prefix.then(|| node.hash_using::<H>());
+            }
+
+            let index = prefix.get_nibble(node_key.depth());
+            let Some(child) = node.children.get(index) else {
+                break None;
+            };
+
+            let child_node_key = node_key.child(child.version, index);
+            let child_node = self.load_child(store, &child_node_key)?;
+            ancestors.push((node_key.version, std::mem::replace(&mut node, child_node), index));
+            node_key = child_node_key;
+        };
+
+        let mut proof = vec![self.proof_node(node_key.version, node, None, false)];
+        for (version, ancestor, index) in ancestors.into_iter().rev() {
+            proof.push(self.proof_node(version, ancestor, Some(index), false));
+        }
+
+        Ok(SubtreeRootResponse {
+            hash,
+            proof: to_binary(&proof)?,
+        })
+    }
+
+    pub(crate) fn load_child(&self, store: &dyn Storage, node_key: &NodeKey) -> Result<Node<K, V>> {
+        self.nodes.may_load(store, node_key)?.ok_or_else(|| TreeError::NonRootNodeNotFound {
+            node_key: node_key.clone(),
+        })
+    }
+
+    /// This function signature is inspired by `cosmwasm_std::Storage` trait's
+    /// `range` method.
+    ///
+    /// Notes:
+    /// - The bound `start` is inclusive and `end` is exclusive;
+    /// - `start` should be lexicographically smaller than `end`, regardless of
+    ///   the iteration `order`. If `start` >= `end`, an empty iterator is
+    ///   generated.
+    pub fn iterate<'c, S: Storage>(
+        &'a self,
+        store: &'c S,
+        order: Order,
+        min: Option<&K>,
+        max: Option<&K>,
+        version: Option<u64>,
+    ) -> Result<TreeIterator<'c, K, V, S, C, H>>
+    where
+        'a: 'c,
+    {
+        let version = self.version_or_default(store, version)?;
+        let root_node = self.root_node(store, version)?;
+        Ok(self.iterate_from(store, version, order, min, max, root_node))
+    }
+
+    /// Same as `iterate`, but yield only the keys. Nodes are decoded using
+    /// `NodeCodec::decode_without_value`, so values are skipped rather than
+    /// decoded, to the extent the codec allows.
+    pub fn keys<'c, S: Storage>(
+        &'a self,
+        store: &'c S,
+        order: Order,
+        min: Option<&K>,
+        max: Option<&K>,
+        version: Option<u64>,
+    ) -> Result<Keys<'c, K, V, S, C, H>>
+    where
+        'a: 'c,
+    {
+        let version = self.version_or_default(store, version)?;
+        let root_node = self
+            .nodes
+            .may_load_without_value(store, &NodeKey::root(version))?
+            .ok_or(TreeError::RootNodeNotFound { version })?;
+        Ok(Keys {
+            iter: self.iterate_from(store, version, order, min, max, root_node),
+        })
+    }
+
+    /// Same as `iterate`, but yield only the values. Records are moved out of
+    /// the nodes they're decoded from, so the values aren't cloned.
+    pub fn values<'c, S: Storage>(
+        &'a self,
+        store: &'c S,
+        order: Order,
+        min: Option<&K>,
+        max: Option<&K>,
+        version: Option<u64>,
+    ) -> Result<Values<'c, K, V, S, C, H>>
+    where
+        'a: 'c,
+    {
+        self.iterate(store, order, min, max, version).map(|iter| Values { iter })
+    }
+
+    // create an iterator starting from the given root node, whose values may
+    // or may not have been decoded
+    fn iterate_from<'c, S, W>(
+        &'a self,
+        store: &'c S,
+        version: u64,
+        order: Order,
+        min: Option<&K>,
+        max: Option<&K>,
+        root_node: Node<K, W>,
+    ) -> TreeIterator<'c, K, V, S, C, H, W>
+    where
+        'a: 'c,
+    {
+        // bounds are mapped, but not normalized
+        let mapped = self.key_transform.map(|transform| (min.map(transform), max.map(transform)));
+        let (min, max) = match &mapped {
+            Some((min, max)) => (min.as_ref(), max.as_ref()),
+            None => (min, max),
+        };
+
+        TreeIterator::new(self, store, version, order, min, max, root_node)
+    }
+
+    /// Resume an iteration from a checkpoint taken by `TreeIterator::checkpoint`.
+    /// The iterator yields the items that the original iterator would have
+    /// yielded after the checkpoint, with the same order and bounds.
+    ///
+    /// Only the nodes on the path to the checkpoint's position are loaded, so
+    /// no work done before the checkpoint is repeated.
+    pub fn resume_iterate<'c, S: Storage>(
+        &'a self,
+        store: &'c S,
+        checkpoint: &IteratorCheckpoint,
+    ) -> Result<TreeIterator<'c, K, V, S, C, H>>
+    where
+        'a: 'c,
+    {
+        let root_node = self.root_node(store, checkpoint.version)?;
+
+        // rebuild the stack of nodes from the root down to the position
+        let mut visited_nodes = vec![];
+        if !checkpoint.finished {
+            let mut node_key = NodeKey::root(checkpoint.version);
+            let mut node = root_node;
+            for nibble in checkpoint.visited_nibbles.nibbles() {
+                let child = node.children.get(nibble).ok_or(TreeError::InvalidCheckpoint)?;
+                let child_node_key = node_key.child(child.version, nibble);
+                let child_node = self.load_child(store, &child_node_key)?;
+                visited_nodes.push(node);
+                node_key = child_node_key;
+                node = child_node;
+            }
+            visited_nodes.push(node);
+        }
+
+        let order = if checkpoint.ascending {
+            Order::Ascending
+        } else {
+            Order::Descending
+        };
+
+        Ok(TreeIterator {
+            tree: self,
+            store,
+            version: checkpoint.version,
+            order,
+            min: checkpoint.min.clone(),
+            max: checkpoint.max.clone(),
+            visited_nibbles: checkpoint.visited_nibbles.clone(),
+            visited_nodes,
+            start_after_index: checkpoint.start_after_index,
+            started: checkpoint.started,
+            reads_left: None,
+        })
+    }
+
+    /// Similar to `iterate`, but stops once `max_items` items have been
+    /// collected, or once including the next item would make the items exceed
+    /// `max_bytes` in size, whichever comes first. The response reports which
+    /// limit was hit, if any, along with a cursor from which the iteration can
+    /// be continued using `resume_iterate_limited`.
+    ///
+    /// The size of an item is that of its JSON encoding, which is what the
+    /// items would be serialized into in a query response. This allows query
+    /// handlers to keep their responses under the chain's size cap.
+    ///
+    /// Note that if the first item alone exceeds `max_bytes`, no item is
+    /// returned and the caller can't make progress with the same limit.
+    #[allow(clippy::too_many_arguments)]
+    pub fn iterate_limited<S: Storage>(
+        &'a self,
+        store: &S,
+        order: Order,
+        min: Option<&K>,
+        max: Option<&K>,
+        version: Option<u64>,
+        max_items: usize,
+        max_bytes: usize,
+    ) -> Result<IterateResponse<K, V>> {
+        let iter = self.iterate(store, order, min, max, version)?;
+        collect_limited(iter, max_items, max_bytes)
+    }
+
+    /// Continue an iteration from the cursor of an `IterateResponse`, with the
+    /// same limits as `iterate_limited`. The cursor only holds the path to the
+    /// iterator's position, so continuing doesn't revisit the items returned
+    /// before, and the cursor can be handed to a client between queries.
+    pub fn resume_iterate_limited<S: Storage>(
+        &'a self,
+        store: &S,
+        cursor: &IteratorCheckpoint,
+        max_items: usize,
+        max_bytes: usize,
+    ) -> Result<IterateResponse<K, V>> {
+        let iter = self.resume_iterate(store, cursor)?;
+        collect_limited(iter, max_items, max_bytes)
+    }
+
+    #[cfg(feature = "debug")]
+    pub fn node(
+        &self,
+        store: &dyn Storage,
+        node_key: NodeKey,
+    ) -> Result<Option<NodeResponse<K, V>>> {
+        Ok(self
+            .nodes
+            .may_load(store, &node_key)?
+            .map(|node| NodeResponse {
+                node_key,
+                hash: node.hash_using::<H>(),
+                node,
+            }))
+    }
+
+    #[cfg(feature = "debug")]
+    pub fn nodes(
+        &self,
+        store: &dyn Storage,
+        start_after: Option<&NodeKey>,
+        limit: Option<usize>,
+    ) -> Result<Vec<NodeResponse<K, V>>> {
+        let start = start_after.map(Bound::exclusive);
+        let limit = limit.unwrap_or(DEFAULT_QUERY_BATCH_SIZE);
+
+        self.nodes
+            .range(store, start, None, Order::Ascending)
+            .take(limit)
+            .map(|item| {
+                let (node_key, node) = item?;
+                Ok(NodeResponse {
+                    node_key,
+                    hash: node.hash_using::<H>(),
+                    node,
+                })
+            })
+            .collect()
+    }
+
+    /// Load the nodes of the given keys, in the same order as the keys. A key
+    /// whose node doesn't exist (e.g. it has been pruned) results in `None`.
+    #[cfg(feature = "debug")]
+    pub fn nodes_by_keys(
+        &self,
+        store: &dyn Storage,
+        node_keys: Vec<NodeKey>,
+    ) -> Result<Vec<Option<NodeResponse<K, V>>>> {
+        node_keys.into_iter().map(|node_key| self.node(store, node_key)).collect()
+    }
+
+    /// Dump the node of the given key and all its descendants, in depth-first
+    /// order (a node comes before its children, which are ordered by index).
+    /// Returns an empty list if the node doesn't exist, and an error if any of
+    /// the descendants is missing.
+    #[cfg(feature = "debug")]
+    pub fn subtree(
+        &self,
+        store: &dyn Storage,
+        node_key: NodeKey,
+    ) -> Result<Vec<NodeResponse<K, V>>> {
+        let Some(node) = self.nodes.may_load(store, &node_key)? else {
+            return Ok(vec![]);
+        };
+
+        let mut responses = vec![];
+        let mut stack = vec![(node_key, node)];
+
+        while let Some((node_key, node)) = stack.pop() {
+            // push in reverse so that the child with the smallest index is
+            // popped first
+            for child in node.children.as_ref().iter().rev() {
+                let child_node_key = node_key.child(child.version, child.index);
+                let child_node = self.load_child(store, &child_node_key)?;
+                stack.push((child_node_key, child_node));
+            }
+
+            responses.push(NodeResponse {
+                node_key,
+                hash: node.hash_using::<H>(),
+                node,
+            });
+        }
+
+        Ok(responses)
+    }
+
+    /// List the nodes on the path from the root to the given key at the given
+    /// version, indicating for each one whether it's shared with the previous
+    /// version or newly created in this version.
+    ///
+    /// Since the tree is copy-on-write, a version only creates the nodes on
+    /// the paths to the keys it changes; all other nodes are shared with the
+    /// previous version. This is useful for seeing the copy-on-write behavior
+    /// in action, or for verifying it is preserved.
+    #[cfg(feature = "debug")]
+    pub fn path_sharing(
+        &self,
+        store: &dyn Storage,
+        key: &K,
+        version: Option<u64>,
+    ) -> Result<Vec<PathNodeResponse>> {
+        let version = self.version_or_default(store, version)?;
+        let nibble_path = NibblePath::from(&*self.normalize_key(key));
+
+        let mut node_key = NodeKey::root(version);
+        let Some(mut node) = self.nodes.may_load(store, &node_key)? else {
+            return Ok(vec![]);
+        };
+
+        let mut responses = vec![];
+        for depth in 0..=nibble_path.num_nibbles {
+            let child = if depth < nibble_path.num_nibbles {
+                node.children.get(nibble_path.get_nibble(depth)).cloned()
+            } else {
+                None
+            };
+
+            responses.push(PathNodeResponse {
+                node_key: node_key.clone(),
+                hash: node.hash_using::<H>(),
+                shared: node_key.version < version,
+            });
+
+            let Some(child) = child else {
+                break;
+            };
+
+            node_key = node_key.child(child.version, child.index);
+            node = self.load_child(store, &node_key)?;
+        }
+
+        Ok(responses)
+    }
+
+    #[cfg(feature = "debug")]
+    pub fn orphans(
+        &self,
+        store: &dyn Storage,
+        start_after: Option<&OrphanResponse>,
+        limit: Option<usize>,
+    ) -> Result<Vec<OrphanResponse>> {
+        let start = start_after.map(|o| {
+            Bound::exclusive((o.since_version / self.orphan_epoch_len, &o.node_key))
+        });
+        let limit = limit.unwrap_or(DEFAULT_QUERY_BATCH_SIZE);
+
+        self.orphans
+            .items(store, start, None, Order::Ascending)
+            .take(limit)
+            .map(|item| {
+                let (epoch, node_key) = item?;
+                Ok(OrphanResponse {
+                    node_key,
+                    since_version: epoch * self.orphan_epoch_len,
+                })
+            })
+            .collect()
+    }
+}
+
+impl<'a, K, C, H> Tree<'a, K, Vec<u8>, C, H>
+where
+    K: Serialize + DeserializeOwned + Clone + PartialEq + AsRef<[u8]>,
+    C: NodeCodec<K, Vec<u8>>,
+    H: TreeHasher,
+{
+    /// Query the value associated with the key, deserialized from the bytes
+    /// written by `Op::insert_typed`.
+    pub fn get_typed<T: DeserializeOwned>(
+        &self,
+        store: &dyn Storage,
+        key: &K,
+        version: Option<u64>,
+    ) -> Result<Option<T>> {
+        self.get(store, key, false, version)?
+            .value
+            .map(|bytes| from_slice(&bytes))
+            .transpose()
+            .map_err(Into::into)
+    }
+}
+
+// adapts a tree and the storage it lives in into a source of nodes for the
+// apply algorithm
+pub(crate) struct TreeNodes<'a, 'b, K, V, C, H> {
+    tree: &'b Tree<'a, K, V, C, H>,
+    store: &'b mut dyn Storage,
+    // the records changed so far, indexed by the keys' raw bytes; `None` if
+    // not collected
+    changes: Option<BTreeMap<Vec<u8>, Change<K>>>,
+    nodes_written: u64,
+    // `None` if stats are not tracked
+    stats: Option<StatsDelta>,
+}
+
+impl<'a, 'b, K, V, C, H> TreeNodes<'a, 'b, K, V, C, H>
+where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+    C: NodeCodec<K, V>,
+{
+    // count a node that is no longer part of the tree in the stats, if tracked,
+    // which takes reading it
+    fn count_removed(&mut self, node_key: &NodeKey) -> Result<()> {
+        let Some(stats) = &mut self.stats else {
+            return Ok(());
+        };
+
+        if let Some(node) = self.tree.nodes.may_load(self.store, node_key)? {
+            stats.remove_node(&node);
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a, 'b, K, V, C, H> NodeSource<K, V> for TreeNodes<'a, 'b, K, V, C, H>
+where
+    K: Serialize + DeserializeOwned + Clone + AsRef<[u8]>,
+    V: Serialize + DeserializeOwned + HashableValue,
+    C: NodeCodec<K, V>,
+    H: TreeHasher,
+{
+    fn load(&self, node_key: &NodeKey) -> Result<Option<Node<K, V>>> {
+        self.tree.nodes.may_load(self.store, node_key).map_err(Into::into)
+    }
+
+    fn save(&mut self, node_key: NodeKey, node: Node<K, V>) -> Result<()> {
+        self.nodes_written += 1;
+        if let Some(stats) = &mut self.stats {
+            stats.add_node(&node);
+        }
+        self.tree.nodes.save(self.store, &node_key, &node).map_err(Into::into)
+    }
+
+    fn mark_orphaned(&mut self, orphaned_since_version: u64, node_key: &NodeKey) -> Result<()> {
+        self.count_removed(node_key)?;
+        self.tree
+            .mark_node_as_orphaned(self.store, orphaned_since_version, node_key)
+            .map_err(Into::into)
+    }
+
+    fn remove(&mut self, node_key: &NodeKey) -> Result<()> {
+        self.count_removed(node_key)?;
+        self.tree.nodes.remove(self.store, node_key);
+        Ok(())
+    }
+
+    fn on_record_change(&mut self, key: &K, old: Option<&V>, new: Option<&V>) {
+        if let Some(stats) = &mut self.stats {
+            stats.on_record_change(old, new);
+        }
+
+        let Some(changes) = &mut self.changes else {
+            return;
+        };
+
+        // the value before the first change is the original value, and the
+        // value after the last change is the final value
+        let change = changes.entry(key.as_ref().to_vec()).or_insert_with(|| Change {
+            key: key.clone(),
+            old_value_hash: old.map(value_hash_using::<H, V>),
+            new_value_hash: None,
+        });
+        change.new_value_hash = new.map(value_hash_using::<H, V>);
+    }
+
+    fn aggregation(&self) -> Option<fn(&K, &V) -> u128> {
+        self.tree.aggregation
+    }
+
+    fn collapse_policy(&self) -> CollapsePolicy {
+        self.tree.collapse_policy
+    }
+
+    fn hash(&self, node: &Node<K, V>) -> Hash {
+        node.hash_using::<H>()
+    }
+}
+
+/// Iterates key-value pairs in a tree.
+///
+/// Keys are yielded in the lexicographical order of their raw bytes (ascending
+/// or descending, depending on `order`), where a key is considered smaller than
+/// any other key that it is a prefix of. Exactly those keys for which
+/// `min <= key < max` are yielded.
+///
+/// Note that this holds regardless of how the keys are laid out in the tree. In
+/// particular, a node's nibble path may consist of an odd number of nibbles, or
+/// be shorter than the key of the data it stores (if the leaf was collapsed).
+///
+/// `W` is the type the values are decoded into, which is `()` for the iterator
+/// underlying `Tree::keys`, as it doesn't decode them.
+pub struct TreeIterator<'a, K, V, S, C = JsonCodec, H = Blake3, W = V> {
+    tree: &'a Tree<'a, K, V, C, H>,
+    store: &'a S,
+    version: u64,
+    order: Order,
+    min: Option<NibblePath>,
+    max: Option<NibblePath>,
+    visited_nibbles: NibblePath,
+    visited_nodes: Vec<Node<K, W>>,
+    start_after_index: Option<Nibble>,
+    started: bool,
+    // the number of nodes that may still be loaded, if limited
+    reads_left: Option<u64>,
+}
+
+impl<'a, K, V, S, C, H, W> TreeIterator<'a, K, V, S, C, H, W>
+where
+    K: AsRef<[u8]>,
+{
+    /// Iterate the tree whose root node at `version` is `root_node`.
+    pub fn new(
+        tree: &'a Tree<'a, K, V, C, H>,
+        store: &'a S,
+        version: u64,
+        order: Order,
+        min: Option<&K>,
+        max: Option<&K>,
+        root_node: Node<K, W>,
+    ) -> Self {
+        Self {
+            tree,
+            store,
+            version,
+            order,
+            min: min.map(NibblePath::from),
+            max: max.map(NibblePath::from),
+            visited_nibbles: NibblePath::empty(),
+            visited_nodes: vec![root_node],
+            start_after_index: None,
+            started: false,
+            reads_left: None,
+        }
+    }
+
+    /// Abort the iteration with `TreeError::ReadBudgetExceeded` once more than
+    /// `max_reads` nodes have been loaded from now on, e.g. to bound the work
+    /// done by a query over a wide range. The root node, which is loaded when
+    /// the iterator is created, doesn't count.
+    pub fn with_read_budget(self, max_reads: u64) -> Self {
+        Self {
+            reads_left: Some(max_reads),
+            ..self
+        }
+    }
+
+    /// Skip the keys up to and including the given one in the order of
+    /// iteration, i.e. those smaller than or equal to it if ascending, or
+    /// greater than or equal to it if descending, as with `Bound::exclusive`.
+    /// The key is mapped, as the bounds of `Tree::iterate` are.
+    ///
+    /// This lets a query page through records without returning the boundary
+    /// key again, by passing the last key of a page as `start_after` for the
+    /// next. It must be called before the iterator is advanced.
+    pub fn start_after(self, key: &K) -> Self {
+        let after = match self.tree.key_transform {
+            Some(transform) => NibblePath::from(transform(key)),
+            None => NibblePath::from(key),
+        };
+
+        match self.order {
+            // the smallest key greater than the given one is that key followed
+            // by a zero byte
+            Order::Ascending => {
+                let min = after.successor();
+                let min = match self.min {
+                    Some(old) => old.max(min),
+                    None => min,
+                };
+                Self {
+                    min: Some(min),
+                    ..self
+                }
+            },
+            Order::Descending => {
+                let max = match self.max {
+                    Some(old) => old.min(after),
+                    None => after,
+                };
+                Self {
+                    max: Some(max),
+                    ..self
+                }
+            },
+        }
+    }
+
+    /// Capture the iterator's position, from which the iteration can later be
+    /// resumed using `Tree::resume_iterate`.
+    pub fn checkpoint(&self) -> IteratorCheckpoint {
+        IteratorCheckpoint {
+            version: self.version,
+            ascending: matches!(self.order, Order::Ascending),
+            min: self.min.clone(),
+            max: self.max.clone(),
+            visited_nibbles: self.visited_nibbles.clone(),
+            start_after_index: self.start_after_index,
+            started: self.started,
+            // once the root node has been popped off the stack, there's
+            // nothing left to iterate
+            finished: self.visited_nodes.is_empty(),
+        }
+    }
+}
+
+impl<'a, K, V, S, C, H, W> TreeIterator<'a, K, V, S, C, H, W>
+where
+    S: Storage,
+    K: Serialize + DeserializeOwned + Clone + AsRef<[u8]>,
+    V: Serialize + DeserializeOwned + Clone,
+    C: NodeCodec<K, V>,
+    H: TreeHasher,
+{
+    // yield the next record, loading nodes using `load`
+    fn next_with(
+        &mut self,
+        load: LoadNodeFrom<'a, K, V, C, W>,
+    ) -> Option<Result<(K, W)>> {
+        // the root node doesn't go through `iterate_at`'s descending logic, so
+        // we need to check its data here. the root node can have data if the
+        // tree only contains a single leaf.
+        if !self.started {
+            self.started = true;
+            if let Order::Ascending = self.order {
+                let (min, max) = (self.min.as_ref(), self.max.as_ref());
+                if let Some(item) = take_data_in_range(self.visited_nodes.last_mut(), min, max) {
+                    return Some(Ok(item));
+                }
+            }
+        }
+
+        let (nodes, store) = (&self.tree.nodes, self.store);
+        iterate_at(
+            |node_key| load(nodes, store, node_key),
+            self.order,
+            self.min.as_ref(),
+            self.max.as_ref(),
+            &mut self.visited_nibbles,
+            &mut self.visited_nodes,
+            &mut self.start_after_index,
+            &mut self.reads_left,
+        )
+        .transpose()
+    }
+}
+
+impl<'a, K, V, S, C, H> Iterator for TreeIterator<'a, K, V, S, C, H>
+where
+    S: Storage,
+    K: Serialize + DeserializeOwned + Clone + AsRef<[u8]>,
+    V: Serialize + DeserializeOwned + Clone,
+    C: NodeCodec<K, V>,
+    H: TreeHasher,
+{
+    type Item = Result<(K, V)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_with(NodeMap::load)
+    }
+}
+
+/// Yields the keys of a tree's records. Created by `Tree::keys`.
+pub struct Keys<'a, K, V, S, C = JsonCodec, H = Blake3> {
+    iter: TreeIterator<'a, K, V, S, C, H, ()>,
+}
+
+impl<'a, K, V, S, C, H> Keys<'a, K, V, S, C, H>
+where
+    K: AsRef<[u8]>,
+{
+    /// See `TreeIterator::checkpoint`.
+    pub fn checkpoint(&self) -> IteratorCheckpoint {
+        self.iter.checkpoint()
+    }
+}
+
+impl<'a, K, V, S, C, H> Iterator for Keys<'a, K, V, S, C, H>
+where
+    S: Storage,
+    K: Serialize + DeserializeOwned + Clone + AsRef<[u8]>,
+    V: Serialize + DeserializeOwned + Clone,
+    C: NodeCodec<K, V>,
+    H: TreeHasher,
+{
+    type Item = Result<K>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next_with(NodeMap::load_without_value).map(|item| item.map(|(key, ())| key))
+    }
+}
+
+/// Yields the values of a tree's records. Created by `Tree::values`.
+pub struct Values<'a, K, V, S, C = JsonCodec, H = Blake3> {
+    iter: TreeIterator<'a, K, V, S, C, H>,
+}
+
+impl<'a, K, V, S, C, H> Values<'a, K, V, S, C, H>
+where
+    K: AsRef<[u8]>,
+{
+    /// See `TreeIterator::checkpoint`.
+    pub fn checkpoint(&self) -> IteratorCheckpoint {
+        self.iter.checkpoint()
+    }
+}
+
+impl<'a, K, V, S, C, H> Iterator for Values<'a, K, V, S, C, H>
+where
+    S: Storage,
+    K: Serialize + DeserializeOwned + Clone + AsRef<[u8]>,
+    V: Serialize + DeserializeOwned + Clone,
+    C: NodeCodec<K, V>,
+    H: TreeHasher,
+{
+    type Item = Result<V>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next().map(|item| item.map(|(_, value)| value))
+    }
+}
+
+// collect the items of an iterator up to the limits of `iterate_limited`. the
+// cursor is taken before each item is pulled, so that the item that hits a
+// limit is yielded again once resumed
+fn collect_limited<K, V, S, C, H>(
+    mut iter: TreeIterator<K, V, S, C, H>,
+    max_items: usize,
+    max_bytes: usize,
+) -> Result<IterateResponse<K, V>>
+where
+    S: Storage,
+    K: Serialize + DeserializeOwned + Clone + AsRef<[u8]>,
+    V: Serialize + DeserializeOwned + Clone,
+    C: NodeCodec<K, V>,
+    H: TreeHasher,
+{
+    let mut items = vec![];
+    let mut bytes = 0;
+
+    loop {
+        let cursor = iter.checkpoint();
+        let Some(item) = iter.next() else {
+            return Ok(IterateResponse {
+                items,
+                stop_reason: StopReason::Exhausted,
+                cursor: None,
+            });
+        };
+
+        if items.len() >= max_items {
+            return Ok(IterateResponse {
+                items,
+                stop_reason: StopReason::MaxItems,
+                cursor: Some(cursor),
+            });
+        }
+
+        let item = item?;
+        let size = to_vec(&item)?.len();
+        if bytes + size > max_bytes {
+            return Ok(IterateResponse {
+                items,
+                stop_reason: StopReason::MaxBytes,
+                cursor: Some(cursor),
+            });
+        }
+
+        bytes += size;
+        items.push(item);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn iterate_at<K, W>(
+    mut load: impl FnMut(&NodeKey) -> StdResult<Node<K, W>>,
+    order: Order,
+    min: Option<&NibblePath>,
+    max: Option<&NibblePath>,
+    visited_nibbles: &mut NibblePath,
+    visited_nodes: &mut Vec<Node<K, W>>,
+    start_after_index: &mut Option<Nibble>,
+    reads_left: &mut Option<u64>,
+) -> Result<Option<(K, W)>>
+where
+    K: AsRef<[u8]>,
+{
+    loop {
+        let Some(current_node) = visited_nodes.last() else {
+            return Ok(None);
+        };
+
+        // going through the node's children, looking for the first one that's
+        // in the range and that we haven't explored yet
+        let next_child = iter_with_order(&current_node.children, order)
+            .filter(|child| !skip(child.index, *start_after_index, order))
+            .find(|child| nibbles_in_range(&visited_nibbles.child(child.index), min, max))
+            .cloned();
+
+        // if found, push it into the stack, so that we do a depth-first search,
+        // exploring the children of this child next
+        if let Some(child) = next_child {
+            let child_node_key = NodeKey::new(child.version, visited_nibbles.child(child.index));
+            spend_read(reads_left)?;
+            let child_node = load(&child_node_key)?;
+
+            visited_nibbles.push(child.index);
+            visited_nodes.push(child_node);
+            *start_after_index = None;
+
+            // a node's key is a prefix of all keys in its subtree, so it's
+            // smaller than all of them. in ascending order, the node's data
+            // therefore comes before its subtree
+            if let Order::Ascending = order {
+                if let Some(item) = take_data_in_range(visited_nodes.last_mut(), min, max) {
+                    return Ok(Some(item));
+                }
+            }
+
+            continue;
+        }
+
+        // now we've gone over all the childs of the current node. we need to go
+        // up one level and search in the siblings.
+        let mut node = visited_nodes.pop();
+        *start_after_index = visited_nibbles.pop();
+
+        // in descending order, the node's data comes after its subtree
+        if let Order::Descending = order {
+            if let Some(item) = take_data_in_range(node.as_mut(), min, max) {
+                return Ok(Some(item));
+            }
+        }
+    }
+}
+
+// count a node read against the budget, if there is one
+fn spend_read(reads_left: &mut Option<u64>) -> Result<()> {
+    if let Some(reads_left) = reads_left {
+        *reads_left = reads_left.checked_sub(1).ok_or(TreeError::ReadBudgetExceeded)?;
+    }
+    Ok(())
+}
+
+fn iter_with_order<'a, I>(items: I, order: Order) -> Box<dyn Iterator<Item = I::Item> + 'a>
+where
+    I: IntoIterator,
+    I::IntoIter: DoubleEndedIterator + 'a,
+{
+    match order {
+        Order::Ascending => Box::new(items.into_iter()),
+        Order::Descending => Box::new(items.into_iter().rev()),
+    }
+}
+
+fn skip(index: Nibble, start_after: Option<Nibble>, order: Order) -> bool {
+    let Some(after) = start_after else {
+        return false;
+    };
+
+    match order {
+        Order::Ascending => index <= after,
+        Order::Descending => index >= after,
+    }
+}
+
+/// Whether the subtree under the given nibble path may contain keys within the
+/// range, in which case it needs to be explored.
+///
+/// All keys in the subtree start with the nibble path. Therefore we compare the
+/// nibble path with each bound nibble-by-nibble, up to the length of the
+/// shorter of the two. If they tie, the tie is broken as follows:
+///
+/// - for `min`: the subtree may contain `min` itself, or keys that `min` is a
+///   prefix of, which are all in range. explore it.
+/// - for `max`: if the nibble path is at least as long as `max`, every key in
+///   the subtree either equals `max` or has `max` as a prefix, so is out of
+///   range. skip it. otherwise, the subtree may contain keys that are prefixes
+///   of `max` or that diverge from it with a smaller nibble. explore it.
+///
+/// Note that comparing the raw bytes does not work here, because if the nibble
+/// path has an odd length, its last byte is padded with a zero nibble.
+fn nibbles_in_range(
+    nibble_path: &NibblePath,
+    min: Option<&NibblePath>,
+    max: Option<&NibblePath>,
+) -> bool {
+    if let Some(min) = min {
+        if cmp_common_nibbles(nibble_path, min) == Ordering::Less {
+            return false;
+        }
+    }
+
+    if let Some(max) = max {
+        match cmp_common_nibbles(nibble_path, max) {
+            Ordering::Greater => return false,
+            Ordering::Equal if nibble_path.num_nibbles >= max.num_nibbles => return false,
+            _ => (),
+        }
+    }
+
+    true
+}
+
+// compare two nibble paths, up to the length of the shorter one
+fn cmp_common_nibbles(a: &NibblePath, b: &NibblePath) -> Ordering {
+    (0..a.num_nibbles.min(b.num_nibbles))
+        .map(|i| a.get_nibble(i).cmp(&b.get_nibble(i)))
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+/// Whether the key is within the range. Unlike `nibbles_in_range`, here we're
+/// dealing with a full key, so the raw bytes can simply be compared.
+///
+/// We need to check both bounds here. Even if a node's nibble path is in range,
+/// the key of its data may not be: if the node is a collapsed leaf, the key may
+/// be longer than the nibble path.
+fn key_in_range<K: AsRef<[u8]>>(
+    key: &K,
+    min: Option<&NibblePath>,
+    max: Option<&NibblePath>,
+) -> bool {
+    if let Some(min) = min {
+        if key.as_ref() < min.bytes.as_slice() {
+            return false;
+        }
+    }
+
+    if let Some(max) = max {
+        if key.as_ref() >= max.bytes.as_slice() {
+            return false;
+        }
+    }
+
+    true
+}
+
+// the node's data, if its key is within the range. the data is moved out of
+// the node rather than cloned, as the iterator only needs the node's children
+// from then on
+fn take_data_in_range<K, V>(
+    node: Option<&mut Node<K, V>>,
+    min: Option<&NibblePath>,
+    max: Option<&NibblePath>,
+) -> Option<(K, V)>
+where
+    K: AsRef<[u8]>,
+{
+    let node = node?;
+    if !key_in_range(&node.data.as_ref()?.key, min, max) {
+        return None;
+    }
+
+    node.data.take().map(|Record { key, value }| (key, value))
+}
+
+// the pseudorandom number from which the choice of the sample of the given
+// index is made at the given depth, which is the first 8 bytes of
+// hash(seed || index || depth)
+fn sample_choice<H: TreeHasher>(seed: &[u8], index: u32, depth: usize) -> u64 {
+    let mut hasher = H::default();
+    hasher.update(seed);
+    hasher.update(&index.to_be_bytes());
+    hasher.update(&(depth as u32).to_be_bytes());
+    u64::from_be_bytes(hasher.finalize().as_bytes()[..8].try_into().unwrap())
+}
+
+// maps store their entries under their length-prefixed namespaces, whereas
+// items are stored under their namespaces as is
+fn map_prefix(namespace: &[u8]) -> Vec<u8> {
+    let mut prefix = (namespace.len() as u16).to_be_bytes().to_vec();
+    prefix.extend_from_slice(namespace);
+    prefix
+}
+
+// a function that loads the node of the given key, used by `get_at_with`
+pub(crate) type LoadNode<'l, K, V> = dyn FnMut(&NodeKey) -> Result<Option<Node<K, V>>> + 'l;
+
+// a function that loads the node of the given key from the node map, used by
+// `TreeIterator` to decode nodes with or without their values
+type LoadNodeFrom<'a, K, V, C, W> =
+    fn(&NodeMap<'a, K, V, C>, &dyn Storage, &NodeKey) -> StdResult<Node<K, W>>;
+
+// versions start from 1 and go up to u64::MAX, after which the tree can no
+// longer be updated
+pub(crate) fn next_version(version: u64) -> Result<u64> {
+    version.checked_add(1).ok_or(TreeError::VersionOverflow)
+}
+
+// the smallest key whose record differs between two lists of records, both in
+// ascending order of keys
+fn first_different_record<K, V>(a: Vec<(K, V)>, b: Vec<(K, V)>) -> Option<K>
+where
+    K: AsRef<[u8]>,
+    V: PartialEq,
+{
+    let mut a = a.into_iter().peekable();
+    let mut b = b.into_iter().peekable();
+    loop {
+        let ordering = match (a.peek(), b.peek()) {
+            (Some((key_a, value_a)), Some((key_b, value_b))) => {
+                match key_a.as_ref().cmp(key_b.as_ref()) {
+                    Ordering::Equal if value_a == value_b => {
+                        a.next();
+                        b.next();
+                        continue;
+                    },
+                    ordering => ordering,
+                }
+            },
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => return None,
+        };
+
+        // the record that comes first is either missing on the other side, or
+        // has a different value
+        return match ordering {
+            Ordering::Greater => b.next().map(|(key, _)| key),
+            _ => a.next().map(|(key, _)| key),
+        };
+    }
+}
+
+// where the record adjacent to a key is, when looking for it in `adjacent_key`
+enum Adjacent<K, V> {
+    Record(Record<K, V>),
+    Subtree(NodeKey),
+}
+
+// the weight of every record when aggregating subtree counts
+fn count_one<K, V>(_: &K, _: &V) -> u128 {
+    1
+}
+
+/// Check that no namespace is used twice, typically those of all the trees and
+/// other storage primitives of a contract:
+///
+/// ```rust
+/// use tree::{check_namespaces, Tree};
+///
+/// const BALANCES: Tree<Vec<u8>, Vec<u8>> = Tree::new("b/v", "b/n", "b/o");
+/// const ACCOUNTS: Tree<Vec<u8>, Vec<u8>> = Tree::new("a/v", "a/n", "a/o");
+///
+/// check_namespaces(&[BALANCES.namespaces(), ACCOUNTS.namespaces()].concat()).unwrap();
+/// ```
+///
+/// Trees sharing a namespace silently overwrite each other's records, which
+/// corrupts both. Namespaces within a single tree are checked when it's
+/// created; namespaces across trees can't be, since trees are usually declared
+/// as independent constants. Call this in a unit test, or on instantiation.
+pub fn check_namespaces(namespaces: &[&[u8]]) -> Result<()> {
+    for (i, namespace) in namespaces.iter().enumerate() {
+        if namespaces[..i].contains(namespace) {
+            return Err(TreeError::NamespaceCollision {
+                namespace: String::from_utf8_lossy(namespace).into(),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+// string comparison usable in const fns
+const fn same_namespace(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+
+    true
+}
+
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum TreeError {
+    #[error(transparent)]
+    Std(#[from] cosmwasm_std::StdError),
+
+    #[error("cannot query at version {querying} which is newer than the latest ({latest})")]
+    VersionNewerThanLatest {
+        latest: u64,
+        querying: u64,
+    },
+
+    #[error("root node of version {version} not found, probably pruned")]
+    RootNodeNotFound {
+        version: u64,
+    },
+
+    #[error(
+        "tree corrupted! non-root node not found (version: {}, nibble_path: {})",
+        node_key.version,
+        node_key.nibble_path.to_hex(),
+    )]
+    NonRootNodeNotFound {
+        node_key: NodeKey,
+    },
+
+    #[error("store not found: {name}")]
+    UnknownStore {
+        name: String,
+    },
+
+    #[error("cannot append a key that is not greater than all existing keys")]
+    NotAppendOnly,
+
+    #[error("aggregates are not tracked; create the tree using `with_aggregation`")]
+    AggregationNotTracked,
+
+    #[error("aggregate exceeds the maximum ({})", u128::MAX)]
+    AggregateOverflow,
+
+    #[error("keys are not filtered; create the tree using `with_key_filter`")]
+    KeyFilterNotTracked,
+
+    #[error("version headers are not recorded; create the tree using `with_version_headers`")]
+    VersionHeadersNotTracked,
+
+    #[error("root history is not recorded; create the tree using `with_root_history`")]
+    RootHistoryNotTracked,
+
+    #[error("keys don't expire; create the tree using `with_expiration`")]
+    ExpirationNotTracked,
+
+    #[error("churn is not counted; create the tree using `with_churn_counters`")]
+    ChurnNotTracked,
+
+    #[error("activity is not counted; create the tree using `with_counters`")]
+    CountersNotTracked,
+
+    #[error("stats are not tracked; create the tree using `with_stats`")]
+    StatsNotTracked,
+
+    #[cfg(feature = "journal")]
+    #[error("ops are not journaled; create the tree using `with_journal`")]
+    JournalNotTracked,
+
+    #[cfg(feature = "proof-metrics")]
+    #[error("proof sizes are not tracked; create the tree using `with_proof_metrics`")]
+    ProofMetricsNotTracked,
+
+    #[error("key is too long: {num_nibbles} nibbles, exceeding the maximum of {}", MAX_NIBBLES)]
+    KeyTooLong {
+        num_nibbles: usize,
+    },
+
+    #[error("key is empty")]
+    EmptyKey,
+
+    #[error("version has reached the maximum ({}), no more versions can be created", u64::MAX)]
+    VersionOverflow,
+
+    #[error("cannot commit version {version} which is not newer than the latest ({latest})")]
+    VersionNotNewer {
+        latest: u64,
+        version: u64,
+    },
+
+    #[error("version {version} has no counterpart in the application's version type")]
+    VersionNotRepresentable {
+        version: u64,
+    },
+
+    #[error("batch contains more than one op on nibble path {}", nibble_path.to_hex())]
+    DuplicateNibblePath {
+        nibble_path: NibblePath,
+    },
+
+    #[error("keys must be given in strictly ascending order")]
+    KeysNotSorted,
+
+    #[error("all keys must be of the same length to compute a sparse merkle root")]
+    KeyLengthsDiffer,
+
+    #[error("iterator checkpoint doesn't match the tree")]
+    InvalidCheckpoint,
+
+    #[error("query exceeded its budget of node reads")]
+    ReadBudgetExceeded,
+
+    #[error("version {version} was not committed while the root history was recorded")]
+    VersionNotInRootHistory {
+        version: u64,
+    },
+
+    #[error("version {version} has been pruned, so its records can't be read or proven")]
+    VersionPruned {
+        version: u64,
+    },
+
+    #[error("namespace `{namespace}` is used more than once")]
+    NamespaceCollision {
+        namespace: String,
+    },
+
+    #[error("cannot import into a tree that is not empty (latest version: {latest})")]
+    TreeNotEmpty {
+        latest: u64,
+    },
+
+    #[error("tree has no version but holds nodes or orphans; call `clear` or `recover` first")]
+    LeftoverRecords,
+
+    #[error("snapshot chunk {index} doesn't match the manifest")]
+    SnapshotChunkMismatch {
+        index: usize,
+    },
+
+    #[error("snapshot has {expected} chunks, but only {received} were imported")]
+    SnapshotIncomplete {
+        expected: usize,
+        received: usize,
+    },
+
+    #[error("imported snapshot has root hash {computed}, but the manifest has {expected}")]
+    SnapshotRootMismatch {
+        expected: Hash,
+        computed: Hash,
+    },
+}
+
+type Result<T> = std::result::Result<T, TreeError>;
+
+// ----------------------------------- tests -----------------------------------
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::{
+            apply_subtree, tree::TreeNodes, value_hash, verify_aggregate, verify_batch_membership,
+            verify_get_many_response, verify_get_response, verify_get_response_in_domain,
+            verify_historical_root, verify_membership, verify_membership_in_domain,
+            verify_proof_versions, verify_subtree, verify_version_chain, Batch, BatchProof, Change,
+            CollapsePolicy, HashableValue, NibblePath, NodeKey, Op, Overlay, Proof, ProofBytes,
+            ProofEncoding, PrunedVersionPolicy, StopReason, Tree, TreeError, VerificationError,
+            Version, MAX_KEY_LEN, MAX_NIBBLES,
+        },
+        cosmwasm_schema::cw_serde,
+        cosmwasm_std::{
+            from_binary, from_slice, testing::MockStorage, to_vec, Order, StdError, Storage,
+        },
+        rand::{rngs::StdRng, Rng, SeedableRng},
+        std::{
+            borrow::Cow,
+            collections::{BTreeMap, BTreeSet},
+        },
+    };
+
+    const TREE: Tree<Vec<u8>, Vec<u8>> = Tree::new_default();
+
+    // keys drawn from a tiny alphabet, so that many of them are prefixes of
+    // each other, or share prefixes at odd nibble positions
+    fn rand_key<R: Rng>(rng: &mut R) -> Vec<u8> {
+        let len = rng.gen_range(1..=4);
+        (0..len).map(|_| [0x00, 0x01, 0x10, 0x11, 0xff][rng.gen_range(0..5)]).collect()
+    }
+
+    fn check_iteration(
+        store: &MockStorage,
+        model: &BTreeMap<Vec<u8>, Vec<u8>>,
+        min: Option<&Vec<u8>>,
+        max: Option<&Vec<u8>>,
+    ) {
+        for order in [Order::Ascending, Order::Descending] {
+            let mut expected = model
+                .iter()
+                .filter(|(k, _)| min.map_or(true, |min| *k >= min))
+                .filter(|(k, _)| max.map_or(true, |max| *k < max))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect::<Vec<_>>();
+            if let Order::Descending = order {
+                expected.reverse();
+            }
+
+            let actual = TREE
+                .iterate(store, order, min, max, None)
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+
+            assert_eq!(actual, expected, "min = {min:?}, max = {max:?}");
+        }
+    }
+
+    #[test]
+    fn iteration_matches_lexicographical_order() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut store = MockStorage::new();
+        let mut model = BTreeMap::new();
+
+        for _ in 0..50 {
+            let mut batch = Batch::new();
+            for _ in 0..rng.gen_range(1..10) {
+                let key = rand_key(&mut rng);
+                if rng.gen_bool(0.3) {
+                    model.remove(&key);
+                    batch.insert(key, Op::Delete);
+                } else {
+                    let value = vec![rng.gen()];
+                    model.insert(key.clone(), value.clone());
+                    batch.insert(key, Op::Insert(value));
+                }
+            }
+            TREE.apply(&mut store, batch).unwrap();
+
+            if model.is_empty() {
+                continue;
+            }
+
+            check_iteration(&store, &model, None, None);
+            for _ in 0..10 {
+                let min = rand_key(&mut rng);
+                let max = rand_key(&mut rng);
+                check_iteration(&store, &model, Some(&min), None);
+                check_iteration(&store, &model, None, Some(&max));
+                check_iteration(&store, &model, Some(&min), Some(&max));
+            }
+        }
+    }
+
+    #[test]
+    fn iterating_with_limits() {
+        let mut store = MockStorage::new();
+        let batch = (0u8..5).map(|i| (vec![i], Op::Insert(vec![i; 10]))).collect();
+        TREE.apply(&mut store, batch).unwrap();
+
+        // each item is encoded as `[[i],[i,i,i,i,i,i,i,i,i,i]]`, which is 27 bytes
+        let iterate = |max_items, max_bytes| {
+            TREE.iterate_limited(&store, Order::Ascending, None, None, None, max_items, max_bytes)
+                .unwrap()
+        };
+
+        let res = iterate(10, 1000);
+        assert_eq!(res.items.len(), 5);
+        assert_eq!(res.stop_reason, StopReason::Exhausted);
+
+        let res = iterate(5, 1000);
+        assert_eq!(res.items.len(), 5);
+        assert_eq!(res.stop_reason, StopReason::Exhausted);
+
+        let res = iterate(3, 1000);
+        assert_eq!(
+            res.items,
+            vec![(vec![0], vec![0; 10]), (vec![1], vec![1; 10]), (vec![2], vec![2; 10])],
+        );
+        assert_eq!(res.stop_reason, StopReason::MaxItems);
+
+        let res = iterate(10, 27 * 2);
+        assert_eq!(res.items.len(), 2);
+        assert_eq!(res.stop_reason, StopReason::MaxBytes);
+
+        let res = iterate(10, 27 * 2 - 1);
+        assert_eq!(res.items.len(), 1);
+        assert_eq!(res.stop_reason, StopReason::MaxBytes);
+
+        // continuing from the cursor, the item that hit the limit comes first
+        let res = TREE.resume_iterate_limited(&store, &res.cursor.unwrap(), 3, 1000).unwrap();
+        assert_eq!(
+            res.items,
+            vec![(vec![1], vec![1; 10]), (vec![2], vec![2; 10]), (vec![3], vec![3; 10])],
+        );
+        assert_eq!(res.stop_reason, StopReason::MaxItems);
+        let res = TREE.resume_iterate_limited(&store, &res.cursor.unwrap(), 3, 1000).unwrap();
+        assert_eq!(res.items, vec![(vec![4], vec![4; 10])]);
+        assert_eq!((res.stop_reason, res.cursor), (StopReason::Exhausted, None));
+    }
+
+    #[test]
+    fn iterating_after_keys() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let mut store = MockStorage::new();
+        let batch: Batch<_, _> =
+            (0..50).map(|_| (rand_key(&mut rng), Op::Insert(vec![1]))).collect();
+        TREE.apply(&mut store, batch).unwrap();
+
+        let (min, max) = (vec![0x01], vec![0x11, 0x00]);
+        for order in [Order::Ascending, Order::Descending] {
+            let all = TREE
+                .keys(&store, order, Some(&min), Some(&max), None)
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+
+            // starting after each key, including ones that aren't in the tree
+            // or are out of bounds
+            for after in all.iter().cloned().chain([vec![], vec![0x01, 0x05], vec![0xff]]) {
+                let expected = all
+                    .iter()
+                    .filter(|key| match order {
+                        Order::Ascending => **key > after,
+                        Order::Descending => **key < after,
+                    })
+                    .cloned()
+                    .collect::<Vec<_>>();
+                let keys = TREE
+                    .iterate(&store, order, Some(&min), Some(&max), None)
+                    .unwrap()
+                    .start_after(&after)
+                    .map(|item| item.map(|(key, _)| key))
+                    .collect::<Result<Vec<_>, _>>()
+                    .unwrap();
+                assert_eq!(keys, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn resuming_from_checkpoints() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut store = MockStorage::new();
+        let batch: Batch<_, _> =
+            (0..50).map(|_| (rand_key(&mut rng), Op::Insert(vec![1]))).collect();
+        TREE.apply(&mut store, batch).unwrap();
+
+        let min = vec![0x01];
+        let max = vec![0x11, 0x00];
+        for order in [Order::Ascending, Order::Descending] {
+            let all = TREE
+                .iterate(&store, order, Some(&min), Some(&max), None)
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+
+            // take a checkpoint after every possible number of items, including
+            // none, and after the iterator is exhausted. round trip it through
+            // serialization, as if it was persisted
+            for taken in 0..=(all.len() + 1) {
+                let mut iter = TREE.iterate(&store, order, Some(&min), Some(&max), None).unwrap();
+                let mut items = iter.by_ref().take(taken).collect::<Result<Vec<_>, _>>().unwrap();
+                let checkpoint = from_slice(&to_vec(&iter.checkpoint()).unwrap()).unwrap();
+
+                for item in TREE.resume_iterate(&store, &checkpoint).unwrap() {
+                    items.push(item.unwrap());
+                }
+                assert_eq!(items, all);
+            }
+        }
+    }
+
+    #[test]
+    fn pruning_while_reading() {
+        let mut rng = StdRng::seed_from_u64(777);
+        let mut store = MockStorage::new();
+        let mut model = BTreeMap::new();
+        let mut models = vec![BTreeMap::new()];
+
+        for _ in 0..20 {
+            let mut batch = Batch::new();
+            for _ in 0..rng.gen_range(1..10) {
+                let key = rand_key(&mut rng);
+                if rng.gen_bool(0.3) {
+                    model.remove(&key);
+                    batch.insert(key, Op::Delete);
+                } else {
+                    model.insert(key.clone(), vec![rng.gen()]);
+                    batch.insert(key.clone(), Op::Insert(model[&key].clone()));
+                }
+            }
+            let version = TREE.apply(&mut store, batch).unwrap().version;
+            // versions are only created if the batch changes the tree
+            models.resize(version as usize + 1, model.clone());
+        }
+        let latest = models.len() as u64 - 1;
+
+        // an iteration at the latest version, paused halfway
+        let mut iter = TREE.iterate(&store, Order::Ascending, None, None, None).unwrap();
+        let mut items = iter.by_ref().take(model.len() / 2).collect::<Result<Vec<_>, _>>().unwrap();
+        let checkpoint = iter.checkpoint();
+
+        // prune one version at a time, each time reading all retained versions
+        for up_to_version in 1..latest {
+            TREE.prune(&mut store, Some(up_to_version)).unwrap();
+            for version in (up_to_version + 1)..=latest {
+                let expected = &models[version as usize];
+                let iter = TREE.iterate(&store, Order::Ascending, None, None, Some(version));
+                let records = iter.unwrap().collect::<Result<BTreeMap<_, _>, _>>().unwrap();
+                assert_eq!(&records, expected);
+
+                let root_hash = TREE.root(&store, Some(version)).ok().map(|res| res.root_hash);
+                for (key, value) in expected {
+                    let res = TREE.get(&store, key, true, Some(version)).unwrap();
+                    let proof = from_binary(&res.proof.unwrap()).unwrap();
+                    let root_hash = root_hash.as_ref().unwrap();
+                    assert!(verify_membership(root_hash, key, value, &proof).is_ok());
+                }
+            }
+        }
+
+        for item in TREE.resume_iterate(&store, &checkpoint).unwrap() {
+            items.push(item.unwrap());
+        }
+        assert_eq!(items, model.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn collapse_policies() {
+        let mut rng = StdRng::seed_from_u64(3998);
+        let batches = (0..30)
+            .map(|_| {
+                (0..rng.gen_range(1..10))
+                    .map(|_| {
+                        let op = if rng.gen_bool(0.4) { Op::Delete } else { Op::Insert(vec![1]) };
+                        (rand_key(&mut rng), op)
+                    })
+                    .collect::<Batch<_, _>>()
+            })
+            .collect::<Vec<_>>();
+
+        let mut root_hashes = vec![];
+        for policy in [CollapsePolicy::Always, CollapsePolicy::Never, CollapsePolicy::MinDepth(2)] {
+            let tree: Tree<Vec<u8>, Vec<u8>> = Tree::new_default().with_collapse_policy(policy);
+            let mut store = MockStorage::new();
+            let mut replayed_store = MockStorage::new();
+            let mut model = BTreeMap::new();
+            let mut policy_root_hashes = vec![];
+
+            for batch in &batches {
+                for (key, op) in batch {
+                    match op {
+                        Op::Insert(value) => model.insert(key.clone(), value.clone()),
+                        Op::Delete => model.remove(key),
+                    };
+                }
+                tree.apply(&mut store, batch.clone()).unwrap();
+                tree.apply(&mut replayed_store, batch.clone()).unwrap();
+
+                // the same history leads to the same root
+                let root_hash = tree.root(&store, None).ok().map(|res| res.root_hash);
+                let replayed_root_hash = tree.root(&replayed_store, None).ok();
+                assert_eq!(root_hash, replayed_root_hash.map(|res| res.root_hash));
+
+                // the tree remains queryable and provable
+                let iter = tree.iterate(&store, Order::Ascending, None, None, None).unwrap();
+                let records = iter.collect::<Result<BTreeMap<_, _>, _>>().unwrap();
+                assert_eq!(records, model);
+                if let Some(root_hash) = &root_hash {
+                    for key in batch.keys() {
+                        let res = tree.get(&store, key, true, None).unwrap();
+                        let value = verify_get_response(root_hash, &res).unwrap();
+                        assert_eq!(value, model.get(key));
+                    }
+                }
+
+                policy_root_hashes.push(root_hash);
+            }
+
+            // only with the default policy does the root depend on the records
+            // alone, regardless of the history
+            if policy == CollapsePolicy::Always {
+                let batch = model.into_iter().map(|(key, value)| (key, Op::Insert(value)));
+                let mut fresh_store = MockStorage::new();
+                tree.apply(&mut fresh_store, batch.collect()).unwrap();
+                let root_hash = tree.root(&fresh_store, None).unwrap().root_hash;
+                assert_eq!(policy_root_hashes.last().unwrap().as_ref(), Some(&root_hash));
+            }
+
+            root_hashes.push(policy_root_hashes);
+        }
+
+        // leaves left alone after deletions are kept in place, so the roots
+        // diverge from those of the default policy
+        assert_ne!(root_hashes[0], root_hashes[1]);
+        assert_ne!(root_hashes[0], root_hashes[2]);
+    }
+
+    #[test]
+    fn rank_and_select() {
+        const COUNTED_TREE: Tree<Vec<u8>, Vec<u8>> = Tree::new_default().with_subtree_counts();
+
+        let mut rng = StdRng::seed_from_u64(420);
+        let mut store = MockStorage::new();
+        let mut model = BTreeMap::new();
+
+        for _ in 0..30 {
+            let mut batch = Batch::new();
+            for _ in 0..rng.gen_range(1..10) {
+                let key = rand_key(&mut rng);
+                if rng.gen_bool(0.3) {
+                    model.remove(&key);
+                    batch.insert(key, Op::Delete);
+                } else {
+                    model.insert(key.clone(), vec![rng.gen()]);
+                    batch.insert(key.clone(), Op::Insert(model[&key].clone()));
+                }
+            }
+            COUNTED_TREE.apply(&mut store, batch).unwrap();
+
+            if model.is_empty() {
+                continue;
+            }
+
+            for (n, (key, value)) in model.iter().enumerate() {
+                let n = n as u128;
+                assert_eq!(COUNTED_TREE.rank(&store, key, None).unwrap(), n);
+                assert_eq!(
+                    COUNTED_TREE.select(&store, n, None).unwrap(),
+                    Some((key.clone(), value.clone())),
+                );
+            }
+            assert_eq!(COUNTED_TREE.select(&store, model.len() as u128, None).unwrap(), None);
+
+            for _ in 0..10 {
+                let key = rand_key(&mut rng);
+                let rank = model.range(..key.clone()).count() as u128;
+                assert_eq!(COUNTED_TREE.rank(&store, &key, None).unwrap(), rank);
+            }
+        }
+    }
+
+    #[test]
+    fn rank_requires_counts() {
+        let mut store = MockStorage::new();
+        let batch = (0u8..5).map(|i| (vec![i], Op::Insert(vec![i]))).collect();
+        TREE.apply(&mut store, batch).unwrap();
+
+        assert_eq!(TREE.rank(&store, &vec![3], None), Err(TreeError::AggregationNotTracked));
+        assert_eq!(TREE.select(&store, 3, None), Err(TreeError::AggregationNotTracked));
+    }
+
+    #[test]
+    fn aggregate_overflow() {
+        const HEAVY: Tree<Vec<u8>, Vec<u8>> =
+            Tree::new_default().with_aggregation(|_, _| u128::MAX / 2 + 1);
+
+        // either weight fits, but their sum at the root doesn't
+        let mut store = MockStorage::new();
+        HEAVY.apply(&mut store, Batch::from([(b"a".to_vec(), Op::Insert(vec![]))])).unwrap();
+        assert_eq!(
+            HEAVY.apply(&mut store, Batch::from([(b"b".to_vec(), Op::Insert(vec![]))])),
+            Err(TreeError::AggregateOverflow),
+        );
+        assert_eq!(HEAVY.latest_version::<u64>(&store).unwrap(), Some(1));
+    }
+
+    #[test]
+    fn proving_aggregates() {
+        // every value is a single byte, which is the weight
+        const STAKES: Tree<Vec<u8>, Vec<u8>> =
+            Tree::new_default().with_aggregation(|_, value| value[0] as u128);
+
+        let mut rng = StdRng::seed_from_u64(69);
+        let mut store = MockStorage::new();
+        let mut model = BTreeMap::new();
+
+        for _ in 0..30 {
+            let mut batch = Batch::new();
+            for _ in 0..rng.gen_range(1..10) {
+                let key = rand_key(&mut rng);
+                if rng.gen_bool(0.3) {
+                    model.remove(&key);
+                    batch.insert(key, Op::Delete);
+                } else {
+                    let value = vec![rng.gen()];
+                    model.insert(key.clone(), value.clone());
+                    batch.insert(key, Op::Insert(value));
+                }
+            }
+            STAKES.apply(&mut store, batch).unwrap();
+
+            if model.is_empty() {
+                continue;
+            }
+
+            let root_hash = STAKES.root(&store, None).unwrap().root_hash;
+
+            for _ in 0..10 {
+                let mut prefix = rand_key(&mut rng);
+                prefix.truncate(rng.gen_range(0..=2));
+
+                let expected = model
+                    .iter()
+                    .filter(|(k, _)| k.starts_with(&prefix))
+                    .map(|(_, v)| v[0] as u128)
+                    .sum::<u128>();
+
+                let res = STAKES.aggregate(&store, &prefix, true, None).unwrap();
+                assert_eq!(res.total, expected, "prefix = {prefix:?}");
+
+                let proof: Proof<Vec<u8>, Vec<u8>> = from_binary(&res.proof.unwrap()).unwrap();
+                assert!(verify_aggregate(&root_hash, &prefix, expected, &proof).is_ok());
+                assert!(verify_aggregate(&root_hash, &prefix, expected + 1, &proof).is_err());
+            }
+
+            // membership proofs still work, since the proof nodes carry their
+            // aggregates
+            let (key, value) = model.iter().next().unwrap();
+            let res = STAKES.get(&store, key, true, None).unwrap();
+            let proof: Proof<Vec<u8>, Vec<u8>> = from_binary(&res.proof.unwrap()).unwrap();
+            assert!(verify_membership(&root_hash, key, value, &proof).is_ok());
+        }
+    }
+
+    #[test]
+    fn proving_subtrees() {
+        let mut rng = StdRng::seed_from_u64(17);
+        let mut store = MockStorage::new();
+        let mut keys = BTreeSet::new();
+        for _ in 0..10 {
+            let batch: Batch<_, _> = (0..rng.gen_range(1..10))
+                .map(|_| (rand_key(&mut rng), Op::Insert(vec![rng.gen()])))
+                .collect();
+            keys.extend(batch.keys().cloned());
+            TREE.apply(&mut store, batch).unwrap();
+        }
+        let root_hash = TREE.root(&store, None).unwrap().root_hash;
+
+        for _ in 0..50 {
+            let nibble_path = NibblePath::from(rand_key(&mut rng));
+            let prefix = nibble_path.checked_crop(rng.gen_range(0..=3)).unwrap_or(nibble_path);
+            let res = TREE.subtree_root(&store, &prefix, None).unwrap();
+            let under_prefix = keys.iter().any(|key| NibblePath::from(key).starts_with(&prefix));
+            assert_eq!(res.hash.is_some(), under_prefix, "prefix = {prefix:?}");
+
+            let proof: Proof<Vec<u8>, Vec<u8>> = from_binary(&res.proof).unwrap();
+            assert!(verify_subtree(&root_hash, &prefix, res.hash.as_ref(), &proof).is_ok());
+            let wrong_hash = res.hash.map_or(Some(root_hash.clone()), |_| None);
+            assert!(verify_subtree(&root_hash, &prefix, wrong_hash.as_ref(), &proof).is_err());
+        }
+
+        // the subtree's hash only changes when a key under its prefix does
+        let prefix = NibblePath::from([0x01]);
+        let hash = TREE.subtree_root(&store, &prefix, None).unwrap().hash;
+        TREE.apply(&mut store, Batch::from([(vec![0x11], Op::Insert(vec![0]))])).unwrap();
+        assert_eq!(TREE.subtree_root(&store, &prefix, None).unwrap().hash, hash);
+        TREE.apply(&mut store, Batch::from([(vec![0x01, 0x01], Op::Insert(vec![0]))])).unwrap();
+        assert_ne!(TREE.subtree_root(&store, &prefix, None).unwrap().hash, hash);
+    }
+
+    #[cfg(feature = "debug")]
+    #[test]
+    fn path_sharing() {
+        let mut store = MockStorage::new();
+        let batch: Batch<_, _> = [("foo", "1"), ("bar", "2")]
+            .into_iter()
+            .map(|(k, v)| (k.as_bytes().to_vec(), Op::Insert(v.as_bytes().to_vec())))
+            .collect();
+        TREE.apply(&mut store, batch).unwrap();
+
+        let batch = Batch::from([(b"bar".to_vec(), Op::Insert(b"3".to_vec()))]);
+        TREE.apply(&mut store, batch).unwrap();
+
+        // "foo" and "bar" share the first nibble (6), so only the leaf of "foo"
+        // is shared with version 1
+        let shared = |key: &[u8]| -> Vec<bool> {
+            let res = TREE.path_sharing(&store, &key.to_vec(), None).unwrap();
+            res.into_iter().map(|node| node.shared).collect()
+        };
+        assert_eq!(shared(b"foo"), [false, false, true]);
+        assert_eq!(shared(b"bar"), [false, false, false]);
+    }
+
+    #[test]
+    fn getting_many() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut store = MockStorage::new();
+        let model = (0..50)
+            .map(|_| (rand_key(&mut rng), rand_key(&mut rng)))
+            .collect::<BTreeMap<_, _>>();
+        let batch = model.iter().map(|(k, v)| (k.clone(), Op::Insert(v.clone()))).collect();
+        TREE.apply(&mut store, batch).unwrap();
+        let root_hash = TREE.root(&store, None).unwrap().root_hash;
+
+        // some of the keys don't exist, and some are requested more than once
+        let keys = (0..30).map(|_| rand_key(&mut rng)).collect::<Vec<_>>();
+        let res = TREE.get_many(&store, &keys, true, None).unwrap();
+        let expected = keys
+            .iter()
+            .map(|key| (key.clone(), model.get(key).cloned()))
+            .collect::<BTreeMap<_, _>>();
+        assert_eq!(res.records, expected.into_iter().collect::<Vec<_>>());
+        assert!(res.records.iter().any(|(_, value)| value.is_some()));
+        assert!(res.records.iter().any(|(_, value)| value.is_none()));
+        verify_get_many_response(&root_hash, &res).unwrap();
+
+        // the proof is smaller than separate proofs of the same keys
+        let proof_len = res.proof.as_ref().unwrap().len();
+        let separate_len = res
+            .records
+            .iter()
+            .map(|(key, _)| TREE.get(&store, key, true, None).unwrap().proof.unwrap().len())
+            .sum::<usize>();
+        assert!(proof_len < separate_len);
+
+        // the proof can't be used to claim other values
+        let proof: BatchProof<Vec<u8>, Vec<u8>> = from_binary(&res.proof.unwrap()).unwrap();
+        let (present, _) = res.records.iter().find(|(_, value)| value.is_some()).unwrap();
+        let (absent, _) = res.records.iter().find(|(_, value)| value.is_none()).unwrap();
+        assert!(matches!(
+            verify_batch_membership(&root_hash, &[(present.clone(), Some(b"x".to_vec()))], &proof),
+            Err(VerificationError::ValueMismatch),
+        ));
+        assert!(matches!(
+            verify_batch_membership(&root_hash, &[(present.clone(), None)], &proof),
+            Err(VerificationError::KeyExists),
+        ));
+        assert!(matches!(
+            verify_batch_membership(&root_hash, &[(absent.clone(), Some(b"x".to_vec()))], &proof),
+            Err(VerificationError::KeyMissing),
+        ));
+
+        // without proofs, the values are the same
+        let unproven = TREE.get_many(&store, &keys, false, None).unwrap();
+        assert_eq!((unproven.records, unproven.proof), (res.records, None));
+    }
+
+    #[test]
+    fn comparing_trees() {
+        const UNCOLLAPSED_TREE: Tree<Vec<u8>, Vec<u8>> =
+            Tree::new_default().with_collapse_policy(CollapsePolicy::Never);
+
+        let mut rng = StdRng::seed_from_u64(42);
+        let model = (0..50)
+            .map(|_| (rand_key(&mut rng), rand_key(&mut rng)))
+            .collect::<BTreeMap<_, _>>();
+        let batch: Batch<_, _> =
+            model.iter().map(|(k, v)| (k.clone(), Op::Insert(v.clone()))).collect();
+
+        // the same records, but laid out differently
+        let mut store_a = MockStorage::new();
+        let mut store_b = MockStorage::new();
+        TREE.apply(&mut store_a, batch.clone()).unwrap();
+        UNCOLLAPSED_TREE.apply(&mut store_b, batch).unwrap();
+        assert_eq!(TREE.equal_at(&store_a, None, &store_b, None).unwrap(), None);
+
+        // each kind of difference is found, and the smallest one is reported.
+        // each change is to a smaller key than the previous ones
+        let keys = model.keys().cloned().collect::<Vec<_>>();
+        let absent = (0..)
+            .map(|_| rand_key(&mut rng))
+            .find(|key| !model.contains_key(key))
+            .unwrap();
+        for (i, op) in [(10, Op::Delete), (5, Op::Insert(b"changed".to_vec()))] {
+            let batch = Batch::from([(keys[i].clone(), op), (keys[i + 20].clone(), Op::Delete)]);
+            TREE.apply(&mut store_b, batch).unwrap();
+            let expected = Some(keys[i].clone());
+            assert_eq!(TREE.equal_at(&store_a, None, &store_b, None).unwrap(), expected);
+            assert_eq!(TREE.equal_at(&store_b, None, &store_a, None).unwrap(), expected);
+        }
+        TREE.apply(&mut store_a, Batch::from([(absent.clone(), Op::Insert(vec![1]))])).unwrap();
+        let expected = absent.clone().min(keys[5].clone());
+        assert_eq!(TREE.equal_at(&store_a, None, &store_b, None).unwrap(), Some(expected));
+
+        // versions of the same tree
+        assert_eq!(TREE.equal_at(&store_a, Some(1), &store_a, Some(1)).unwrap(), None);
+        assert_eq!(TREE.equal_at(&store_a, Some(1), &store_a, None).unwrap(), Some(absent));
+    }
+
+    #[test]
+    fn version_bounds() {
+        let mut store = MockStorage::new();
+        let batch = Batch::from([(b"foo".to_vec(), Op::Insert(b"bar".to_vec()))]);
+        TREE.apply(&mut store, batch.clone()).unwrap();
+
+        assert_eq!(
+            TREE.root(&store, Some(2)),
+            Err(TreeError::VersionNewerThanLatest {
+                latest: 1,
+                querying: 2,
+            }),
+        );
+
+        TREE.version.save(&mut store, &u64::MAX).unwrap();
+        assert_eq!(TREE.apply(&mut store, batch), Err(TreeError::VersionOverflow));
+        TREE.prune(&mut store, Some(u64::MAX)).unwrap();
+    }
+
+    #[test]
+    fn recovering_from_interrupted_apply() {
+        // a storage that stops persisting writes after the given number of them,
+        // as if the host crashed
+        struct CrashingStorage {
+            inner: MockStorage,
+            writes_left: usize,
+        }
+
+        impl Storage for CrashingStorage {
+            fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+                self.inner.get(key)
+            }
+
+            fn range<'a>(
+                &'a self,
+                start: Option<&[u8]>,
+                end: Option<&[u8]>,
+                order: Order,
+            ) -> Box<dyn Iterator<Item = (Vec<u8>, Vec<u8>)> + 'a> {
+                self.inner.range(start, end, order)
+            }
+
+            fn set(&mut self, key: &[u8], value: &[u8]) {
+                if self.writes_left > 0 {
+                    self.writes_left -= 1;
+                    self.inner.set(key, value);
+                }
+            }
+
+            fn remove(&mut self, key: &[u8]) {
+                if self.writes_left > 0 {
+                    self.writes_left -= 1;
+                    self.inner.remove(key);
+                }
+            }
+        }
+
+        fn dump(store: &MockStorage) -> Vec<(Vec<u8>, Vec<u8>)> {
+            store.range(None, None, Order::Ascending).collect()
+        }
+
+        let mut rng = StdRng::seed_from_u64(3964);
+        let batches = (0..4)
+            .map(|_| (0..10).map(|_| (rand_key(&mut rng), Op::Insert(vec![rng.gen()]))).collect())
+            .collect::<Vec<Batch<_, _>>>();
+        let (last_batch, batches) = batches.split_last().unwrap();
+
+        let committed = || {
+            let mut store = MockStorage::new();
+            for batch in batches {
+                TREE.apply(&mut store, batch.clone()).unwrap();
+            }
+            store
+        };
+
+        let mut expected_store = committed();
+        let expected = dump(&expected_store);
+        TREE.apply(&mut expected_store, last_batch.clone()).unwrap();
+        let expected_after_retry = dump(&expected_store);
+
+        let mut writes = 0;
+        loop {
+            let mut store = CrashingStorage {
+                inner: committed(),
+                writes_left: writes,
+            };
+            TREE.apply(&mut store, last_batch.clone()).unwrap();
+            let mut store = store.inner;
+
+            // the version is bumped by the last write, so it's done
+            if dump(&store) == expected_after_retry {
+                break;
+            }
+
+            assert_eq!(TREE.recover(&mut store).unwrap(), writes > 0);
+            assert_eq!(dump(&store), expected);
+
+            TREE.apply(&mut store, last_batch.clone()).unwrap();
+            assert_eq!(dump(&store), expected_after_retry);
+
+            writes += 1;
+        }
+
+        assert!(!TREE.recover(&mut expected_store).unwrap());
+    }
+
+    #[test]
+    fn reporting_changes() {
+        let mut rng = StdRng::seed_from_u64(3965);
+        let mut store = MockStorage::new();
+        let mut model = BTreeMap::new();
+
+        for _ in 0..50 {
+            let mut batch = Batch::new();
+            for _ in 0..rng.gen_range(1..10) {
+                // values from a small range, so that some inserts don't change
+                // the existing value
+                let op = if rng.gen_bool(0.3) {
+                    Op::Delete
+                } else {
+                    Op::Insert(vec![rng.gen_range(0..3)])
+                };
+                batch.insert(rand_key(&mut rng), op);
+            }
+
+            let mut expected = vec![];
+            for (key, op) in &batch {
+                let old = model.get(key).map(value_hash);
+                let new = match op {
+                    Op::Insert(value) => {
+                        let new = Some(value_hash(value));
+                        model.insert(key.clone(), value.clone());
+                        new
+                    },
+                    Op::Delete => {
+                        model.remove(key);
+                        None
+                    },
+                };
+                if old != new {
+                    expected.push(Change {
+                        key: key.clone(),
+                        old_value_hash: old,
+                        new_value_hash: new,
+                    });
+                }
+            }
+
+            let old_version = TREE.version.may_load(&store).unwrap().unwrap_or(0);
+            let change_set = TREE.apply(&mut store, batch).unwrap();
+            assert_eq!(change_set.changes, expected);
+
+            let version = if expected.is_empty() {
+                old_version
             } else {
-                vec![]
+                old_version + 1
             };
-            return Ok((None, proof));
+            assert_eq!(change_set.version, version);
+        }
+    }
+
+    #[test]
+    fn skipping_unchanged_subtrees() {
+        let mut store = MockStorage::new();
+        let batch: Batch<_, _> = [b"food".to_vec(), b"fuzz".to_vec(), b"larry".to_vec()]
+            .into_iter()
+            .map(|key| (key, Op::Insert(b"1".to_vec())))
+            .collect();
+        TREE.apply(&mut store, batch).unwrap();
+        let before = store.range(None, None, Order::Ascending).collect::<Vec<_>>();
+
+        // these keys don't exist, but lead to the leaves of "food" and "larry",
+        // which have been collapsed. the leaves are taken apart in search of
+        // the keys and put back together as they were, so nothing should be
+        // rewritten, and no new version created
+        let batch: Batch<_, _> =
+            [b"foo".to_vec(), b"lazy".to_vec()].into_iter().map(|key| (key, Op::Delete)).collect();
+        TREE.apply(&mut store, batch).unwrap();
+        let after = store.range(None, None, Order::Ascending).collect::<Vec<_>>();
+        assert_eq!(before, after);
+    }
+
+    #[test]
+    fn aliasing_unchanged_roots() {
+        const ALIAS_TREE: Tree<Vec<u8>, Vec<u8>> = Tree::new_default().with_root_aliases();
+
+        let mut store = MockStorage::new();
+        let key = b"foo".to_vec();
+        let value = b"bar".to_vec();
+        let batch = Batch::from([(key.clone(), Op::Insert(value.clone()))]);
+        ALIAS_TREE.apply(&mut store, batch.clone()).unwrap();
+
+        // the batch changes nothing, but a new version is committed anyways
+        let change_set = ALIAS_TREE.apply(&mut store, batch).unwrap();
+        assert_eq!(change_set.version, 2);
+        assert!(change_set.changes.is_empty());
+
+        let root_1 = ALIAS_TREE.root(&store, Some(1)).unwrap().root_hash;
+        let root_2 = ALIAS_TREE.root(&store, Some(2)).unwrap().root_hash;
+        assert_eq!(root_1, root_2);
+
+        // the alias is a root like any other: it can be proven against, and
+        // the root it replaced can be pruned
+        ALIAS_TREE.prune(&mut store, Some(2)).unwrap();
+        assert!(ALIAS_TREE.root(&store, Some(1)).is_err());
+        let res = ALIAS_TREE.get(&store, &key, true, Some(2)).unwrap();
+        let proof: Proof<_, _> = from_binary(&res.proof.unwrap()).unwrap();
+        assert!(verify_membership(&root_2, &key, &value, &proof).is_ok());
+
+        // an empty tree has no root to alias, but the version is bumped still
+        let batch = Batch::from([(key, Op::Delete)]);
+        ALIAS_TREE.apply(&mut store, batch.clone()).unwrap();
+        assert_eq!(ALIAS_TREE.apply(&mut store, batch).unwrap().version, 4);
+    }
+
+    #[test]
+    fn filtering_keys() {
+        const FILTERED_TREE: Tree<Vec<u8>, Vec<u8>> =
+            Tree::new_default().with_key_filter("f", 256, 4);
+
+        let mut store = MockStorage::new();
+        let keys = (0..100u32).map(|i| i.to_be_bytes().to_vec()).collect::<Vec<_>>();
+        let absent_keys = (100..1100u32).map(|i| i.to_be_bytes().to_vec()).collect::<Vec<_>>();
+
+        // insert the keys over two versions, then delete one
+        for chunk in keys.chunks(50) {
+            let batch = chunk.iter().map(|key| (key.clone(), Op::Insert(key.clone()))).collect();
+            FILTERED_TREE.apply(&mut store, batch).unwrap();
+        }
+        let batch = Batch::from([(keys[0].clone(), Op::Delete)]);
+        FILTERED_TREE.apply(&mut store, batch).unwrap();
+
+        // no false negatives, and few false positives
+        let maybe_contains = |key, version| FILTERED_TREE.maybe_contains(&store, key, version);
+        for key in &keys {
+            assert!(maybe_contains(key, None).unwrap());
+        }
+        let false_positives =
+            absent_keys.iter().filter(|key| maybe_contains(key, None).unwrap()).count();
+        assert!(false_positives < 100, "too many false positives: {false_positives}");
+
+        // each version has its own filter
+        let filtered_out =
+            keys[50..].iter().filter(|key| !maybe_contains(key, Some(1)).unwrap()).count();
+        assert!(filtered_out > 40);
+
+        // the filters of pruned versions are deleted, after which nothing is
+        // filtered out at those versions
+        FILTERED_TREE.prune(&mut store, None).unwrap();
+        let maybe_contains = |key, version| FILTERED_TREE.maybe_contains(&store, key, version);
+        assert!(absent_keys.iter().all(|key| maybe_contains(key, Some(1)).unwrap()));
+        assert_eq!(
+            absent_keys.iter().filter(|key| maybe_contains(key, Some(3)).unwrap()).count(),
+            false_positives,
+        );
+
+        assert!(matches!(
+            TREE.maybe_contains(&store, &keys[0], None),
+            Err(TreeError::KeyFilterNotTracked),
+        ));
+    }
+
+    #[test]
+    fn finding_adjacent_keys() {
+        let mut rng = StdRng::seed_from_u64(5678);
+        let mut store = MockStorage::new();
+
+        // short keys from a small alphabet, so that many are prefixes of others
+        let mut rand_key = || {
+            let len = rng.gen_range(1..=4);
+            (0..len).map(|_| [0x00, 0x01, 0x10, 0x11, 0xff][rng.gen_range(0..5)]).collect()
+        };
+
+        let mut expected = BTreeMap::new();
+        for _ in 0..60 {
+            let key: Vec<u8> = rand_key();
+            expected.insert(key.clone(), key);
+        }
+        let batch = expected.iter().map(|(k, v)| (k.clone(), Op::Insert(v.clone()))).collect();
+        TREE.apply(&mut store, batch).unwrap();
+        let root_hash = TREE.root(&store, None).unwrap().root_hash;
+
+        for _ in 0..200 {
+            let key = rand_key();
+
+            let next = TREE.next_key(&store, &key, true, None).unwrap();
+            let expected_next = expected.range(key.clone()..).find(|(k, _)| **k != key);
+            assert_eq!(next.as_ref().map(|res| &res.key), expected_next.map(|(k, _)| k));
+
+            let prev = TREE.prev_key(&store, &key, true, None).unwrap();
+            let expected_prev = expected.range(..key.clone()).next_back();
+            assert_eq!(prev.as_ref().map(|res| &res.key), expected_prev.map(|(k, _)| k));
+
+            for res in next.into_iter().chain(prev) {
+                let proof: Proof<_, _> = from_binary(&res.proof.unwrap()).unwrap();
+                let value = res.value.unwrap();
+                assert!(verify_membership(&root_hash, &res.key, &value, &proof).is_ok());
+            }
+        }
+
+        // an emptied tree has no adjacent keys
+        let batch = expected.into_keys().map(|key| (key, Op::Delete)).collect();
+        TREE.apply(&mut store, batch).unwrap();
+        assert!(TREE.next_key(&store, &vec![0x01], false, None).unwrap().is_none());
+        assert!(TREE.prev_key(&store, &vec![0x01], false, None).unwrap().is_none());
+    }
+
+    #[test]
+    fn sampling_records() {
+        let mut store = MockStorage::new();
+        let batch = (0..100u32)
+            .map(|i| (i.to_be_bytes().to_vec(), Op::Insert(i.to_le_bytes().to_vec())))
+            .collect();
+        TREE.apply(&mut store, batch).unwrap();
+        let root_hash = TREE.root(&store, None).unwrap().root_hash;
+
+        let samples = TREE.sample(&store, b"block hash", 20, None).unwrap();
+        assert_eq!(samples.len(), 20);
+        for res in &samples {
+            let proof: Proof<_, _> = from_binary(res.proof.as_ref().unwrap()).unwrap();
+            let value = res.value.as_ref().unwrap();
+            assert!(verify_membership(&root_hash, &res.key, value, &proof).is_ok());
+        }
+
+        // deterministic given the seed, yet spread over many records
+        assert_eq!(TREE.sample(&store, b"block hash", 20, None).unwrap(), samples);
+        assert_ne!(TREE.sample(&store, b"another hash", 20, None).unwrap(), samples);
+        let distinct = samples.iter().map(|res| &res.key).collect::<BTreeSet<_>>();
+        assert!(distinct.len() > 10);
+
+        // an emptied tree has nothing to sample
+        let batch = (0..100u32).map(|i| (i.to_be_bytes().to_vec(), Op::Delete)).collect();
+        TREE.apply(&mut store, batch).unwrap();
+        assert!(TREE.sample(&store, b"block hash", 20, None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn enforcing_read_budgets() {
+        let mut store = MockStorage::new();
+        // each key is a prefix of the next, so the longest is deep in the tree
+        let batch = (1..=8)
+            .map(|len| (b"deadbeef"[..len].to_vec(), Op::Insert(vec![len as u8])))
+            .collect();
+        TREE.apply(&mut store, batch).unwrap();
+
+        // a node is read at every level down to the key
+        let key = b"deadbeef".to_vec();
+        let expected = TREE.get(&store, &key, true, None).unwrap();
+        let min_reads = (0..100)
+            .find(|max_reads| TREE.get_with_budget(&store, &key, true, None, *max_reads).is_ok())
+            .unwrap();
+        assert!(min_reads > 8);
+        assert_eq!(TREE.get_with_budget(&store, &key, true, None, min_reads).unwrap(), expected);
+        assert!(matches!(
+            TREE.get_with_budget(&store, &key, true, None, min_reads - 1),
+            Err(TreeError::ReadBudgetExceeded),
+        ));
+
+        let iterate = |max_reads| {
+            TREE.iterate(&store, Order::Ascending, None, None, None)
+                .unwrap()
+                .with_read_budget(max_reads)
+                .collect::<Result<Vec<_>, _>>()
+        };
+        assert_eq!(iterate(min_reads - 1).unwrap().len(), 8);
+        assert!(matches!(iterate(min_reads - 2), Err(TreeError::ReadBudgetExceeded)));
+    }
+
+    #[test]
+    fn getting_at_pruned_versions() {
+        const SERVING_TREE: Tree<Vec<u8>, Vec<u8>> =
+            Tree::new_default().with_pruned_version_policy(PrunedVersionPolicy::ServeWithoutProof);
+
+        let mut store = MockStorage::new();
+        let batch = Batch::from([
+            (b"a".to_vec(), Op::Insert(b"1".to_vec())),
+            (b"b".to_vec(), Op::Insert(b"2".to_vec())),
+        ]);
+        TREE.apply(&mut store, batch).unwrap();
+        let batch = Batch::from([(b"b".to_vec(), Op::Insert(b"3".to_vec()))]);
+        TREE.apply(&mut store, batch).unwrap();
+        TREE.prune(&mut store, None).unwrap();
+
+        for key in [b"a".to_vec(), b"b".to_vec()] {
+            assert!(matches!(
+                TREE.get(&store, &key, true, Some(1)),
+                Err(TreeError::VersionPruned { version: 1 }),
+            ));
+        }
+
+        // "a" hasn't changed since version 1, so its value is served, whereas
+        // "b" has changed, so its value at version 1 is unknown
+        let res = SERVING_TREE.get(&store, &b"a".to_vec(), true, Some(1)).unwrap();
+        assert_eq!(res.value, Some(b"1".to_vec()));
+        assert!(res.proof.is_none());
+        assert!(matches!(
+            SERVING_TREE.get(&store, &b"b".to_vec(), true, Some(1)),
+            Err(TreeError::VersionPruned { version: 1 }),
+        ));
+    }
+
+    #[test]
+    fn versioning_proofs() {
+        const VERSIONED_TREE: Tree<Vec<u8>, Vec<u8>> = Tree::new_default().with_versioned_proofs();
+
+        let mut store = MockStorage::new();
+        for i in 0..10u8 {
+            let batch = Batch::from([(vec![i, i], Op::Insert(vec![i]))]);
+            VERSIONED_TREE.apply(&mut store, batch).unwrap();
+        }
+        let root_hash = VERSIONED_TREE.root(&store, None).unwrap().root_hash;
+
+        let key = vec![3, 3];
+        let res = VERSIONED_TREE.get(&store, &key, true, None).unwrap();
+        let mut proof: Proof<_, _> = from_binary(&res.proof.unwrap()).unwrap();
+        assert_eq!(proof.last().unwrap().version, Some(10));
+        assert_eq!(proof.first().unwrap().version, Some(4));
+        assert!(verify_proof_versions(&proof).is_ok());
+
+        // versions aren't hashed, so a proof with a tampered version still
+        // verifies, but the inconsistency is caught
+        proof[0].version = Some(11);
+        assert!(verify_membership(&root_hash, &key, &vec![3], &proof).is_ok());
+        assert!(matches!(
+            verify_proof_versions(&proof),
+            Err(VerificationError::VersionIncreasing { parent: 10, child: 11 }),
+        ));
+
+        let res = TREE.get(&store, &key, true, None).unwrap();
+        let proof: Proof<Vec<u8>, Vec<u8>> = from_binary(&res.proof.unwrap()).unwrap();
+        assert!(matches!(verify_proof_versions(&proof), Err(VerificationError::VersionMissing)));
+    }
+
+    #[test]
+    fn iterating_keys_and_values() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut store = MockStorage::new();
+        let batch: Batch<_, _> =
+            (0..100).map(|_| (rand_key(&mut rng), Op::Insert(rand_key(&mut rng)))).collect();
+        TREE.apply(&mut store, batch.clone()).unwrap();
+
+        let (min, max) = (vec![0x01], vec![0x11, 0x00]);
+        for order in [Order::Ascending, Order::Descending] {
+            for (min, max) in [(None, None), (Some(&min), Some(&max))] {
+                let records = TREE
+                    .iterate(&store, order, min, max, None)
+                    .unwrap()
+                    .collect::<Result<Vec<_>, _>>()
+                    .unwrap();
+                let keys = TREE.keys(&store, order, min, max, None).unwrap();
+                let values = TREE.values(&store, order, min, max, None).unwrap();
+                assert_eq!(
+                    keys.collect::<Result<Vec<_>, _>>().unwrap(),
+                    records.iter().map(|(key, _)| key.clone()).collect::<Vec<_>>(),
+                );
+                assert_eq!(
+                    values.collect::<Result<Vec<_>, _>>().unwrap(),
+                    records.into_iter().map(|(_, value)| value).collect::<Vec<_>>(),
+                );
+            }
+        }
+
+        // listing the keys doesn't decode the values, so it works even if the
+        // values don't decode as the tree's value type
+        const STRING_TREE: Tree<Vec<u8>, String> = Tree::new_default();
+        let records = STRING_TREE.iterate(&store, Order::Ascending, None, None, None).unwrap();
+        assert!(records.collect::<Result<Vec<_>, _>>().is_err());
+        let keys = STRING_TREE.keys(&store, Order::Ascending, None, None, None).unwrap();
+        assert_eq!(
+            keys.collect::<Result<Vec<_>, _>>().unwrap(),
+            batch.into_keys().collect::<Vec<_>>(),
+        );
+    }
+
+    #[test]
+    fn encoding_proofs_compactly() {
+        const BINARY_TREE: Tree<Vec<u8>, Vec<u8>> =
+            Tree::new_default().with_proof_encoding(ProofEncoding::Binary);
+
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut store = MockStorage::new();
+        let batch: Batch<_, _> =
+            (0..50).map(|_| (rand_key(&mut rng), Op::Insert(vec![1]))).collect();
+        BINARY_TREE.apply(&mut store, batch.clone()).unwrap();
+        let root_hash = BINARY_TREE.root(&store, None).unwrap().root_hash;
+
+        for key in batch.keys().take(5).cloned().chain([b"missing".to_vec()]) {
+            let json_res = TREE.get(&store, &key, true, None).unwrap();
+            let res = BINARY_TREE.get(&store, &key, true, None).unwrap();
+            let (json_proof, proof) = (json_res.proof.unwrap(), res.proof.clone().unwrap());
+            assert!(proof.len() * 2 < json_proof.len());
+
+            // the same proof, in either encoding
+            let decoded: Proof<Vec<u8>, Vec<u8>> = Proof::from_bytes(&proof).unwrap();
+            assert_eq!(decoded, from_binary::<Proof<_, _>>(&json_proof).unwrap());
+            assert_eq!(verify_get_response(&root_hash, &res).unwrap(), res.value.as_ref());
+        }
+    }
+
+    #[test]
+    fn rejecting_duplicate_nibble_paths() {
+        let mut store = MockStorage::new();
+        let batch = Batch::from([(b"food".to_vec(), Op::Insert(b"1".to_vec()))]);
+        TREE.apply(&mut store, batch).unwrap();
+
+        // two distinct keys that were encoded to the same bytes by mistake
+        let nibble_path = NibblePath::from(b"foo");
+        let batch = [
+            (nibble_path.clone(), b"foo".to_vec(), Op::Insert(b"1".to_vec())),
+            (nibble_path.clone(), b"FOO".to_vec(), Op::Insert(b"2".to_vec())),
+        ];
+        let mut nodes = TreeNodes {
+            tree: &TREE,
+            store: &mut store,
+            changes: None,
+            nodes_written: 0,
+            stats: None,
+        };
+        assert_eq!(
+            apply_subtree(&mut nodes, 2, &NodeKey::root(1), None, &batch),
+            Err(TreeError::DuplicateNibblePath {
+                nibble_path,
+            }),
+        );
+    }
+
+    #[test]
+    fn key_length_limit() {
+        let mut store = MockStorage::new();
+
+        // 32767 bytes = 65534 nibbles, which is the longest possible key
+        let key = vec![0xab; MAX_NIBBLES / 2];
+        let batch = Batch::from([(key.clone(), Op::Insert(b"1".to_vec()))]);
+        TREE.apply(&mut store, batch).unwrap();
+        assert_eq!(TREE.get(&store, &key, false, None).unwrap().value, Some(b"1".to_vec()));
+
+        let key = vec![0xab; MAX_KEY_LEN + 1];
+        let batch = Batch::from([(key.clone(), Op::Insert(b"2".to_vec()))]);
+        assert_eq!(
+            TREE.apply(&mut store, batch),
+            Err(TreeError::KeyTooLong {
+                num_nibbles: MAX_NIBBLES + 1,
+            }),
+        );
+        assert!(matches!(
+            TREE.get(&store, &key, false, None),
+            Err(TreeError::KeyTooLong { num_nibbles }) if num_nibbles == MAX_NIBBLES + 1,
+        ));
+        assert!(matches!(
+            TREE.get_many(&store, &[b"a".to_vec(), key], false, None),
+            Err(TreeError::KeyTooLong { num_nibbles }) if num_nibbles == MAX_NIBBLES + 1,
+        ));
+
+        // the empty key is rejected as well
+        let batch = Batch::from([(vec![], Op::Insert(b"3".to_vec()))]);
+        assert_eq!(TREE.apply(&mut store, batch), Err(TreeError::EmptyKey));
+        assert!(matches!(
+            TREE.get(&store, &vec![], false, None),
+            Err(TreeError::EmptyKey),
+        ));
+        assert!(matches!(
+            TREE.get_many(&store, &[vec![]], true, None),
+            Err(TreeError::EmptyKey),
+        ));
+    }
+
+    #[test]
+    fn domain_tagged_roots() {
+        const APP_A: Tree<Vec<u8>, Vec<u8>> = Tree::new_default().with_domain(b"app-a");
+        const APP_B: Tree<Vec<u8>, Vec<u8>> = Tree::new_default().with_domain(b"app-b");
+
+        let key = b"foo".to_vec();
+        let value = b"bar".to_vec();
+        let batch = Batch::from([(key.clone(), Op::Insert(value.clone()))]);
+
+        let mut store_a = MockStorage::new();
+        let mut store_b = MockStorage::new();
+        APP_A.apply(&mut store_a, batch.clone()).unwrap();
+        APP_B.apply(&mut store_b, batch).unwrap();
+
+        let root_a = APP_A.root(&store_a, None).unwrap().root_hash;
+        let root_b = APP_B.root(&store_b, None).unwrap().root_hash;
+        assert_ne!(root_a, root_b);
+
+        let res = APP_A.get(&store_a, &key, true, None).unwrap();
+        let proof: Proof<_, _> = from_binary(&res.proof.unwrap()).unwrap();
+        assert!(verify_membership_in_domain(b"app-a", &root_a, &key, &value, &proof).is_ok());
+        assert!(verify_membership_in_domain(b"app-a", &root_b, &key, &value, &proof).is_err());
+        assert!(verify_membership_in_domain(b"app-b", &root_a, &key, &value, &proof).is_err());
+        assert!(verify_membership(&root_a, &key, &value, &proof).is_err());
+    }
+
+    #[test]
+    fn storing_struct_values() {
+        #[cw_serde]
+        struct Account {
+            balance: u64,
+        }
+
+        impl HashableValue for Account {
+            fn hash_bytes(&self) -> Cow<[u8]> {
+                Cow::Owned(self.balance.to_be_bytes().to_vec())
+            }
+        }
+
+        const ACCOUNTS: Tree<String, Account> = Tree::new_default();
+
+        let mut store = MockStorage::new();
+        let key = "alice".to_string();
+        let value = Account {
+            balance: 100,
+        };
+        let batch = Batch::from([(key.clone(), Op::Insert(value.clone()))]);
+        ACCOUNTS.apply(&mut store, batch).unwrap();
+
+        let root_hash = ACCOUNTS.root(&store, None).unwrap().root_hash;
+        let res = ACCOUNTS.get(&store, &key, true, None).unwrap();
+        let proof: Proof<_, _> = from_binary(&res.proof.unwrap()).unwrap();
+        assert_eq!(res.value, Some(value.clone()));
+        assert!(verify_membership(&root_hash, &key, &value, &proof).is_ok());
+    }
+
+    #[test]
+    fn typed_values() {
+        #[cw_serde]
+        struct Account {
+            owner: String,
+            balance: u64,
+        }
+
+        let mut store = MockStorage::new();
+        let key = b"alice".to_vec();
+        let value = Account {
+            owner: "alice".into(),
+            balance: 100,
+        };
+        let batch = Batch::from([(key.clone(), Op::insert_typed(&value).unwrap())]);
+        TREE.apply(&mut store, batch).unwrap();
+
+        assert_eq!(TREE.get_typed(&store, &key, None).unwrap(), Some(value));
+        assert_eq!(TREE.get_typed::<Account>(&store, &b"bob".to_vec(), None).unwrap(), None);
+
+        // the committed bytes follow the documented rule
+        let res = TREE.get(&store, &key, false, None).unwrap();
+        assert_eq!(res.value.unwrap(), br#"{"owner":"alice","balance":100}"#);
+    }
+
+    #[cfg(feature = "debug")]
+    #[test]
+    fn streaming_matches_apply() {
+        fn items(store: &MockStorage) -> Vec<(Vec<u8>, Vec<u8>)> {
+            let iter = TREE.iterate(store, Order::Ascending, None, None, None).unwrap();
+            iter.collect::<Result<_, _>>().unwrap()
+        }
+
+        // node records are namespaced under "n"
+        fn num_nodes(store: &MockStorage) -> usize {
+            store
+                .range(None, None, Order::Ascending)
+                .filter(|(k, _)| k.starts_with(&[0, 1, b'n']))
+                .count()
+        }
+
+        let mut rng = StdRng::seed_from_u64(3959);
+        let mut store = MockStorage::new();
+        let mut expected_store = MockStorage::new();
+
+        for _ in 0..30 {
+            // unsorted, and deleting some of the keys inserted earlier in the
+            // same batch, so that chunks delete or collapse nodes written by
+            // earlier chunks
+            let mut ops = (0..rng.gen_range(1..20))
+                .map(|_| {
+                    let op = if rng.gen_bool(0.3) {
+                        Op::Delete
+                    } else {
+                        Op::Insert(vec![rng.gen()])
+                    };
+                    (rand_key(&mut rng), op)
+                })
+                .collect::<Vec<_>>();
+            for i in 0..ops.len() {
+                if rng.gen_bool(0.5) {
+                    ops.push((ops[i].0.clone(), Op::Delete));
+                }
+            }
+
+            let batch: Batch<_, _> = ops.iter().cloned().collect();
+            TREE.apply_streaming(&mut store, ops, 3).unwrap();
+            TREE.apply(&mut expected_store, batch).unwrap();
+
+            let root = TREE.root(&store, None).map(|res| res.root_hash);
+            let expected_root = TREE.root(&expected_store, None).map(|res| res.root_hash);
+            assert_eq!(root, expected_root);
+            assert_eq!(items(&store), items(&expected_store));
+
+            // once old versions are pruned, every node left must be reachable
+            // from the latest root. nodes left behind by earlier chunks are not
+            TREE.prune(&mut store, None).unwrap();
+            let version = TREE.version.load(&store).unwrap();
+            let reachable = TREE.subtree(&store, NodeKey::root(version)).unwrap();
+            assert_eq!(num_nodes(&store), reachable.len());
+        }
+    }
+
+    #[test]
+    fn pruning_epochs() {
+        const EPOCH_TREE: Tree<Vec<u8>, Vec<u8>> = Tree::new_default().with_orphan_epochs(5);
+
+        // node records are namespaced under "n"
+        fn nodes(store: &MockStorage) -> Vec<Vec<u8>> {
+            store
+                .range(None, None, Order::Ascending)
+                .map(|(k, _)| k)
+                .filter(|k| k.starts_with(&[0, 1, b'n']))
+                .collect()
+        }
+
+        let mut rng = StdRng::seed_from_u64(1234);
+        let mut store = MockStorage::new();
+        let mut expected_store = MockStorage::new();
+
+        for _ in 0..12 {
+            let batch: Batch<_, _> =
+                (0..5).map(|_| (rand_key(&mut rng), Op::Insert(vec![1]))).collect();
+            EPOCH_TREE.apply(&mut store, batch.clone()).unwrap();
+            TREE.apply(&mut expected_store, batch).unwrap();
+        }
+
+        // the epoch of versions 5..=9 hasn't ended by version 8, so only the
+        // first epoch is pruned
+        EPOCH_TREE.prune(&mut store, Some(8)).unwrap();
+        TREE.prune(&mut expected_store, Some(4)).unwrap();
+        assert_eq!(nodes(&store), nodes(&expected_store));
+
+        EPOCH_TREE.prune_epoch(&mut store, 1).unwrap();
+        TREE.prune(&mut expected_store, Some(9)).unwrap();
+        assert_eq!(nodes(&store), nodes(&expected_store));
+
+        // versions after the pruned epochs can still be queried
+        assert!(EPOCH_TREE.root(&store, Some(9)).is_ok());
+        let iter = EPOCH_TREE.iterate(&store, Order::Ascending, None, None, Some(9)).unwrap();
+        assert!(iter.collect::<Result<Vec<_>, _>>().is_ok());
+    }
+
+    #[test]
+    fn pruning_largest_epochs() {
+        const CHURN_TREE: Tree<Vec<u8>, Vec<u8>> = Tree::new_default().with_churn_counters("c");
+
+        // the total bytes of the node records, which are namespaced under "n"
+        fn node_bytes(store: &MockStorage) -> u64 {
+            store
+                .range(None, None, Order::Ascending)
+                .filter(|(k, _)| k.starts_with(&[0, 1, b'n']))
+                .map(|(_, v)| v.len() as u64)
+                .sum()
+        }
+
+        let mut store = MockStorage::new();
+        let insert = |keys: &[&[u8]], len| -> Batch<Vec<u8>, Vec<u8>> {
+            keys.iter().map(|key| (key.to_vec(), Op::Insert(vec![1; len]))).collect()
         };
+        CHURN_TREE.apply(&mut store, insert(&[b"a", b"b", b"c"], 100)).unwrap();
+        // overwriting the large values orphans more bytes than overwriting the
+        // small ones after them
+        CHURN_TREE.apply(&mut store, insert(&[b"a", b"b", b"c"], 1)).unwrap();
+        CHURN_TREE.apply(&mut store, insert(&[b"a"], 2)).unwrap();
+        CHURN_TREE.apply(&mut store, insert(&[b"b"], 3)).unwrap();
 
-        let (value, mut proof) = self.get_at(
-            store,
-            current_node_key.child(child.version, index),
-            nibble_iter,
-            prove,
-        )?;
+        let orphaned = [2, 3, 4].map(|epoch| CHURN_TREE.orphaned_bytes(&store, epoch).unwrap());
+        assert!(orphaned[0] > orphaned[1] && orphaned[1] > 0);
 
-        if prove {
-            proof.push(ProofNode::from_node(current_node, Some(index), false));
-        }
+        // the counters match the bytes that pruning reclaims
+        let before = node_bytes(&store);
+        assert_eq!(CHURN_TREE.prune_largest(&mut store, Some(3), 1).unwrap(), [2]);
+        assert_eq!(before - node_bytes(&store), orphaned[0]);
+        assert_eq!(CHURN_TREE.orphaned_bytes(&store, 2).unwrap(), 0);
 
-        Ok((value, proof))
+        // epochs that haven't ended by the given version are left alone
+        assert_eq!(CHURN_TREE.prune_largest(&mut store, Some(3), 10).unwrap(), [3]);
+        assert_eq!(CHURN_TREE.prune_largest(&mut store, None, 10).unwrap(), [4]);
+        assert!(CHURN_TREE.root(&store, None).is_ok());
+
+        assert!(matches!(TREE.prune_largest(&mut store, None, 1), Err(TreeError::ChurnNotTracked)));
     }
 
-    /// This function signature is inspired by `cosmwasm_std::Storage` trait's
-    /// `range` method.
-    ///
-    /// Notes:
-    /// - The bound `start` is inclusive and `end` is exclusive;
-    /// - `start` should be lexicographically smaller than `end`, regardless of
-    ///   the iteration `order`. If `start` >= `end`, an empty iterator is
-    ///   generated.
-    pub fn iterate<'c, S: Storage>(
-        &'a self,
-        store: &'c S,
-        order: Order,
-        min: Option<&K>,
-        max: Option<&K>,
-        version: Option<u64>,
-    ) -> Result<TreeIterator<'c, K, V, S>>
-    where
-        'a: 'c,
-    {
-        let version = self.version_or_default(store, version)?;
-        let root_node = self.root_node(store, version)?;
+    #[test]
+    fn pruning_with_limit() {
+        const CHURN_TREE: Tree<Vec<u8>, Vec<u8>> =
+            Tree::new_default().with_churn_counters("c").with_prune_batch_size(4);
 
-        Ok(TreeIterator::new(self, store, order, min, max, root_node))
-    }
+        let mut rng = StdRng::seed_from_u64(1234);
+        let mut store = MockStorage::new();
+        let mut expected_store = MockStorage::new();
+        for _ in 0..10 {
+            let batch: Batch<_, _> =
+                (0..5).map(|_| (rand_key(&mut rng), Op::Insert(vec![1]))).collect();
+            CHURN_TREE.apply(&mut store, batch.clone()).unwrap();
+            CHURN_TREE.apply(&mut expected_store, batch).unwrap();
+        }
 
-    #[cfg(feature = "debug")]
-    pub fn node(
-        &self,
-        store: &dyn Storage,
-        node_key: NodeKey,
-    ) -> Result<Option<NodeResponse<K, V>>> {
-        Ok(self
-            .nodes
-            .may_load(store, &node_key)?
-            .map(|node| NodeResponse {
-                node_key,
-                hash: node.hash(),
-                node,
-            }))
+        // orphan records are namespaced under "o"
+        fn orphans(store: &MockStorage) -> u64 {
+            store
+                .range(None, None, Order::Ascending)
+                .filter(|(k, _)| k.starts_with(&[0, 1, b'o']))
+                .count() as u64
+        }
+
+        // nothing is pruned, but there are orphans left
+        let total = orphans(&store);
+        assert!(CHURN_TREE.prune_with_limit(&mut store, None, 0).unwrap());
+        assert!(CHURN_TREE.prune_with_limit(&mut store, Some(6), 0).unwrap());
+        assert_eq!(orphans(&store), total);
+        let up_to_6 = CHURN_TREE.orphan_count(&store, Some(6)).unwrap().prunable;
+        assert!(up_to_6 > 14 && up_to_6 < total);
+
+        // each call prunes 7 of the remaining orphans
+        let mut remaining = vec![up_to_6];
+        while CHURN_TREE.prune_with_limit(&mut store, Some(6), 7).unwrap() {
+            remaining.push(CHURN_TREE.orphan_count(&store, Some(6)).unwrap().prunable);
+        }
+        remaining.push(0);
+        assert!(remaining.windows(2).all(|pair| pair[1] == pair[0].saturating_sub(7)));
+        assert_eq!(orphans(&store), total - up_to_6);
+
+        // the result is the same as pruning all at once, counters included
+        CHURN_TREE.prune(&mut expected_store, Some(6)).unwrap();
+        assert_eq!(
+            store.range(None, None, Order::Ascending).collect::<Vec<_>>(),
+            expected_store.range(None, None, Order::Ascending).collect::<Vec<_>>(),
+        );
     }
 
-    #[cfg(feature = "debug")]
-    pub fn nodes(
-        &self,
-        store: &dyn Storage,
-        start_after: Option<&NodeKey>,
-        limit: Option<usize>,
-    ) -> Result<Vec<NodeResponse<K, V>>> {
-        let start = start_after.map(Bound::exclusive);
-        let limit = limit.unwrap_or(DEFAULT_QUERY_BATCH_SIZE);
+    #[test]
+    fn counting_orphans() {
+        const EPOCH_TREE: Tree<Vec<u8>, Vec<u8>> = Tree::new_default().with_orphan_epochs(4);
 
-        self.nodes
-            .range(store, start, None, Order::Ascending)
-            .take(limit)
-            .map(|item| {
-                let (node_key, node) = item?;
-                Ok(NodeResponse {
-                    node_key,
-                    hash: node.hash(),
-                    node,
-                })
-            })
-            .collect()
+        let mut rng = StdRng::seed_from_u64(99);
+        let mut store = MockStorage::new();
+        assert_eq!(EPOCH_TREE.orphan_count(&store, None).unwrap().orphans, 0);
+        for _ in 0..10 {
+            let batch: Batch<_, _> =
+                (0..5).map(|_| (rand_key(&mut rng), Op::Insert(vec![1]))).collect();
+            EPOCH_TREE.apply(&mut store, batch).unwrap();
+        }
+
+        let status = EPOCH_TREE.orphan_count(&store, None).unwrap();
+        assert_eq!(status.prunable, status.orphans);
+        assert!(status.orphans > 0);
+
+        // no epoch has ended by version 2
+        let status_2 = EPOCH_TREE.orphan_count(&store, Some(2)).unwrap();
+        assert_eq!((status_2.prunable, status_2.orphans), (0, status.orphans));
+
+        // only the first epoch has ended by version 6
+        let status_6 = EPOCH_TREE.orphan_count(&store, Some(6)).unwrap();
+        assert!(status_6.prunable > 0 && status_6.prunable < status.orphans);
+        EPOCH_TREE.prune(&mut store, Some(6)).unwrap();
+        let pruned = EPOCH_TREE.orphan_count(&store, Some(6)).unwrap();
+        assert_eq!((pruned.prunable, pruned.orphans), (0, status.orphans - status_6.prunable));
+
+        EPOCH_TREE.prune(&mut store, None).unwrap();
+        assert_eq!(EPOCH_TREE.orphan_count(&store, None).unwrap().orphans, 0);
     }
 
-    #[cfg(feature = "debug")]
-    pub fn orphans(
-        &self,
-        store: &dyn Storage,
-        start_after: Option<&OrphanResponse>,
-        limit: Option<usize>,
-    ) -> Result<Vec<OrphanResponse>> {
-        let start = start_after.map(|o| Bound::exclusive((o.since_version, &o.node_key)));
-        let limit = limit.unwrap_or(DEFAULT_QUERY_BATCH_SIZE);
+    #[test]
+    fn counting_activity() {
+        const COUNTED_TREE: Tree<Vec<u8>, Vec<u8>> = Tree::new_default().with_counters("c");
 
-        self.orphans
-            .items(store, start, None, Order::Ascending)
-            .take(limit)
-            .map(|item| {
-                let (since_version, node_key) = item?;
-                Ok(OrphanResponse {
-                    node_key,
-                    since_version,
-                })
-            })
-            .collect()
+        let mut store = MockStorage::new();
+        let insert = |key: &[u8]| Batch::from([(key.to_vec(), Op::Insert(b"1".to_vec()))]);
+        COUNTED_TREE.apply(&mut store, insert(b"a")).unwrap();
+        COUNTED_TREE.apply(&mut store, insert(b"b")).unwrap();
+        // nothing changes, so nothing is counted
+        COUNTED_TREE.apply(&mut store, insert(b"b")).unwrap();
+        COUNTED_TREE.apply_streaming(&mut store, [(b"c".to_vec(), Op::Delete)], 1).unwrap();
+
+        // nothing has been pruned, so every node written is still stored, under
+        // the namespace "n"
+        let stored_nodes = store
+            .range(None, None, Order::Ascending)
+            .filter(|(k, _)| k.starts_with(&[0, 1, b'n']))
+            .count() as u64;
+        let counters = COUNTED_TREE.counters(&store).unwrap();
+        assert_eq!(
+            (counters.applies, counters.nodes_written, counters.orphans_pruned),
+            (2, stored_nodes, 0),
+        );
+
+        COUNTED_TREE.prune(&mut store, None).unwrap();
+        assert_eq!(COUNTED_TREE.counters(&store).unwrap().orphans_pruned, 1);
+
+        assert!(matches!(TREE.counters(&store), Err(TreeError::CountersNotTracked)));
     }
-}
 
-pub struct TreeIterator<'a, K, V, S> {
-    tree: &'a Tree<'a, K, V>,
-    store: &'a S,
-    order: Order,
-    min: Option<NibblePath>,
-    max: Option<NibblePath>,
-    visited_nibbles: NibblePath,
-    visited_nodes: Vec<Node<K, V>>,
-}
+    #[test]
+    fn normalizing_keys() {
+        const LOWERCASE_TREE: Tree<String, String> =
+            Tree::new_default().with_key_normalizer(|key| key.to_lowercase());
 
-impl<'a, K, V, S> TreeIterator<'a, K, V, S>
-where
-    K: AsRef<[u8]>,
-{
-    pub fn new(
-        tree: &'a Tree<'a, K, V>,
-        store: &'a S,
-        order: Order,
-        min: Option<&K>,
-        max: Option<&K>,
-        root_node: Node<K, V>,
-    ) -> Self {
-        Self {
-            tree,
-            store,
-            order,
-            min: min.map(NibblePath::from),
-            max: max.map(NibblePath::from),
-            visited_nibbles: NibblePath::empty(),
-            visited_nodes: vec![root_node],
-        }
+        let mut store = MockStorage::new();
+        let batch = Batch::from([("Foo".to_string(), Op::Insert("bar".to_string()))]);
+        LOWERCASE_TREE.apply(&mut store, batch).unwrap();
+
+        // lookups agree with inserts, and the response carries the canonical
+        // key, which is what the proof is verified against
+        let res = LOWERCASE_TREE.get(&store, &"FOO".to_string(), true, None).unwrap();
+        assert_eq!(res.key, "foo");
+        assert_eq!(res.value, Some("bar".to_string()));
+        let root_hash = LOWERCASE_TREE.root(&store, None).unwrap().root_hash;
+        let proof = from_binary(&res.proof.unwrap()).unwrap();
+        assert!(verify_membership(&root_hash, &res.key, &"bar".to_string(), &proof).is_ok());
+
+        // overwriting under another form of the key doesn't add a record
+        let batch = Batch::from([("fOO".to_string(), Op::Insert("baz".to_string()))]);
+        LOWERCASE_TREE.apply(&mut store, batch).unwrap();
+        let iter = LOWERCASE_TREE.iterate(&store, Order::Ascending, None, None, None).unwrap();
+        assert_eq!(
+            iter.collect::<Result<Vec<_>, _>>().unwrap(),
+            [("foo".to_string(), "baz".to_string())],
+        );
+
+        // a batch with two forms of the same key is ambiguous
+        let batch = Batch::from([
+            ("Fuzz".to_string(), Op::Insert("1".to_string())),
+            ("fuzz".to_string(), Op::Delete),
+        ]);
+        assert!(matches!(
+            LOWERCASE_TREE.apply(&mut store, batch),
+            Err(TreeError::DuplicateNibblePath { .. }),
+        ));
+
+        let mut overlay = Overlay::new(&LOWERCASE_TREE, &store, 2);
+        overlay.insert("BUZZ".to_string(), "1".to_string());
+        assert_eq!(overlay.get(&"buzz".to_string()).unwrap(), Some("1".to_string()));
+        assert_eq!(overlay.get(&"Foo".to_string()).unwrap(), Some("baz".to_string()));
     }
-}
 
-impl<'a, K, V, S> Iterator for TreeIterator<'a, K, V, S>
-where
-    S: Storage,
-    K: Serialize + DeserializeOwned + Clone + AsRef<[u8]>,
-    V: Serialize + DeserializeOwned + Clone,
-{
-    type Item = Result<(K, V)>;
+    #[test]
+    fn transforming_keys() {
+        const PREFIXED_TREE: Tree<String, String> =
+            Tree::new_default().with_key_transform(|key| format!("app/{key}"));
 
-    fn next(&mut self) -> Option<Self::Item> {
-        iterate_at(
-            self.tree,
-            self.store,
-            self.order,
-            self.min.as_ref(),
-            self.max.as_ref(),
-            &mut self.visited_nibbles,
-            &mut self.visited_nodes,
-            None,
-        )
-        .transpose()
+        let mut store = MockStorage::new();
+        let batch = Batch::from([
+            ("bar".to_string(), Op::Insert("1".to_string())),
+            ("foo".to_string(), Op::Insert("2".to_string())),
+        ]);
+        PREFIXED_TREE.apply(&mut store, batch).unwrap();
+
+        // the response carries the mapped key, which the proof is verified
+        // against
+        let res = PREFIXED_TREE.get(&store, &"foo".to_string(), true, None).unwrap();
+        assert_eq!(res.key, "app/foo");
+        let root_hash = PREFIXED_TREE.root(&store, None).unwrap().root_hash;
+        assert_eq!(verify_get_response(&root_hash, &res).unwrap(), Some(&"2".to_string()));
+
+        // bounds are mapped too
+        let iter = PREFIXED_TREE
+            .iterate(&store, Order::Ascending, Some(&"c".to_string()), None, None)
+            .unwrap();
+        assert_eq!(
+            iter.collect::<Result<Vec<_>, _>>().unwrap(),
+            [("app/foo".to_string(), "2".to_string())],
+        );
+
+        // a key that is already mapped is mapped again
+        let batch = Batch::from([
+            ("app/foo".to_string(), Op::Insert("3".to_string())),
+            ("foo".to_string(), Op::Delete),
+        ]);
+        PREFIXED_TREE.apply(&mut store, batch).unwrap();
+        let res = PREFIXED_TREE.get(&store, &"app/foo".to_string(), false, None).unwrap();
+        assert_eq!(res.key, "app/app/foo");
+        assert_eq!(res.value, Some("3".to_string()));
+
+        // the batch of an overlay can be applied to the tree, and the overlay
+        // computes the same root as doing so
+        let mut overlay = Overlay::new(&PREFIXED_TREE, &store, 2);
+        overlay.insert("baz".to_string(), "4".to_string());
+        assert_eq!(overlay.get(&"baz".to_string()).unwrap(), Some("4".to_string()));
+        let overlay_root_hash = overlay.root_hash().unwrap();
+        let batch = overlay.into_batch();
+        PREFIXED_TREE.apply(&mut store, batch).unwrap();
+        assert_eq!(Some(PREFIXED_TREE.root(&store, None).unwrap().root_hash), overlay_root_hash);
+        let res = PREFIXED_TREE.get(&store, &"baz".to_string(), false, None).unwrap();
+        assert_eq!(res.key, "app/baz");
     }
-}
 
-#[allow(clippy::too_many_arguments)]
-fn iterate_at<K, V>(
-    tree: &Tree<K, V>,
-    store: &dyn Storage,
-    order: Order,
-    min: Option<&NibblePath>,
-    max: Option<&NibblePath>,
-    visited_nibbles: &mut NibblePath,
-    visited_nodes: &mut Vec<Node<K, V>>,
-    start_after_index: Option<Nibble>,
-) -> Result<Option<(K, V)>>
-where
-    K: Serialize + DeserializeOwned + Clone + AsRef<[u8]>,
-    V: Serialize + DeserializeOwned + Clone,
-{
-    let Some(current_node) = visited_nodes.last().cloned() else {
-        return Ok(None);
-    };
+    #[test]
+    fn chaining_version_headers() {
+        const HEADER_TREE: Tree<Vec<u8>, Vec<u8>> = Tree::new_default().with_version_headers("h");
 
-    // going through the node's children. pushing the first one that's in
-    // the range into the stack
-    for child in iter_with_order(current_node.children, order) {
-        if skip(child.index, start_after_index, order) {
-            continue;
+        let mut store = MockStorage::new();
+        let mut root_hashes = vec![];
+        for op in [Op::Insert(b"1".to_vec()), Op::Delete, Op::Insert(b"2".to_vec())] {
+            HEADER_TREE.apply(&mut store, Batch::from([(b"foo".to_vec(), op)])).unwrap();
+            root_hashes.push(HEADER_TREE.root(&store, None).ok().map(|res| res.root_hash));
         }
+        assert_eq!(root_hashes[1], None);
 
-        let child_nibble_path = visited_nibbles.child(child.index);
-        if !nibbles_in_range(&child_nibble_path, min, max) {
-            continue;
+        let headers = HEADER_TREE.version_headers(&store, None, None).unwrap();
+        assert_eq!(headers.len(), 3);
+        for (i, header) in headers.iter().enumerate() {
+            assert_eq!(header.version, i as u64 + 1);
+            assert_eq!(header.root_hash, root_hashes[i]);
+            let prev_root_hash = i.checked_sub(1).and_then(|i| root_hashes[i].clone());
+            assert_eq!(header.prev_root_hash, prev_root_hash);
         }
+        assert!(verify_version_chain(&headers).is_ok());
+        assert_eq!(HEADER_TREE.version_headers(&store, Some(1), Some(1)).unwrap(), headers[1..2]);
+
+        // a header that is altered or removed breaks the chain
+        let mut tampered = headers.clone();
+        tampered[0].root_hash = root_hashes[2].clone();
+        assert!(matches!(
+            verify_version_chain(&tampered),
+            Err(VerificationError::VersionChainBroken { version: 2 }),
+        ));
+        assert!(matches!(
+            verify_version_chain(&[headers[0].clone(), headers[2].clone()]),
+            Err(VerificationError::VersionChainBroken { version: 3 }),
+        ));
+    }
+
+    #[test]
+    fn proving_historical_roots() {
+        const HISTORY_TREE: Tree<Vec<u8>, Vec<u8>> =
+            Tree::new_default().with_root_history("r", "h");
 
-        let child_node_key = NodeKey::new(child.version, child_nibble_path);
-        let child_node = tree.nodes.load(store, &child_node_key)?;
+        let mut store = MockStorage::new();
+        assert_eq!(HISTORY_TREE.history_root(&store).unwrap(), None);
 
-        visited_nibbles.push(child.index);
-        visited_nodes.push(child_node.clone());
+        // every version is proven against each history root since, as the
+        // number of versions goes through various numbers of mountains
+        let mut root_hashes = vec![];
+        for i in 0..12u8 {
+            let op = if i % 5 == 4 {
+                Op::Delete
+            } else {
+                Op::Insert(vec![i])
+            };
+            HISTORY_TREE.apply(&mut store, Batch::from([(b"foo".to_vec(), op)])).unwrap();
+            root_hashes.push(HISTORY_TREE.root(&store, None).ok().map(|res| res.root_hash));
 
-        // if the child node has data, and the key is in range, then we stop the
-        // recursion and return this data
-        if let Some(Record { key, value }) = child_node.data {
-            // we only need to compare the key with the min. we don't need to
-            // compare with the max because any key greater than max should have
-            // already been dropped when comparing `nibbles_in_range`
-            if key_in_range(&key, min) {
-                return Ok(Some((key, value)));
+            let history_root = HISTORY_TREE.history_root(&store).unwrap().unwrap();
+            for (version, root_hash) in (1..).zip(&root_hashes) {
+                let res = HISTORY_TREE.prove_historical_root(&store, version).unwrap();
+                assert_eq!(res.root_hash, *root_hash);
+                assert!(verify_historical_root(
+                    &history_root,
+                    version,
+                    root_hash.as_ref(),
+                    &res.proof,
+                )
+                .is_ok());
             }
         }
+        assert_eq!(root_hashes[4], None);
+
+        // old roots can still be proven after their versions are pruned
+        HISTORY_TREE.prune(&mut store, None).unwrap();
+        let history_root = HISTORY_TREE.history_root(&store).unwrap().unwrap();
+        let res = HISTORY_TREE.prove_historical_root(&store, 3).unwrap();
+        let root_hash = root_hashes[2].as_ref();
+        assert!(verify_historical_root(&history_root, 3, root_hash, &res.proof).is_ok());
 
-        // if the current node has no data, then we do a depth-first search,
-        // exploring the children of this child
-        return iterate_at(tree, store, order, min, max, visited_nibbles, visited_nodes, None);
+        // a root that wasn't committed at the version isn't accepted
+        assert!(matches!(
+            verify_historical_root(&history_root, 3, root_hashes[3].as_ref(), &res.proof),
+            Err(VerificationError::RootHashMismatch { .. }),
+        ));
+        assert!(matches!(
+            verify_historical_root(&history_root, 4, root_hash, &res.proof),
+            Err(VerificationError::RootHashMismatch { .. }),
+        ));
+        let mut proof = res.proof.clone();
+        proof.peaks.pop();
+        assert!(matches!(
+            verify_historical_root(&history_root, 3, root_hash, &proof),
+            Err(VerificationError::HistoryProofMalformed),
+        ));
+        assert!(matches!(
+            HISTORY_TREE.prove_historical_root(&store, 13),
+            Err(TreeError::VersionNotInRootHistory { version: 13 }),
+        ));
+        assert!(matches!(
+            TREE.prove_historical_root(&store, 3),
+            Err(TreeError::RootHistoryNotTracked),
+        ));
+
+        // a version that is staged but not committed is taken back out of the
+        // history by `recover`
+        let batch = Batch::from([(b"bar".to_vec(), Op::Insert(b"1".to_vec()))]);
+        HISTORY_TREE.apply(&mut store, batch.clone()).unwrap();
+        let next_history_root = HISTORY_TREE.history_root(&store).unwrap();
+        HISTORY_TREE.version.save(&mut store, &12).unwrap();
+        assert!(HISTORY_TREE.recover(&mut store).unwrap());
+        assert_eq!(HISTORY_TREE.history_root(&store).unwrap(), Some(history_root));
+        HISTORY_TREE.apply(&mut store, batch).unwrap();
+        assert_eq!(HISTORY_TREE.history_root(&store).unwrap(), next_history_root);
     }
 
-    // now we've gone over all the childs of the current node, and still hasn't
-    // returned. this means there is no data found in any of the subtrees below
-    // the current node. we need to go up one level and search in the siblings.
-    let (Some(index), Some(_)) = (visited_nibbles.pop(), visited_nodes.pop()) else {
-        return Ok(None);
-    };
+    #[test]
+    fn clearing() {
+        const CLEAR_TREE: Tree<Vec<u8>, Vec<u8>> = Tree::new_default()
+            .with_prune_batch_size(3)
+            .with_churn_counters("cc")
+            .with_counters("c")
+            .with_stats("s")
+            .with_key_filter("kf", 64, 3)
+            .with_version_headers("h")
+            .with_root_history("rr", "rh")
+            .with_expiration("et", "ei");
 
-    iterate_at(tree, store, order, min, max, visited_nibbles, visited_nodes, Some(index))
-}
+        let mut store = MockStorage::new();
+        store.set(b"other", b"1");
 
-fn iter_with_order<'a, I>(items: I, order: Order) -> Box<dyn Iterator<Item = I::Item> + 'a>
-where
-    I: IntoIterator,
-    I::IntoIter: DoubleEndedIterator + 'a,
-{
-    match order {
-        Order::Ascending => Box::new(items.into_iter()),
-        Order::Descending => Box::new(items.into_iter().rev()),
-    }
-}
+        let mut rng = StdRng::seed_from_u64(3971);
+        for _ in 0..5 {
+            let batch: Batch<_, _> =
+                (0..20).map(|_| (rand_key(&mut rng), Op::Insert(vec![rng.gen()]))).collect();
+            CLEAR_TREE.apply(&mut store, batch).unwrap();
+        }
+        CLEAR_TREE.set_expiration(&mut store, &b"a".to_vec(), Some(100)).unwrap();
+        CLEAR_TREE.prune(&mut store, Some(2)).unwrap();
 
-fn skip(index: Nibble, start_after: Option<Nibble>, order: Order) -> bool {
-    let Some(after) = start_after else {
-        return false;
-    };
+        // only records outside the tree's namespaces remain
+        CLEAR_TREE.clear(&mut store).unwrap();
+        assert_eq!(
+            store.range(None, None, Order::Ascending).collect::<Vec<_>>(),
+            [(b"other".to_vec(), b"1".to_vec())],
+        );
 
-    match order {
-        Order::Ascending => index <= after,
-        Order::Descending => index >= after,
-    }
-}
+        // the tree can be reinitialized as if it were new
+        let records = [(b"a".to_vec(), b"1".to_vec()), (b"b".to_vec(), b"2".to_vec())];
+        CLEAR_TREE.initialize_from(&mut store, records.clone()).unwrap();
+        let mut new_store = MockStorage::new();
+        new_store.set(b"other", b"1");
+        CLEAR_TREE.initialize_from(&mut new_store, records.clone()).unwrap();
+        assert_eq!(
+            store.range(None, None, Order::Ascending).collect::<Vec<_>>(),
+            new_store.range(None, None, Order::Ascending).collect::<Vec<_>>(),
+        );
 
-fn nibbles_in_range(
-    nibble_path: &NibblePath,
-    min: Option<&NibblePath>,
-    max: Option<&NibblePath>,
-) -> bool {
-    // the min bound is a bit complex
-    //
-    // for example, if nibble_path = [12], min = [12345]
-    // if we just compare the bytes, we get nibble_path < min and thus out of
-    // range. however we don't want to discard this nibble_path just yet,
-    // because if we go down the tree, we may find a node >= min
-    //
-    // to fix this, we crop min to the same length as nibble_path and then do
-    // the comparison
-    if let Some(min) = min {
-        if nibble_path.bytes < min.crop(nibble_path.num_nibbles).bytes {
-            return false;
+        // a clear interrupted right after deleting the version leaves a tree
+        // that reads as empty, but holds the nodes and orphans of the old one.
+        // the new version's nodes would be pruned as stale orphans, so the
+        // tree can't be written to until the clear is finished
+        let mut interrupted_store = MockStorage::new();
+        interrupted_store.set(b"other", b"1");
+        for _ in 0..2 {
+            let batch: Batch<_, _> =
+                (0..20).map(|_| (rand_key(&mut rng), Op::Insert(vec![rng.gen()]))).collect();
+            CLEAR_TREE.apply(&mut interrupted_store, batch).unwrap();
         }
+        interrupted_store.remove(b"v");
+        assert!(matches!(
+            CLEAR_TREE.initialize_from(&mut interrupted_store, records.clone()),
+            Err(TreeError::LeftoverRecords),
+        ));
+        let batch = Batch::from([(b"a".to_vec(), Op::Insert(b"1".to_vec()))]);
+        assert!(matches!(
+            CLEAR_TREE.apply(&mut interrupted_store, batch),
+            Err(TreeError::LeftoverRecords),
+        ));
+
+        CLEAR_TREE.clear(&mut interrupted_store).unwrap();
+        CLEAR_TREE.initialize_from(&mut interrupted_store, records).unwrap();
+        assert_eq!(
+            interrupted_store.range(None, None, Order::Ascending).collect::<Vec<_>>(),
+            new_store.range(None, None, Order::Ascending).collect::<Vec<_>>(),
+        );
     }
 
-    // the max bound is simpler, we just compare the bytes
-    if let Some(max) = max {
-        if nibble_path.bytes >= max.bytes {
-            return false;
-        }
+    #[test]
+    fn applying_at_versions() {
+        const HEIGHT_TREE: Tree<Vec<u8>, Vec<u8>> = Tree::new_default().with_version_headers("h");
+
+        let mut store = MockStorage::new();
+        let insert = |key: &[u8]| Batch::from([(key.to_vec(), Op::Insert(b"1".to_vec()))]);
+        assert_eq!(HEIGHT_TREE.apply_at(&mut store, insert(b"foo"), 100u32).unwrap().version, 100);
+        assert_eq!(HEIGHT_TREE.latest_version::<u32>(&store).unwrap(), Some(100));
+        assert!(matches!(
+            HEIGHT_TREE.apply_at(&mut store, insert(b"bar"), 100u32),
+            Err(TreeError::VersionNotNewer { latest: 100, version: 100 }),
+        ));
+
+        // composite versions are ordered by epoch first
+        HEIGHT_TREE.apply_at(&mut store, insert(b"bar"), (1u32, 5u32)).unwrap();
+        assert_eq!(HEIGHT_TREE.latest_version::<(u32, u32)>(&store).unwrap(), Some((1, 5)));
+        assert!(matches!(
+            HEIGHT_TREE.latest_version::<u32>(&store),
+            Err(TreeError::VersionNotRepresentable { .. }),
+        ));
+
+        // the skipped versions can't be queried, but the committed ones can
+        assert!(HEIGHT_TREE.get(&store, &b"foo".to_vec(), false, Some(99)).is_err());
+        let res = HEIGHT_TREE.get(&store, &b"bar".to_vec(), false, Some(100)).unwrap();
+        assert_eq!(res.value, None);
+        let res = HEIGHT_TREE.get(&store, &b"bar".to_vec(), false, Some((1, 5).to_u64())).unwrap();
+        assert_eq!(res.value, Some(b"1".to_vec()));
+
+        // `apply` carries on from the latest version
+        let changes = HEIGHT_TREE.apply(&mut store, insert(b"baz")).unwrap();
+        assert_eq!(changes.version, (1, 6).to_u64());
+
+        let headers = HEIGHT_TREE.version_headers(&store, None, None).unwrap();
+        let versions = headers.iter().map(|header| (header.prev_version, header.version));
+        assert_eq!(
+            versions.collect::<Vec<_>>(),
+            [(0, 100), (100, (1, 5).to_u64()), ((1, 5).to_u64(), (1, 6).to_u64())],
+        );
+        assert!(verify_version_chain(&headers).is_ok());
     }
 
-    true
-}
+    #[test]
+    fn verifying_get_responses() {
+        const DOMAIN_TREE: Tree<Vec<u8>, Vec<u8>> = Tree::new_default().with_domain(b"app");
 
-fn key_in_range<K: AsRef<[u8]>>(key: &K, min: Option<&NibblePath>) -> bool {
-    if let Some(min) = min {
-        if key.as_ref() < min.bytes.as_slice() {
-            return false;
-        }
+        let mut store = MockStorage::new();
+        let batch = Batch::from([(b"foo".to_vec(), Op::Insert(b"bar".to_vec()))]);
+        DOMAIN_TREE.apply(&mut store, batch).unwrap();
+        let root_hash = DOMAIN_TREE.root(&store, None).unwrap().root_hash;
+
+        let res = DOMAIN_TREE.get(&store, &b"foo".to_vec(), true, None).unwrap();
+        let value = verify_get_response_in_domain(b"app", &root_hash, &res).unwrap();
+        assert_eq!(value, Some(&b"bar".to_vec()));
+        let res = DOMAIN_TREE.get(&store, &b"fuzz".to_vec(), true, None).unwrap();
+        assert_eq!(verify_get_response_in_domain(b"app", &root_hash, &res).unwrap(), None);
+
+        // a response claiming the key to be absent doesn't verify
+        let mut res = DOMAIN_TREE.get(&store, &b"foo".to_vec(), true, None).unwrap();
+        res.value = None;
+        assert!(verify_get_response_in_domain(b"app", &root_hash, &res).is_err());
+        // nor does one without the domain
+        res.value = Some(b"bar".to_vec());
+        let err = verify_get_response(&root_hash, &res).unwrap_err();
+        assert!(matches!(StdError::from(err), StdError::GenericErr { .. }));
+
+        res.proof = None;
+        assert!(matches!(
+            verify_get_response_in_domain(b"app", &root_hash, &res),
+            Err(VerificationError::ProofMissing),
+        ));
     }
 
-    true
-}
+    #[test]
+    fn expiring_keys() {
+        const EXPIRING_TREE: Tree<Vec<u8>, Vec<u8>> = Tree::new_default().with_expiration("t", "e");
 
-#[derive(Debug, PartialEq, thiserror::Error)]
-pub enum TreeError {
-    #[error(transparent)]
-    Std(#[from] cosmwasm_std::StdError),
+        let mut store = MockStorage::new();
+        let batch = Batch::from([
+            (b"a".to_vec(), Op::Insert(b"1".to_vec())),
+            (b"b".to_vec(), Op::Insert(b"2".to_vec())),
+            (b"c".to_vec(), Op::Insert(b"3".to_vec())),
+        ]);
+        EXPIRING_TREE.apply(&mut store, batch).unwrap();
+        EXPIRING_TREE.set_expiration(&mut store, &b"a".to_vec(), Some(20)).unwrap();
+        EXPIRING_TREE.set_expiration(&mut store, &b"b".to_vec(), Some(10)).unwrap();
+        EXPIRING_TREE.set_expiration(&mut store, &b"c".to_vec(), Some(10)).unwrap();
+        // setting it again replaces the previous time
+        EXPIRING_TREE.set_expiration(&mut store, &b"a".to_vec(), Some(30)).unwrap();
+        assert_eq!(EXPIRING_TREE.expiration(&store, &b"a".to_vec()).unwrap(), Some(30));
 
-    #[error("cannot query at version {querying} which is newer than the latest ({latest})")]
-    VersionNewerThanLatest {
-        latest: u64,
-        querying: u64,
-    },
+        // nothing has expired yet, so no version is created
+        let changes = EXPIRING_TREE.expire(&mut store, 9, 10).unwrap();
+        assert_eq!((changes.version, changes.changes.len()), (1, 0));
 
-    #[error("root node of version {version} not found, probably pruned")]
-    RootNodeNotFound {
-        version: u64,
-    },
+        // keys expire earliest first, up to the limit
+        let changes = EXPIRING_TREE.expire(&mut store, 10, 1).unwrap();
+        assert_eq!(changes.version, 2);
+        let keys = changes.changes.iter().map(|change| &change.key);
+        assert_eq!(keys.collect::<Vec<_>>(), [&b"b".to_vec()]);
+        assert_eq!(EXPIRING_TREE.expiration(&store, &b"b".to_vec()).unwrap(), None);
 
-    #[error(
-        "tree corrupted! non-root node not found (version: {}, nibble_path: {})",
-        node_key.version,
-        node_key.nibble_path.to_hex(),
-    )]
-    NonRootNodeNotFound {
-        node_key: NodeKey,
-    },
-}
+        // an op applied to a key clears its expiration
+        let batch = Batch::from([(b"c".to_vec(), Op::Insert(b"4".to_vec()))]);
+        EXPIRING_TREE.apply(&mut store, batch).unwrap();
+        assert_eq!(EXPIRING_TREE.expiration(&store, &b"c".to_vec()).unwrap(), None);
 
-type Result<T> = std::result::Result<T, TreeError>;
+        EXPIRING_TREE.expire(&mut store, 100, 10).unwrap();
+        let remaining = EXPIRING_TREE.iterate(&store, Order::Ascending, None, None, None).unwrap();
+        let keys = remaining.map(|item| item.unwrap().0);
+        assert_eq!(keys.collect::<Vec<_>>(), [b"c".to_vec()]);
+
+        assert!(matches!(
+            TREE.expire(&mut store, 100, 10),
+            Err(TreeError::ExpirationNotTracked),
+        ));
+    }
+
+    #[cfg(feature = "sha256")]
+    #[test]
+    fn hashing_with_sha256() {
+        use crate::{
+            value_hash_using, verify_batch_membership_using, verify_get_response_using, JsonCodec,
+            Sha256,
+        };
+
+        const SHA256_TREE: Tree<String, String, JsonCodec, Sha256> =
+            Tree::new_default().with_domain(b"sha");
+        const BLAKE3_TREE: Tree<String, String> = Tree::new_default().with_domain(b"sha");
+
+        let mut store = MockStorage::new();
+        let mut blake3_store = MockStorage::new();
+        let batch = Batch::from([
+            ("foo".to_string(), Op::Insert("bar".to_string())),
+            ("fuzz".to_string(), Op::Insert("buzz".to_string())),
+            ("larry".to_string(), Op::Insert("engineer".to_string())),
+        ]);
+        let changes = SHA256_TREE.apply(&mut store, batch.clone()).unwrap().changes;
+        BLAKE3_TREE.apply(&mut blake3_store, batch).unwrap();
+
+        // value hashes in change sets use the same hash function
+        let hashes = changes.into_iter().map(|change| change.new_value_hash).collect::<Vec<_>>();
+        let expected = ["bar", "buzz", "engineer"]
+            .map(|value| Some(value_hash_using::<Sha256, _>(&value.to_string())));
+        assert_eq!(hashes, expected);
+
+        // deleting a record collapses its sibling, which rehashes the path
+        let batch = Batch::from([("fuzz".to_string(), Op::Delete)]);
+        SHA256_TREE.apply(&mut store, batch.clone()).unwrap();
+        BLAKE3_TREE.apply(&mut blake3_store, batch).unwrap();
+
+        // the same records commit to a different root hash
+        let root_hash = SHA256_TREE.root(&store, None).unwrap().root_hash;
+        assert_ne!(root_hash, BLAKE3_TREE.root(&blake3_store, None).unwrap().root_hash);
+
+        // proofs verify against the hash function the tree uses, and only that
+        for key in ["foo", "fuzz"] {
+            let res = SHA256_TREE.get(&store, &key.to_string(), true, None).unwrap();
+            let value =
+                verify_get_response_using::<Sha256, _, _>(Some(b"sha"), &root_hash, &res).unwrap();
+            assert_eq!(value, res.value.as_ref());
+            assert!(verify_get_response_in_domain(b"sha", &root_hash, &res).is_err());
+        }
+
+        let keys = ["foo".to_string(), "fuzz".to_string(), "larry".to_string()];
+        let res = SHA256_TREE.get_many(&store, &keys, true, None).unwrap();
+        let proof: BatchProof<String, String> = from_binary(&res.proof.unwrap()).unwrap();
+        verify_batch_membership_using::<Sha256, _, _>(
+            Some(b"sha"),
+            &root_hash,
+            &res.records,
+            &proof,
+        )
+        .unwrap();
+    }
+}
@@ -1,25 +1,33 @@
-//! Eventually we want to represent children with a BTreeMap<Nibble, Child>
-//! but CosmWasm currently doesn't support serializing maps. This type is a
-//! walkaround. Once map serialization is supported, we can delete this.
+//! A node's children, indexed by a bitmap of the nibbles present, so that a
+//! child is found without scanning. CosmWasm doesn't support serializing maps,
+//! so the children are serialized as a list, in ascending order of indexes.
 
 use {
     crate::{Child, Nibble},
-    cosmwasm_schema::cw_serde,
+    schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema},
+    serde::{
+        de::{Deserialize, Deserializer},
+        ser::{Serialize, Serializer},
+    },
 };
 
-#[cw_serde]
-#[derive(Default)]
-pub struct Children(Vec<Child>);
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Children {
+    // bit `i` is set if there's a child at index `i`
+    bitmap: u16,
+    // the children, in ascending order of indexes
+    children: Vec<Child>,
+}
 
 impl From<Vec<Child>> for Children {
     fn from(vec: Vec<Child>) -> Self {
-        Self(vec)
+        Self::new(vec)
     }
 }
 
 impl AsRef<[Child]> for Children {
     fn as_ref(&self) -> &[Child] {
-        self.0.as_slice()
+        self.children.as_slice()
     }
 }
 
@@ -28,7 +36,7 @@ impl IntoIterator for Children {
     type IntoIter = std::vec::IntoIter<Child>;
 
     fn into_iter(self) -> Self::IntoIter {
-        self.0.into_iter()
+        self.children.into_iter()
     }
 }
 
@@ -37,54 +45,151 @@ impl<'a> IntoIterator for &'a Children {
     type IntoIter = std::slice::Iter<'a, Child>;
 
     fn into_iter(self) -> Self::IntoIter {
-        self.0.as_slice().iter()
+        self.children.as_slice().iter()
     }
 }
 
 impl Children {
+    /// Children in any order are sorted by index. Of children with the same
+    /// index, the last one is kept.
     pub fn new(vec: Vec<Child>) -> Self {
-        Self(vec)
+        let mut children = Self::default();
+        for child in vec {
+            children.insert(child);
+        }
+        children
     }
 
     pub fn count(&self) -> usize {
-        self.0.len()
+        self.children.len()
     }
 
     pub fn is_empty(&self) -> bool {
-        self.0.is_empty()
+        self.children.is_empty()
     }
 
     pub fn get(&self, index: Nibble) -> Option<&Child> {
-        self.0.iter().find(|child| child.index == index)
+        self.contains(index).then(|| &self.children[self.position(index)])
     }
 
     /// If there is one and only one child, return a reference to this child.
     /// Otherwise (no child or more than one children), panic.
     pub fn get_only(&self) -> &Child {
-        assert!(self.count() == 1);
-        &self.0[0]
+        self.only().expect("expecting exactly one child")
     }
 
-    pub fn insert(&mut self, new_child: Child) {
-        for (pos, child) in self.0.iter().enumerate() {
-            if child.index == new_child.index {
-                self.0[pos] = new_child;
-                return;
-            }
-
-            if child.index > new_child.index {
-                self.0.insert(pos, new_child);
-                return;
-            }
+    /// Same as `get_only`, but returns `None` instead of panicking if there
+    /// isn't exactly one child.
+    pub fn only(&self) -> Option<&Child> {
+        match self.children.as_slice() {
+            [child] => Some(child),
+            _ => None,
         }
+    }
 
-        self.0.push(new_child);
+    pub fn insert(&mut self, new_child: Child) {
+        let pos = self.position(new_child.index);
+        if self.contains(new_child.index) {
+            self.children[pos] = new_child;
+        } else {
+            self.bitmap |= 1 << new_child.index.byte();
+            self.children.insert(pos, new_child);
+        }
     }
 
     // note: attempting to delete a non-existent child results no-op, not error
     pub fn remove(&mut self, index: Nibble) {
-        if let Some(pos) = self.0.iter().position(|child| child.index == index) {
-            self.0.remove(pos);
+        if self.contains(index) {
+            self.children.remove(self.position(index));
+            self.bitmap &= !(1 << index.byte());
+        }
+    }
+
+    fn contains(&self, index: Nibble) -> bool {
+        self.bitmap & (1 << index.byte()) != 0
+    }
+
+    // the position in `children` of the child at the index if there is one, or
+    // where it would be inserted otherwise, which is the number of children at
+    // smaller indexes
+    fn position(&self, index: Nibble) -> usize {
+        (self.bitmap & ((1 << index.byte()) - 1)).count_ones() as usize
+    }
+}
+
+impl Serialize for Children {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.children.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Children {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Vec::deserialize(deserializer).map(Self::new)
+    }
+}
+
+impl JsonSchema for Children {
+    fn schema_name() -> String {
+        "Children".into()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        <Vec<Child>>::json_schema(gen)
+    }
+}
+
+// ----------------------------------- tests -----------------------------------
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::{Child, Children, Hash, Nibble, HASH_LEN},
+        cosmwasm_std::{from_slice, to_vec},
+    };
+
+    fn child(index: u8, version: u64) -> Child {
+        Child {
+            index: Nibble::new(index),
+            version,
+            hash: Hash::from([index; HASH_LEN]),
+            aggregate: None,
+        }
+    }
+
+    #[test]
+    fn indexing_children() {
+        let mut children = Children::default();
+        for index in [7, 0, 15, 3] {
+            children.insert(child(index, 1));
         }
+        children.insert(child(3, 2));
+
+        let indexes = |children: &Children| {
+            children.as_ref().iter().map(|child| child.index.byte()).collect::<Vec<_>>()
+        };
+        assert_eq!(indexes(&children), [0, 3, 7, 15]);
+        assert_eq!(children.get(Nibble::new(3)), Some(&child(3, 2)));
+        assert_eq!(children.get(Nibble::new(15)), Some(&child(15, 1)));
+        assert_eq!(children.get(Nibble::new(4)), None);
+
+        children.remove(Nibble::new(0));
+        children.remove(Nibble::new(4));
+        assert_eq!(indexes(&children), [3, 7, 15]);
+        assert_eq!(children.get(Nibble::new(7)), Some(&child(7, 1)));
+        assert_eq!(children.get(Nibble::new(0)), None);
+
+        // serialized as a plain list, and sorted when deserialized
+        let bytes = to_vec(&children).unwrap();
+        assert_eq!(bytes, to_vec(&vec![child(3, 2), child(7, 1), child(15, 1)]).unwrap());
+        assert_eq!(from_slice::<Children>(&bytes).unwrap(), children);
+        let unsorted = to_vec(&vec![child(15, 1), child(3, 2), child(7, 1)]).unwrap();
+        assert_eq!(from_slice::<Children>(&unsorted).unwrap(), children);
     }
 }
@@ -1,30 +1,165 @@
 use {
     crate::{Child, ProofChild, Record},
-    blake3::Hasher,
     schemars::JsonSchema,
     serde::{
         de::{self, Deserialize, Deserializer, Visitor},
         ser::{Serialize, Serializer},
     },
-    std::{array::TryFromSliceError, fmt},
+    std::{array::TryFromSliceError, borrow::Cow, fmt},
 };
 
+/// Length of the hashes committed to by the tree, including the root hash.
+///
+/// By default, this is the full blake3 output (32 bytes). With the
+/// `truncated-hash` feature, it's 20 bytes, which some bridge formats expect.
+/// A truncated hash is the prefix of the full output, as blake3 supports
+/// natively. Node hashes, proofs and verification all use this length, so a
+/// tree's nodes and proofs are not compatible across the two settings.
+#[cfg(not(feature = "truncated-hash"))]
 pub const HASH_LEN: usize = blake3::OUT_LEN;
+#[cfg(feature = "truncated-hash")]
+pub const HASH_LEN: usize = 20;
 
-pub(super) fn hash_child(hasher: &mut Hasher, child: &Child) {
+/// The hash function that a tree's nodes are hashed with, and thus its root
+/// hash and proofs. The tree's type parameter `H` selects it; see `Tree`.
+///
+/// Implemented by `Blake3`, the default, and by `Sha256` with the `sha256`
+/// feature, e.g. for chains that standardize on SHA-256. Outputs longer than
+/// `HASH_LEN` are truncated to it. Hashes that aren't committed to by the
+/// tree, such as those of values in a `ChangeSet`, are always blake3.
+pub trait TreeHasher: Default {
+    fn update(&mut self, bytes: &[u8]);
+
+    fn finalize(self) -> Hash;
+}
+
+/// The blake3 hash function.
+#[derive(Default)]
+pub struct Blake3(blake3::Hasher);
+
+impl TreeHasher for Blake3 {
+    fn update(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+
+    fn finalize(self) -> Hash {
+        self.0.finalize().into()
+    }
+}
+
+/// The SHA-256 hash function.
+#[cfg(feature = "sha256")]
+#[derive(Default)]
+pub struct Sha256(sha2::Sha256);
+
+#[cfg(feature = "sha256")]
+impl TreeHasher for Sha256 {
+    fn update(&mut self, bytes: &[u8]) {
+        sha2::Digest::update(&mut self.0, bytes);
+    }
+
+    fn finalize(self) -> Hash {
+        let output = sha2::Digest::finalize(self.0);
+        let mut bytes = [0; HASH_LEN];
+        bytes.copy_from_slice(&output[..HASH_LEN]);
+        Hash(bytes)
+    }
+}
+
+/// Defines the bytes that a value is committed to in the tree, i.e. the bytes
+/// that are hashed. This is implemented for all types that can be referenced
+/// as raw bytes, such as `Vec<u8>` and `String`.
+///
+/// Implement this for other value types, e.g. structs, to store them in the
+/// tree directly, instead of pre-serializing them into bytes. The bytes must
+/// be deterministic, since they determine the root hash, and verifiers must
+/// derive them the same way.
+pub trait HashableValue {
+    fn hash_bytes(&self) -> Cow<[u8]>;
+}
+
+impl<T: AsRef<[u8]> + ?Sized> HashableValue for T {
+    fn hash_bytes(&self) -> Cow<[u8]> {
+        Cow::Borrowed(self.as_ref())
+    }
+}
+
+/// Where the bytes to be hashed are written into: either a hasher, or a buffer
+/// that collects the preimage. This way the concatenation rules are defined
+/// once for both.
+pub(super) trait Sink {
+    fn update(&mut self, bytes: &[u8]);
+}
+
+impl<H: TreeHasher> Sink for H {
+    fn update(&mut self, bytes: &[u8]) {
+        TreeHasher::update(self, bytes);
+    }
+}
+
+impl Sink for Vec<u8> {
+    fn update(&mut self, bytes: &[u8]) {
+        self.extend_from_slice(bytes);
+    }
+}
+
+pub(super) fn hash_child<S: Sink>(hasher: &mut S, child: &Child) {
     hasher.update(&[child.index.byte()]);
     hasher.update(child.hash.as_bytes());
 }
 
-pub(super) fn hash_proof_child(hasher: &mut Hasher, child: &ProofChild) {
+pub(super) fn hash_proof_child<S: Sink>(hasher: &mut S, child: &ProofChild) {
     hasher.update(&[child.index.byte()]);
     hasher.update(child.hash.as_bytes());
 }
 
-pub(super) fn hash_data<K: AsRef<[u8]>, V: AsRef<[u8]>>(hasher: &mut Hasher, data: &Record<K, V>) {
+pub(super) fn hash_data<S, K, V>(hasher: &mut S, data: &Record<K, V>)
+where
+    S: Sink,
+    K: AsRef<[u8]>,
+    V: HashableValue,
+{
     hasher.update((data.key.as_ref().len() as u16).to_be_bytes().as_slice());
     hasher.update(data.key.as_ref());
-    hasher.update(data.value.as_ref());
+    hasher.update(&data.value.hash_bytes());
+}
+
+pub(super) fn hash_aggregate<S: Sink>(hasher: &mut S, aggregate: u128) {
+    hasher.update(&aggregate.to_be_bytes());
+}
+
+/// Compute the hash of a value on its own, as reported in a `ChangeSet`, which
+/// is defined as hash(value), where the value is the bytes given by
+/// `HashableValue::hash_bytes`.
+pub fn value_hash<V: HashableValue + ?Sized>(value: &V) -> Hash {
+    value_hash_using::<Blake3, V>(value)
+}
+
+/// Same as `value_hash`, for a tree that uses the given hash function.
+pub fn value_hash_using<H: TreeHasher, V: HashableValue + ?Sized>(value: &V) -> Hash {
+    let mut hasher = H::default();
+    hasher.update(&value.hash_bytes());
+    hasher.finalize()
+}
+
+/// Compute the root hash of a tree that is created with a domain tag (see
+/// `Tree::with_domain`), which is defined as:
+///
+/// hash(len(domain) || domain || root_node_hash)
+///
+/// where `len()` returns a 16-bit (2 bytes) unsigned integer in big endian
+/// encoding, and `root_node_hash` is the hash of the root node.
+pub fn domain_root_hash(domain: &[u8], root_node_hash: &Hash) -> Hash {
+    domain_root_hash_using::<Blake3>(domain, root_node_hash)
+}
+
+/// Same as `domain_root_hash`, for a tree that uses the given hash function.
+pub fn domain_root_hash_using<H: TreeHasher>(domain: &[u8], root_node_hash: &Hash) -> Hash {
+    let mut hasher = H::default();
+    hasher.update(&(domain.len() as u16).to_be_bytes());
+    hasher.update(domain);
+    hasher.update(root_node_hash.as_bytes());
+    hasher.finalize()
 }
 
 /// The `blake3::Hash` type doesn't implement JsonSchema and doesn't have a good
@@ -50,9 +185,12 @@ impl From<[u8; HASH_LEN]> for Hash {
     }
 }
 
+// truncates the hash if HASH_LEN is shorter than the blake3 output
 impl From<blake3::Hash> for Hash {
     fn from(hash: blake3::Hash) -> Self {
-        Self(*hash.as_bytes())
+        let mut bytes = [0; HASH_LEN];
+        bytes.copy_from_slice(&hash.as_bytes()[..HASH_LEN]);
+        Self(bytes)
     }
 }
 
@@ -105,7 +243,7 @@ impl<'de> Visitor<'de> for HashVisitor {
     type Value = Hash;
 
     fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-        formatter.write_str("a 32-byte array in hex encoding")
+        write!(formatter, "a {HASH_LEN}-byte array in hex encoding")
     }
 
     fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
@@ -0,0 +1,204 @@
+use {
+    crate::{Blake3, Hash, TreeHasher},
+    schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema},
+    serde::{
+        de::{Deserialize, Deserializer},
+        ser::{Serialize, Serializer},
+    },
+    std::{cmp::Ordering, fmt, marker::PhantomData},
+};
+
+/// A key that the tree places by its hash rather than by its bytes, as in
+/// Diem's Jellyfish Merkle tree. See `HashedTree`.
+///
+/// All keys then have the same length and are uniformly distributed, so the
+/// tree stays balanced and proofs stay short, however the keys are chosen: an
+/// adversary can't make them share long prefixes without finding partial hash
+/// collisions. The cost is ordering: keys are compared, iterated over and
+/// bounded by their hashes, so ranges and prefixes of the original keys can't
+/// be queried.
+///
+/// The key is serialized as is, both in storage and in proofs; its hash is
+/// computed when it's created or deserialized. Proofs are verified with the
+/// same functions as those of any other key.
+pub struct HashedKey<K, H = Blake3> {
+    key: K,
+    hash: Hash,
+    hasher: PhantomData<H>,
+}
+
+impl<K, H> HashedKey<K, H>
+where
+    K: AsRef<[u8]>,
+    H: TreeHasher,
+{
+    pub fn new(key: K) -> Self {
+        let mut hasher = H::default();
+        hasher.update(key.as_ref());
+        Self {
+            hash: hasher.finalize(),
+            key,
+            hasher: PhantomData,
+        }
+    }
+}
+
+impl<K, H> HashedKey<K, H> {
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    pub fn hash(&self) -> &Hash {
+        &self.hash
+    }
+}
+
+impl<K, H> From<K> for HashedKey<K, H>
+where
+    K: AsRef<[u8]>,
+    H: TreeHasher,
+{
+    fn from(key: K) -> Self {
+        Self::new(key)
+    }
+}
+
+// the tree places the key by these bytes, and commits to them in the leaf
+impl<K, H> AsRef<[u8]> for HashedKey<K, H> {
+    fn as_ref(&self) -> &[u8] {
+        self.hash.as_bytes()
+    }
+}
+
+// implemented by hand, as deriving would require `H` to implement these too.
+// keys are compared by their hashes, in the order the tree holds them in
+impl<K: Clone, H> Clone for HashedKey<K, H> {
+    fn clone(&self) -> Self {
+        Self {
+            key: self.key.clone(),
+            hash: self.hash.clone(),
+            hasher: PhantomData,
+        }
+    }
+}
+
+impl<K, H> PartialEq for HashedKey<K, H> {
+    fn eq(&self, other: &Self) -> bool {
+        self.hash == other.hash
+    }
+}
+
+impl<K, H> Eq for HashedKey<K, H> {}
+
+impl<K, H> PartialOrd for HashedKey<K, H> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K, H> Ord for HashedKey<K, H> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.hash.cmp(&other.hash)
+    }
+}
+
+impl<K: fmt::Debug, H> fmt::Debug for HashedKey<K, H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "HashedKey({:?}, {})", self.key, self.hash)
+    }
+}
+
+impl<K: Serialize, H> Serialize for HashedKey<K, H> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.key.serialize(serializer)
+    }
+}
+
+impl<'de, K, H> Deserialize<'de> for HashedKey<K, H>
+where
+    K: Deserialize<'de> + AsRef<[u8]>,
+    H: TreeHasher,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        K::deserialize(deserializer).map(Self::new)
+    }
+}
+
+impl<K: JsonSchema, H> JsonSchema for HashedKey<K, H> {
+    fn schema_name() -> String {
+        K::schema_name()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        K::json_schema(gen)
+    }
+}
+
+// ----------------------------------- tests -----------------------------------
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::{
+            verify_get_response, verify_membership, Batch, HashedKey, HashedTree, Op, Proof, Tree,
+        },
+        cosmwasm_std::{from_binary, testing::MockStorage, Order},
+    };
+
+    const TREE: HashedTree<String, String> = Tree::new_default();
+
+    // keys sharing a 100-byte prefix, which would make the proofs of an
+    // unhashed tree at least 200 nodes long
+    fn adversarial_key(i: usize) -> String {
+        format!("{}{i}", "a".repeat(100))
+    }
+
+    #[test]
+    fn hashing_keys() {
+        let mut store = MockStorage::new();
+        let batch: Batch<_, _> = (0..20)
+            .map(|i| (HashedKey::new(adversarial_key(i)), Op::Insert(i.to_string())))
+            .collect();
+        TREE.apply(&mut store, batch).unwrap();
+        let root_hash = TREE.root(&store, None).unwrap().root_hash;
+
+        for i in 0..20 {
+            let key = HashedKey::new(adversarial_key(i));
+            let res = TREE.get(&store, &key, true, None).unwrap();
+            assert_eq!(res.key.key(), &adversarial_key(i));
+            assert_eq!(verify_get_response(&root_hash, &res).unwrap(), Some(&i.to_string()));
+
+            // the proof round trips with the original key, and is as short as
+            // that of a tree of random keys
+            let proof: Proof<HashedKey<String>, String> = from_binary(&res.proof.unwrap()).unwrap();
+            assert!(proof.len() <= 4);
+            verify_membership(&root_hash, &key, &i.to_string(), &proof).unwrap();
+        }
+
+        let res = TREE.get(&store, &HashedKey::new(adversarial_key(20)), true, None).unwrap();
+        assert_eq!(verify_get_response(&root_hash, &res).unwrap(), None);
+
+        // records are iterated over in the order of the hashes of their keys
+        let records = TREE
+            .iterate(&store, Order::Ascending, None, None, None)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(records.len(), 20);
+        assert!(records.windows(2).all(|pair| pair[0].0.hash() < pair[1].0.hash()));
+
+        TREE.apply(&mut store, Batch::from([(adversarial_key(0).into(), Op::Delete)])).unwrap();
+        let res = TREE.get(&store, &adversarial_key(0).into(), false, None).unwrap();
+        assert_eq!(res.value, None);
+    }
+}
@@ -1,29 +1,48 @@
 //! TODO: add attribution to Diem
 
+// the types needed for verifying proofs derive the serde and schemars traits
+// directly, rather than using `cw_serde`, which expands to the same derives, so
+// that they compile without the `storage` feature
 mod children;
 mod hash;
+mod hashed_key;
 mod nibble;
 mod nibble_path;
 mod nibble_range;
 mod node;
+#[cfg(feature = "storage")]
 mod node_key;
 mod op;
 mod proof;
+#[cfg(feature = "storage")]
 mod query;
 
 pub use {
     children::Children,
-    hash::{Hash, HASH_LEN},
+    hash::{
+        domain_root_hash, domain_root_hash_using, value_hash, value_hash_using, Blake3, Hash,
+        HashableValue, TreeHasher, HASH_LEN,
+    },
+    hashed_key::HashedKey,
     nibble::Nibble,
-    nibble_path::{NibbleIterator, NibblePath},
-    nibble_range::{NibbleRange, NibbleRangeIterator},
+    nibble_path::{NibbleIterator, NibblePath, MAX_KEY_LEN, MAX_NIBBLES},
+    nibble_range::{HasNibblePath, NibbleRange, NibbleRangeIterator},
     node::{Child, Node, Record},
+    op::{Batch, Change, ChangeSet, Op, OpResponse},
+    proof::{BatchProof, BatchProofNode, MultiProof, Proof, ProofChild, ProofNode},
+};
+#[cfg(feature = "storage")]
+pub use {
     node_key::NodeKey,
-    op::{Batch, Op, OpResponse},
-    proof::{Proof, ProofChild, ProofNode},
-    query::{GetResponse, RootResponse},
+    query::{
+        AggregateResponse, Counters, GetManyResponse, GetResponse, IterateResponse,
+        IteratorCheckpoint, MultiGetResponse, ProverRequest, PruneStatus, RootResponse,
+        StopReason, SubtreeRootResponse,
+    },
 };
 #[cfg(feature = "debug")]
-pub use query::{NodeResponse, OrphanResponse};
+pub use query::{NodeResponse, OrphanResponse, PathNodeResponse};
+#[cfg(feature = "sha256")]
+pub use hash::Sha256;
 
-use hash::{hash_child, hash_data, hash_proof_child};
+use hash::{hash_aggregate, hash_child, hash_data, hash_proof_child, Sink};
@@ -6,17 +6,27 @@ use {
     },
     std::fmt,
 };
+#[cfg(feature = "storage")]
+use cosmwasm_std::{StdError, StdResult};
 
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, JsonSchema)]
 pub struct Nibble(u8);
 
 impl Nibble {
+    /// Panics if the byte is greater than 0x0f. Use `try_new` for bytes that
+    /// don't come from a trusted source.
     pub fn new(byte: u8) -> Self {
+        assert!(byte <= 0x0f, "nibble value cannot be greater than 0x0f");
+        Self(byte)
+    }
+
+    #[cfg(feature = "storage")]
+    pub fn try_new(byte: u8) -> StdResult<Self> {
         if byte > 0x0f {
-            panic!("nibble value cannot be greater than 0x0f");
+            return Err(StdError::generic_err("nibble value cannot be greater than 0x0f"));
         }
 
-        Self(byte)
+        Ok(Self(byte))
     }
 
     pub fn byte(self) -> u8 {
@@ -68,8 +78,11 @@ impl<'de> Visitor<'de> for NibbleVisitor {
     where
         E: de::Error,
     {
-        let hex_str = format!("0{v}");
-        let bytes = hex::decode(hex_str).map_err(|err| E::custom(err))?;
-        Ok(Nibble(bytes[0]))
+        if v.len() != 1 {
+            return Err(E::invalid_length(v.len(), &self));
+        }
+
+        let byte = u8::from_str_radix(v, 16).map_err(|err| E::custom(err))?;
+        Ok(Nibble(byte))
     }
 }
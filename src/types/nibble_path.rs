@@ -1,16 +1,27 @@
 use {
     crate::Nibble,
-    cosmwasm_std::{ensure, ensure_eq, StdError, StdResult},
-    cw_storage_plus::KeyDeserialize,
     hex::FromHexError,
     schemars::JsonSchema,
     serde::{
         de::{self, Deserialize, Deserializer, Visitor},
         ser::{Serialize, Serializer},
     },
-    std::{any::type_name, cmp::Ordering, fmt, ops::Range},
+    std::{cmp::Ordering, fmt, ops::Range},
+};
+#[cfg(feature = "storage")]
+use {
+    cosmwasm_std::{ensure, ensure_eq, StdError, StdResult},
+    cw_storage_plus::KeyDeserialize,
+    std::any::type_name,
 };
 
+/// The maximum number of nibbles a key can have. Node keys encode the number
+/// of nibbles as a `u16`, so keys can be at most 32767 bytes long.
+pub const MAX_NIBBLES: usize = u16::MAX as usize;
+
+/// The maximum length of a key in bytes, i.e. `MAX_NIBBLES` in whole bytes.
+pub const MAX_KEY_LEN: usize = MAX_NIBBLES / 2;
+
 #[derive(Clone, PartialEq, Eq, Hash, JsonSchema)]
 pub struct NibblePath {
     pub num_nibbles: usize,
@@ -29,6 +40,12 @@ impl NibblePath {
         self.num_nibbles == 0
     }
 
+    /// Whether this path starts with the given one, e.g. `123` with `12`.
+    pub fn starts_with(&self, prefix: &NibblePath) -> bool {
+        prefix.num_nibbles <= self.num_nibbles
+            && (0..prefix.num_nibbles).all(|i| self.get_nibble(i) == prefix.get_nibble(i))
+    }
+
     pub fn child(&self, index: Nibble) -> Self {
         let mut nibble_path = self.clone();
         nibble_path.push(index);
@@ -64,25 +81,81 @@ impl NibblePath {
     }
 
     /// Return a new NibblePath whose length is at most `n`. Nibbles above `n`
-    /// are dropped. Only works if `n` < current length.
+    /// are dropped. Only works if `n` < current length; panics otherwise.
     pub fn crop(&self, n: usize) -> Self {
-        assert!(n < self.num_nibbles);
+        self.checked_crop(n).expect("cropped length must be smaller than current length")
+    }
+
+    /// Same as `crop`, but returns `None` instead of panicking if `n` is not
+    /// smaller than the current length.
+    pub fn checked_crop(&self, n: usize) -> Option<Self> {
+        if n >= self.num_nibbles {
+            return None;
+        }
 
         let mut bytes = self.bytes[..(n / 2)].to_vec();
         if n % 2 != 0 {
             bytes.push(self.bytes[n / 2] & 0xf0);
         }
 
-        Self {
+        Some(Self {
             num_nibbles: n,
             bytes,
-        }
+        })
     }
 
     // panics if index is out of range
     pub fn get_nibble(&self, i: usize) -> Nibble {
-        assert!(i < self.num_nibbles);
-        Nibble::new((self.bytes[i / 2] >> (if i % 2 == 1 { 0 } else { 4 })) & 0xf)
+        self.checked_get_nibble(i).expect("nibble index out of range")
+    }
+
+    /// Same as `get_nibble`, but returns `None` instead of panicking if the
+    /// index is out of range.
+    pub fn checked_get_nibble(&self, i: usize) -> Option<Nibble> {
+        if i >= self.num_nibbles {
+            return None;
+        }
+
+        // the value is masked to 4 bits so it's always a valid nibble
+        Some(Nibble::new((self.bytes[i / 2] >> (if i % 2 == 1 { 0 } else { 4 })) & 0xf))
+    }
+
+    /// The smallest nibble path greater than this one, which is this one
+    /// followed by a zero nibble, e.g. `12` => `120`. This turns an exclusive
+    /// lower bound into an inclusive one.
+    pub fn successor(&self) -> Self {
+        self.child(Nibble::new(0))
+    }
+
+    /// The next nibble path of the same length, treating the nibbles as the
+    /// digits of a base-16 number, e.g. `12ff` => `1300`. Returns `None` if
+    /// there isn't one, i.e. the path is empty or consists of only `f`s.
+    pub fn increment(&self) -> Option<Self> {
+        let mut nibble_path = self.prefix_upper_bound()?;
+        while nibble_path.num_nibbles < self.num_nibbles {
+            nibble_path.push(Nibble::new(0));
+        }
+        Some(nibble_path)
+    }
+
+    /// The smallest nibble path greater than all paths that have this one as a
+    /// prefix, e.g. `12ff` => `13`, which is the exclusive upper bound for
+    /// scanning the prefix. Returns `None` if there isn't one, i.e. the path is
+    /// empty or consists of only `f`s, in which case the scan is unbounded.
+    ///
+    /// For a path created from a key's bytes, the `bytes` of the result are an
+    /// exclusive upper bound of keys with the key as a prefix as well, as the
+    /// bytes of a path with an odd number of nibbles end in a zero nibble.
+    pub fn prefix_upper_bound(&self) -> Option<Self> {
+        let mut nibble_path = self.clone();
+        // drop trailing `f`s, which carry over into the nibble before them
+        while let Some(nibble) = nibble_path.pop() {
+            if nibble.byte() < 0xf {
+                nibble_path.push(Nibble::new(nibble.byte() + 1));
+                return Some(nibble_path);
+            }
+        }
+        None
     }
 
     pub fn nibbles(&self) -> NibbleIterator {
@@ -202,13 +275,14 @@ impl<'de> Visitor<'de> for NibblePathVisitor {
     }
 }
 
+#[cfg(feature = "storage")]
 impl KeyDeserialize for NibblePath {
     type Output = NibblePath;
 
     fn from_vec(value: Vec<u8>) -> StdResult<Self::Output> {
         ensure!(
-            !value.is_empty(),
-            StdError::parse_err(type_name::<Self::Output>(), "raw key must have at least 1 byte")
+            value.len() >= 2,
+            StdError::parse_err(type_name::<Self::Output>(), "raw key must have at least 2 bytes")
         );
 
         let num_nibbles = u16::from_be_bytes(value[..2].try_into().unwrap()) as usize;
@@ -280,3 +354,50 @@ impl<'a> NibbleIterator<'a> {
         self.visited_nibbles().chain(self.remaining_nibbles()).collect()
     }
 }
+
+// ----------------------------------- tests -----------------------------------
+
+#[cfg(test)]
+mod tests {
+    use {super::NibblePath, test_case::test_case};
+
+    fn nibble_path(hex_str: &str) -> NibblePath {
+        NibblePath::from_hex(hex_str.into()).unwrap()
+    }
+
+    #[test_case("", None, None; "empty")]
+    #[test_case("12", Some("13"), Some("13"); "no carry")]
+    #[test_case("1f", Some("20"), Some("2"); "carry across bytes")]
+    #[test_case("12f", Some("130"), Some("13"); "odd length")]
+    #[test_case("0fff", Some("1000"), Some("1"); "carry to the first nibble")]
+    #[test_case("f", None, None; "single f")]
+    #[test_case("ffff", None, None; "overflow")]
+    fn computing_bounds(hex_str: &str, increment: Option<&str>, upper_bound: Option<&str>) {
+        let path = nibble_path(hex_str);
+        assert_eq!(path.increment(), increment.map(nibble_path));
+        assert_eq!(path.prefix_upper_bound(), upper_bound.map(nibble_path));
+
+        // the successor immediately follows the path
+        let successor = path.successor();
+        assert!(path < successor);
+        assert_eq!(successor.to_hex(), format!("{hex_str}0"));
+
+        // the upper bound is greater than any extension of the path, and the
+        // extensions of the increment are all greater still
+        if let Some(upper_bound) = path.prefix_upper_bound() {
+            let extended = nibble_path(&format!("{hex_str}ffff"));
+            assert!(path < upper_bound && extended < upper_bound);
+            assert!(upper_bound <= path.increment().unwrap());
+        }
+    }
+
+    #[test]
+    fn bounding_key_prefixes() {
+        // the bytes of the upper bound bound keys with the prefix
+        for (prefix, bound) in [(&[0x1f, 0x2f][..], &[0x1f, 0x30][..]), (&[0x1f, 0xff], &[0x20])] {
+            let upper_bound = NibblePath::from(prefix).prefix_upper_bound().unwrap();
+            assert_eq!(upper_bound.bytes, bound);
+        }
+        assert_eq!(NibblePath::from([0xff, 0xff]).prefix_upper_bound(), None);
+    }
+}
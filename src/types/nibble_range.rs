@@ -1,13 +1,41 @@
 use crate::{Nibble, NibblePath};
 
-#[cfg_attr(test, derive(Debug, PartialEq))]
+/// A range of positions in a sorted list of nibble paths, all of which have
+/// the same nibble at the index being looked at. Yielded by
+/// `NibbleRangeIterator`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct NibbleRange {
+    /// The nibble that the nibble paths in this range have in common.
     pub nibble: Nibble,
+    /// The position of the first nibble path in the range.
     pub start: usize,
-    // end is inclusive
+    /// The position of the last nibble path in the range (inclusive).
     pub end: usize,
 }
 
+/// An item that comes with a nibble path, so that a list of them can be split
+/// into `NibbleRange`s.
+///
+/// This is implemented for nibble paths themselves, and for the
+/// `(NibblePath, K, V)` tuples of a prepared batch (see `prepare_batch`).
+/// Implement it on your own type to partition lists of it, e.g. in custom
+/// traversal code.
+pub trait HasNibblePath {
+    fn nibble_path(&self) -> &NibblePath;
+}
+
+impl HasNibblePath for NibblePath {
+    fn nibble_path(&self) -> &NibblePath {
+        self
+    }
+}
+
+impl<K, V> HasNibblePath for (NibblePath, K, V) {
+    fn nibble_path(&self) -> &NibblePath {
+        &self.0
+    }
+}
+
 /// Assume we have a list of nibble paths, which can be of variable lengths,
 /// ordered ascendingly (important!). For example:
 ///
@@ -27,17 +55,32 @@ pub struct NibbleRange {
 /// list, so pos = 4.
 ///
 /// If we were to iterate this list of nibble paths from pos = 0, we would
-/// iterate over two tuples:
+/// iterate over two ranges:
 ///
 /// - (0, 3)
 /// - (4, 5)
 ///
-/// The first one (0, 4) is the pos range of the nibble paths that have Nibble(0)
+/// The first one (0, 3) is the pos range of the nibble paths that have Nibble(0)
 /// on it's nibble_idx. Both 0 and 3 are inclusive (important!)
 ///
-/// Similarly, (4, 6) is the pos range of the nibble paths that have Nibble(1)
+/// Similarly, (4, 5) is the pos range of the nibble paths that have Nibble(1)
 /// on it's nibble_idx. Both 4 and 5 are inclusive (important!)
 ///
+/// The items can be anything with a nibble path (see `HasNibblePath`), and
+/// each nibble path must have more than `nibble_idx` nibbles. This is how
+/// `apply_subtree` splits a batch among the children of a node:
+///
+/// ```rust
+/// use tree::{Nibble, NibblePath, NibbleRange, NibbleRangeIterator};
+///
+/// let nibble_paths = [b"\x01\x23", b"\x01\x45", b"\x12\x34"].map(NibblePath::from);
+/// let ranges = NibbleRangeIterator::new(&nibble_paths, 1).collect::<Vec<_>>();
+/// assert_eq!(ranges, [
+///     NibbleRange { nibble: Nibble::new(1), start: 0, end: 1 },
+///     NibbleRange { nibble: Nibble::new(2), start: 2, end: 2 },
+/// ]);
+/// ```
+///
 /// This iterator type is adapted from Diem:
 /// https://github.com/diem/diem/blob/diem-core-v1.4.4/storage/jellyfish-merkle/src/lib.rs#L188
 /// which is open source under Apache-2.0 license.
@@ -45,17 +88,17 @@ pub struct NibbleRange {
 /// A difference is that Diem assumes all nibble paths are of the same length
 /// (because in Jellyfish Merkle Tree, the keys are hashed), while we do not
 /// make this assumption.
-pub struct NibbleRangeIterator<'a, K, V> {
+pub struct NibbleRangeIterator<'a, T> {
     // must be sorted by nibble path
-    batch: &'a [(NibblePath, K, V)],
+    batch: &'a [T],
     // which index in the nibble path we're looking at
     nibble_idx: usize,
     // which nibble path in the batch we're looking at
     pos: usize,
 }
 
-impl<'a, K, V> NibbleRangeIterator<'a, K, V> {
-    pub fn new(batch: &'a [(NibblePath, K, V)], nibble_idx: usize) -> Self {
+impl<'a, T: HasNibblePath> NibbleRangeIterator<'a, T> {
+    pub fn new(batch: &'a [T], nibble_idx: usize) -> Self {
         Self {
             batch,
             nibble_idx,
@@ -65,11 +108,11 @@ impl<'a, K, V> NibbleRangeIterator<'a, K, V> {
     }
 
     fn get_nibble(&self, pos: usize) -> Nibble {
-        self.batch[pos].0.get_nibble(self.nibble_idx)
+        self.batch[pos].nibble_path().get_nibble(self.nibble_idx)
     }
 }
 
-impl<'a, K, V> Iterator for NibbleRangeIterator<'a, K, V> {
+impl<'a, T: HasNibblePath> Iterator for NibbleRangeIterator<'a, T> {
     type Item = NibbleRange;
 
     fn next(&mut self) -> Option<Self::Item> {
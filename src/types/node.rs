@@ -1,18 +1,29 @@
 use {
-    crate::types::{hash_child, hash_data, Children, Hash, Nibble, NibblePath, Op},
-    blake3::Hasher,
-    cosmwasm_schema::cw_serde,
+    crate::types::{
+        hash_aggregate, hash_child, hash_data, Blake3, Children, Hash, HashableValue, Nibble,
+        NibblePath, Op, TreeHasher,
+    },
+    schemars::JsonSchema,
+    serde::{Deserialize, Serialize},
 };
+#[cfg(feature = "storage")]
+use crate::TreeError;
 
-#[cw_serde]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
 #[derive(Eq)]
 pub struct Child {
     pub index: Nibble,
     pub version: u64,
     pub hash: Hash,
+    /// The child node's `aggregate`, copied here so that it can be read without
+    /// loading the child node.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aggregate: Option<u128>,
 }
 
-#[cw_serde]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct Record<K, V> {
     pub key: K,
     pub value: V,
@@ -32,12 +43,18 @@ pub struct Record<K, V> {
 ///   long common substring, but this is unlikely as dataset gets bigger, so the
 ///   opimization is limited with the tradeoff of higher code complexity. We
 ///   consider it's not worth it. See a similar discussion in Diem's JMT paper.
-#[cw_serde]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
 #[derive(Default)]
 pub struct Node<K, V> {
     // TODO: replace this with BTreeMap<Nibble, Child> when possible
     pub children: Children,
     pub data: Option<Record<K, V>>,
+    /// Sum of the weights of all records in the node's subtree, including its
+    /// own data. Only tracked if the tree was created with `with_aggregation`
+    /// (or `with_subtree_counts`); otherwise `None`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aggregate: Option<u128>,
 }
 
 impl<K, V> Node<K, V> {
@@ -45,6 +62,7 @@ impl<K, V> Node<K, V> {
         Self {
             children: Children::new(vec![]),
             data: None,
+            aggregate: None,
         }
     }
 
@@ -52,13 +70,15 @@ impl<K, V> Node<K, V> {
         Self {
             children: children.into(),
             data: None,
+            aggregate: None,
         }
     }
 
     pub fn new_leaf(key: K, value: V) -> Self {
         Self {
             children: Children::new(vec![]),
-            data: Some(Record { key, value })
+            data: Some(Record { key, value }),
+            aggregate: None,
         }
     }
 
@@ -69,6 +89,27 @@ impl<K, V> Node<K, V> {
     pub fn is_empty(&self) -> bool {
         self.children.is_empty() && self.data.is_none()
     }
+
+    /// Compute the node's aggregate from the weight of its own data and the
+    /// aggregates of its children. `None` if any child's aggregate is missing.
+    /// Errors with `TreeError::AggregateOverflow` if the sum doesn't fit in a
+    /// u128.
+    #[cfg(feature = "storage")]
+    pub fn compute_aggregate(
+        &self,
+        weight: fn(&K, &V) -> u128,
+    ) -> Result<Option<u128>, TreeError> {
+        let mut aggregate =
+            self.data.as_ref().map_or(0, |Record { key, value }| weight(key, value));
+        for child in &self.children {
+            let Some(child_aggregate) = child.aggregate else {
+                return Ok(None);
+            };
+            aggregate =
+                aggregate.checked_add(child_aggregate).ok_or(TreeError::AggregateOverflow)?;
+        }
+        Ok(Some(aggregate))
+    }
 }
 
 impl<K, V> Node<K, V>
@@ -90,19 +131,30 @@ where
 impl<K, V> Node<K, V>
 where
     K: AsRef<[u8]>,
-    V: AsRef<[u8]>,
+    V: HashableValue,
 {
     /// Compute the node's hash, which is defined as:
     ///
-    /// hash(childA.index || childA.hash || ... || childZ.hash || childZ.value || len(key) || key || value)
+    /// hash(childA.index || childA.hash || ... || childZ.hash || childZ.value || len(key) || key || value || aggregate)
     ///
     /// where:
     /// - `||` means byte concatenation.
     /// - `child{A..Z}` are the node's children, ordered ascendingly by indexes.
     ///   Only children that exist are included.
     /// - `len()` returns a 16-bit (2 bytes) unsigned integer in big endian encoding.
+    /// - `aggregate` is a 128-bit (16 bytes) unsigned integer in big endian
+    ///   encoding, only included if the tree tracks aggregates. This commits
+    ///   the node to the aggregate, which can thus be proven.
+    ///
+    /// This uses blake3, the default hash function; see `hash_using` for others.
     pub fn hash(&self) -> Hash {
-        let mut hasher = Hasher::new();
+        self.hash_using::<Blake3>()
+    }
+
+    /// Compute the node's hash as defined in `hash`, using the given hash
+    /// function.
+    pub fn hash_using<H: TreeHasher>(&self) -> Hash {
+        let mut hasher = H::default();
 
         for child in &self.children {
             hash_child(&mut hasher, child);
@@ -112,6 +164,10 @@ where
             hash_data(&mut hasher, data)
         }
 
-        hasher.finalize().into()
+        if let Some(aggregate) = self.aggregate {
+            hash_aggregate(&mut hasher, aggregate);
+        }
+
+        hasher.finalize()
     }
 }
@@ -55,10 +55,8 @@ impl<'a> PrimaryKey<'a> for &'a NodeKey {
     fn key(&self) -> Vec<Key> {
         let mut key = vec![];
         key.extend(self.version.to_be_bytes());
-        // TODO: we should set a limit to nibble_path length so that num_nibbles
-        // is always smaller than u16::MAX = 65535
-        // this means keys must be no longer than 32767 bytes which should be
-        // more than enough
+        // num_nibbles fits in a u16 because keys longer than MAX_NIBBLES are
+        // rejected by `prepare_batch`
         key.extend((self.nibble_path.num_nibbles as u16).to_be_bytes());
         key.extend(self.nibble_path.bytes.as_slice());
         vec![Key::Owned(key)]
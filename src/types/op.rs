@@ -1,18 +1,37 @@
 use {
-    crate::Node,
-    cosmwasm_schema::cw_serde,
+    crate::{Hash, Node},
+    schemars::JsonSchema,
+    serde::{Deserialize, Serialize},
     std::collections::BTreeMap,
 };
+#[cfg(feature = "storage")]
+use cosmwasm_std::{to_vec, StdResult};
 
 pub type Batch<K, V> = BTreeMap<K, Op<V>>;
 
-#[cw_serde]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
 pub enum Op<V> {
     Insert(V),
     Delete,
 }
 
-#[cw_serde]
+#[cfg(feature = "storage")]
+impl Op<Vec<u8>> {
+    /// Insert a typed value into a tree of raw byte values, by serializing it.
+    /// Read it back using `Tree::get_typed`.
+    ///
+    /// The committed bytes are the value's JSON serialization produced by
+    /// `cosmwasm_std::to_vec`: fields in the order they are declared in, with
+    /// no whitespace. Clients that serialize the same logical value this way
+    /// produce identical bytes, and thus identical root hashes.
+    pub fn insert_typed<T: Serialize>(value: &T) -> StdResult<Self> {
+        to_vec(value).map(Op::Insert)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
 pub enum OpResponse<K, V> {
     /// The node's children and/or data have been changed. This signals to the
     /// node's parent that the hash needs to be recomputed.
@@ -24,3 +43,33 @@ pub enum OpResponse<K, V> {
     /// to be recomputed.
     Unchanged,
 }
+
+/// A record that has been changed by `Tree::apply`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Change<K> {
+    pub key: K,
+    /// Hash of the value before the change (see `value_hash`, and
+    /// `value_hash_using` for trees that don't use blake3), or `None` if the
+    /// key didn't exist
+    pub old_value_hash: Option<Hash>,
+    /// Hash of the value after the change, or `None` if the key is deleted
+    pub new_value_hash: Option<Hash>,
+}
+
+/// The records changed by `Tree::apply`, intended to be emitted as events by
+/// the host chain, so that downstream consumers of proofs can index which keys
+/// have changed in which version without replaying the batches.
+///
+/// Only values that actually changed are included; e.g. inserting a value that
+/// equals the existing one, or deleting a key that doesn't exist, is not a
+/// change.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ChangeSet<K> {
+    /// The latest version after the apply. If nothing changed, no new version
+    /// is created, so this is the version from before.
+    pub version: u64,
+    /// The changes, in the ascending order of the keys' raw bytes
+    pub changes: Vec<Change<K>>,
+}
@@ -1,7 +1,10 @@
 use {
-    crate::types::{hash_data, hash_proof_child, Children, Hash, Nibble, Node, Record},
-    blake3::Hasher,
-    cosmwasm_schema::cw_serde,
+    crate::types::{
+        hash_aggregate, hash_data, hash_proof_child, Blake3, Children, Hash, HashableValue, Nibble,
+        Node, Record, Sink, TreeHasher,
+    },
+    schemars::JsonSchema,
+    serde::{Deserialize, Serialize},
 };
 
 /// This can either be a membership or a non-membership proof.
@@ -13,9 +16,42 @@ use {
 /// child that would lead to the KV of interest if it existed, to the root.
 pub type Proof<K, V> = Vec<ProofNode<K, V>>;
 
+/// A proof of multiple keys at once, generated by `Tree::get_many`. It consists
+/// of the nodes on the paths from the root to each of the keys, in depth-first
+/// order starting from the root, where nodes shared by the paths are included
+/// only once, instead of once per key as in separate proofs.
+pub type BatchProof<K, V> = Vec<BatchProofNode<K, V>>;
+
+/// A node of a `BatchProof`. Unlike in a `Proof`, the node's data is kept, so
+/// that it can prove any of the keys. The children that are themselves part of
+/// the proof are left out, as their hashes are computed from the nodes that
+/// follow.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct BatchProofNode<K, V> {
+    pub node: ProofNode<K, V>,
+    /// Indexes of the children left out of the node, in ascending order
+    pub proven_children: Vec<Nibble>,
+}
+
+/// A proof against the root hash of a `MultiTree`. It consists of two levels:
+/// the store-level proof that the store's root hash is committed in the
+/// top-level tree (or that the store is empty), and the key-level proof within
+/// the store.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct MultiProof<K, V> {
+    /// None if the store is empty, in which case `store_proof` is a
+    /// non-membership proof, and `key_proof` is empty
+    pub store_root_hash: Option<Hash>,
+    pub store_proof: Proof<String, Hash>,
+    pub key_proof: Proof<K, V>,
+}
+
 /// ProofChild is like Child but simplified by removing the version. We don't
 /// need the version for proof because the version isn't merklized.
-#[cw_serde]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct ProofChild {
     pub index: Nibble,
     pub hash: Hash,
@@ -33,15 +69,24 @@ impl From<Children> for Vec<ProofChild> {
     }
 }
 
-/// ProofNode is like Node but simplified in three ways:
+/// ProofNode is like Node but simplified in three ways (the aggregate, if any,
+/// is kept as is):
 /// - contains ProofChild instead of Child
 /// - children doesn't need to include the child of interest, because it can be
 ///   inferred, and for the sake of reducing proof size, we leave it out
 /// - similarly, for membership proofs, the data does not need to be included.
-#[cw_serde]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct ProofNode<K, V> {
     pub children: Vec<ProofChild>,
     pub data: Option<Record<K, V>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aggregate: Option<u128>,
+    /// The version of the node, i.e. that of the link from its parent, if the
+    /// tree includes versions in proofs (see `Tree::with_versioned_proofs`).
+    /// Versions are not hashed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u64>,
 }
 
 impl<K, V> ProofNode<K, V> {
@@ -61,6 +106,15 @@ impl<K, V> ProofNode<K, V> {
         Self {
             children: node.children.into(),
             data: node.data,
+            aggregate: node.aggregate,
+            version: None,
+        }
+    }
+
+    pub fn with_version(self, version: u64) -> Self {
+        Self {
+            version: Some(version),
+            ..self
         }
     }
 
@@ -72,41 +126,162 @@ impl<K, V> ProofNode<K, V> {
 impl<K, V> ProofNode<K, V>
 where
     K: AsRef<[u8]>,
-    V: AsRef<[u8]>,
+    V: HashableValue,
 {
-    // TODO: refactor this code to make it less ugly??
-    pub fn hash(
+    /// Compute the hash of the node, as it would be computed for the `Node`
+    /// that this proof node was created from, provided nothing was dropped.
+    pub fn hash(&self) -> Hash {
+        self.hash_with(None, None)
+    }
+
+    /// Compute the hash of the node, with the following substitutions:
+    ///
+    /// - `child_override`: if provided, the child is hashed as one of the
+    ///   node's children, in the correct position by index. If the node
+    ///   already has a child at the same index, the override replaces it.
+    /// - `data_override`: if provided, the data is hashed in place of the
+    ///   node's data (if any).
+    ///
+    /// When verifying a proof, the child of interest or the data of interest
+    /// are dropped from the proof nodes; this puts them back. This can also be
+    /// used by outer commitment layers (e.g. a multistore whose leaves are the
+    /// root hashes of trees) to compose proofs.
+    pub fn hash_with(
         &self,
-        maybe_child: Option<&ProofChild>,
-        maybe_data: Option<&Record<K, V>>,
+        child_override: Option<&ProofChild>,
+        data_override: Option<&Record<K, V>>,
     ) -> Hash {
-        let mut hasher = Hasher::new();
-        let mut maybe_child_hashed = false;
+        self.hash_using::<Blake3>(child_override, data_override)
+    }
+
+    /// Same as `hash_with`, using the given hash function, for proofs of a
+    /// tree that doesn't use the default one.
+    pub fn hash_using<H: TreeHasher>(
+        &self,
+        child_override: Option<&ProofChild>,
+        data_override: Option<&Record<K, V>>,
+    ) -> Hash {
+        let mut hasher = H::default();
+        self.write_preimage(&mut hasher, child_override, data_override);
+        hasher.finalize()
+    }
+
+    /// Return the exact bytes that `hash_with` hashes, given the same
+    /// substitutions. This is for building and debugging external verifiers
+    /// (e.g. in circuits) against the crate's concatenation rules.
+    pub fn preimage_with(
+        &self,
+        child_override: Option<&ProofChild>,
+        data_override: Option<&Record<K, V>>,
+    ) -> Vec<u8> {
+        let mut preimage = vec![];
+        self.write_preimage(&mut preimage, child_override, data_override);
+        preimage
+    }
+
+    fn write_preimage<S: Sink>(
+        &self,
+        hasher: &mut S,
+        child_override: Option<&ProofChild>,
+        data_override: Option<&Record<K, V>>,
+    ) {
+        let mut child_override = child_override;
 
         for child in &self.children {
-            if let Some(c) = maybe_child {
-                if !maybe_child_hashed && c.index < child.index {
-                    hash_proof_child(&mut hasher, c);
-                    maybe_child_hashed = true;
+            if let Some(c) = child_override {
+                if c.index <= child.index {
+                    hash_proof_child(hasher, c);
+                    child_override = None;
+
+                    // the override replaces the existing child
+                    if c.index == child.index {
+                        continue;
+                    }
                 }
             }
 
-            hash_proof_child(&mut hasher, child)
+            hash_proof_child(hasher, child);
         }
 
-        if let Some(c) = maybe_child {
-            if !maybe_child_hashed {
-                hash_proof_child(&mut hasher, c);
-            }
+        // the override comes after all existing children
+        if let Some(c) = child_override {
+            hash_proof_child(hasher, c);
+        }
+
+        if let Some(data) = data_override.or(self.data.as_ref()) {
+            hash_data(hasher, data);
+        }
+
+        if let Some(aggregate) = self.aggregate {
+            hash_aggregate(hasher, aggregate);
+        }
+    }
+}
+
+// ----------------------------------- tests -----------------------------------
+
+#[cfg(test)]
+mod tests {
+    use crate::{Child, Hash, Nibble, Node, ProofChild, ProofNode, Record, HASH_LEN};
+
+    fn mock_node() -> Node<String, String> {
+        let mut node = Node::new_leaf("foo".to_string(), "bar".to_string());
+        for (index, byte) in [(1, 11), (5, 55), (9, 99)] {
+            node.children.insert(Child {
+                index: Nibble::new(index),
+                version: 1,
+                hash: Hash::from([byte; HASH_LEN]),
+                aggregate: None,
+            });
         }
+        node
+    }
+
+    #[test]
+    fn hashing_without_overrides() {
+        let node = mock_node();
+        assert_eq!(ProofNode::from_node(node.clone(), None, false).hash(), node.hash());
+    }
 
-        match (maybe_data, &self.data) {
-            (Some(d), None) | (Some(d), Some(_)) | (None, Some(d)) => {
-                hash_data(&mut hasher, d);
-            },
-            _ => (),
+    #[test]
+    fn hashing_with_dropped_child_and_data() {
+        let node = mock_node();
+
+        for index in [1, 5, 9] {
+            let index = Nibble::new(index);
+            let child = node.children.get(index).unwrap();
+            let child = ProofChild {
+                index,
+                hash: child.hash.clone(),
+            };
+            let data = node.data.clone().unwrap();
+
+            let proof_node = ProofNode::from_node(node.clone(), Some(index), true);
+            assert_eq!(proof_node.hash_with(Some(&child), Some(&data)), node.hash());
         }
+    }
+
+    #[test]
+    fn overriding_existing_child_and_data() {
+        let node = mock_node();
+        let proof_node = ProofNode::from_node(node, None, false);
+
+        let mut expected = mock_node();
+        expected.children.insert(Child {
+            index: Nibble::new(5),
+            version: 1,
+            hash: Hash::from([0; HASH_LEN]),
+            aggregate: None,
+        });
+        expected.data = Some(Record {
+            key: "fuzz".into(),
+            value: "buzz".into(),
+        });
 
-        hasher.finalize().into()
+        let child = ProofChild {
+            index: Nibble::new(5),
+            hash: Hash::from([0; HASH_LEN]),
+        };
+        assert_eq!(proof_node.hash_with(Some(&child), expected.data.as_ref()), expected.hash());
     }
 }
@@ -1,5 +1,5 @@
 use {
-    crate::Hash,
+    crate::{Hash, Nibble, NibblePath},
     cosmwasm_schema::cw_serde,
     cosmwasm_std::Binary,
 };
@@ -21,6 +21,135 @@ pub struct GetResponse<K, V> {
     pub proof: Option<Binary>,
 }
 
+/// A query to be answered by `prove_queries`.
+#[cw_serde]
+pub enum ProverRequest<K> {
+    /// Query the value of a single key
+    Get {
+        key: K,
+        prove: bool,
+    },
+    /// Query the key-value pairs in a range, in ascending order. `min` is
+    /// inclusive and `max` is exclusive.
+    Range {
+        min: Option<K>,
+        max: Option<K>,
+        limit: Option<u32>,
+        prove: bool,
+    },
+}
+
+#[cw_serde]
+pub struct GetManyResponse<K, V> {
+    /// The requested keys along with their values, None if not found, in
+    /// ascending order of keys, without duplicates
+    pub records: Vec<(K, Option<V>)>,
+    /// The `BatchProof` in binary; None if proof is not requested, or if the
+    /// tree is empty
+    pub proof: Option<Binary>,
+}
+
+#[cw_serde]
+pub struct MultiGetResponse<K, V> {
+    pub key: K,
+    /// None if not found
+    pub value: Option<V>,
+    /// The `MultiProof` in binary; None if proof is not requested
+    pub proof: Option<Binary>,
+}
+
+#[cw_serde]
+pub struct AggregateResponse {
+    /// Total weight of all keys that start with the prefix
+    pub total: u128,
+    /// None if proof is not requested
+    pub proof: Option<Binary>,
+}
+
+#[cw_serde]
+pub struct SubtreeRootResponse {
+    /// The hash of the subtree holding exactly the keys under the prefix, which
+    /// is that of the node at the prefix, or of a leaf above it if that leaf
+    /// holds the only such key. None if no key is under the prefix
+    pub hash: Option<Hash>,
+    /// The `Proof` in binary, to be verified with `verify_subtree`
+    pub proof: Binary,
+}
+
+#[cw_serde]
+pub struct IterateResponse<K, V> {
+    pub items: Vec<(K, V)>,
+    /// Why the iteration stopped. Unless it's `Exhausted`, there may be more
+    /// items after the last one returned.
+    pub stop_reason: StopReason,
+    /// The position after the last item returned, from which the iteration
+    /// can be continued using `Tree::resume_iterate_limited`. None if
+    /// `Exhausted`
+    pub cursor: Option<IteratorCheckpoint>,
+}
+
+#[cw_serde]
+pub enum StopReason {
+    /// All items within the bounds have been returned
+    Exhausted,
+    /// The number of items has reached `max_items`
+    MaxItems,
+    /// Including the next item would have exceeded `max_bytes`
+    MaxBytes,
+}
+
+/// The position of a `TreeIterator`, from which the iteration can be resumed
+/// using `Tree::resume_iterate`. It can be persisted, so that a long-running
+/// scan of the tree can be resumed after a restart.
+///
+/// The checkpoint references nodes of the version being iterated, so it
+/// becomes invalid once that version is pruned.
+#[cw_serde]
+pub struct IteratorCheckpoint {
+    pub(crate) version: u64,
+    pub(crate) ascending: bool,
+    pub(crate) min: Option<NibblePath>,
+    pub(crate) max: Option<NibblePath>,
+    /// The nibble path of the deepest node on the iterator's stack
+    pub(crate) visited_nibbles: NibblePath,
+    /// The index of the child of that node visited last, if any
+    pub(crate) start_after_index: Option<Nibble>,
+    pub(crate) started: bool,
+    pub(crate) finished: bool,
+}
+
+impl IteratorCheckpoint {
+    /// The version of the tree being iterated.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+}
+
+/// Cumulative counters of a tree's activity since `Tree::with_counters` was
+/// enabled.
+#[cw_serde]
+#[derive(Default)]
+pub struct Counters {
+    /// The number of applies that committed a new version
+    pub applies: u64,
+    /// The number of nodes written by those applies
+    pub nodes_written: u64,
+    /// The number of orphaned nodes deleted by pruning
+    pub orphans_pruned: u64,
+}
+
+/// How many orphaned nodes are pending deletion. Returned by
+/// `Tree::orphan_count`.
+#[cw_serde]
+pub struct PruneStatus {
+    /// The number of orphaned nodes that `prune` would delete, given the same
+    /// `up_to_version`
+    pub prunable: u64,
+    /// The number of orphaned nodes in total, including those of epochs that
+    /// haven't ended yet
+    pub orphans: u64,
+}
+
 #[cfg(feature = "debug")]
 #[cw_serde]
 pub struct NodeResponse<K, V> {
@@ -29,9 +158,21 @@ pub struct NodeResponse<K, V> {
     pub hash: Hash,
 }
 
+#[cfg(feature = "debug")]
+#[cw_serde]
+pub struct PathNodeResponse {
+    pub node_key: NodeKey,
+    pub hash: Hash,
+    /// Whether the node was created in an earlier version, and thus is shared
+    /// with the previous version; false if it's newly created in this version
+    pub shared: bool,
+}
+
 #[cfg(feature = "debug")]
 #[cw_serde]
 pub struct OrphanResponse {
     pub node_key: NodeKey,
+    /// If orphans are grouped into epochs, this is the first version of the
+    /// epoch during which the node became orphaned
     pub since_version: u64,
 }
@@ -1,4 +1,13 @@
-use crate::{Hash, NibblePath, Proof, ProofChild, Record};
+use crate::{
+    domain_root_hash_using, BatchProof, BatchProofNode, Blake3, Hash, HashableValue, MultiProof,
+    Nibble, NibblePath, Proof, ProofChild, ProofNode, Record, TreeHasher,
+};
+#[cfg(feature = "storage")]
+use {
+    crate::{decode_proof, GetManyResponse, GetResponse, VersionHeader},
+    cosmwasm_std::{from_binary, StdError},
+    serde::{de::DeserializeOwned, ser::Serialize},
+};
 
 pub fn verify_membership<K, V>(
     root_hash: &Hash,
@@ -8,7 +17,176 @@ pub fn verify_membership<K, V>(
 ) -> Result<()>
 where
     K: Clone + AsRef<[u8]>,
-    V: Clone + AsRef<[u8]>,
+    V: Clone + HashableValue,
+{
+    verify_membership_using::<Blake3, _, _>(None, root_hash, key, value, proof)
+}
+
+/// Same as `verify_membership`, but for a tree created with a domain tag using
+/// `Tree::with_domain`.
+pub fn verify_membership_in_domain<K, V>(
+    domain: &[u8],
+    root_hash: &Hash,
+    key: &K,
+    value: &V,
+    proof: &Proof<K, V>,
+) -> Result<()>
+where
+    K: Clone + AsRef<[u8]>,
+    V: Clone + HashableValue,
+{
+    verify_membership_using::<Blake3, _, _>(Some(domain), root_hash, key, value, proof)
+}
+
+pub fn verify_non_membership<K, V>(
+    root_hash: &Hash,
+    key: &K,
+    proof: &Proof<K, V>,
+) -> Result<()>
+where
+    K: AsRef<[u8]> + PartialEq,
+    V: HashableValue,
+{
+    verify_non_membership_using::<Blake3, _, _>(None, root_hash, key, proof)
+}
+
+/// Same as `verify_non_membership`, but for a tree created with a domain tag
+/// using `Tree::with_domain`.
+pub fn verify_non_membership_in_domain<K, V>(
+    domain: &[u8],
+    root_hash: &Hash,
+    key: &K,
+    proof: &Proof<K, V>,
+) -> Result<()>
+where
+    K: AsRef<[u8]> + PartialEq,
+    V: HashableValue,
+{
+    verify_non_membership_using::<Blake3, _, _>(Some(domain), root_hash, key, proof)
+}
+
+/// Verify a `GetResponse` read from another contract's tree, e.g. relayed by a
+/// user into this contract's execution, against a root hash that this
+/// contract trusts, e.g. queried from the other contract or pushed by it. The
+/// response must include a proof, in either encoding (see `ProofEncoding`).
+///
+/// Returns the value if the proof shows that the key exists, or `None` if it
+/// shows that the key doesn't exist. Errors convert into `StdError`, so they
+/// can be returned from contract entry points using `?`.
+#[cfg(feature = "storage")]
+pub fn verify_get_response<'r, K, V>(
+    root_hash: &Hash,
+    res: &'r GetResponse<K, V>,
+) -> Result<Option<&'r V>>
+where
+    K: Clone + AsRef<[u8]> + PartialEq + Serialize + DeserializeOwned,
+    V: Clone + HashableValue + Serialize + DeserializeOwned,
+{
+    verify_get_response_using::<Blake3, _, _>(None, root_hash, res)
+}
+
+/// Same as `verify_get_response`, but for a tree created with a domain tag
+/// using `Tree::with_domain`.
+#[cfg(feature = "storage")]
+pub fn verify_get_response_in_domain<'r, K, V>(
+    domain: &[u8],
+    root_hash: &Hash,
+    res: &'r GetResponse<K, V>,
+) -> Result<Option<&'r V>>
+where
+    K: Clone + AsRef<[u8]> + PartialEq + Serialize + DeserializeOwned,
+    V: Clone + HashableValue + Serialize + DeserializeOwned,
+{
+    verify_get_response_using::<Blake3, _, _>(Some(domain), root_hash, res)
+}
+
+/// Same as `verify_get_response`, but for a tree that uses the given hash
+/// function (see `TreeHasher`), and with a domain tag if there is one.
+#[cfg(feature = "storage")]
+pub fn verify_get_response_using<'r, H, K, V>(
+    domain: Option<&[u8]>,
+    root_hash: &Hash,
+    res: &'r GetResponse<K, V>,
+) -> Result<Option<&'r V>>
+where
+    H: TreeHasher,
+    K: Clone + AsRef<[u8]> + PartialEq + Serialize + DeserializeOwned,
+    V: Clone + HashableValue + Serialize + DeserializeOwned,
+{
+    let proof = res.proof.as_ref().ok_or(VerificationError::ProofMissing)?;
+    let proof: Proof<K, V> = decode_proof(proof)?;
+
+    match &res.value {
+        Some(value) => {
+            verify_membership_using::<H, _, _>(domain, root_hash, &res.key, value, &proof)?
+        },
+        None => verify_non_membership_using::<H, _, _>(domain, root_hash, &res.key, &proof)?,
+    }
+
+    Ok(res.value.as_ref())
+}
+
+/// Same as `verify_membership`, but for a tree that uses the given hash
+/// function (see `TreeHasher`), and with a domain tag if there is one.
+pub fn verify_membership_using<H, K, V>(
+    domain: Option<&[u8]>,
+    root_hash: &Hash,
+    key: &K,
+    value: &V,
+    proof: &Proof<K, V>,
+) -> Result<()>
+where
+    H: TreeHasher,
+    K: Clone + AsRef<[u8]>,
+    V: Clone + HashableValue,
+{
+    let hash = compute_root_from_membership_using::<H, _, _>(key, value, proof)?;
+    check_root_hash::<H>(domain, root_hash, hash)
+}
+
+/// Same as `verify_non_membership`, but for a tree that uses the given hash
+/// function (see `TreeHasher`), and with a domain tag if there is one.
+pub fn verify_non_membership_using<H, K, V>(
+    domain: Option<&[u8]>,
+    root_hash: &Hash,
+    key: &K,
+    proof: &Proof<K, V>,
+) -> Result<()>
+where
+    H: TreeHasher,
+    K: AsRef<[u8]> + PartialEq,
+    V: HashableValue,
+{
+    let hash = compute_root_from_non_membership_using::<H, _, _>(key, proof)?;
+    check_root_hash::<H>(domain, root_hash, hash)
+}
+
+/// Compute the root hash that a membership proof implies, i.e. the root hash of
+/// the tree if the key-value pair indeed exists in it. This is useful if the
+/// trusted root hash is not yet known when the proof is collected; compare the
+/// result against it later.
+///
+/// For a tree created with a domain tag, the root hash is the result tagged
+/// using `domain_root_hash`.
+pub fn compute_root_from_membership<K, V>(key: &K, value: &V, proof: &Proof<K, V>) -> Result<Hash>
+where
+    K: Clone + AsRef<[u8]>,
+    V: Clone + HashableValue,
+{
+    compute_root_from_membership_using::<Blake3, _, _>(key, value, proof)
+}
+
+/// Same as `compute_root_from_membership`, for a tree that uses the given hash
+/// function.
+pub fn compute_root_from_membership_using<H, K, V>(
+    key: &K,
+    value: &V,
+    proof: &Proof<K, V>,
+) -> Result<Hash>
+where
+    H: TreeHasher,
+    K: Clone + AsRef<[u8]>,
+    V: Clone + HashableValue,
 {
     let nibble_path = NibblePath::from(key);
 
@@ -19,19 +197,29 @@ where
         key: key.clone(),
         value: value.clone(),
     };
-    let hash = node.hash(None, Some(&data));
+    let hash = node.hash_using::<H>(None, Some(&data));
 
-    compute_and_check_root_hash(root_hash, proof, nibble_path, hash)
+    compute_root_hash::<H, _, _>(proof, nibble_path, hash)
 }
 
-pub fn verify_non_membership<K, V>(
-    root_hash: &Hash,
-    key: &K,
-    proof: &Proof<K, V>,
-) -> Result<()>
+/// Compute the root hash that a non-membership proof implies. Errors if the
+/// proof doesn't show the key's absence, regardless of the root hash. See
+/// `compute_root_from_membership`.
+pub fn compute_root_from_non_membership<K, V>(key: &K, proof: &Proof<K, V>) -> Result<Hash>
+where
+    K: AsRef<[u8]> + PartialEq,
+    V: HashableValue,
+{
+    compute_root_from_non_membership_using::<Blake3, _, _>(key, proof)
+}
+
+/// Same as `compute_root_from_non_membership`, for a tree that uses the given
+/// hash function.
+pub fn compute_root_from_non_membership_using<H, K, V>(key: &K, proof: &Proof<K, V>) -> Result<Hash>
 where
+    H: TreeHasher,
     K: AsRef<[u8]> + PartialEq,
-    V: AsRef<[u8]>,
+    V: HashableValue,
 {
     let proof_len = proof.len();
     let nibble_path = NibblePath::from(key);
@@ -54,37 +242,516 @@ where
         }
     }
 
-    let hash = node.hash(None, None);
+    let hash = node.hash_using::<H>(None, None);
+
+    compute_root_hash::<H, _, _>(proof, nibble_path, hash)
+}
+
+/// Verify that each key has the given value, or doesn't exist if the value is
+/// `None`, given a proof generated by `Tree::get_many`. The proof may cover
+/// keys other than the given ones.
+pub fn verify_batch_membership<K, V>(
+    root_hash: &Hash,
+    records: &[(K, Option<V>)],
+    proof: &BatchProof<K, V>,
+) -> Result<()>
+where
+    K: Clone + AsRef<[u8]>,
+    V: Clone + HashableValue + PartialEq,
+{
+    verify_batch_membership_using::<Blake3, _, _>(None, root_hash, records, proof)
+}
+
+/// Same as `verify_batch_membership`, but for a tree created with a domain tag
+/// using `Tree::with_domain`.
+pub fn verify_batch_membership_in_domain<K, V>(
+    domain: &[u8],
+    root_hash: &Hash,
+    records: &[(K, Option<V>)],
+    proof: &BatchProof<K, V>,
+) -> Result<()>
+where
+    K: Clone + AsRef<[u8]>,
+    V: Clone + HashableValue + PartialEq,
+{
+    verify_batch_membership_using::<Blake3, _, _>(Some(domain), root_hash, records, proof)
+}
+
+/// Verify a `GetManyResponse`, as `verify_get_response` does a `GetResponse`.
+/// The response must include a proof.
+#[cfg(feature = "storage")]
+pub fn verify_get_many_response<K, V>(root_hash: &Hash, res: &GetManyResponse<K, V>) -> Result<()>
+where
+    K: Clone + AsRef<[u8]> + DeserializeOwned,
+    V: Clone + HashableValue + PartialEq + DeserializeOwned,
+{
+    let proof = res.proof.as_ref().ok_or(VerificationError::ProofMissing)?;
+    let proof: BatchProof<K, V> = from_binary(proof)?;
+    verify_batch_membership_using::<Blake3, _, _>(None, root_hash, &res.records, &proof)
+}
+
+/// Same as `verify_batch_membership`, but for a tree that uses the given hash
+/// function (see `TreeHasher`), and with a domain tag if there is one.
+pub fn verify_batch_membership_using<H, K, V>(
+    domain: Option<&[u8]>,
+    root_hash: &Hash,
+    records: &[(K, Option<V>)],
+    proof: &BatchProof<K, V>,
+) -> Result<()>
+where
+    H: TreeHasher,
+    K: Clone + AsRef<[u8]>,
+    V: Clone + HashableValue + PartialEq,
+{
+    if proof.is_empty() {
+        return Err(VerificationError::ProofEmpty);
+    }
+
+    // hash the nodes from the last one to the first, so that the subtrees of a
+    // node's proven children have been hashed by the time it's reached. the
+    // stack holds the hashes and positions of the subtrees whose parents
+    // haven't been reached yet, the first child's being on top
+    let mut subtrees: Vec<(Hash, usize)> = vec![];
+    let mut proven_children = vec![vec![]; proof.len()];
+    for (position, proof_node) in proof.iter().enumerate().rev() {
+        let BatchProofNode {
+            node,
+            proven_children: indexes,
+        } = proof_node;
+        let mut children = node.children.clone();
+        for index in indexes {
+            let (hash, child_position) = subtrees.pop().ok_or(VerificationError::ProofMalformed)?;
+            children.push(ProofChild {
+                index: *index,
+                hash,
+            });
+            proven_children[position].push((*index, child_position));
+        }
+
+        children.sort_by_key(|child| child.index);
+        if children.windows(2).any(|pair| pair[0].index == pair[1].index) {
+            return Err(VerificationError::UnexpectedChild);
+        }
+
+        let node = ProofNode {
+            children,
+            data: node.data.clone(),
+            aggregate: node.aggregate,
+            version: None,
+        };
+        subtrees.push((node.hash_using::<H>(None, None), position));
+    }
+
+    // all nodes must be part of the root's subtree
+    let [(hash, _)] = &subtrees[..] else {
+        return Err(VerificationError::ProofMalformed);
+    };
+    check_root_hash::<H>(domain, root_hash, hash.clone())?;
+
+    for (key, value) in records {
+        let proven = find_in_batch_proof(proof, &proven_children, key)?;
+        match (proven, value) {
+            (Some(proven), Some(value)) if proven != value => {
+                return Err(VerificationError::ValueMismatch);
+            },
+            (Some(_), None) => return Err(VerificationError::KeyExists),
+            (None, Some(_)) => return Err(VerificationError::KeyMissing),
+            _ => (),
+        }
+    }
+
+    Ok(())
+}
+
+// the value of the key according to a batch proof whose root hash is verified,
+// following the path of the key from the root, or `None` if the proof shows
+// that it doesn't exist
+fn find_in_batch_proof<'p, K, V>(
+    proof: &'p BatchProof<K, V>,
+    proven_children: &[Vec<(Nibble, usize)>],
+    key: &K,
+) -> Result<Option<&'p V>>
+where
+    K: AsRef<[u8]>,
+{
+    let nibble_path = NibblePath::from(key);
+    let mut position = 0;
+    let mut depth = 0;
+    loop {
+        let node = &proof[position].node;
+        if let Some(data) = &node.data {
+            if data.key.as_ref() == key.as_ref() {
+                return Ok(Some(&data.value));
+            }
+        }
+
+        // the key would be held by this node if it existed
+        let Some(index) = nibble_path.checked_get_nibble(depth) else {
+            return Ok(None);
+        };
+
+        let proven_child = proven_children[position].iter().find(|(i, _)| *i == index);
+        if let Some((_, child_position)) = proven_child {
+            position = *child_position;
+            depth += 1;
+            continue;
+        }
+
+        // the child that would hold the key exists, but isn't in the proof
+        if node.has_child_at_index(index) {
+            return Err(VerificationError::UnexpectedChild);
+        }
+
+        return Ok(None);
+    }
+}
+
+/// Verify that a key-value pair exists in the given store of a `MultiTree`,
+/// given a proof generated by `MultiTree::get`. The `MultiTree` must use the
+/// default hash function.
+pub fn verify_multi_membership<K, V>(
+    root_hash: &Hash,
+    store_name: &str,
+    key: &K,
+    value: &V,
+    proof: &MultiProof<K, V>,
+) -> Result<()>
+where
+    K: Clone + AsRef<[u8]>,
+    V: Clone + HashableValue,
+{
+    let store_root_hash = proof.store_root_hash.as_ref().ok_or(VerificationError::StoreEmpty)?;
+
+    verify_membership(store_root_hash, key, value, &proof.key_proof)?;
+    verify_membership(root_hash, &store_name.to_string(), store_root_hash, &proof.store_proof)
+}
+
+/// Verify that a key doesn't exist in the given store of a `MultiTree`, given
+/// a proof generated by `MultiTree::get`. The `MultiTree` must use the default
+/// hash function.
+pub fn verify_multi_non_membership<K, V>(
+    root_hash: &Hash,
+    store_name: &str,
+    key: &K,
+    proof: &MultiProof<K, V>,
+) -> Result<()>
+where
+    K: AsRef<[u8]> + PartialEq,
+    V: HashableValue,
+{
+    let store_name = store_name.to_string();
+
+    // if the store is empty, it suffices to prove it's not in the top-level tree
+    let Some(store_root_hash) = &proof.store_root_hash else {
+        return verify_non_membership(root_hash, &store_name, &proof.store_proof);
+    };
+
+    verify_non_membership(store_root_hash, key, &proof.key_proof)?;
+    verify_membership(root_hash, &store_name, store_root_hash, &proof.store_proof)
+}
+
+/// Verify that the total weight of all keys that start with `prefix` is
+/// `total`, given a proof generated by `Tree::aggregate`.
+pub fn verify_aggregate<K, V>(
+    root_hash: &Hash,
+    prefix: &[u8],
+    total: u128,
+    proof: &Proof<K, V>,
+) -> Result<()>
+where
+    K: AsRef<[u8]>,
+    V: HashableValue,
+{
+    verify_aggregate_using::<Blake3, _, _>(None, root_hash, prefix, total, proof)
+}
+
+/// Same as `verify_aggregate`, but for a tree created with a domain tag using
+/// `Tree::with_domain`.
+pub fn verify_aggregate_in_domain<K, V>(
+    domain: &[u8],
+    root_hash: &Hash,
+    prefix: &[u8],
+    total: u128,
+    proof: &Proof<K, V>,
+) -> Result<()>
+where
+    K: AsRef<[u8]>,
+    V: HashableValue,
+{
+    verify_aggregate_using::<Blake3, _, _>(Some(domain), root_hash, prefix, total, proof)
+}
+
+/// Same as `verify_aggregate`, but for a tree that uses the given hash function
+/// (see `TreeHasher`), and with a domain tag if there is one.
+pub fn verify_aggregate_using<H, K, V>(
+    domain: Option<&[u8]>,
+    root_hash: &Hash,
+    prefix: &[u8],
+    total: u128,
+    proof: &Proof<K, V>,
+) -> Result<()>
+where
+    H: TreeHasher,
+    K: AsRef<[u8]>,
+    V: HashableValue,
+{
+    let nibble_path = NibblePath::from(prefix);
+
+    let Some(node) = proof.first() else {
+        return Err(VerificationError::ProofEmpty);
+    };
+
+    // the depth of the first node in the proof
+    let depth = proof.len() - 1;
+    if depth > nibble_path.num_nibbles {
+        return Err(VerificationError::ProofTooLong);
+    }
+
+    // the first node can be one of three:
+    // - the node whose subtree contains exactly the keys with the prefix, in
+    //   which case the total is that node's aggregate;
+    // - a leaf above that, in which case the total is either the leaf's
+    //   aggregate or zero, depending on whether the leaf's key has the prefix;
+    // - a node above that, which lacks the child that would lead to the keys
+    //   with the prefix, in which case the total is zero.
+    let proven = if depth == nibble_path.num_nibbles {
+        node.aggregate.ok_or(VerificationError::AggregateMissing)?
+    } else if node.children.is_empty() {
+        match &node.data {
+            Some(data) if data.key.as_ref().starts_with(prefix) => {
+                node.aggregate.ok_or(VerificationError::AggregateMissing)?
+            },
+            _ => 0,
+        }
+    } else if node.has_child_at_index(nibble_path.get_nibble(depth)) {
+        return Err(VerificationError::UnexpectedChild);
+    } else {
+        0
+    };
+
+    if proven != total {
+        return Err(VerificationError::AggregateMismatch {
+            given: total,
+            proven,
+        });
+    }
+
+    let hash = node.hash_using::<H>(None, None);
+    let hash = compute_root_hash::<H, _, _>(proof, nibble_path, hash)?;
+    check_root_hash::<H>(domain, root_hash, hash)
+}
 
-    compute_and_check_root_hash(root_hash, proof, nibble_path, hash)
+/// Verify a proof returned by `Tree::subtree_root`, i.e. that the subtree of
+/// the tree with the given root hash that holds the keys under the nibble
+/// prefix has the given hash, or that no key is under the prefix if `None`.
+pub fn verify_subtree<K, V>(
+    root_hash: &Hash,
+    prefix: &NibblePath,
+    subtree_hash: Option<&Hash>,
+    proof: &Proof<K, V>,
+) -> Result<()>
+where
+    K: AsRef<[u8]>,
+    V: HashableValue,
+{
+    verify_subtree_using::<Blake3, _, _>(None, root_hash, prefix, subtree_hash, proof)
 }
 
-fn compute_and_check_root_hash<K, V>(
+/// Same as `verify_subtree`, but for a tree created with a domain tag using
+/// `Tree::with_domain`.
+pub fn verify_subtree_in_domain<K, V>(
+    domain: &[u8],
     root_hash: &Hash,
+    prefix: &NibblePath,
+    subtree_hash: Option<&Hash>,
+    proof: &Proof<K, V>,
+) -> Result<()>
+where
+    K: AsRef<[u8]>,
+    V: HashableValue,
+{
+    verify_subtree_using::<Blake3, _, _>(Some(domain), root_hash, prefix, subtree_hash, proof)
+}
+
+/// Same as `verify_subtree`, but for a tree that uses the given hash function
+/// (see `TreeHasher`), and with a domain tag if there is one.
+pub fn verify_subtree_using<H, K, V>(
+    domain: Option<&[u8]>,
+    root_hash: &Hash,
+    prefix: &NibblePath,
+    subtree_hash: Option<&Hash>,
+    proof: &Proof<K, V>,
+) -> Result<()>
+where
+    H: TreeHasher,
+    K: AsRef<[u8]>,
+    V: HashableValue,
+{
+    let Some(node) = proof.first() else {
+        return Err(VerificationError::ProofEmpty);
+    };
+
+    // the depth of the first node in the proof
+    let depth = proof.len() - 1;
+    if depth > prefix.num_nibbles {
+        return Err(VerificationError::ProofTooLong);
+    }
+
+    // as in `verify_aggregate`, the first node is either the node at the
+    // prefix, a leaf above it, or a node above it that lacks the child that
+    // would lead to the prefix
+    let hash = node.hash_using::<H>(None, None);
+    let proven = if depth == prefix.num_nibbles {
+        Some(hash.clone())
+    } else if node.children.is_empty() {
+        match &node.data {
+            Some(data) if NibblePath::from(&data.key).starts_with(prefix) => Some(hash.clone()),
+            _ => None,
+        }
+    } else if node.has_child_at_index(prefix.get_nibble(depth)) {
+        return Err(VerificationError::UnexpectedChild);
+    } else {
+        None
+    };
+
+    if proven.as_ref() != subtree_hash {
+        return Err(VerificationError::SubtreeMismatch {
+            given: subtree_hash.cloned(),
+            proven,
+        });
+    }
+
+    let hash = compute_root_hash::<H, _, _>(proof, prefix.clone(), hash)?;
+    check_root_hash::<H>(domain, root_hash, hash)
+}
+
+/// Return the exact bytes that are hashed at each level when verifying the
+/// proof, in order from the node containing the key up to the root. The hash
+/// of each preimage is the child hash contained in the next one; the hash of
+/// the last one is the root hash.
+///
+/// Provide the value to get the preimages of a membership proof, or `None` for
+/// a non-membership proof. The proof is not checked for validity here.
+pub fn proof_preimages<K, V>(
+    key: &K,
+    value: Option<&V>,
+    proof: &Proof<K, V>,
+) -> Result<Vec<Vec<u8>>>
+where
+    K: Clone + AsRef<[u8]>,
+    V: Clone + HashableValue,
+{
+    let nibble_path = NibblePath::from(key);
+    let proof_len = proof.len();
+
+    let node = proof.first().ok_or(VerificationError::ProofEmpty)?;
+    let data = value.map(|value| Record {
+        key: key.clone(),
+        value: value.clone(),
+    });
+    let mut preimages = vec![node.preimage_with(None, data.as_ref())];
+
+    for i in 1..proof_len {
+        let child = ProofChild {
+            index: nibble_path
+                .checked_get_nibble(proof_len - i - 1)
+                .ok_or(VerificationError::ProofTooLong)?,
+            hash: blake3::hash(&preimages[i - 1]).into(),
+        };
+        preimages.push(proof[i].preimage_with(Some(&child), None));
+    }
+
+    Ok(preimages)
+}
+
+/// Check that the versions in a proof (see `Tree::with_versioned_proofs`) don't
+/// increase from the root down. A node is written at the same version as its
+/// parent or earlier, so a child newer than its parent indicates a bug, e.g. a
+/// stale link to a node that has since been rewritten.
+///
+/// Versions are not committed to by the root hash, so this is a consistency
+/// check for debugging implementations, not a security check.
+pub fn verify_proof_versions<K, V>(proof: &Proof<K, V>) -> Result<()> {
+    let versions = proof
+        .iter()
+        .map(|node| node.version.ok_or(VerificationError::VersionMissing))
+        .collect::<Result<Vec<_>>>()?;
+
+    // the proof starts from the deepest node, and ends at the root
+    for pair in versions.windows(2) {
+        let (child, parent) = (pair[0], pair[1]);
+        if child > parent {
+            return Err(VerificationError::VersionIncreasing {
+                parent,
+                child,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Check the continuity of consecutive version headers, e.g. those exported by
+/// `Tree::version_headers` (see `Tree::with_version_headers`): each header
+/// must include the version and the root hash of the one before it.
+///
+/// This detects headers that have been altered, inserted or removed, provided
+/// that the root hash of the last version is known from a trusted source.
+#[cfg(feature = "storage")]
+pub fn verify_version_chain(headers: &[VersionHeader]) -> Result<()> {
+    for pair in headers.windows(2) {
+        let (prev, header) = (&pair[0], &pair[1]);
+        if header.prev_version != prev.version || header.prev_root_hash != prev.root_hash {
+            return Err(VerificationError::VersionChainBroken {
+                version: header.version,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+// starting from the hash of the first node in the proof, traverse up the tree
+// and compute the hash of each node. eventually we should reach the root
+fn compute_root_hash<H, K, V>(
     proof: &Proof<K, V>,
     nibble_path: NibblePath,
     mut hash: Hash,
-) -> Result<()>
+) -> Result<Hash>
 where
+    H: TreeHasher,
     K: AsRef<[u8]>,
-    V: AsRef<[u8]>,
+    V: HashableValue,
 {
     let proof_len = proof.len();
 
-    // traverse up the tree and compute the hash of each node
-    // eventually we should reach the root
     #[allow(clippy::needless_range_loop)]
     for i in 1..proof_len {
         let node = &proof[i];
         let child = ProofChild {
-            index: nibble_path.get_nibble(proof_len - i - 1),
+            // a proof longer than the key would otherwise index past its end
+            index: nibble_path
+                .checked_get_nibble(proof_len - i - 1)
+                .ok_or(VerificationError::ProofTooLong)?,
             hash,
         };
-        hash = node.hash(Some(&child), None);
+        hash = node.hash_using::<H>(Some(&child), None);
+    }
+
+    Ok(hash)
+}
+
+// compare the root hash computed from a proof with the given root hash
+fn check_root_hash<H: TreeHasher>(
+    domain: Option<&[u8]>,
+    root_hash: &Hash,
+    mut hash: Hash,
+) -> Result<()> {
+    // if the tree has a domain, the root hash is tagged with it
+    if let Some(domain) = domain {
+        hash = domain_root_hash_using::<H>(domain, &hash);
     }
 
-    // now we have arrived at the root, the computed root hash should match the
-    // given root hash
     if hash != *root_hash {
         return Err(VerificationError::RootHashMismatch {
             given: root_hash.clone(),
@@ -97,18 +764,75 @@ where
 
 #[derive(Debug, thiserror::Error)]
 pub enum VerificationError {
+    #[cfg(feature = "storage")]
+    #[error(transparent)]
+    Std(#[from] StdError),
+
+    #[error("response doesn't include a proof")]
+    ProofMissing,
+
     #[error("proof cannot be empty")]
     ProofEmpty,
 
     #[error("proof is too long")]
     ProofTooLong,
 
+    #[error("proof nodes don't form a single tree")]
+    ProofMalformed,
+
     #[error("want to prove non-membership but key in fact exists")]
     KeyExists,
 
+    #[error("want to prove membership but key in fact doesn't exist")]
+    KeyMissing,
+
+    #[error("proven value doesn't match the given value")]
+    ValueMismatch,
+
     #[error("expecting node to not have a certain child but it does")]
     UnexpectedChild,
 
+    #[error("store is empty, so the key can't exist in it")]
+    StoreEmpty,
+
+    #[error("proof node is missing its aggregate")]
+    AggregateMissing,
+
+    #[error("proof node is missing its version")]
+    VersionMissing,
+
+    #[error("node at version {child} is the child of an older node at version {parent}")]
+    VersionIncreasing {
+        parent: u64,
+        child: u64,
+    },
+
+    #[error("aggregate mismatch! proven: {proven}, given: {given}")]
+    AggregateMismatch {
+        given: u128,
+        proven: u128,
+    },
+
+    #[error("subtree hash mismatch! proven: {proven:?}, given: {given:?}")]
+    SubtreeMismatch {
+        given: Option<Hash>,
+        proven: Option<Hash>,
+    },
+
+    #[error("root history proof doesn't match the number of versions recorded")]
+    HistoryProofMalformed,
+
+    #[error("header of version {version} doesn't chain onto the previous header")]
+    VersionChainBroken {
+        version: u64,
+    },
+
+    #[cfg(feature = "journal")]
+    #[error("journal entry {index} doesn't chain onto the previous entry")]
+    JournalBroken {
+        index: u64,
+    },
+
     #[error("hash mismatch! computed: {computed}, given: {given}")]
     RootHashMismatch {
         given: Hash,
@@ -116,6 +840,17 @@ pub enum VerificationError {
     },
 }
 
+// contracts report errors as `StdError`
+#[cfg(feature = "storage")]
+impl From<VerificationError> for StdError {
+    fn from(err: VerificationError) -> Self {
+        match err {
+            VerificationError::Std(err) => err,
+            err => StdError::generic_err(err.to_string()),
+        }
+    }
+}
+
 type Result<T> = std::result::Result<T, VerificationError>;
 
 // ----------------------------------- tests -----------------------------------
@@ -124,16 +859,20 @@ type Result<T> = std::result::Result<T, VerificationError>;
 mod tests {
     use {
         crate::{
+            compute_root_from_membership, compute_root_from_non_membership, proof_preimages,
             verify_membership, verify_non_membership, Hash, Nibble, Proof, ProofChild, ProofNode,
-            Record,
+            Record, VerificationError, HASH_LEN,
         },
         test_case::test_case,
     };
 
+    #[cfg(not(feature = "truncated-hash"))]
     fn hash(hex_str: &str) -> Hash {
         hex::decode(hex_str).unwrap().as_slice().try_into().unwrap()
     }
 
+    // the test vectors use full-length hashes
+    #[cfg(not(feature = "truncated-hash"))]
     #[test_case(
         hash("15484df8d087ecd9e58d6b7c8c6bc3e80718d367e1e55861bac3207709bf92fa"),
         "fuzz".into(),
@@ -142,6 +881,8 @@ mod tests {
             ProofNode {
                 children: vec![],
                 data: None,
+                aggregate: None,
+                version: None,
             },
             ProofNode {
                 children: vec![ProofChild {
@@ -149,6 +890,8 @@ mod tests {
                     hash: hash("0aaeb7f6ce9c7ee7d47fc5643f3fe54eb30ae79a52d1a637b8723dc06d82d76a"),
                 }],
                 data: None,
+                aggregate: None,
+                version: None,
             },
             ProofNode {
                 children: vec![ProofChild {
@@ -156,6 +899,8 @@ mod tests {
                     hash: hash("33f24d09639e54c70bfac0168b9ffa29bca260877fa9d01aecb7a9edf8299c43"),
                 }],
                 data: None,
+                aggregate: None,
+                version: None,
             },
             ProofNode {
                 children: vec![ProofChild {
@@ -163,6 +908,8 @@ mod tests {
                     hash: hash("330dd01838a67a80022676874011c607b694b9ba3ca81503dbc2f422870ae664"),
                 }],
                 data: None,
+                aggregate: None,
+                version: None,
             },
         ];
         "proving (fuzz, buzz) exists"
@@ -174,8 +921,15 @@ mod tests {
         proof: Proof<String, String>,
     ) {
         assert!(verify_membership(&root_hash, &key, &value, &proof).is_ok());
+        assert_eq!(compute_root_from_membership(&key, &value, &proof).unwrap(), root_hash);
+
+        let preimages = proof_preimages(&key, Some(&value), &proof).unwrap();
+        assert_eq!(preimages.len(), proof.len());
+        assert_eq!(Hash::from(blake3::hash(preimages.last().unwrap())), root_hash);
     }
 
+    // the test vectors use full-length hashes
+    #[cfg(not(feature = "truncated-hash"))]
     #[test_case(
         hash("15484df8d087ecd9e58d6b7c8c6bc3e80718d367e1e55861bac3207709bf92fa"),
         "f".into(),
@@ -192,6 +946,8 @@ mod tests {
                     },
                 ],
                 data: None,
+                aggregate: None,
+                version: None,
             },
             ProofNode {
                 children: vec![ProofChild {
@@ -199,6 +955,8 @@ mod tests {
                     hash: hash("33f24d09639e54c70bfac0168b9ffa29bca260877fa9d01aecb7a9edf8299c43"),
                 }],
                 data: None,
+                aggregate: None,
+                version: None,
             },
             ProofNode {
                 children: vec![ProofChild {
@@ -206,6 +964,8 @@ mod tests {
                     hash: hash("330dd01838a67a80022676874011c607b694b9ba3ca81503dbc2f422870ae664"),
                 }],
                 data: None,
+                aggregate: None,
+                version: None,
             },
         ];
         "proving f does not exist"
@@ -220,6 +980,8 @@ mod tests {
                     key: "food".into(),
                     value: "ramen".into(),
                 }),
+                aggregate: None,
+                version: None,
             },
             ProofNode {
                 children: vec![ProofChild {
@@ -227,6 +989,8 @@ mod tests {
                     hash: hash("8b71a1adc67423c9bb53a1eb6a20f664138f112697d8f419f1c0ee1528c47d9f"),
                 }],
                 data: None,
+                aggregate: None,
+                version: None,
             },
             ProofNode {
                 children: vec![ProofChild {
@@ -234,6 +998,8 @@ mod tests {
                     hash: hash("33f24d09639e54c70bfac0168b9ffa29bca260877fa9d01aecb7a9edf8299c43"),
                 }],
                 data: None,
+                aggregate: None,
+                version: None,
             },
             ProofNode {
                 children: vec![ProofChild {
@@ -241,6 +1007,8 @@ mod tests {
                     hash: hash("330dd01838a67a80022676874011c607b694b9ba3ca81503dbc2f422870ae664"),
                 }],
                 data: None,
+                aggregate: None,
+                version: None,
             },
         ];
         "proving foo does not exist"
@@ -251,5 +1019,32 @@ mod tests {
         proof: Proof<String, String>,
     ) {
         assert!(verify_non_membership(&root_hash, &key, &proof).is_ok());
+        assert_eq!(compute_root_from_non_membership(&key, &proof).unwrap(), root_hash);
+
+        let preimages = proof_preimages(&key, None, &proof).unwrap();
+        assert_eq!(Hash::from(blake3::hash(preimages.last().unwrap())), root_hash);
+    }
+
+    #[test]
+    fn rejecting_overlong_proof() {
+        // "a" is 2 nibbles long, so the proof can have at most 3 nodes
+        let node = ProofNode::<String, String> {
+            children: vec![],
+            data: None,
+            aggregate: None,
+            version: None,
+        };
+        let proof = vec![node; 4];
+        let root_hash = Hash::from([0; HASH_LEN]);
+        let key = "a".to_string();
+
+        assert!(matches!(
+            verify_membership(&root_hash, &key, &"b".to_string(), &proof),
+            Err(VerificationError::ProofTooLong),
+        ));
+        assert!(matches!(
+            proof_preimages(&key, None, &proof),
+            Err(VerificationError::ProofTooLong),
+        ));
     }
 }
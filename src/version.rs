@@ -0,0 +1,45 @@
+/// An application's notion of versions, such as 32-bit block heights, or
+/// composite (epoch, height) pairs, mapped onto the tree's versions, which are
+/// `u64`s. This lets the tree's versions equal the application's, so that no
+/// separate mapping between the two needs to be kept.
+///
+/// The mapping must preserve order: if `a < b`, then `a.to_u64() < b.to_u64()`.
+/// Version 0 means the tree is empty, so it must not be mapped onto.
+pub trait Version: Sized {
+    fn to_u64(&self) -> u64;
+
+    /// `None` if the tree's version has no counterpart of this type.
+    fn from_u64(version: u64) -> Option<Self>;
+}
+
+impl Version for u64 {
+    fn to_u64(&self) -> u64 {
+        *self
+    }
+
+    fn from_u64(version: u64) -> Option<Self> {
+        Some(version)
+    }
+}
+
+impl Version for u32 {
+    fn to_u64(&self) -> u64 {
+        (*self).into()
+    }
+
+    fn from_u64(version: u64) -> Option<Self> {
+        version.try_into().ok()
+    }
+}
+
+// (epoch, height), with the epoch in the high 32 bits, so that versions are
+// ordered by epoch first
+impl Version for (u32, u32) {
+    fn to_u64(&self) -> u64 {
+        (u64::from(self.0) << 32) | u64::from(self.1)
+    }
+
+    fn from_u64(version: u64) -> Option<Self> {
+        Some(((version >> 32) as u32, version as u32))
+    }
+}
@@ -0,0 +1,116 @@
+// only run this test if the "differential" feature is enabled, as it pulls in
+// the jmt crate which we don't otherwise need:
+// $ cargo test --features differential --test differential -- --nocapture
+#![cfg(feature = "differential")]
+
+//! Differential testing against penumbra's [jmt](https://github.com/penumbra-zone/jmt)
+//! crate, which implements Diem's Jellyfish Merkle tree.
+//!
+//! JMT hashes keys before inserting them, whereas we insert keys as they are.
+//! If we hash the keys ourselves before inserting them into our tree, the two
+//! should behave identically, apart from the hash functions used for nodes,
+//! so root hashes and proofs are not compared. Specifically:
+//!
+//! - at every version, a get of any key returns the same value;
+//! - at every version, iterating the whole tree yields the same key-value
+//!   pairs in the same order, that is, ascending by the key hashes.
+//!
+//! This catches changes that break our tree in the special case where all
+//! keys are of the same length, which is the only case JMT supports.
+
+use {
+    cosmwasm_std::{testing::MockStorage, Order},
+    jmt::{iterator::JellyfishMerkleIterator, mock::MockTreeStore, KeyHash, Sha256Jmt},
+    rand::{rngs::StdRng, Rng, SeedableRng},
+    sha2::Sha256,
+    std::{collections::BTreeSet, sync::Arc},
+    tree::{Batch, Op, Tree},
+};
+
+const TREE: Tree<Vec<u8>, Vec<u8>> = Tree::new_default();
+
+const NUM_BATCHES: u64 = 50;
+const BATCH_SIZE: usize = 100;
+
+// keys are drawn from a small space, so that batches often overwrite or delete
+// existing keys
+fn rand_key<R: Rng>(rng: &mut R) -> Vec<u8> {
+    rng.gen_range(0..1000u32).to_be_bytes().to_vec()
+}
+
+fn rand_value<R: Rng>(rng: &mut R) -> Vec<u8> {
+    let len = rng.gen_range(1..=20);
+    (0..len).map(|_| rng.gen()).collect()
+}
+
+#[test]
+fn differential() {
+    let mut rng = StdRng::seed_from_u64(42);
+    let mut store = MockStorage::new();
+    let jmt_store = Arc::new(MockTreeStore::default());
+    let jmt = Sha256Jmt::new(&*jmt_store);
+
+    // the hashes of all keys ever touched
+    let mut key_hashes = BTreeSet::new();
+
+    for version in 1..=NUM_BATCHES {
+        // each batch inserts at least one new key, so our version is always
+        // incremented, and matches jmt's, which start from zero
+        let mut batch = Batch::new();
+        let new_key = (1000 + version as u32).to_be_bytes().to_vec();
+        batch.insert(new_key, Op::Insert(rand_value(&mut rng)));
+        for _ in 0..BATCH_SIZE {
+            let op = if rng.gen_bool(0.8) {
+                Op::Insert(rand_value(&mut rng))
+            } else {
+                Op::Delete
+            };
+            batch.insert(rand_key(&mut rng), op);
+        }
+
+        let batch: Batch<_, _> = batch
+            .into_iter()
+            .map(|(key, op)| (KeyHash::with::<Sha256>(key).0.to_vec(), op))
+            .collect();
+        let value_set = batch
+            .iter()
+            .map(|(key_hash, op)| {
+                let key_hash = KeyHash(key_hash.as_slice().try_into().unwrap());
+                match op {
+                    Op::Insert(value) => (key_hash, Some(value.clone())),
+                    Op::Delete => (key_hash, None),
+                }
+            })
+            .collect::<Vec<_>>();
+        key_hashes.extend(batch.keys().cloned());
+
+        let (_, update_batch) = jmt.put_value_set(value_set, version - 1).unwrap();
+        jmt_store.write_tree_update_batch(update_batch).unwrap();
+        assert_eq!(TREE.apply(&mut store, batch).unwrap().version, version);
+
+        // check the latest version, and a random older one
+        for version in [version, rng.gen_range(1..=version)] {
+            for key_hash in &key_hashes {
+                let value = TREE.get(&store, key_hash, false, Some(version)).unwrap().value;
+                let jmt_key_hash = KeyHash(key_hash.as_slice().try_into().unwrap());
+                let jmt_value = jmt.get(jmt_key_hash, version - 1).unwrap();
+                assert_eq!(value, jmt_value, "get mismatch at version {version}");
+            }
+
+            let items = TREE
+                .iterate(&store, Order::Ascending, None, None, Some(version))
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            let jmt_items =
+                JellyfishMerkleIterator::new(jmt_store.clone(), version - 1, KeyHash([0; 32]))
+                    .unwrap()
+                    .map(|item| item.map(|(key_hash, value)| (key_hash.0.to_vec(), value)))
+                    .collect::<Result<Vec<_>, _>>()
+                    .unwrap();
+            assert_eq!(items, jmt_items, "iteration mismatch at version {version}");
+        }
+
+        println!("version {version}: {} keys checked, ok", key_hashes.len());
+    }
+}